@@ -0,0 +1,7 @@
+-- Wrapped end-to-end encryption key per sync key. The server never sees the
+-- passphrase or the data key; the envelope is an opaque blob to it.
+CREATE TABLE IF NOT EXISTS key_envelopes (
+    sync_key TEXT PRIMARY KEY,
+    envelope TEXT NOT NULL,
+    updated_at INTEGER NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Manual sidebar order (fractional keys chosen by the clients) and note colors.
+ALTER TABLE notes ADD COLUMN sort_key TEXT NOT NULL DEFAULT '';
+ALTER TABLE notes ADD COLUMN color TEXT;
+ALTER TABLE folders ADD COLUMN sort_key TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,9 @@
+-- Store every field the clients send: per-row versions, folder colors, and an
+-- `extra` JSON object with fields this server version does not know yet.
+ALTER TABLE notes ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE notes ADD COLUMN extra TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE folders ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE folders ADD COLUMN color TEXT;
+ALTER TABLE folders ADD COLUMN extra TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE workspaces ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE workspaces ADD COLUMN extra TEXT NOT NULL DEFAULT '{}';
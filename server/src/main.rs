@@ -6,7 +6,7 @@ use axum::{
     routing::{get, post},
     Json, Router,
 };
-use models::{SyncRequest, SyncResponse, Note, Folder, Workspace, NoteRow, FolderRow, WorkspaceRow};
+use models::{extra_from_json, extra_to_json, SyncRequest, SyncResponse, KeysPayload, Note, Folder, Workspace, NoteRow, FolderRow, WorkspaceRow};
 use sqlx::sqlite::SqlitePoolOptions;
 use std::net::SocketAddr;
 use tower_http::cors::CorsLayer;
@@ -64,6 +64,7 @@ async fn main() {
     let app = Router::new()
         .route("/health", get(health_check))
         .route("/sync", post(sync_handler))
+        .route("/keys", get(get_keys_handler).put(put_keys_handler))
         .layer(CorsLayer::permissive())
         .with_state(state);
 
@@ -78,22 +79,81 @@ async fn health_check() -> (StatusCode, Json<serde_json::Value>) {
 }
 
 
-async fn sync_handler(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(payload): Json<SyncRequest>,
-) -> Result<Json<SyncResponse>, (StatusCode, String)> {
+fn sync_key_from(headers: &HeaderMap) -> Result<String, (StatusCode, String)> {
     // Try both lowercase and camelCase header names (HTTP headers are case-insensitive but some libraries normalize them)
-    let sync_key = headers
+    headers
         .get("x-sync-key")
         .or_else(|| headers.get("X-Sync-Key"))
         .and_then(|h: &axum::http::HeaderValue| h.to_str().ok())
+        .map(|h| h.to_string())
         .ok_or_else(|| {
             tracing::warn!("Missing x-sync-key header. Available headers: {:?}", headers.keys().map(|k| k.as_str()).collect::<Vec<_>>());
             (StatusCode::UNAUTHORIZED, "Missing x-sync-key header".to_string())
-        })?;
-    
-    let sync_key = sync_key.to_string();
+        })
+}
+
+async fn get_keys_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<KeysPayload>, (StatusCode, String)> {
+    let sync_key = sync_key_from(&headers)?;
+
+    let envelope: Option<String> = sqlx::query_scalar("SELECT envelope FROM key_envelopes WHERE sync_key = ?")
+        .bind(&sync_key)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(KeysPayload { envelope, previous: None }))
+}
+
+async fn put_keys_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<KeysPayload>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let sync_key = sync_key_from(&headers)?;
+    let envelope = payload
+        .envelope
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing envelope".to_string()))?;
+
+    let mut tx = state.pool.begin().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // Compare-and-swap: only replace the envelope the client last saw
+    let current: Option<String> = sqlx::query_scalar("SELECT envelope FROM key_envelopes WHERE sync_key = ?")
+        .bind(&sync_key)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if current != payload.previous {
+        return Err((StatusCode::CONFLICT, "Key envelope was changed by another device".to_string()));
+    }
+
+    sqlx::query(
+        "INSERT INTO key_envelopes (sync_key, envelope, updated_at) VALUES (?, ?, ?)
+         ON CONFLICT(sync_key) DO UPDATE SET envelope = excluded.envelope, updated_at = excluded.updated_at"
+    )
+    .bind(&sync_key)
+    .bind(&envelope)
+    .bind(chrono::Utc::now().timestamp_millis())
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    tx.commit().await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tracing::info!("Key envelope updated for sync_key length: {}", sync_key.len());
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn sync_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<SyncRequest>,
+) -> Result<Json<SyncResponse>, (StatusCode, String)> {
+    let sync_key = sync_key_from(&headers)?;
     tracing::info!("Sync request received with sync_key length: {}", sync_key.len());
     tracing::debug!("Sync payload: {} notes, {} folders, {} workspaces, last_sync_time: {}", payload.notes.len(), payload.folders.len(), payload.workspaces.len(), payload.last_sync_time);
 
@@ -105,16 +165,21 @@ async fn sync_handler(
         let created_at = note.created_at.unwrap_or(note.updated_at);
         
         sqlx::query(
-            "INSERT INTO notes (id, sync_key, title, content, folder_id, workspace_id, created_at, updated_at, is_deleted)
-             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "INSERT INTO notes (id, sync_key, title, content, folder_id, workspace_id, created_at, updated_at, version, sort_key, color, is_deleted, extra)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
              ON CONFLICT(id) DO UPDATE SET
                  title = excluded.title,
                  content = excluded.content,
                  folder_id = excluded.folder_id,
                  workspace_id = excluded.workspace_id,
                  updated_at = excluded.updated_at,
-                 is_deleted = excluded.is_deleted
-             WHERE excluded.updated_at > notes.updated_at"
+                 version = excluded.version,
+                 sort_key = excluded.sort_key,
+                 color = excluded.color,
+                 is_deleted = excluded.is_deleted,
+                 extra = json_patch(notes.extra, excluded.extra)
+             WHERE excluded.updated_at > notes.updated_at
+                OR (? AND excluded.updated_at = notes.updated_at)"
         )
         .bind(&note.id)
         .bind(&sync_key)
@@ -124,7 +189,12 @@ async fn sync_handler(
         .bind(&note.workspace_id)
         .bind(created_at)
         .bind(note.updated_at)
+        .bind(note.version)
+        .bind(&note.sort_key)
+        .bind(&note.color)
         .bind(note.is_deleted)
+        .bind(extra_to_json(&note.extra))
+        .bind(payload.replace)
         .execute(&state.pool)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
@@ -136,15 +206,20 @@ async fn sync_handler(
         let created_at = folder.created_at.unwrap_or(folder.updated_at);
         
         sqlx::query(
-            "INSERT INTO folders (id, sync_key, name, parent_id, workspace_id, created_at, updated_at, is_deleted)
-             VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "INSERT INTO folders (id, sync_key, name, parent_id, workspace_id, created_at, updated_at, version, color, sort_key, is_deleted, extra)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
              ON CONFLICT(id) DO UPDATE SET
                  name = excluded.name,
                  parent_id = excluded.parent_id,
                  workspace_id = excluded.workspace_id,
                  updated_at = excluded.updated_at,
-                 is_deleted = excluded.is_deleted
-             WHERE excluded.updated_at > folders.updated_at"
+                 version = excluded.version,
+                 color = excluded.color,
+                 sort_key = excluded.sort_key,
+                 is_deleted = excluded.is_deleted,
+                 extra = json_patch(folders.extra, excluded.extra)
+             WHERE excluded.updated_at > folders.updated_at
+                OR (? AND excluded.updated_at = folders.updated_at)"
         )
         .bind(&folder.id)
         .bind(&sync_key)
@@ -153,7 +228,12 @@ async fn sync_handler(
         .bind(&folder.workspace_id)
         .bind(created_at)
         .bind(folder.updated_at)
+        .bind(folder.version)
+        .bind(&folder.color)
+        .bind(&folder.sort_key)
         .bind(folder.is_deleted)
+        .bind(extra_to_json(&folder.extra))
+        .bind(payload.replace)
         .execute(&state.pool)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
@@ -165,14 +245,17 @@ async fn sync_handler(
         let created_at = workspace.created_at.unwrap_or(workspace.updated_at);
         
         sqlx::query(
-            "INSERT INTO workspaces (id, sync_key, name, color, created_at, updated_at, is_deleted)
-             VALUES (?, ?, ?, ?, ?, ?, ?)
+            "INSERT INTO workspaces (id, sync_key, name, color, created_at, updated_at, version, is_deleted, extra)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
              ON CONFLICT(id) DO UPDATE SET
                  name = excluded.name,
                  color = excluded.color,
                  updated_at = excluded.updated_at,
-                 is_deleted = excluded.is_deleted
-             WHERE excluded.updated_at > workspaces.updated_at"
+                 version = excluded.version,
+                 is_deleted = excluded.is_deleted,
+                 extra = json_patch(workspaces.extra, excluded.extra)
+             WHERE excluded.updated_at > workspaces.updated_at
+                OR (? AND excluded.updated_at = workspaces.updated_at)"
         )
         .bind(&workspace.id)
         .bind(&sync_key)
@@ -180,7 +263,10 @@ async fn sync_handler(
         .bind(&workspace.color)
         .bind(created_at)
         .bind(workspace.updated_at)
+        .bind(workspace.version)
         .bind(workspace.is_deleted)
+        .bind(extra_to_json(&workspace.extra))
+        .bind(payload.replace)
         .execute(&state.pool)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
@@ -188,7 +274,7 @@ async fn sync_handler(
 
     // 4. Fetch remote updates for client
     let remote_note_rows = sqlx::query_as::<_, NoteRow>(
-        "SELECT id, title, content, folder_id, workspace_id, created_at, updated_at, is_deleted
+        "SELECT id, title, content, folder_id, workspace_id, created_at, updated_at, version, sort_key, color, is_deleted, extra
          FROM notes
          WHERE sync_key = ? AND updated_at > ? AND updated_at < ?"
     )
@@ -208,11 +294,15 @@ async fn sync_handler(
         workspace_id: row.workspace_id,
         created_at: Some(row.created_at),
         updated_at: row.updated_at,
+        version: row.version,
+        sort_key: row.sort_key,
+        color: row.color,
         is_deleted: row.is_deleted,
+        extra: extra_from_json(&row.extra),
     }).collect();
 
     let remote_folder_rows = sqlx::query_as::<_, FolderRow>(
-        "SELECT id, name, parent_id, workspace_id, created_at, updated_at, is_deleted
+        "SELECT id, name, parent_id, workspace_id, created_at, updated_at, version, color, sort_key, is_deleted, extra
          FROM folders
          WHERE sync_key = ? AND updated_at > ? AND updated_at < ?"
     )
@@ -231,11 +321,15 @@ async fn sync_handler(
         workspace_id: row.workspace_id,
         created_at: Some(row.created_at),
         updated_at: row.updated_at,
+        version: row.version,
+        color: row.color,
+        sort_key: row.sort_key,
         is_deleted: row.is_deleted,
+        extra: extra_from_json(&row.extra),
     }).collect();
 
     let remote_workspace_rows = sqlx::query_as::<_, WorkspaceRow>(
-        "SELECT id, name, color, created_at, updated_at, is_deleted
+        "SELECT id, name, color, created_at, updated_at, version, is_deleted, extra
          FROM workspaces
          WHERE sync_key = ? AND updated_at > ? AND updated_at < ?"
     )
@@ -253,7 +347,9 @@ async fn sync_handler(
         color: row.color,
         created_at: Some(row.created_at),
         updated_at: row.updated_at,
+        version: row.version,
         is_deleted: row.is_deleted,
+        extra: extra_from_json(&row.extra),
     }).collect();
 
     Ok(Json(SyncResponse {
@@ -263,3 +359,126 @@ async fn sync_handler(
         workspaces: remote_workspaces,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    async fn test_state() -> AppState {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        AppState { pool }
+    }
+
+    async fn sync(state: &AppState, request: Value) -> Value {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-sync-key", "test-key".parse().unwrap());
+        let request: SyncRequest = serde_json::from_value(request).unwrap();
+        let Json(response) = sync_handler(State(state.clone()), headers, Json(request)).await.unwrap();
+        serde_json::to_value(response).unwrap()
+    }
+
+    fn note() -> Value {
+        json!({
+            "id": "n1", "title": "Title", "content": "Body", "folder_id": "f1", "workspace_id": "w1",
+            "created_at": 100, "updated_at": 200, "version": 7, "sort_key": "V", "color": "#E94F37",
+            "is_deleted": false, "pinned": true
+        })
+    }
+
+    fn folder() -> Value {
+        json!({
+            "id": "f1", "name": "Folder", "parent_id": null, "workspace_id": "w1",
+            "created_at": 100, "updated_at": 200, "version": 3, "color": "#3CB371", "sort_key": "a",
+            "is_deleted": false, "icon": { "name": "star" }
+        })
+    }
+
+    fn workspace() -> Value {
+        json!({
+            "id": "w1", "name": "Work", "color": "#4F7DF3",
+            "created_at": 100, "updated_at": 200, "version": 5, "is_deleted": false, "archived": false
+        })
+    }
+
+    #[tokio::test]
+    async fn test_every_field_round_trips() {
+        let state = test_state().await;
+        sync(&state, json!({
+            "last_sync_time": 0, "notes": [note()], "folders": [folder()], "workspaces": [workspace()]
+        })).await;
+
+        let pulled = sync(&state, json!({ "last_sync_time": 0, "notes": [], "folders": [], "workspaces": [] })).await;
+        assert_eq!(pulled["notes"], json!([note()]));
+        assert_eq!(pulled["folders"], json!([folder()]));
+        assert_eq!(pulled["workspaces"], json!([workspace()]));
+    }
+
+    #[tokio::test]
+    async fn test_older_client_keeps_unknown_fields() {
+        let state = test_state().await;
+        sync(&state, json!({ "last_sync_time": 0, "notes": [note()], "folders": [], "workspaces": [] })).await;
+
+        // A client that has never heard of `pinned` edits the note.
+        let mut edited = note();
+        edited["title"] = json!("Edited");
+        edited["updated_at"] = json!(300);
+        edited["version"] = json!(8);
+        edited.as_object_mut().unwrap().remove("pinned");
+        sync(&state, json!({ "last_sync_time": 0, "notes": [edited], "folders": [], "workspaces": [] })).await;
+
+        let pulled = sync(&state, json!({ "last_sync_time": 0, "notes": [], "folders": [], "workspaces": [] })).await;
+        let pulled = &pulled["notes"][0];
+        assert_eq!(pulled["title"], "Edited");
+        assert_eq!(pulled["version"], 8);
+        assert_eq!(pulled["pinned"], true);
+    }
+
+    #[tokio::test]
+    async fn test_stale_update_and_missing_fields() {
+        let state = test_state().await;
+        sync(&state, json!({ "last_sync_time": 0, "notes": [note()], "folders": [], "workspaces": [] })).await;
+
+        // Older than what the server has: ignored, including its version.
+        let mut stale = note();
+        stale["updated_at"] = json!(150);
+        stale["version"] = json!(1);
+        stale["color"] = Value::Null;
+        // A client from before versions and colors existed.
+        let legacy_folder = json!({
+            "id": "f2", "name": "Old", "parent_id": null, "workspace_id": "w1", "updated_at": 200, "is_deleted": false
+        });
+        sync(&state, json!({ "last_sync_time": 0, "notes": [stale], "folders": [legacy_folder], "workspaces": [] })).await;
+
+        let pulled = sync(&state, json!({ "last_sync_time": 0, "notes": [], "folders": [], "workspaces": [] })).await;
+        assert_eq!(pulled["notes"], json!([note()]));
+        assert_eq!(pulled["folders"][0]["version"], 1);
+        assert_eq!(pulled["folders"][0]["created_at"], 200);
+        assert_eq!(pulled["folders"][0]["color"], Value::Null);
+        assert_eq!(pulled["folders"][0]["sort_key"], "");
+    }
+
+    #[tokio::test]
+    async fn test_replace_overwrites_rows_with_the_same_timestamp() {
+        let state = test_state().await;
+        sync(&state, json!({ "last_sync_time": 0, "notes": [note()], "folders": [], "workspaces": [] })).await;
+
+        let mut encrypted = note();
+        encrypted["title"] = json!("lnenc1:...");
+        sync(&state, json!({ "last_sync_time": 0, "notes": [encrypted.clone()], "folders": [], "workspaces": [] })).await;
+        let pulled = sync(&state, json!({ "last_sync_time": 0, "notes": [], "folders": [], "workspaces": [] })).await;
+        assert_eq!(pulled["notes"][0]["title"], "Title");
+
+        sync(&state, json!({
+            "last_sync_time": 0, "replace": true, "notes": [encrypted], "folders": [], "workspaces": []
+        })).await;
+        let pulled = sync(&state, json!({ "last_sync_time": 0, "notes": [], "folders": [], "workspaces": [] })).await;
+        assert_eq!(pulled["notes"][0]["title"], "lnenc1:...");
+        assert_eq!(pulled["notes"][0]["updated_at"], 200);
+    }
+}
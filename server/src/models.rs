@@ -1,6 +1,24 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+/// Fields a newer client sent that this server has no column for. They are
+/// stored as a JSON object and sent back as top-level fields, so they survive
+/// a round trip through an older server.
+pub type Extra = serde_json::Map<String, serde_json::Value>;
+
+fn default_version() -> i32 {
+    1
+}
+
+pub fn extra_to_json(extra: &Extra) -> String {
+    serde_json::Value::Object(extra.clone()).to_string()
+}
+
+/// Reads a stored `extra` column back; anything unparsable counts as empty.
+pub fn extra_from_json(raw: &str) -> Extra {
+    serde_json::from_str(raw).unwrap_or_default()
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Note {
     pub id: String,
@@ -10,7 +28,15 @@ pub struct Note {
     pub workspace_id: String,
     pub created_at: Option<i64>,  // Optional in request, required in response
     pub updated_at: i64,
+    #[serde(default = "default_version")]
+    pub version: i32,
+    #[serde(default)]
+    pub sort_key: String,  // Absent from older clients
+    #[serde(default)]
+    pub color: Option<String>,
     pub is_deleted: bool,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 // Separate struct for database rows (with required created_at)
@@ -23,7 +49,11 @@ pub struct NoteRow {
     pub workspace_id: String,
     pub created_at: i64,
     pub updated_at: i64,
+    pub version: i32,
+    pub sort_key: String,
+    pub color: Option<String>,
     pub is_deleted: bool,
+    pub extra: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -34,7 +64,15 @@ pub struct Folder {
     pub workspace_id: String,
     pub created_at: Option<i64>,  // Optional in request, required in response
     pub updated_at: i64,
+    #[serde(default = "default_version")]
+    pub version: i32,
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub sort_key: String,  // Absent from older clients
     pub is_deleted: bool,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 // Separate struct for database rows (with required created_at)
@@ -46,7 +84,11 @@ pub struct FolderRow {
     pub workspace_id: String,
     pub created_at: i64,
     pub updated_at: i64,
+    pub version: i32,
+    pub color: Option<String>,
+    pub sort_key: String,
     pub is_deleted: bool,
+    pub extra: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -56,7 +98,11 @@ pub struct Workspace {
     pub color: String,
     pub created_at: Option<i64>,  // Optional in request, required in response
     pub updated_at: i64,
+    #[serde(default = "default_version")]
+    pub version: i32,
     pub is_deleted: bool,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 // Separate struct for database rows (with required created_at)
@@ -67,12 +113,18 @@ pub struct WorkspaceRow {
     pub color: String,
     pub created_at: i64,
     pub updated_at: i64,
+    pub version: i32,
     pub is_deleted: bool,
+    pub extra: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SyncRequest {
     pub last_sync_time: i64,
+    /// The client re-encrypted every row: its rows also replace stored ones
+    /// with the same `updated_at`, so no plaintext copy is left behind.
+    #[serde(default)]
+    pub replace: bool,
     pub notes: Vec<Note>,
     pub folders: Vec<Folder>,
     pub workspaces: Vec<Workspace>,
@@ -85,3 +137,13 @@ pub struct SyncResponse {
     pub folders: Vec<Folder>,
     pub workspaces: Vec<Workspace>,
 }
+
+/// Encrypted key envelope shared between a user's devices. `previous` is the
+/// envelope the client based its change on; writes are refused when it no
+/// longer matches the stored one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeysPayload {
+    pub envelope: Option<String>,
+    #[serde(default)]
+    pub previous: Option<String>,
+}
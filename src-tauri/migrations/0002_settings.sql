@@ -0,0 +1,6 @@
+-- Key/value store for backend state (sync configuration and watermarks).
+-- Unversioned databases may already have it.
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
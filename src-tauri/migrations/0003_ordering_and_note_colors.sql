@@ -0,0 +1,5 @@
+-- Manual sidebar order as fractional keys (see ordering.rs) and note colors.
+-- Existing rows keep an empty key and fall back to creation order.
+ALTER TABLE notes ADD COLUMN sort_key TEXT NOT NULL DEFAULT '';
+ALTER TABLE notes ADD COLUMN color TEXT;
+ALTER TABLE folders ADD COLUMN sort_key TEXT NOT NULL DEFAULT '';
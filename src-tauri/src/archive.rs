@@ -0,0 +1,602 @@
+//! `lumenote-backup` files: every workspace, folder, note, revision and
+//! setting as JSON, one record per line, optionally zstd-compressed. Unlike
+//! the Markdown export they keep ids, versions and deleted rows, so restoring
+//! one brings a database back to where sync can pick up again, and unlike the
+//! snapshots in `backup` they don't depend on the SQLite schema.
+//!
+//! The first line is a `header`, the last an `end` record with the number of
+//! records before it, so a truncated file is refused instead of half-restored.
+//! The file is never encrypted, not even when the database is (`vault`), and
+//! holds the sync key in the clear; keep it as safe as the database.
+
+use crate::db::{now_millis, Db, FolderRecord, NoteRecord, WorkspaceRecord};
+use crate::error::AppError;
+use crate::grants::{write_atomic_with, Access, FileGrants};
+use crate::migrations;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// Value of `format` in the header.
+pub const BACKUP_FORMAT: &str = "lumenote-backup";
+
+/// Bumped whenever records change in a way older versions can't read.
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Rows read from the database per query while exporting.
+const PAGE_SIZE: i64 = 500;
+
+/// Compression level; zstd's default, already far smaller than the JSON.
+const ZSTD_LEVEL: i32 = 3;
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ChangeRecord {
+    pub id: String,
+    pub note_id: String,
+    pub old_content: Option<String>,
+    pub new_content: Option<String>,
+    pub timestamp: i64,
+    pub version: i32,
+}
+
+/// One line of a backup file, e.g. `{ "type": "note", "id": "...", ... }`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackupRecord {
+    Header {
+        format: String,
+        format_version: u32,
+        /// Schema of the database that wrote the file, for diagnostics.
+        schema_version: i64,
+        created_at: i64,
+    },
+    Workspace(WorkspaceRecord),
+    Folder(FolderRecord),
+    Note(NoteRecord),
+    Change(ChangeRecord),
+    Setting {
+        key: String,
+        value: String,
+    },
+    End {
+        /// Records between the header and this one.
+        records: u64,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BackupSummary {
+    pub workspaces: u64,
+    pub folders: u64,
+    pub notes: u64,
+    pub changes: u64,
+    pub settings: u64,
+}
+
+impl BackupSummary {
+    fn count(&mut self, record: &BackupRecord) {
+        match record {
+            BackupRecord::Workspace(_) => self.workspaces += 1,
+            BackupRecord::Folder(_) => self.folders += 1,
+            BackupRecord::Note(_) => self.notes += 1,
+            BackupRecord::Change(_) => self.changes += 1,
+            BackupRecord::Setting { .. } => self.settings += 1,
+            BackupRecord::Header { .. } | BackupRecord::End { .. } => {}
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.workspaces + self.folders + self.notes + self.changes + self.settings
+    }
+}
+
+/// Writes a backup of the whole database to `path`. Rows are read a page at a
+/// time and handed to a writer thread, so memory stays flat however large the
+/// database is. The backup is never encrypted, even when the database is.
+pub async fn export_backup_logic(
+    db: &Db,
+    path: PathBuf,
+    compress: bool,
+) -> Result<BackupSummary, AppError> {
+    // `None` marks the end of a complete backup; if the sender goes away
+    // without it, the writer drops the temp file
+    let (sender, receiver) = mpsc::channel::<Option<Vec<u8>>>(64);
+    let writer = tokio::task::spawn_blocking(move || write_backup(&path, compress, receiver));
+
+    let result = send_records(db, &sender).await;
+    if result.is_ok() {
+        let _ = sender.send(None).await;
+    }
+    drop(sender);
+    let written = writer
+        .await
+        .map_err(|e| AppError::internal(format!("Backup writer failed: {}", e)))?;
+    let summary = result?;
+    written?;
+    Ok(summary)
+}
+
+async fn send_records(
+    db: &Db,
+    sender: &mpsc::Sender<Option<Vec<u8>>>,
+) -> Result<BackupSummary, AppError> {
+    // One read transaction for the whole file, so a note saved mid-export
+    // can't show up without the folder or revisions it needs
+    let mut conn = db.pool.begin().await?;
+    let mut summary = BackupSummary::default();
+    let send = |record: BackupRecord| async move {
+        let mut line = serde_json::to_vec(&record)
+            .map_err(|e| AppError::internal(format!("Failed to serialize backup: {}", e)))?;
+        line.push(b'\n');
+        sender
+            .send(Some(line))
+            .await
+            .map_err(|_| AppError::internal("Backup writer stopped"))
+    };
+
+    send(BackupRecord::Header {
+        format: BACKUP_FORMAT.to_string(),
+        format_version: BACKUP_VERSION,
+        schema_version: migrations::current_version(&mut conn).await?,
+        created_at: now_millis(),
+    })
+    .await?;
+
+    // Keyset pages in id order, so rows written meanwhile can't shift them
+    for table in ["workspaces", "folders", "notes", "changes"] {
+        let mut after = String::new();
+        loop {
+            let page = read_page(&mut conn, table, &after).await?;
+            let Some(last) = page.last() else { break };
+            after = record_id(last).to_string();
+            for record in page {
+                summary.count(&record);
+                send(record).await?;
+            }
+        }
+    }
+
+    let settings: Vec<(String, String)> =
+        sqlx::query_as("SELECT key, value FROM settings ORDER BY key")
+            .fetch_all(&mut *conn)
+            .await?;
+    for (key, value) in settings {
+        let record = BackupRecord::Setting { key, value };
+        summary.count(&record);
+        send(record).await?;
+    }
+
+    send(BackupRecord::End {
+        records: summary.total(),
+    })
+    .await?;
+    conn.commit().await?;
+    Ok(summary)
+}
+
+/// Next rows of `table` with an id after `after`, deleted ones included.
+async fn read_page(
+    conn: &mut SqliteConnection,
+    table: &str,
+    after: &str,
+) -> Result<Vec<BackupRecord>, AppError> {
+    Ok(match table {
+        "workspaces" => rows(conn, table, after)
+            .await?
+            .into_iter()
+            .map(BackupRecord::Workspace)
+            .collect(),
+        "folders" => rows(conn, table, after)
+            .await?
+            .into_iter()
+            .map(BackupRecord::Folder)
+            .collect(),
+        "notes" => rows(conn, table, after)
+            .await?
+            .into_iter()
+            .map(BackupRecord::Note)
+            .collect(),
+        _ => rows(conn, table, after)
+            .await?
+            .into_iter()
+            .map(BackupRecord::Change)
+            .collect(),
+    })
+}
+
+async fn rows<T>(conn: &mut SqliteConnection, table: &str, after: &str) -> Result<Vec<T>, AppError>
+where
+    T: for<'r> sqlx::FromRow<'r, sqlx::sqlite::SqliteRow> + Send + Unpin,
+{
+    sqlx::query_as::<_, T>(&format!(
+        "SELECT * FROM {} WHERE id > ?1 ORDER BY id LIMIT ?2",
+        table
+    ))
+    .bind(after)
+    .bind(PAGE_SIZE)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(AppError::from)
+}
+
+fn record_id(record: &BackupRecord) -> &str {
+    match record {
+        BackupRecord::Workspace(w) => &w.id,
+        BackupRecord::Folder(f) => &f.id,
+        BackupRecord::Note(n) => &n.id,
+        BackupRecord::Change(c) => &c.id,
+        _ => "",
+    }
+}
+
+/// Plain or zstd output on top of the temp file `write_atomic_with` hands out.
+enum Sink {
+    Plain(BufWriter<File>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Sink {
+    fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(w) => w.write_all(bytes),
+            Sink::Zstd(w) => w.write_all(bytes),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<File> {
+        let buffered = match self {
+            Sink::Plain(w) => w,
+            Sink::Zstd(w) => w.finish()?,
+        };
+        buffered.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+fn write_backup(
+    path: &Path,
+    compress: bool,
+    mut receiver: mpsc::Receiver<Option<Vec<u8>>>,
+) -> Result<(), AppError> {
+    write_atomic_with(path, |file| {
+        let io = |e| AppError::io(path, e);
+        let file = BufWriter::new(file);
+        let mut sink = if compress {
+            Sink::Zstd(zstd::Encoder::new(file, ZSTD_LEVEL).map_err(io)?)
+        } else {
+            Sink::Plain(file)
+        };
+        loop {
+            match receiver.blocking_recv() {
+                Some(Some(line)) => sink.write_all(&line).map_err(io)?,
+                Some(None) => break,
+                None => {
+                    return Err(AppError::Cancelled);
+                }
+            }
+        }
+        sink.finish().map_err(io)
+    })
+}
+
+/// Restores a backup, keeping every id. An empty database (no folders or
+/// notes yet) takes the backup as it is, settings included. An existing one
+/// keeps whichever side of a row was updated last, as a sync would, and its
+/// own settings. Nothing is written unless the whole file reads cleanly.
+pub async fn import_backup_logic(db: &Db, path: PathBuf) -> Result<BackupSummary, AppError> {
+    // Decompressing and parsing block, so a reader thread hands records over;
+    // dropping the receiver on an error stops it
+    let (sender, mut receiver) = mpsc::channel::<Result<BackupRecord, AppError>>(64);
+    let reader = tokio::task::spawn_blocking(move || read_backup(&path, sender));
+    let result = restore_records(db, &mut receiver).await;
+    drop(receiver);
+    reader
+        .await
+        .map_err(|e| AppError::internal(format!("Backup reader failed: {}", e)))?;
+    result
+}
+
+/// Reads `path` record by record into `sender`, stopping at the first error
+/// or once nobody is listening.
+fn read_backup(path: &Path, sender: mpsc::Sender<Result<BackupRecord, AppError>>) {
+    let open = || -> Result<Box<dyn BufRead>, AppError> {
+        let file = File::open(path).map_err(|e| AppError::io(path, e))?;
+        let mut reader = BufReader::new(file);
+        let compressed = reader
+            .fill_buf()
+            .map_err(|e| AppError::io(path, e))?
+            .starts_with(&ZSTD_MAGIC);
+        Ok(if compressed {
+            let decoder = zstd::Decoder::with_buffer(reader).map_err(|e| AppError::io(path, e))?;
+            Box::new(BufReader::new(decoder))
+        } else {
+            Box::new(reader)
+        })
+    };
+    let reader = match open() {
+        Ok(reader) => reader,
+        Err(e) => {
+            let _ = sender.blocking_send(Err(e));
+            return;
+        }
+    };
+    for line in reader.lines() {
+        let record = line.map_err(|e| AppError::io(path, e)).and_then(|line| {
+            serde_json::from_str(&line)
+                .map_err(|e| AppError::validation("backup", format!("Unreadable record: {}", e)))
+        });
+        let failed = record.is_err();
+        if sender.blocking_send(record).is_err() || failed {
+            return;
+        }
+    }
+}
+
+async fn restore_records(
+    db: &Db,
+    receiver: &mut mpsc::Receiver<Result<BackupRecord, AppError>>,
+) -> Result<BackupSummary, AppError> {
+    let invalid = |message: String| AppError::validation("backup", message);
+
+    match receiver.recv().await.transpose()? {
+        Some(BackupRecord::Header {
+            format,
+            format_version,
+            ..
+        }) if format == BACKUP_FORMAT => {
+            if format_version > BACKUP_VERSION {
+                return Err(invalid(format!(
+                    "The backup was written by a newer version of Lumenote (format {}, this version reads {})",
+                    format_version, BACKUP_VERSION
+                )));
+            }
+        }
+        _ => return Err(invalid("Not a Lumenote backup".to_string())),
+    }
+
+    let mut conn = db.pool.begin().await?;
+    // Folders come in id order, not parents first; check references once the
+    // whole backup is in
+    sqlx::query("PRAGMA defer_foreign_keys = ON")
+        .execute(&mut *conn)
+        .await?;
+    let has_items: i64 =
+        sqlx::query_scalar("SELECT (SELECT count(*) FROM notes) + (SELECT count(*) FROM folders)")
+            .fetch_one(&mut *conn)
+            .await?;
+    let empty = has_items == 0;
+    if empty {
+        // The workspace seeded on first start would otherwise win over the
+        // older one from the backup
+        sqlx::query("DELETE FROM workspaces")
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    let mut summary = BackupSummary::default();
+    loop {
+        let Some(record) = receiver.recv().await.transpose()? else {
+            return Err(invalid("The backup is incomplete".to_string()));
+        };
+        if let BackupRecord::End { records } = record {
+            if records != summary.total() {
+                return Err(invalid(format!(
+                    "The backup should hold {} records but has {}",
+                    records,
+                    summary.total()
+                )));
+            }
+            break;
+        }
+        summary.count(&record);
+        restore_record(&mut conn, record, empty).await?;
+    }
+
+    conn.commit().await?;
+    db.notify_changed();
+    Ok(summary)
+}
+
+async fn restore_record(
+    conn: &mut SqliteConnection,
+    record: BackupRecord,
+    empty: bool,
+) -> Result<(), AppError> {
+    use crate::db::tx;
+    match record {
+        BackupRecord::Workspace(workspace) => tx::apply_remote_workspace(conn, &workspace).await,
+        BackupRecord::Folder(folder) => tx::apply_remote_folder(conn, &folder).await,
+        BackupRecord::Note(note) => tx::apply_remote_note(conn, &note).await,
+        BackupRecord::Change(change) => {
+            sqlx::query(
+                "INSERT OR IGNORE INTO changes (id, note_id, old_content, new_content, timestamp, version)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .bind(&change.id)
+            .bind(&change.note_id)
+            .bind(&change.old_content)
+            .bind(&change.new_content)
+            .bind(change.timestamp)
+            .bind(change.version)
+            .execute(&mut *conn)
+            .await?;
+            Ok(())
+        }
+        BackupRecord::Setting { key, value } if empty => tx::set_setting(conn, &key, &value).await,
+        BackupRecord::Setting { key, value } => {
+            sqlx::query("INSERT OR IGNORE INTO settings (key, value) VALUES (?1, ?2)")
+                .bind(&key)
+                .bind(&value)
+                .execute(&mut *conn)
+                .await?;
+            Ok(())
+        }
+        BackupRecord::Header { .. } | BackupRecord::End { .. } => Err(AppError::validation(
+            "backup",
+            "Unexpected header or end record",
+        )),
+    }
+}
+
+/// Writes a backup to the path granted by `pick_save_path`.
+#[tauri::command]
+pub async fn export_full_backup(
+    state: tauri::State<'_, crate::DbState>,
+    grants: tauri::State<'_, FileGrants>,
+    token: String,
+    compress: bool,
+) -> Result<BackupSummary, AppError> {
+    let db = state.db()?;
+    let path = grants.redeem(&token, Access::Write)?;
+    export_backup_logic(&db, path, compress).await
+}
+
+/// Restores the backup granted by `pick_open_path`.
+#[tauri::command]
+pub async fn import_full_backup(
+    state: tauri::State<'_, crate::DbState>,
+    grants: tauri::State<'_, FileGrants>,
+    token: String,
+) -> Result<BackupSummary, AppError> {
+    let db = state.db()?;
+    let path = grants.redeem(&token, Access::Read)?;
+    import_backup_logic(&db, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_db, tx};
+
+    async fn new_db() -> (Db, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        (Db::new(init_db(dir.clone()).await.unwrap()), dir)
+    }
+
+    fn note(id: &str, content: &str, updated_at: i64) -> NoteRecord {
+        NoteRecord {
+            id: id.to_string(),
+            title: id.to_string(),
+            content: content.to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at,
+            version: 2,
+            sort_key: crate::ordering::key_between(None, None).unwrap(),
+            color: None,
+            is_deleted: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backup_round_trip_keeps_ids_and_history() {
+        let (source, dir) = new_db().await;
+        let mut conn = source.pool.acquire().await.unwrap();
+        tx::restore_folder(
+            &mut conn,
+            &FolderRecord {
+                id: "f1".to_string(),
+                name: "Folder".to_string(),
+                parent_id: None,
+                workspace_id: "default".to_string(),
+                created_at: 1,
+                updated_at: 1,
+                version: 1,
+                color: None,
+                sort_key: String::new(),
+                is_deleted: true,
+            },
+        )
+        .await
+        .unwrap();
+        tx::restore_note(
+            &mut conn,
+            &NoteRecord {
+                folder_id: Some("f1".to_string()),
+                ..note("n1", "Hello\nworld", 5)
+            },
+        )
+        .await
+        .unwrap();
+        tx::set_setting(&mut conn, "sync.url", "https://example.com")
+            .await
+            .unwrap();
+        drop(conn);
+
+        for compress in [false, true] {
+            let path = dir.join(format!("backup-{}.lumenote-backup", compress));
+            let written = export_backup_logic(&source, path.clone(), compress)
+                .await
+                .unwrap();
+            assert_eq!(written.notes, 1);
+            assert_eq!(written.changes, 1);
+            assert_eq!(
+                std::fs::read(&path).unwrap().starts_with(&ZSTD_MAGIC),
+                compress
+            );
+
+            let (target, _) = new_db().await;
+            let restored = import_backup_logic(&target, path).await.unwrap();
+            assert_eq!(restored, written);
+
+            let notes = target.get_sync_data(0).await.unwrap().0;
+            assert_eq!(notes.len(), 1);
+            assert_eq!(notes[0].content, "Hello\nworld");
+            assert_eq!(notes[0].version, 2);
+            let folder: bool = sqlx::query_scalar("SELECT is_deleted FROM folders WHERE id = 'f1'")
+                .fetch_one(&target.pool)
+                .await
+                .unwrap();
+            assert!(folder);
+            assert_eq!(
+                target.get_setting("sync.url").await.unwrap().as_deref(),
+                Some("https://example.com")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backup_into_existing_database_keeps_newer_rows() {
+        let (source, dir) = new_db().await;
+        let mut conn = source.pool.acquire().await.unwrap();
+        tx::restore_note(&mut conn, &note("n1", "old", 5))
+            .await
+            .unwrap();
+        tx::restore_note(&mut conn, &note("n2", "only in backup", 5))
+            .await
+            .unwrap();
+        drop(conn);
+        let path = dir.join("backup.lumenote-backup");
+        export_backup_logic(&source, path.clone(), false)
+            .await
+            .unwrap();
+
+        let (target, _) = new_db().await;
+        let mut conn = target.pool.acquire().await.unwrap();
+        tx::restore_note(&mut conn, &note("n1", "newer", 9))
+            .await
+            .unwrap();
+        drop(conn);
+        import_backup_logic(&target, path.clone()).await.unwrap();
+
+        let notes = target.get_sync_data(0).await.unwrap().0;
+        let content = |id: &str| notes.iter().find(|n| n.id == id).unwrap().content.clone();
+        assert_eq!(content("n1"), "newer");
+        assert_eq!(content("n2"), "only in backup");
+
+        // A cut-off file is refused as a whole
+        let bytes = std::fs::read(&path).unwrap();
+        let truncated = dir.join("truncated.lumenote-backup");
+        std::fs::write(&truncated, &bytes[..bytes.len() - 20]).unwrap();
+        assert!(matches!(
+            import_backup_logic(&target, truncated).await,
+            Err(AppError::Validation { .. })
+        ));
+    }
+}
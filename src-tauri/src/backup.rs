@@ -0,0 +1,682 @@
+//! Automatic snapshots of the local database into `backups/`, plus commands to
+//! list, verify and restore them. Snapshots are written with `VACUUM INTO`,
+//! which reads inside a single transaction, so they are consistent even while
+//! the app keeps writing, and are encrypted with the same key as the database.
+
+use crate::db::{connect, now_millis, open_db, Db, DbState, DB_FILE};
+use crate::error::{AppError, Entity};
+use crate::migrations;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+/// Directory inside the app data dir that holds every kind of backup.
+pub const BACKUP_DIR: &str = "backups";
+
+const INTERVAL_SETTING: &str = "backup.interval_hours";
+const RETENTION_SETTING: &str = "backup.retention";
+const LAST_ERROR_SETTING: &str = "backup.last_error";
+const DEFAULT_INTERVAL_HOURS: u32 = 24;
+const DEFAULT_RETENTION: u32 = 7;
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupKind {
+    /// Periodic snapshot; the only kind that is rotated.
+    Auto,
+    Manual,
+    /// Taken by the migration runner before a schema upgrade.
+    PreMigration,
+    /// The database as it was right before a restore replaced it.
+    PreRestore,
+    /// Taken before `repair_database` changes anything.
+    PreRepair,
+}
+
+impl BackupKind {
+    fn prefix(self) -> &'static str {
+        match self {
+            BackupKind::Auto => "auto-",
+            BackupKind::Manual => "manual-",
+            BackupKind::PreMigration => "pre-migration-",
+            BackupKind::PreRestore => "pre-restore-",
+            BackupKind::PreRepair => "pre-repair-",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupInfo {
+    pub file_name: String,
+    pub kind: BackupKind,
+    pub created_at: i64,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupSettings {
+    /// Hours between automatic snapshots; 0 turns them off.
+    pub interval_hours: u32,
+    /// Number of automatic snapshots to keep.
+    pub retention: u32,
+    /// Why the last automatic snapshot failed, until one succeeds. Ignored
+    /// when saving.
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupVerification {
+    pub file_name: String,
+    pub ok: bool,
+    pub schema_version: Option<i64>,
+    pub notes: Option<i64>,
+    pub error: Option<String>,
+}
+
+pub fn backup_dir(app_dir: &Path) -> PathBuf {
+    app_dir.join(BACKUP_DIR)
+}
+
+/// Parses `<prefix><millis>.db` (pre-migration names also carry the schema
+/// version they were taken from: `pre-migration-v<n>-<millis>.db`).
+fn parse_name(file_name: &str) -> Option<(BackupKind, i64)> {
+    let stem = file_name.strip_suffix(".db")?;
+    [
+        BackupKind::Auto,
+        BackupKind::Manual,
+        BackupKind::PreMigration,
+        BackupKind::PreRestore,
+        BackupKind::PreRepair,
+    ]
+    .into_iter()
+    .find_map(|kind| {
+        let rest = stem.strip_prefix(kind.prefix())?;
+        let millis = rest.rsplit('-').next()?.parse().ok()?;
+        Some((kind, millis))
+    })
+}
+
+pub fn list(app_dir: &Path) -> Result<Vec<BackupInfo>, AppError> {
+    let dir = backup_dir(app_dir);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(AppError::io(&dir, e)),
+    };
+
+    let mut backups = Vec::new();
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let Some((kind, created_at)) = parse_name(&file_name) else {
+            continue;
+        };
+        let size_bytes = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        backups.push(BackupInfo {
+            file_name,
+            kind,
+            created_at,
+            size_bytes,
+        });
+    }
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+    Ok(backups)
+}
+
+/// Resolves a name from `list` to a path, refusing anything that is not a
+/// plain backup file name (so callers cannot point outside `backups/`).
+pub fn backup_path(app_dir: &Path, file_name: &str) -> Result<PathBuf, AppError> {
+    if parse_name(file_name).is_none()
+        || Path::new(file_name).file_name() != Some(file_name.as_ref())
+    {
+        return Err(AppError::validation(
+            "file_name",
+            format!("not a backup: {}", file_name),
+        ));
+    }
+    let path = backup_dir(app_dir).join(file_name);
+    if !path.is_file() {
+        return Err(AppError::not_found(Entity::Backup, file_name));
+    }
+    Ok(path)
+}
+
+pub async fn settings(db: &Db) -> Result<BackupSettings, AppError> {
+    let read =
+        |value: Option<String>, default: u32| value.and_then(|v| v.parse().ok()).unwrap_or(default);
+    Ok(BackupSettings {
+        interval_hours: read(
+            db.get_setting(INTERVAL_SETTING).await?,
+            DEFAULT_INTERVAL_HOURS,
+        ),
+        retention: read(db.get_setting(RETENTION_SETTING).await?, DEFAULT_RETENTION),
+        last_error: db
+            .get_setting(LAST_ERROR_SETTING)
+            .await?
+            .filter(|e| !e.is_empty()),
+    })
+}
+
+/// Snapshots the database. The copy is written under a temporary name and
+/// renamed once complete, so a crash never leaves a truncated backup behind.
+pub async fn create(db: &Db, app_dir: &Path, kind: BackupKind) -> Result<BackupInfo, AppError> {
+    let dir = backup_dir(app_dir);
+    fs::create_dir_all(&dir).map_err(|e| AppError::io(&dir, e))?;
+
+    let created_at = now_millis();
+    let file_name = format!("{}{}.db", kind.prefix(), created_at);
+    let temp = dir.join(format!("{}.tmp", file_name));
+    let _ = fs::remove_file(&temp);
+
+    let written = sqlx::query("VACUUM INTO ?1")
+        .bind(temp.to_string_lossy())
+        .execute(&db.pool)
+        .await;
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(e.into());
+    }
+    fs::rename(&temp, dir.join(&file_name)).map_err(|e| AppError::io(&temp, e))?;
+
+    let size_bytes = fs::metadata(dir.join(&file_name))
+        .map(|m| m.len())
+        .unwrap_or(0);
+    Ok(BackupInfo {
+        file_name,
+        kind,
+        created_at,
+        size_bytes,
+    })
+}
+
+/// Deletes the oldest automatic snapshots beyond `retention`.
+pub fn rotate(app_dir: &Path, retention: u32) -> Result<(), AppError> {
+    let autos = list(app_dir)?
+        .into_iter()
+        .filter(|b| b.kind == BackupKind::Auto);
+    for stale in autos.skip(retention.max(1) as usize) {
+        let path = backup_dir(app_dir).join(&stale.file_name);
+        fs::remove_file(&path).map_err(|e| AppError::io(&path, e))?;
+    }
+    Ok(())
+}
+
+/// Copies a plaintext snapshot into `target` encrypted under `passphrase`.
+async fn encrypt_copy(source: &Path, target: &Path, passphrase: &str) -> Result<(), AppError> {
+    let pool = connect(source, None).await?;
+    let exported = crate::vault::export(&pool, target, Some(passphrase)).await;
+    pool.close().await;
+    exported
+}
+
+/// Re-encrypts every plaintext snapshot under `passphrase`, so enabling
+/// encryption leaves no readable copy of the database behind. A snapshot that
+/// cannot be re-encrypted is deleted instead.
+pub async fn encrypt_plaintext(app_dir: &Path, passphrase: &str) -> Result<(), AppError> {
+    let dir = backup_dir(app_dir);
+    for backup in list(app_dir)? {
+        let path = dir.join(&backup.file_name);
+        if crate::vault::is_encrypted(&path) {
+            continue;
+        }
+        let temp = dir.join(format!("{}.tmp", backup.file_name));
+        let _ = fs::remove_file(&temp);
+        let encrypted = encrypt_copy(&path, &temp, passphrase)
+            .await
+            .and_then(|_| fs::rename(&temp, &path).map_err(|e| AppError::io(&temp, e)));
+        if encrypted.is_err() {
+            let _ = fs::remove_file(&temp);
+            fs::remove_file(&path).map_err(|e| AppError::io(&path, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Opens a backup next to the live database and checks it. Backups share the
+/// database key, but older ones may predate enabling encryption, so a
+/// plaintext attach is tried as well.
+async fn inspect(db: &Db, path: &Path) -> Result<BackupVerification, AppError> {
+    let mut conn = db.pool.acquire().await?;
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut error = "Backup cannot be opened with the current key".to_string();
+    for attach in [
+        "ATTACH DATABASE ?1 AS backup",
+        "ATTACH DATABASE ?1 AS backup KEY ''",
+    ] {
+        if let Err(e) = sqlx::query(attach)
+            .bind(path.to_string_lossy())
+            .execute(&mut *conn)
+            .await
+        {
+            error = e.to_string();
+            continue;
+        }
+        let checked = check_attached(&mut conn).await;
+        let _ = sqlx::query("DETACH DATABASE backup")
+            .execute(&mut *conn)
+            .await;
+        match checked {
+            Ok((schema_version, notes)) => {
+                return Ok(BackupVerification {
+                    file_name,
+                    ok: true,
+                    schema_version: Some(schema_version),
+                    notes: Some(notes),
+                    error: None,
+                })
+            }
+            Err(e) => error = e.to_string(),
+        }
+    }
+
+    Ok(BackupVerification {
+        file_name,
+        ok: false,
+        schema_version: None,
+        notes: None,
+        error: Some(error),
+    })
+}
+
+async fn check_attached(conn: &mut sqlx::SqliteConnection) -> Result<(i64, i64), AppError> {
+    let (check,): (String,) = sqlx::query_as("PRAGMA backup.integrity_check(1)")
+        .fetch_one(&mut *conn)
+        .await?;
+    if check != "ok" {
+        return Err(AppError::Database {
+            message: format!("Integrity check failed: {}", check),
+        });
+    }
+    let (version,): (Option<i64>,) =
+        sqlx::query_as("SELECT MAX(version) FROM backup.schema_version")
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap_or((None,));
+    let version = version.unwrap_or(0);
+    if version > migrations::latest_version() {
+        return Err(AppError::conflict(
+            "Backup was made by a newer version of Lumenote",
+        ));
+    }
+    let (notes,): (i64,) = sqlx::query_as("SELECT count(*) FROM backup.notes")
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok((version, notes))
+}
+
+pub async fn verify(
+    db: &Db,
+    app_dir: &Path,
+    file_name: &str,
+) -> Result<BackupVerification, AppError> {
+    let path = backup_path(app_dir, file_name)?;
+    inspect(db, &path).await
+}
+
+/// Replaces the live database with a verified backup. The current file is
+/// kept as a `pre-restore-` backup first; if reopening the restored copy
+/// fails, that safety copy is put back.
+pub async fn restore(state: &DbState, file_name: &str) -> Result<BackupInfo, AppError> {
+    let db = state.db()?;
+    let path = backup_path(&state.app_dir, file_name)?;
+    if let Some(error) = inspect(&db, &path).await?.error {
+        return Err(AppError::validation(
+            "file_name",
+            format!("backup failed verification: {}", error),
+        ));
+    }
+    drop(db);
+
+    // A backup that passed verification is either plaintext or under the
+    // current key. A plaintext one is encrypted on the way in, so restoring
+    // it never silently decrypts the live database.
+    let key = state.passphrase();
+    let temp = state.app_dir.join(format!("{}.restoring", DB_FILE));
+    let _ = fs::remove_file(&temp);
+    let prepared = match key.as_deref() {
+        Some(passphrase) if !crate::vault::is_encrypted(&path) => {
+            encrypt_copy(&path, &temp, passphrase).await
+        }
+        _ => fs::copy(&path, &temp)
+            .map(|_| ())
+            .map_err(|e| AppError::io(&temp, e)),
+    };
+    if let Err(e) = prepared {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    let live = state.path();
+    let dir = backup_dir(&state.app_dir);
+    let safety_name = format!("{}{}.db", BackupKind::PreRestore.prefix(), now_millis());
+    let safety = dir.join(&safety_name);
+
+    state.close().await;
+    let swapped = fs::copy(&live, &safety)
+        .and_then(|_| fs::File::open(&temp)?.sync_all())
+        .and_then(|_| fs::rename(&temp, &live))
+        .map_err(|e| AppError::Io {
+            path: Some(live.display().to_string()),
+            message: format!("Failed to restore backup: {}", e),
+        });
+    if swapped.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    let reopened = match &swapped {
+        Ok(()) => open_db(&state.app_dir, key.as_deref())
+            .await
+            .map_err(crate::vault::open_error),
+        Err(e) => Err(e.clone()),
+    };
+    match reopened {
+        Ok(pool) => {
+            state.open(Db::new(pool), key.as_deref());
+            state.db()?.notify_changed();
+        }
+        Err(e) => {
+            if swapped.is_ok() {
+                let _ = fs::copy(&safety, &live);
+            }
+            let pool = open_db(&state.app_dir, key.as_deref())
+                .await
+                .map_err(crate::vault::open_error)?;
+            state.open(Db::new(pool), key.as_deref());
+            return Err(e);
+        }
+    }
+
+    let size_bytes = fs::metadata(&safety).map(|m| m.len()).unwrap_or(0);
+    Ok(BackupInfo {
+        created_at: parse_name(&safety_name).map(|(_, at)| at).unwrap_or(0),
+        file_name: safety_name,
+        kind: BackupKind::PreRestore,
+        size_bytes,
+    })
+}
+
+/// Takes an automatic snapshot when the newest one is older than the
+/// configured interval.
+async fn run_scheduled(db: &Db, app_dir: &Path) -> Result<(), AppError> {
+    let settings = settings(db).await?;
+    if settings.interval_hours == 0 {
+        return Ok(());
+    }
+    let last = list(app_dir)?
+        .into_iter()
+        .find(|b| b.kind == BackupKind::Auto)
+        .map(|b| b.created_at)
+        .unwrap_or(0);
+    if now_millis() - last < i64::from(settings.interval_hours) * 3_600_000 {
+        return Ok(());
+    }
+    create(db, app_dir, BackupKind::Auto).await?;
+    rotate(app_dir, settings.retention)?;
+    if settings.last_error.is_some() {
+        db.set_setting(LAST_ERROR_SETTING, "").await?;
+    }
+    Ok(())
+}
+
+/// Starts the backup scheduler. Uses `peek` so it neither counts as activity
+/// nor runs while the database is locked.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let state = app.state::<DbState>();
+            if let Some(db) = state.peek() {
+                if let Err(e) = run_scheduled(&db, &state.app_dir).await {
+                    // Shown with the backup settings
+                    let _ = db.set_setting(LAST_ERROR_SETTING, &e.to_string()).await;
+                }
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn list_backups(state: tauri::State<'_, DbState>) -> Result<Vec<BackupInfo>, AppError> {
+    list(&state.app_dir)
+}
+
+#[tauri::command]
+pub async fn create_backup(state: tauri::State<'_, DbState>) -> Result<BackupInfo, AppError> {
+    let db = state.db()?;
+    create(&db, &state.app_dir, BackupKind::Manual).await
+}
+
+#[tauri::command]
+pub async fn verify_backup(
+    state: tauri::State<'_, DbState>,
+    file_name: String,
+) -> Result<BackupVerification, AppError> {
+    let db = state.db()?;
+    verify(&db, &state.app_dir, &file_name).await
+}
+
+/// Restores a backup and returns the safety copy of the replaced database.
+#[tauri::command]
+pub async fn restore_backup(
+    state: tauri::State<'_, DbState>,
+    file_name: String,
+) -> Result<BackupInfo, AppError> {
+    restore(&state, &file_name).await
+}
+
+#[tauri::command]
+pub async fn get_backup_settings(
+    state: tauri::State<'_, DbState>,
+) -> Result<BackupSettings, AppError> {
+    let db = state.db()?;
+    settings(&db).await
+}
+
+#[tauri::command]
+pub async fn set_backup_settings(
+    state: tauri::State<'_, DbState>,
+    settings: BackupSettings,
+) -> Result<BackupSettings, AppError> {
+    let db = state.db()?;
+    db.set_setting(INTERVAL_SETTING, &settings.interval_hours.to_string())
+        .await?;
+    db.set_setting(RETENTION_SETTING, &settings.retention.max(1).to_string())
+        .await?;
+    rotate(&state.app_dir, settings.retention)?;
+    self::settings(&db).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_db, NoteRecord};
+
+    fn note(id: &str, title: &str) -> NoteRecord {
+        NoteRecord {
+            id: id.to_string(),
+            title: title.to_string(),
+            content: String::new(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        }
+    }
+
+    async fn setup() -> DbState {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+        db.upsert_note(note("n1", "Before")).await.unwrap();
+        DbState::new(dir, Some(db))
+    }
+
+    #[test]
+    fn test_parse_name() {
+        assert_eq!(parse_name("auto-1700.db"), Some((BackupKind::Auto, 1700)));
+        assert_eq!(
+            parse_name("pre-migration-v1-1700.db"),
+            Some((BackupKind::PreMigration, 1700))
+        );
+        assert_eq!(parse_name("auto-1700.db.tmp"), None);
+        assert_eq!(parse_name("lumenote.db"), None);
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_newest_auto_backups() {
+        let state = setup().await;
+        let dir = backup_dir(&state.app_dir);
+        fs::create_dir_all(&dir).unwrap();
+        for at in 1..=5 {
+            fs::write(dir.join(format!("auto-{}.db", at)), b"x").unwrap();
+        }
+        fs::write(dir.join("manual-1.db"), b"x").unwrap();
+
+        rotate(&state.app_dir, 2).unwrap();
+        let names: Vec<String> = list(&state.app_dir)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.file_name)
+            .collect();
+        assert_eq!(names, vec!["auto-5.db", "auto-4.db", "manual-1.db"]);
+
+        state.close().await;
+        let _ = fs::remove_dir_all(&state.app_dir);
+    }
+
+    #[tokio::test]
+    async fn test_backup_verify_and_restore() {
+        let state = setup().await;
+        let backup = create(&state.db().unwrap(), &state.app_dir, BackupKind::Manual)
+            .await
+            .unwrap();
+        state
+            .db()
+            .unwrap()
+            .upsert_note(note("n2", "After"))
+            .await
+            .unwrap();
+
+        let verification = verify(&state.db().unwrap(), &state.app_dir, &backup.file_name)
+            .await
+            .unwrap();
+        assert!(verification.ok, "{:?}", verification.error);
+        assert_eq!(verification.notes, Some(1));
+        assert_eq!(
+            verification.schema_version,
+            Some(migrations::latest_version())
+        );
+
+        let corrupt = backup_dir(&state.app_dir).join("manual-1.db");
+        fs::write(&corrupt, b"definitely not sqlite").unwrap();
+        let verification = verify(&state.db().unwrap(), &state.app_dir, "manual-1.db")
+            .await
+            .unwrap();
+        assert!(!verification.ok);
+        assert!(restore(&state, "manual-1.db").await.is_err());
+        assert_eq!(state.db().unwrap().get_notes().await.unwrap().len(), 2);
+        assert!(restore(&state, "../lumenote.db").await.is_err());
+
+        let safety = restore(&state, &backup.file_name).await.unwrap();
+        let notes = state.db().unwrap().get_notes().await.unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "Before");
+
+        // The replaced database is itself restorable.
+        restore(&state, &safety.file_name).await.unwrap();
+        assert_eq!(state.db().unwrap().get_notes().await.unwrap().len(), 2);
+
+        state.close().await;
+        let _ = fs::remove_dir_all(&state.app_dir);
+    }
+
+    #[tokio::test]
+    async fn test_restore_keeps_encryption_key() {
+        let state = setup().await;
+        let dir = state.app_dir.clone();
+        let plain = create(&state.db().unwrap(), &dir, BackupKind::Manual)
+            .await
+            .unwrap();
+        state.close().await;
+        let pool = crate::db::connect(&state.path(), None).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        sqlx::query("ATTACH DATABASE ?1 AS enc KEY 'correct horse'")
+            .bind(dir.join("enc.db").to_string_lossy())
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        sqlx::query("SELECT sqlcipher_export('enc')")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        drop(conn);
+        pool.close().await;
+        fs::rename(dir.join("enc.db"), state.path()).unwrap();
+        let pool = open_db(&dir, Some("correct horse")).await.unwrap();
+        state.open(Db::new(pool), Some("correct horse"));
+
+        let backup = create(&state.db().unwrap(), &dir, BackupKind::Auto)
+            .await
+            .unwrap();
+        assert!(crate::vault::is_encrypted(
+            &backup_dir(&dir).join(&backup.file_name)
+        ));
+        state
+            .db()
+            .unwrap()
+            .delete_note("n1".to_string())
+            .await
+            .unwrap();
+
+        restore(&state, &backup.file_name).await.unwrap();
+        assert_eq!(state.passphrase().as_deref(), Some("correct horse"));
+        assert_eq!(state.db().unwrap().get_notes().await.unwrap().len(), 1);
+
+        // A plaintext backup from before encryption is encrypted on the way in.
+        restore(&state, &plain.file_name).await.unwrap();
+        assert!(crate::vault::is_encrypted(&state.path()));
+        assert_eq!(state.passphrase().as_deref(), Some("correct horse"));
+        assert_eq!(state.db().unwrap().get_notes().await.unwrap().len(), 1);
+
+        state.close().await;
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_backups_are_encrypted() {
+        let state = setup().await;
+        let dir = state.app_dir.clone();
+        let backup = create(&state.db().unwrap(), &dir, BackupKind::Manual)
+            .await
+            .unwrap();
+        let path = backup_dir(&dir).join(&backup.file_name);
+        let broken = backup_dir(&dir).join("auto-1.db");
+        fs::write(&broken, b"SQLite format 3\0but nothing else").unwrap();
+
+        encrypt_plaintext(&dir, "correct horse").await.unwrap();
+        assert!(crate::vault::is_encrypted(&path));
+        assert!(!broken.exists());
+        let pool = connect(&path, Some("correct horse")).await.unwrap();
+        let (notes,): (i64,) = sqlx::query_as("SELECT count(*) FROM notes")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(notes, 1);
+        pool.close().await;
+
+        state.close().await;
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,459 @@
+use crate::db::{FolderRecord, NoteRecord, WorkspaceRecord};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+
+/// Marks a field value as ciphertext. Once encryption is on, every synced
+/// field must carry it: turning encryption on re-uploads all rows encrypted,
+/// so a value without it can only be plaintext slipped in by the server.
+pub const ENCRYPTED_PREFIX: &str = "lnenc1:";
+
+const ENVELOPE_VERSION: u32 = 1;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const WRAP_AAD: &[u8] = b"lumenote:data-key";
+/// Upper bounds for `KdfParams` read from an envelope: 1 GiB of memory.
+const MAX_M_COST: u32 = 1024 * 1024;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CryptoError {
+    WrongPassphrase,
+    WrongRecoveryKey,
+    /// The text is not shaped like a recovery key at all.
+    InvalidRecoveryKey,
+    /// A ciphertext did not authenticate: wrong key, corrupted, or moved to
+    /// another row.
+    Decryption,
+    /// A value that should be ciphertext or key material is not.
+    Malformed(String),
+    /// The key derivation parameters were rejected.
+    Kdf(String),
+    Encryption,
+}
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CryptoError::WrongPassphrase => write!(f, "Wrong passphrase"),
+            CryptoError::WrongRecoveryKey => write!(f, "Wrong recovery key"),
+            CryptoError::InvalidRecoveryKey => write!(f, "Invalid recovery key"),
+            CryptoError::Decryption => {
+                write!(f, "Decryption failed: wrong key or corrupted data")
+            }
+            CryptoError::Malformed(message) => write!(f, "{}", message),
+            CryptoError::Kdf(message) => {
+                write!(f, "Invalid key derivation parameters: {}", message)
+            }
+            CryptoError::Encryption => write!(f, "Encryption failed"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory cost in KiB.
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Envelopes come from the server, so their parameters are checked
+    /// before anything is derived: weaker than the defaults would carry over
+    /// to the next passphrase change and make it cheap to guess, and huge
+    /// ones would stall or exhaust memory on unlock.
+    pub fn check(&self) -> Result<(), CryptoError> {
+        let floor = KdfParams::default();
+        if self.m_cost < floor.m_cost || self.t_cost < floor.t_cost || self.p_cost < floor.p_cost {
+            return Err(CryptoError::Kdf("weaker than the defaults".to_string()));
+        }
+        if self.m_cost > MAX_M_COST || self.t_cost > MAX_T_COST || self.p_cost > MAX_P_COST {
+            return Err(CryptoError::Kdf("too expensive".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// The data key wrapped twice: under a key derived from the passphrase and
+/// under the recovery key. This is the only key material that leaves the
+/// device, and the server stores it as an opaque blob.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyEnvelope {
+    pub version: u32,
+    pub kdf: KdfParams,
+    pub salt: String,
+    pub wrapped_key: String,
+    pub recovery_wrapped_key: String,
+}
+
+/// Random key that encrypts the synced fields. It never changes, so changing
+/// the passphrase only re-wraps it and existing ciphertexts stay valid.
+#[derive(Clone)]
+pub struct DataKey([u8; KEY_LEN]);
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn seal(key: &[u8; KEY_LEN], plaintext: &[u8], aad: &[u8]) -> Result<String, CryptoError> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoError::Encryption)?;
+    let mut out = nonce.to_vec();
+    out.extend(ciphertext);
+    Ok(BASE64.encode(out))
+}
+
+fn open(key: &[u8; KEY_LEN], encoded: &str, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let malformed = || CryptoError::Malformed("Malformed ciphertext".to_string());
+    let bytes = BASE64.decode(encoded).map_err(|_| malformed())?;
+    if bytes.len() < NONCE_LEN {
+        return Err(malformed());
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoError::Decryption)
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    kdf: &KdfParams,
+) -> Result<[u8; KEY_LEN], CryptoError> {
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(KEY_LEN))
+        .map_err(|e| CryptoError::Kdf(e.to_string()))?;
+    let mut out = [0u8; KEY_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut out)
+        .map_err(|e| CryptoError::Kdf(e.to_string()))?;
+    Ok(out)
+}
+
+fn unwrap_key(wrapping_key: &[u8; KEY_LEN], wrapped: &str) -> Result<DataKey, CryptoError> {
+    let bytes = open(wrapping_key, wrapped, WRAP_AAD)?;
+    let key: [u8; KEY_LEN] = bytes
+        .try_into()
+        .map_err(|_| CryptoError::Malformed("Malformed data key".to_string()))?;
+    Ok(DataKey(key))
+}
+
+/// Recovery keys are 32 random bytes shown as 16 dash-separated hex groups.
+fn format_recovery_key(bytes: &[u8; KEY_LEN]) -> String {
+    bytes
+        .chunks(2)
+        .map(|pair| format!("{:02X}{:02X}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn parse_recovery_key(text: &str) -> Result<[u8; KEY_LEN], CryptoError> {
+    let hex: String = text
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect();
+    if hex.len() != KEY_LEN * 2 || !hex.is_ascii() {
+        return Err(CryptoError::InvalidRecoveryKey);
+    }
+    let mut out = [0u8; KEY_LEN];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| CryptoError::InvalidRecoveryKey)?;
+    }
+    Ok(out)
+}
+
+impl DataKey {
+    pub fn generate() -> Self {
+        DataKey(random_bytes())
+    }
+
+    /// Encrypts `plaintext`, binding it to `aad` (the row and field it
+    /// belongs to) so ciphertexts cannot be swapped between rows.
+    pub fn encrypt(&self, plaintext: &str, aad: &str) -> Result<String, CryptoError> {
+        Ok(format!(
+            "{}{}",
+            ENCRYPTED_PREFIX,
+            seal(&self.0, plaintext.as_bytes(), aad.as_bytes())?
+        ))
+    }
+
+    /// Decrypts a value from `encrypt`; unprefixed values are rejected.
+    pub fn decrypt(&self, value: &str, aad: &str) -> Result<String, CryptoError> {
+        match value.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(encoded) => {
+                String::from_utf8(open(&self.0, encoded, aad.as_bytes())?).map_err(|_| {
+                    CryptoError::Malformed("Decrypted value is not valid UTF-8".to_string())
+                })
+            }
+            None => Err(CryptoError::Malformed(format!(
+                "Expected an encrypted value for {}",
+                aad
+            ))),
+        }
+    }
+}
+
+impl KeyEnvelope {
+    /// Creates a new data key protected by `passphrase`. Returns the envelope,
+    /// the key and the recovery key to show to the user once.
+    pub fn create(
+        passphrase: &str,
+        kdf: KdfParams,
+    ) -> Result<(Self, DataKey, String), CryptoError> {
+        let key = DataKey::generate();
+        let recovery: [u8; KEY_LEN] = random_bytes();
+        let envelope = KeyEnvelope {
+            version: ENVELOPE_VERSION,
+            kdf,
+            salt: String::new(),
+            wrapped_key: String::new(),
+            recovery_wrapped_key: seal(&recovery, &key.0, WRAP_AAD)?,
+        }
+        .rewrap(&key, passphrase)?;
+        Ok((envelope, key, format_recovery_key(&recovery)))
+    }
+
+    pub fn unlock(&self, passphrase: &str) -> Result<DataKey, CryptoError> {
+        let salt = BASE64
+            .decode(&self.salt)
+            .map_err(|_| CryptoError::Malformed("Malformed key envelope".to_string()))?;
+        let wrapping_key = derive_key(passphrase, &salt, &self.kdf)?;
+        unwrap_key(&wrapping_key, &self.wrapped_key).map_err(|_| CryptoError::WrongPassphrase)
+    }
+
+    pub fn unlock_with_recovery_key(&self, recovery_key: &str) -> Result<DataKey, CryptoError> {
+        let recovery = parse_recovery_key(recovery_key)?;
+        unwrap_key(&recovery, &self.recovery_wrapped_key).map_err(|_| CryptoError::WrongRecoveryKey)
+    }
+
+    /// Wraps the same data key under a new passphrase (with a fresh salt).
+    pub fn rewrap(&self, key: &DataKey, new_passphrase: &str) -> Result<Self, CryptoError> {
+        let salt: [u8; SALT_LEN] = random_bytes();
+        let wrapping_key = derive_key(new_passphrase, &salt, &self.kdf)?;
+        Ok(KeyEnvelope {
+            salt: BASE64.encode(salt),
+            wrapped_key: seal(&wrapping_key, &key.0, WRAP_AAD)?,
+            ..self.clone()
+        })
+    }
+
+    /// Issues a new recovery key; the previous one stops working.
+    pub fn reset_recovery_key(&self, key: &DataKey) -> Result<(Self, String), CryptoError> {
+        let recovery: [u8; KEY_LEN] = random_bytes();
+        let envelope = KeyEnvelope {
+            recovery_wrapped_key: seal(&recovery, &key.0, WRAP_AAD)?,
+            ..self.clone()
+        };
+        Ok((envelope, format_recovery_key(&recovery)))
+    }
+}
+
+// Ids, parents, timestamps and the deleted flag stay readable: the server
+// needs them for last-writer-wins. Everything the user typed is encrypted.
+
+pub fn encrypt_note(key: &DataKey, note: &mut NoteRecord) -> Result<(), CryptoError> {
+    note.title = key.encrypt(&note.title, &format!("note:{}:title", note.id))?;
+    note.content = key.encrypt(&note.content, &format!("note:{}:content", note.id))?;
+    if let Some(color) = &note.color {
+        note.color = Some(key.encrypt(color, &format!("note:{}:color", note.id))?);
+    }
+    Ok(())
+}
+
+pub fn decrypt_note(key: &DataKey, note: &mut NoteRecord) -> Result<(), CryptoError> {
+    note.title = key.decrypt(&note.title, &format!("note:{}:title", note.id))?;
+    note.content = key.decrypt(&note.content, &format!("note:{}:content", note.id))?;
+    if let Some(color) = &note.color {
+        note.color = Some(key.decrypt(color, &format!("note:{}:color", note.id))?);
+    }
+    Ok(())
+}
+
+pub fn encrypt_folder(key: &DataKey, folder: &mut FolderRecord) -> Result<(), CryptoError> {
+    folder.name = key.encrypt(&folder.name, &format!("folder:{}:name", folder.id))?;
+    if let Some(color) = &folder.color {
+        folder.color = Some(key.encrypt(color, &format!("folder:{}:color", folder.id))?);
+    }
+    Ok(())
+}
+
+pub fn decrypt_folder(key: &DataKey, folder: &mut FolderRecord) -> Result<(), CryptoError> {
+    folder.name = key.decrypt(&folder.name, &format!("folder:{}:name", folder.id))?;
+    if let Some(color) = &folder.color {
+        folder.color = Some(key.decrypt(color, &format!("folder:{}:color", folder.id))?);
+    }
+    Ok(())
+}
+
+pub fn encrypt_workspace(
+    key: &DataKey,
+    workspace: &mut WorkspaceRecord,
+) -> Result<(), CryptoError> {
+    workspace.name = key.encrypt(&workspace.name, &format!("workspace:{}:name", workspace.id))?;
+    workspace.color = key.encrypt(
+        &workspace.color,
+        &format!("workspace:{}:color", workspace.id),
+    )?;
+    Ok(())
+}
+
+pub fn decrypt_workspace(
+    key: &DataKey,
+    workspace: &mut WorkspaceRecord,
+) -> Result<(), CryptoError> {
+    workspace.name = key.decrypt(&workspace.name, &format!("workspace:{}:name", workspace.id))?;
+    workspace.color = key.decrypt(
+        &workspace.color,
+        &format!("workspace:{}:color", workspace.id),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters so the tests don't spend seconds in Argon2.
+    fn test_kdf() -> KdfParams {
+        KdfParams {
+            m_cost: 64,
+            t_cost: 1,
+            p_cost: 1,
+        }
+    }
+
+    fn note() -> NoteRecord {
+        NoteRecord {
+            id: "n1".to_string(),
+            title: "Secret plans".to_string(),
+            content: "# Plans\n\nTake over the world.".to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 2,
+            version: 1,
+            sort_key: "V".to_string(),
+            color: Some("#E94F37".to_string()),
+            is_deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_encrypt_note_round_trip() {
+        let key = DataKey::generate();
+        let mut encrypted = note();
+        encrypt_note(&key, &mut encrypted).unwrap();
+
+        assert!(encrypted.title.starts_with(ENCRYPTED_PREFIX));
+        assert!(!encrypted.content.contains("world"));
+        assert!(encrypted
+            .color
+            .as_deref()
+            .unwrap()
+            .starts_with(ENCRYPTED_PREFIX));
+        assert_eq!(encrypted.sort_key, "V");
+        assert_eq!(encrypted.updated_at, 2);
+
+        let mut decrypted = encrypted.clone();
+        decrypt_note(&key, &mut decrypted).unwrap();
+        assert_eq!(decrypted.title, "Secret plans");
+        assert_eq!(decrypted.content, note().content);
+        assert_eq!(decrypted.color, note().color);
+
+        // Ciphertext is bound to its row.
+        let mut moved = encrypted;
+        moved.id = "n2".to_string();
+        assert!(decrypt_note(&key, &mut moved).is_err());
+
+        // Plaintext is refused once encryption is on.
+        let mut plain = note();
+        assert!(decrypt_note(&key, &mut plain).is_err());
+        let mut mixed = note();
+        encrypt_note(&key, &mut mixed).unwrap();
+        mixed.content = "injected".to_string();
+        assert!(decrypt_note(&key, &mut mixed).is_err());
+    }
+
+    #[test]
+    fn test_passphrase_change_keeps_data_key() {
+        let (envelope, key, recovery) = KeyEnvelope::create("correct horse", test_kdf()).unwrap();
+        let ciphertext = key.encrypt("hello", "aad").unwrap();
+
+        assert!(envelope.unlock("wrong horse").is_err());
+        let rewrapped = envelope
+            .rewrap(&envelope.unlock("correct horse").unwrap(), "battery staple")
+            .unwrap();
+        assert_ne!(rewrapped.salt, envelope.salt);
+        assert!(rewrapped.unlock("correct horse").is_err());
+
+        let unlocked = rewrapped.unlock("battery staple").unwrap();
+        assert_eq!(unlocked.decrypt(&ciphertext, "aad").unwrap(), "hello");
+
+        // The recovery key survives a passphrase change.
+        let recovered = rewrapped
+            .unlock_with_recovery_key(&recovery.to_lowercase())
+            .unwrap();
+        assert_eq!(recovered.decrypt(&ciphertext, "aad").unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_kdf_params_are_bounded() {
+        assert_eq!(KdfParams::default().check(), Ok(()));
+        assert!(test_kdf().check().is_err());
+        let huge = KdfParams {
+            m_cost: u32::MAX,
+            ..KdfParams::default()
+        };
+        assert!(huge.check().is_err());
+    }
+
+    #[test]
+    fn test_reset_recovery_key_invalidates_old_one() {
+        let (envelope, key, old) = KeyEnvelope::create("passphrase", test_kdf()).unwrap();
+        let (envelope, new) = envelope.reset_recovery_key(&key).unwrap();
+
+        assert_eq!(new.split('-').count(), 16);
+        assert!(envelope.unlock_with_recovery_key(&old).is_err());
+        assert!(envelope.unlock_with_recovery_key(&new).is_ok());
+        assert_eq!(
+            envelope.unlock_with_recovery_key("not-a-key").err(),
+            Some(CryptoError::InvalidRecoveryKey)
+        );
+        assert!(envelope.unlock("passphrase").is_ok());
+    }
+}
@@ -1,5 +1,15 @@
+use crate::error::{AppError, Entity, LockTarget};
+use crate::ordering::{self, ItemKind};
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Pool, Sqlite,
+};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Notify;
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow, Clone)]
 pub struct NoteRecord {
@@ -11,10 +21,16 @@ pub struct NoteRecord {
     pub created_at: i64,
     pub updated_at: i64,
     pub version: i32,
+    /// Fractional position among siblings, see `ordering`. Empty for rows
+    /// that predate manual ordering; they sort by `created_at`.
+    #[serde(default)]
+    pub sort_key: String,
+    #[serde(default)]
+    pub color: Option<String>,
     pub is_deleted: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow, Clone)]
 pub struct FolderRecord {
     pub id: String,
     pub name: String,
@@ -24,6 +40,8 @@ pub struct FolderRecord {
     pub updated_at: i64,
     pub version: i32,
     pub color: Option<String>,
+    #[serde(default)]
+    pub sort_key: String,
     pub is_deleted: bool,
 }
 
@@ -45,44 +63,613 @@ pub struct SearchResult {
     pub snippet: String,
 }
 
+/// One write in an `apply_batch` call, e.g.
+/// `{ "op": "upsert_note", "note": { ... } }` or `{ "op": "delete_folder", "id": "..." }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOp {
+    UpsertNote { note: NoteRecord },
+    DeleteNote { id: String },
+    UpsertFolder { folder: FolderRecord },
+    DeleteFolder { id: String },
+    UpsertWorkspace { workspace: WorkspaceRecord },
+    DeleteWorkspace { id: String },
+}
+
+impl BatchOp {
+    fn id(&self) -> &str {
+        match self {
+            BatchOp::UpsertNote { note } => &note.id,
+            BatchOp::UpsertFolder { folder } => &folder.id,
+            BatchOp::UpsertWorkspace { workspace } => &workspace.id,
+            BatchOp::DeleteNote { id }
+            | BatchOp::DeleteFolder { id }
+            | BatchOp::DeleteWorkspace { id } => id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpStatus {
+    Applied,
+    /// Ran, but a later operation failed and the batch was rolled back.
+    RolledBack,
+    Failed,
+    /// Not attempted because an earlier operation failed.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OpResult {
+    pub id: String,
+    pub status: OpStatus,
+    /// Version after an upsert.
+    pub version: Option<i32>,
+    pub error: Option<AppError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    /// False when an operation failed; then nothing was written.
+    pub committed: bool,
+    /// One entry per operation, in order.
+    pub results: Vec<OpResult>,
+}
+
 pub struct Db {
     pub pool: Pool<Sqlite>,
+    /// Signalled after every local write so the sync service can push it.
+    pub changes: Notify,
 }
 
 impl Db {
     pub fn new(pool: Pool<Sqlite>) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            changes: Notify::new(),
+        }
+    }
+
+    pub fn notify_changed(&self) {
+        self.changes.notify_one();
+    }
+
+    pub async fn get_notes(&self) -> Result<Vec<NoteRecord>, AppError> {
+        sqlx::query_as::<_, NoteRecord>(
+            "SELECT * FROM notes WHERE is_deleted = 0 ORDER BY sort_key, created_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(AppError::from)
+    }
+
+    /// Saves a note and logs the revision in one transaction, so `changes`
+    /// never disagrees with `notes`.
+    pub async fn upsert_note(&self, note: NoteRecord) -> Result<(), AppError> {
+        let mut conn = self.pool.begin().await?;
+        tx::upsert_note(&mut conn, note).await?;
+        conn.commit().await?;
+        self.notify_changed();
+        Ok(())
+    }
+
+    pub async fn apply_remote_update_note(&self, note: NoteRecord) -> Result<(), AppError> {
+        let mut conn = self.pool.acquire().await?;
+        tx::apply_remote_note(&mut conn, &note).await
+    }
+
+    pub async fn delete_note(&self, id: String) -> Result<(), AppError> {
+        let mut conn = self.pool.acquire().await?;
+        tx::delete_note(&mut conn, &id, now_millis()).await?;
+        self.notify_changed();
+        Ok(())
+    }
+
+    pub async fn get_folders(&self) -> Result<Vec<FolderRecord>, AppError> {
+        sqlx::query_as::<_, FolderRecord>(
+            "SELECT * FROM folders WHERE is_deleted = 0 ORDER BY sort_key, created_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(AppError::from)
+    }
+
+    pub async fn upsert_folder(&self, folder: FolderRecord) -> Result<(), AppError> {
+        let mut conn = self.pool.begin().await?;
+        tx::upsert_folder(&mut conn, folder).await?;
+        conn.commit().await?;
+        self.notify_changed();
+        Ok(())
+    }
+
+    pub async fn apply_remote_update_folder(&self, folder: FolderRecord) -> Result<(), AppError> {
+        let mut conn = self.pool.acquire().await?;
+        tx::apply_remote_folder(&mut conn, &folder).await
+    }
+
+    pub async fn delete_folder(&self, id: String) -> Result<(), AppError> {
+        let mut conn = self.pool.acquire().await?;
+        tx::delete_folder(&mut conn, &id, now_millis()).await?;
+        self.notify_changed();
+        Ok(())
+    }
+
+    pub async fn get_workspaces(&self) -> Result<Vec<WorkspaceRecord>, AppError> {
+        sqlx::query_as::<_, WorkspaceRecord>(
+            "SELECT * FROM workspaces WHERE is_deleted = 0 ORDER BY created_at ASC",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(AppError::from)
+    }
+
+    pub async fn upsert_workspace(&self, workspace: WorkspaceRecord) -> Result<(), AppError> {
+        let mut conn = self.pool.begin().await?;
+        tx::upsert_workspace(&mut conn, workspace).await?;
+        conn.commit().await?;
+        self.notify_changed();
+        Ok(())
+    }
+
+    pub async fn apply_remote_update_workspace(
+        &self,
+        workspace: WorkspaceRecord,
+    ) -> Result<(), AppError> {
+        let mut conn = self.pool.acquire().await?;
+        tx::apply_remote_workspace(&mut conn, &workspace).await
+    }
+
+    pub async fn delete_workspace(&self, id: String) -> Result<(), AppError> {
+        let mut conn = self.pool.acquire().await?;
+        tx::delete_workspace(&mut conn, &id, now_millis()).await?;
+        self.notify_changed();
+        Ok(())
+    }
+
+    /// Moves notes and folders into `folder_id` (the top level when `None`) of
+    /// `workspace_id`, after the items already there. A folder takes its whole
+    /// subtree along; selected items inside another selected folder stay where
+    /// they are within it. All touched rows get a new version and one shared
+    /// timestamp.
+    pub async fn move_items(
+        &self,
+        note_ids: &[String],
+        folder_ids: &[String],
+        workspace_id: &str,
+        folder_id: Option<&str>,
+    ) -> Result<(), AppError> {
+        let now = now_millis();
+        let mut conn = self.pool.begin().await?;
+        tx::check_target(&mut conn, workspace_id, folder_id).await?;
+
+        let mut subtrees = Vec::new();
+        for id in folder_ids {
+            let subtree = tx::folder_subtree(&mut conn, id).await?;
+            if subtree.is_empty() {
+                return Err(AppError::not_found(Entity::Folder, id.as_str()));
+            }
+            if folder_id.is_some_and(|target| subtree.iter().any(|f| f == target)) {
+                return Err(AppError::conflict(
+                    "Cannot move a folder into itself or one of its subfolders",
+                ));
+            }
+            subtrees.push((id, subtree));
+        }
+        let inside_moved = |folder: &str, except: Option<&str>| {
+            subtrees.iter().any(|(root, subtree)| {
+                Some(root.as_str()) != except && subtree.iter().any(|f| f == folder)
+            })
+        };
+
+        for (id, subtree) in &subtrees {
+            if inside_moved(id, Some(id)) {
+                continue;
+            }
+            let sort_key =
+                ordering::append_key(&mut conn, ItemKind::Folder, workspace_id, folder_id).await?;
+            sqlx::query(
+                "UPDATE folders SET parent_id = ?1, workspace_id = ?2, sort_key = ?3, version = version + 1, updated_at = ?4
+                 WHERE id = ?5",
+            )
+            .bind(folder_id)
+            .bind(workspace_id)
+            .bind(&sort_key)
+            .bind(now)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+
+            // Everything below follows the folder into its new workspace
+            for folder in subtree {
+                for query in [
+                    "UPDATE folders SET workspace_id = ?1, version = version + 1, updated_at = ?2
+                     WHERE id = ?3 AND workspace_id != ?1",
+                    "UPDATE notes SET workspace_id = ?1, version = version + 1, updated_at = ?2
+                     WHERE folder_id = ?3 AND is_deleted = 0 AND workspace_id != ?1",
+                ] {
+                    sqlx::query(query)
+                        .bind(workspace_id)
+                        .bind(now)
+                        .bind(folder)
+                        .execute(&mut *conn)
+                        .await?;
+                }
+            }
+        }
+
+        for id in note_ids {
+            let parent: Option<String> =
+                sqlx::query_scalar("SELECT folder_id FROM notes WHERE id = ?1 AND is_deleted = 0")
+                    .bind(id)
+                    .fetch_optional(&mut *conn)
+                    .await?
+                    .ok_or_else(|| AppError::not_found(Entity::Note, id.as_str()))?;
+            if parent.is_some_and(|p| inside_moved(&p, None)) {
+                continue;
+            }
+            let sort_key =
+                ordering::append_key(&mut conn, ItemKind::Note, workspace_id, folder_id).await?;
+            sqlx::query(
+                "UPDATE notes SET folder_id = ?1, workspace_id = ?2, sort_key = ?3, version = version + 1, updated_at = ?4
+                 WHERE id = ?5",
+            )
+            .bind(folder_id)
+            .bind(workspace_id)
+            .bind(&sort_key)
+            .bind(now)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        conn.commit().await?;
+        self.notify_changed();
+        Ok(())
+    }
+
+    /// Copies a note under a new id right below the original. The copy starts
+    /// at version 1 with the content as its first revision.
+    pub async fn duplicate_note(&self, id: &str) -> Result<NoteRecord, AppError> {
+        let now = now_millis();
+        let mut conn = self.pool.begin().await?;
+
+        let original =
+            sqlx::query_as::<_, NoteRecord>("SELECT * FROM notes WHERE id = ?1 AND is_deleted = 0")
+                .bind(id)
+                .fetch_optional(&mut *conn)
+                .await?
+                .ok_or_else(|| AppError::not_found(Entity::Note, id))?;
+        let sort_key = ordering::key_after(
+            &mut conn,
+            ItemKind::Note,
+            &original.workspace_id,
+            original.folder_id.as_deref(),
+            Some(id),
+            None,
+            now,
+        )
+        .await?;
+
+        let copy = NoteRecord {
+            id: uuid::Uuid::new_v4().to_string(),
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            sort_key,
+            ..original
+        };
+        tx::restore_note(&mut conn, &copy).await?;
+
+        conn.commit().await?;
+        self.notify_changed();
+        Ok(copy)
+    }
+
+    /// Deep-copies a folder with its live subfolders and notes under fresh ids
+    /// into `folder_id` of `workspace_id`, after the items already there, and
+    /// returns the new top folder. Order inside the copy is kept.
+    pub async fn copy_folder_tree(
+        &self,
+        id: &str,
+        workspace_id: &str,
+        folder_id: Option<&str>,
+    ) -> Result<FolderRecord, AppError> {
+        let now = now_millis();
+        let mut conn = self.pool.begin().await?;
+        tx::check_target(&mut conn, workspace_id, folder_id).await?;
+
+        // Read the whole subtree first, so copying a folder into itself
+        // doesn't pick up the copy
+        let subtree = tx::folder_subtree(&mut conn, id).await?;
+        if subtree.is_empty() {
+            return Err(AppError::not_found(Entity::Folder, id));
+        }
+        let mut folders = Vec::new();
+        let mut notes = Vec::new();
+        for folder in &subtree {
+            folders.push(
+                sqlx::query_as::<_, FolderRecord>("SELECT * FROM folders WHERE id = ?1")
+                    .bind(folder)
+                    .fetch_one(&mut *conn)
+                    .await?,
+            );
+            notes.extend(
+                sqlx::query_as::<_, NoteRecord>(
+                    "SELECT * FROM notes WHERE folder_id = ?1 AND is_deleted = 0",
+                )
+                .bind(folder)
+                .fetch_all(&mut *conn)
+                .await?,
+            );
+        }
+
+        let new_ids: HashMap<String, String> = subtree
+            .iter()
+            .map(|old| (old.clone(), uuid::Uuid::new_v4().to_string()))
+            .collect();
+        let root_key =
+            ordering::append_key(&mut conn, ItemKind::Folder, workspace_id, folder_id).await?;
+
+        let mut root = None;
+        for folder in folders {
+            let is_root = folder.id == id;
+            let copy = FolderRecord {
+                id: new_ids[&folder.id].clone(),
+                parent_id: if is_root {
+                    folder_id.map(str::to_string)
+                } else {
+                    folder.parent_id.and_then(|p| new_ids.get(&p).cloned())
+                },
+                workspace_id: workspace_id.to_string(),
+                created_at: now,
+                updated_at: now,
+                version: 1,
+                sort_key: if is_root {
+                    root_key.clone()
+                } else {
+                    folder.sort_key
+                },
+                ..folder
+            };
+            tx::restore_folder(&mut conn, &copy).await?;
+            if is_root {
+                root = Some(copy);
+            }
+        }
+        for note in notes {
+            let copy = NoteRecord {
+                id: uuid::Uuid::new_v4().to_string(),
+                folder_id: note
+                    .folder_id
+                    .as_ref()
+                    .and_then(|f| new_ids.get(f).cloned()),
+                workspace_id: workspace_id.to_string(),
+                created_at: now,
+                updated_at: now,
+                version: 1,
+                ..note
+            };
+            tx::restore_note(&mut conn, &copy).await?;
+        }
+
+        conn.commit().await?;
+        self.notify_changed();
+        root.ok_or_else(|| AppError::not_found(Entity::Folder, id))
+    }
+
+    /// Runs all operations in one transaction: either every one is applied or,
+    /// if one fails, none is. Deleting a row that doesn't exist counts as a
+    /// failure, since the caller's view of the data is out of date.
+    pub async fn apply_batch(&self, ops: Vec<BatchOp>) -> Result<BatchResult, AppError> {
+        let now = now_millis();
+        let mut conn = self.pool.begin().await?;
+        let mut results: Vec<OpResult> = ops
+            .iter()
+            .map(|op| OpResult {
+                id: op.id().to_string(),
+                status: OpStatus::Skipped,
+                version: None,
+                error: None,
+            })
+            .collect();
+
+        for (i, op) in ops.into_iter().enumerate() {
+            let outcome = match op {
+                BatchOp::UpsertNote { note } => tx::upsert_note(&mut conn, note).await.map(Some),
+                BatchOp::UpsertFolder { folder } => {
+                    tx::upsert_folder(&mut conn, folder).await.map(Some)
+                }
+                BatchOp::UpsertWorkspace { workspace } => {
+                    tx::upsert_workspace(&mut conn, workspace).await.map(Some)
+                }
+                BatchOp::DeleteNote { id } => existed(
+                    tx::delete_note(&mut conn, &id, now).await,
+                    Entity::Note,
+                    &id,
+                ),
+                BatchOp::DeleteFolder { id } => existed(
+                    tx::delete_folder(&mut conn, &id, now).await,
+                    Entity::Folder,
+                    &id,
+                ),
+                BatchOp::DeleteWorkspace { id } => existed(
+                    tx::delete_workspace(&mut conn, &id, now).await,
+                    Entity::Workspace,
+                    &id,
+                ),
+            };
+
+            match outcome {
+                Ok(version) => {
+                    results[i].status = OpStatus::Applied;
+                    results[i].version = version;
+                }
+                Err(e) => {
+                    results[i].status = OpStatus::Failed;
+                    results[i].error = Some(e);
+                    for result in &mut results[..i] {
+                        result.status = OpStatus::RolledBack;
+                        result.version = None;
+                    }
+                    conn.rollback().await?;
+                    return Ok(BatchResult {
+                        committed: false,
+                        results,
+                    });
+                }
+            }
+        }
+
+        conn.commit().await?;
+        if !results.is_empty() {
+            self.notify_changed();
+        }
+        Ok(BatchResult {
+            committed: true,
+            results,
+        })
     }
 
-    pub async fn get_notes(&self) -> Result<Vec<NoteRecord>, String> {
-        sqlx::query_as::<_, NoteRecord>("SELECT * FROM notes WHERE is_deleted = 0")
+    pub async fn get_sync_data(
+        &self,
+        since: i64,
+    ) -> Result<(Vec<NoteRecord>, Vec<FolderRecord>, Vec<WorkspaceRecord>), AppError> {
+        let notes = sqlx::query_as::<_, NoteRecord>("SELECT * FROM notes WHERE updated_at > ?1")
+            .bind(since)
             .fetch_all(&self.pool)
+            .await?;
+
+        let folders =
+            sqlx::query_as::<_, FolderRecord>("SELECT * FROM folders WHERE updated_at > ?1")
+                .bind(since)
+                .fetch_all(&self.pool)
+                .await?;
+
+        let workspaces =
+            sqlx::query_as::<_, WorkspaceRecord>("SELECT * FROM workspaces WHERE updated_at > ?1")
+                .bind(since)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok((notes, folders, workspaces))
+    }
+
+    pub async fn get_setting(&self, key: &str) -> Result<Option<String>, AppError> {
+        sqlx::query_scalar::<_, String>("SELECT value FROM settings WHERE key = ?1")
+            .bind(key)
+            .fetch_optional(&self.pool)
             .await
-            .map_err(|e| e.to_string())
+            .map_err(AppError::from)
+    }
+
+    pub async fn set_setting(&self, key: &str, value: &str) -> Result<(), AppError> {
+        let mut conn = self.pool.acquire().await?;
+        tx::set_setting(&mut conn, key, value).await
     }
 
-    pub async fn upsert_note(&self, note: NoteRecord) -> Result<(), String> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map_err(|e| e.to_string())?
-            .as_millis() as i64;
+    pub async fn search_notes(&self, query: String) -> Result<Vec<SearchResult>, AppError> {
+        // Prepare FTS query (add * for prefix matching)
+        let fts_query = format!("{}*", query.replace("\"", "\"\""));
 
-        // Get old content and created_at if exists
-        let old_note: Option<(String, i64, i32)> =
-            sqlx::query_as("SELECT content, created_at, version FROM notes WHERE id = ?1")
-                .bind(&note.id)
-                .fetch_optional(&self.pool)
-                .await
-                .unwrap_or(None);
+        sqlx::query_as::<_, SearchResult>(
+            "SELECT id, title, snippet(notes_fts, 2, '<mark>', '</mark>', '...', 20) as snippet
+             FROM notes_fts
+             WHERE notes_fts MATCH ?1
+             ORDER BY rank
+             LIMIT 20",
+        )
+        .bind(fts_query)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(AppError::from)
+    }
+
+    /// Ids of every note matching `query`, unlike `search_notes` which only
+    /// returns the best hits.
+    pub async fn search_note_ids(&self, query: &str) -> Result<HashSet<String>, AppError> {
+        let fts_query = format!("{}*", query.replace("\"", "\"\""));
+        let ids: Vec<String> =
+            sqlx::query_scalar("SELECT id FROM notes_fts WHERE notes_fts MATCH ?1")
+                .bind(fts_query)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(ids.into_iter().collect())
+    }
+
+    /// Notes and folders of a workspace, with the ones in the trash if asked.
+    pub async fn get_workspace_items(
+        &self,
+        workspace_id: &str,
+        include_deleted: bool,
+    ) -> Result<(Vec<NoteRecord>, Vec<FolderRecord>), AppError> {
+        let deleted = if include_deleted {
+            ""
+        } else {
+            " AND is_deleted = 0"
+        };
+        let notes = sqlx::query_as::<_, NoteRecord>(&format!(
+            "SELECT * FROM notes WHERE workspace_id = ?1{} ORDER BY sort_key, created_at",
+            deleted
+        ))
+        .bind(workspace_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let folders = sqlx::query_as::<_, FolderRecord>(&format!(
+            "SELECT * FROM folders WHERE workspace_id = ?1{} ORDER BY sort_key, created_at",
+            deleted
+        ))
+        .bind(workspace_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok((notes, folders))
+    }
+}
+
+/// Connection-level operations, so several of them can share one transaction
+/// (`let mut tx = db.pool.begin().await?; tx::upsert_note(&mut tx, note)`).
+pub mod tx {
+    use super::{FolderRecord, NoteRecord, WorkspaceRecord};
+    use crate::error::{AppError, Entity};
+    use crate::ordering::{self, ItemKind};
+    use sqlx::SqliteConnection;
+
+    /// Saves a note, stamping the current time and the next version, logs the
+    /// revision when the content changed, and returns the new version. Callers
+    /// run it inside a transaction.
+    pub async fn upsert_note(
+        conn: &mut SqliteConnection,
+        note: NoteRecord,
+    ) -> Result<i32, AppError> {
+        let now = super::now_millis();
+
+        // Get old content, created_at and position if exists
+        let old_note: Option<(String, i64, i32, Option<String>, String)> = sqlx::query_as(
+            "SELECT content, created_at, version, folder_id, sort_key FROM notes WHERE id = ?1",
+        )
+        .bind(&note.id)
+        .fetch_optional(&mut *conn)
+        .await?;
 
         let (new_version, created_at) = match old_note {
-            Some((_, ca, v)) => (v + 1, ca),
+            Some((_, ca, v, _, _)) => (v + 1, ca),
             None => (1, now),
         };
+        let sort_key = ordering::key_for_save(
+            &mut *conn,
+            ItemKind::Note,
+            &note.workspace_id,
+            note.folder_id.as_deref(),
+            old_note
+                .as_ref()
+                .map(|(_, _, _, folder_id, key)| (folder_id.clone(), key.clone())),
+            note.sort_key.clone(),
+        )
+        .await?;
 
         sqlx::query(
-            "INSERT INTO notes (id, title, content, folder_id, workspace_id, created_at, updated_at, version, is_deleted)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "INSERT INTO notes (id, title, content, folder_id, workspace_id, created_at, updated_at, version, sort_key, color, is_deleted)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
              ON CONFLICT(id) DO UPDATE SET
                 title = excluded.title,
                 content = excluded.content,
@@ -90,6 +677,8 @@ impl Db {
                 workspace_id = excluded.workspace_id,
                 updated_at = excluded.updated_at,
                 version = excluded.version,
+                sort_key = excluded.sort_key,
+                color = excluded.color,
                 is_deleted = excluded.is_deleted",
         )
         .bind(&note.id)
@@ -100,14 +689,15 @@ impl Db {
         .bind(created_at)
         .bind(now)
         .bind(new_version)
+        .bind(&sort_key)
+        .bind(&note.color)
         .bind(note.is_deleted)
-        .execute(&self.pool)
-        .await
-        .map_err(|e| e.to_string())?;
+        .execute(&mut *conn)
+        .await?;
 
         // Log change if content actually changed or if it's new
         let content_changed = match old_note {
-            Some((ref old_content, _, _)) => old_content != &note.content,
+            Some((ref old_content, ..)) => old_content != &note.content,
             None => true,
         };
 
@@ -119,23 +709,169 @@ impl Db {
             )
             .bind(change_id)
             .bind(&note.id)
-            .bind(old_note.map(|(c, _, _)| c))
+            .bind(old_note.map(|(c, ..)| c))
             .bind(&note.content)
             .bind(now)
             .bind(new_version)
-            .execute(&self.pool)
-            .await
-            .map_err(|e| e.to_string())?;
+            .execute(&mut *conn)
+            .await?;
         }
 
-        Ok(())
+        Ok(new_version)
     }
 
-    pub async fn apply_remote_update_note(&self, note: NoteRecord) -> Result<(), String> {
+    /// Saves a folder, stamping the current time and the next version, and
+    /// returns that version. Position rules are in `ordering::key_for_save`.
+    pub async fn upsert_folder(
+        conn: &mut SqliteConnection,
+        folder: FolderRecord,
+    ) -> Result<i32, AppError> {
+        let now = super::now_millis();
+
+        // Get old created_at, version and position if exists
+        let old_folder: Option<(i64, i32, Option<String>, String)> = sqlx::query_as(
+            "SELECT created_at, version, parent_id, sort_key FROM folders WHERE id = ?1",
+        )
+        .bind(&folder.id)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        let (created_at, new_version) = match old_folder {
+            Some((ca, v, _, _)) => (ca, v + 1),
+            None => (now, 1),
+        };
+        let sort_key = ordering::key_for_save(
+            &mut *conn,
+            ItemKind::Folder,
+            &folder.workspace_id,
+            folder.parent_id.as_deref(),
+            old_folder.map(|(_, _, parent_id, key)| (parent_id, key)),
+            folder.sort_key,
+        )
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO folders (id, name, parent_id, workspace_id, created_at, updated_at, version, color, sort_key, is_deleted)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+             ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name,
+                parent_id = excluded.parent_id,
+                workspace_id = excluded.workspace_id,
+                updated_at = excluded.updated_at,
+                version = excluded.version,
+                is_deleted = excluded.is_deleted,
+                color = excluded.color,
+                sort_key = excluded.sort_key",
+        )
+        .bind(&folder.id)
+        .bind(&folder.name)
+        .bind(&folder.parent_id)
+        .bind(&folder.workspace_id)
+        .bind(created_at)
+        .bind(now)
+        .bind(new_version)
+        .bind(&folder.color)
+        .bind(&sort_key)
+        .bind(folder.is_deleted)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(new_version)
+    }
+
+    /// Saves a workspace like `upsert_folder`.
+    pub async fn upsert_workspace(
+        conn: &mut SqliteConnection,
+        workspace: WorkspaceRecord,
+    ) -> Result<i32, AppError> {
+        let now = super::now_millis();
+
+        // Get old created_at and version if exists
+        let old_workspace: Option<(i64, i32)> =
+            sqlx::query_as("SELECT created_at, version FROM workspaces WHERE id = ?1")
+                .bind(&workspace.id)
+                .fetch_optional(&mut *conn)
+                .await?;
+
+        let (created_at, new_version) = match old_workspace {
+            Some((ca, v)) => (ca, v + 1),
+            None => (now, 1),
+        };
+
+        sqlx::query(
+            "INSERT INTO workspaces (id, name, color, created_at, updated_at, version, is_deleted)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name,
+                color = excluded.color,
+                updated_at = excluded.updated_at,
+                version = excluded.version,
+                is_deleted = excluded.is_deleted",
+        )
+        .bind(&workspace.id)
+        .bind(&workspace.name)
+        .bind(&workspace.color)
+        .bind(created_at)
+        .bind(now)
+        .bind(new_version)
+        .bind(workspace.is_deleted)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(new_version)
+    }
+
+    /// Soft-deletes a note; returns whether it existed.
+    pub async fn delete_note(
+        conn: &mut SqliteConnection,
+        id: &str,
+        now: i64,
+    ) -> Result<bool, AppError> {
+        mark_deleted(conn, "notes", id, now).await
+    }
+
+    pub async fn delete_folder(
+        conn: &mut SqliteConnection,
+        id: &str,
+        now: i64,
+    ) -> Result<bool, AppError> {
+        mark_deleted(conn, "folders", id, now).await
+    }
+
+    pub async fn delete_workspace(
+        conn: &mut SqliteConnection,
+        id: &str,
+        now: i64,
+    ) -> Result<bool, AppError> {
+        mark_deleted(conn, "workspaces", id, now).await
+    }
+
+    async fn mark_deleted(
+        conn: &mut SqliteConnection,
+        table: &str,
+        id: &str,
+        now: i64,
+    ) -> Result<bool, AppError> {
+        sqlx::query(&format!(
+            "UPDATE {} SET is_deleted = 1, updated_at = ?1 WHERE id = ?2",
+            table
+        ))
+        .bind(now)
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .map(|done| done.rows_affected() > 0)
+        .map_err(AppError::from)
+    }
+
+    pub async fn apply_remote_note(
+        conn: &mut SqliteConnection,
+        note: &NoteRecord,
+    ) -> Result<(), AppError> {
         // LWW: Only update if remote updated_at > local updated_at
         sqlx::query(
-            "INSERT INTO notes (id, title, content, folder_id, workspace_id, created_at, updated_at, version, is_deleted)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "INSERT INTO notes (id, title, content, folder_id, workspace_id, created_at, updated_at, version, sort_key, color, is_deleted)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
              ON CONFLICT(id) DO UPDATE SET
                 title = excluded.title,
                 content = excluded.content,
@@ -143,6 +879,8 @@ impl Db {
                 workspace_id = excluded.workspace_id,
                 updated_at = excluded.updated_at,
                 version = excluded.version,
+                sort_key = excluded.sort_key,
+                color = excluded.color,
                 is_deleted = excluded.is_deleted
              WHERE excluded.updated_at > notes.updated_at",
         )
@@ -154,57 +892,22 @@ impl Db {
         .bind(note.created_at)
         .bind(note.updated_at)
         .bind(note.version)
+        .bind(&note.sort_key)
+        .bind(&note.color)
         .bind(note.is_deleted)
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await
         .map(|_| ())
-        .map_err(|e| e.to_string())
+        .map_err(AppError::from)
     }
 
-    pub async fn delete_note(&self, id: String) -> Result<(), String> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as i64;
-
-        sqlx::query("UPDATE notes SET is_deleted = 1, updated_at = ?1 WHERE id = ?2")
-            .bind(now)
-            .bind(id)
-            .execute(&self.pool)
-            .await
-            .map(|_| ())
-            .map_err(|e| e.to_string())
-    }
-
-    pub async fn get_folders(&self) -> Result<Vec<FolderRecord>, String> {
-        sqlx::query_as::<_, FolderRecord>("SELECT * FROM folders WHERE is_deleted = 0")
-            .fetch_all(&self.pool)
-            .await
-            .map_err(|e| e.to_string())
-    }
-
-    pub async fn upsert_folder(&self, folder: FolderRecord) -> Result<(), String> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map_err(|e| e.to_string())?
-            .as_millis() as i64;
-
-        // Get old created_at and version if exists
-        let old_folder: Option<(i64, i32)> =
-            sqlx::query_as("SELECT created_at, version FROM folders WHERE id = ?1")
-                .bind(&folder.id)
-                .fetch_optional(&self.pool)
-                .await
-                .unwrap_or(None);
-
-        let (created_at, new_version) = match old_folder {
-            Some((ca, v)) => (ca, v + 1),
-            None => (now, 1),
-        };
-
+    pub async fn apply_remote_folder(
+        conn: &mut SqliteConnection,
+        folder: &FolderRecord,
+    ) -> Result<(), AppError> {
         sqlx::query(
-            "INSERT INTO folders (id, name, parent_id, workspace_id, created_at, updated_at, version, color, is_deleted)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "INSERT INTO folders (id, name, parent_id, workspace_id, created_at, updated_at, version, color, sort_key, is_deleted)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
              ON CONFLICT(id) DO UPDATE SET
                 name = excluded.name,
                 parent_id = excluded.parent_id,
@@ -212,36 +915,224 @@ impl Db {
                 updated_at = excluded.updated_at,
                 version = excluded.version,
                 is_deleted = excluded.is_deleted,
-                color = excluded.color",
+                color = excluded.color,
+                sort_key = excluded.sort_key
+             WHERE excluded.updated_at > folders.updated_at",
         )
         .bind(&folder.id)
         .bind(&folder.name)
         .bind(&folder.parent_id)
         .bind(&folder.workspace_id)
-        .bind(created_at)
-        .bind(now)
-        .bind(new_version)
+        .bind(folder.created_at)
+        .bind(folder.updated_at)
+        .bind(folder.version)
         .bind(&folder.color)
+        .bind(&folder.sort_key)
         .bind(folder.is_deleted)
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await
         .map(|_| ())
-        .map_err(|e| e.to_string())
+        .map_err(AppError::from)
     }
 
-    pub async fn apply_remote_update_folder(&self, folder: FolderRecord) -> Result<(), String> {
+    pub async fn apply_remote_workspace(
+        conn: &mut SqliteConnection,
+        workspace: &WorkspaceRecord,
+    ) -> Result<(), AppError> {
         sqlx::query(
-            "INSERT INTO folders (id, name, parent_id, workspace_id, created_at, updated_at, version, color, is_deleted)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "INSERT INTO workspaces (id, name, color, created_at, updated_at, version, is_deleted)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
              ON CONFLICT(id) DO UPDATE SET
                 name = excluded.name,
-                parent_id = excluded.parent_id,
-                workspace_id = excluded.workspace_id,
+                color = excluded.color,
                 updated_at = excluded.updated_at,
                 version = excluded.version,
-                is_deleted = excluded.is_deleted,
-                color = excluded.color
-             WHERE excluded.updated_at > folders.updated_at",
+                is_deleted = excluded.is_deleted
+             WHERE excluded.updated_at > workspaces.updated_at",
+        )
+        .bind(&workspace.id)
+        .bind(&workspace.name)
+        .bind(&workspace.color)
+        .bind(workspace.created_at)
+        .bind(workspace.updated_at)
+        .bind(workspace.version)
+        .bind(workspace.is_deleted)
+        .execute(&mut *conn)
+        .await
+        .map(|_| ())
+        .map_err(AppError::from)
+    }
+
+    pub async fn set_setting(
+        conn: &mut SqliteConnection,
+        key: &str,
+        value: &str,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "INSERT INTO settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(key)
+        .bind(value)
+        .execute(&mut *conn)
+        .await
+        .map(|_| ())
+        .map_err(AppError::from)
+    }
+
+    /// Fails unless the workspace exists and, when given, the folder is live
+    /// and in that workspace. Used to validate the destination of a move.
+    pub async fn check_target(
+        conn: &mut SqliteConnection,
+        workspace_id: &str,
+        folder_id: Option<&str>,
+    ) -> Result<(), AppError> {
+        let workspace: i64 =
+            sqlx::query_scalar("SELECT count(*) FROM workspaces WHERE id = ?1 AND is_deleted = 0")
+                .bind(workspace_id)
+                .fetch_one(&mut *conn)
+                .await?;
+        if workspace == 0 {
+            return Err(AppError::not_found(Entity::Workspace, workspace_id));
+        }
+        if let Some(folder_id) = folder_id {
+            let folder: i64 = sqlx::query_scalar(
+                "SELECT count(*) FROM folders WHERE id = ?1 AND workspace_id = ?2 AND is_deleted = 0",
+            )
+            .bind(folder_id)
+            .bind(workspace_id)
+            .fetch_one(&mut *conn)
+            .await?;
+            if folder == 0 {
+                return Err(AppError::not_found(Entity::Folder, folder_id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Ids of a live folder and all of its live descendants, the folder first.
+    /// Empty when the folder doesn't exist.
+    pub async fn folder_subtree(
+        conn: &mut SqliteConnection,
+        id: &str,
+    ) -> Result<Vec<String>, AppError> {
+        // UNION (not UNION ALL) stops on a parent cycle in damaged data
+        sqlx::query_scalar::<_, String>(
+            "WITH RECURSIVE subtree(id) AS (
+                 SELECT id FROM folders WHERE id = ?1 AND is_deleted = 0
+                 UNION
+                 SELECT f.id FROM folders f JOIN subtree s ON f.parent_id = s.id
+                 WHERE f.is_deleted = 0
+             )
+             SELECT id FROM subtree ORDER BY id != ?1",
+        )
+        .bind(id)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(AppError::from)
+    }
+
+    pub async fn has_note(conn: &mut SqliteConnection, id: &str) -> Result<bool, AppError> {
+        sqlx::query_scalar::<_, i64>("SELECT count(*) FROM notes WHERE id = ?1")
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await
+            .map(|count| count > 0)
+            .map_err(AppError::from)
+    }
+
+    pub async fn has_folder(conn: &mut SqliteConnection, id: &str) -> Result<bool, AppError> {
+        sqlx::query_scalar::<_, i64>("SELECT count(*) FROM folders WHERE id = ?1")
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await
+            .map(|count| count > 0)
+            .map_err(AppError::from)
+    }
+
+    pub async fn has_workspace(conn: &mut SqliteConnection, id: &str) -> Result<bool, AppError> {
+        sqlx::query_scalar::<_, i64>("SELECT count(*) FROM workspaces WHERE id = ?1")
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await
+            .map(|count| count > 0)
+            .map_err(AppError::from)
+    }
+
+    pub async fn workspace_notes(
+        conn: &mut SqliteConnection,
+        workspace_id: &str,
+    ) -> Result<Vec<NoteRecord>, AppError> {
+        sqlx::query_as::<_, NoteRecord>(
+            "SELECT * FROM notes WHERE workspace_id = ?1 AND is_deleted = 0",
+        )
+        .bind(workspace_id)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(AppError::from)
+    }
+
+    pub async fn workspace_folders(
+        conn: &mut SqliteConnection,
+        workspace_id: &str,
+    ) -> Result<Vec<FolderRecord>, AppError> {
+        sqlx::query_as::<_, FolderRecord>(
+            "SELECT * FROM folders WHERE workspace_id = ?1 AND is_deleted = 0",
+        )
+        .bind(workspace_id)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(AppError::from)
+    }
+
+    /// Inserts a note exactly as given (id, timestamps, version), unlike
+    /// `upsert_note` which stamps the current time. Used when restoring data
+    /// from an export. The content is logged as the note's first revision.
+    pub async fn restore_note(
+        conn: &mut SqliteConnection,
+        note: &NoteRecord,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "INSERT INTO notes (id, title, content, folder_id, workspace_id, created_at, updated_at, version, sort_key, color, is_deleted)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )
+        .bind(&note.id)
+        .bind(&note.title)
+        .bind(&note.content)
+        .bind(&note.folder_id)
+        .bind(&note.workspace_id)
+        .bind(note.created_at)
+        .bind(note.updated_at)
+        .bind(note.version)
+        .bind(&note.sort_key)
+        .bind(&note.color)
+        .bind(note.is_deleted)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO changes (id, note_id, old_content, new_content, timestamp, version)
+             VALUES (?1, ?2, NULL, ?3, ?4, ?5)",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(&note.id)
+        .bind(&note.content)
+        .bind(note.updated_at)
+        .bind(note.version)
+        .execute(&mut *conn)
+        .await
+        .map(|_| ())
+        .map_err(AppError::from)
+    }
+
+    /// Inserts a folder exactly as given, see `restore_note`.
+    pub async fn restore_folder(
+        conn: &mut SqliteConnection,
+        folder: &FolderRecord,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "INSERT INTO folders (id, name, parent_id, workspace_id, created_at, updated_at, version, color, sort_key, is_deleted)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
         )
         .bind(&folder.id)
         .bind(&folder.name)
@@ -251,88 +1142,22 @@ impl Db {
         .bind(folder.updated_at)
         .bind(folder.version)
         .bind(&folder.color)
+        .bind(&folder.sort_key)
         .bind(folder.is_deleted)
-        .execute(&self.pool)
-        .await
-        .map(|_| ())
-        .map_err(|e| e.to_string())
-    }
-
-    pub async fn delete_folder(&self, id: String) -> Result<(), String> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as i64;
-
-        sqlx::query("UPDATE folders SET is_deleted = 1, updated_at = ?1 WHERE id = ?2")
-            .bind(now)
-            .bind(id)
-            .execute(&self.pool)
-            .await
-            .map(|_| ())
-            .map_err(|e| e.to_string())
-    }
-
-    pub async fn get_workspaces(&self) -> Result<Vec<WorkspaceRecord>, String> {
-        sqlx::query_as::<_, WorkspaceRecord>("SELECT * FROM workspaces WHERE is_deleted = 0 ORDER BY created_at ASC")
-            .fetch_all(&self.pool)
-            .await
-            .map_err(|e| e.to_string())
-    }
-
-    pub async fn upsert_workspace(&self, workspace: WorkspaceRecord) -> Result<(), String> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map_err(|e| e.to_string())?
-            .as_millis() as i64;
-
-        // Get old created_at and version if exists
-        let old_workspace: Option<(i64, i32)> =
-            sqlx::query_as("SELECT created_at, version FROM workspaces WHERE id = ?1")
-                .bind(&workspace.id)
-                .fetch_optional(&self.pool)
-                .await
-                .unwrap_or(None);
-
-        let (created_at, new_version) = match old_workspace {
-            Some((ca, v)) => (ca, v + 1),
-            None => (now, 1),
-        };
-
-        sqlx::query(
-            "INSERT INTO workspaces (id, name, color, created_at, updated_at, version, is_deleted)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-             ON CONFLICT(id) DO UPDATE SET
-                name = excluded.name,
-                color = excluded.color,
-                updated_at = excluded.updated_at,
-                version = excluded.version,
-                is_deleted = excluded.is_deleted",
-        )
-        .bind(&workspace.id)
-        .bind(&workspace.name)
-        .bind(&workspace.color)
-        .bind(created_at)
-        .bind(now)
-        .bind(new_version)
-        .bind(workspace.is_deleted)
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await
         .map(|_| ())
-        .map_err(|e| e.to_string())
+        .map_err(AppError::from)
     }
 
-    pub async fn apply_remote_update_workspace(&self, workspace: WorkspaceRecord) -> Result<(), String> {
+    /// Inserts a workspace exactly as given, see `restore_note`.
+    pub async fn restore_workspace(
+        conn: &mut SqliteConnection,
+        workspace: &WorkspaceRecord,
+    ) -> Result<(), AppError> {
         sqlx::query(
             "INSERT INTO workspaces (id, name, color, created_at, updated_at, version, is_deleted)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-             ON CONFLICT(id) DO UPDATE SET
-                name = excluded.name,
-                color = excluded.color,
-                updated_at = excluded.updated_at,
-                version = excluded.version,
-                is_deleted = excluded.is_deleted
-             WHERE excluded.updated_at > workspaces.updated_at",
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
         )
         .bind(&workspace.id)
         .bind(&workspace.name)
@@ -341,196 +1166,145 @@ impl Db {
         .bind(workspace.updated_at)
         .bind(workspace.version)
         .bind(workspace.is_deleted)
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await
         .map(|_| ())
-        .map_err(|e| e.to_string())
+        .map_err(AppError::from)
     }
+}
 
-    pub async fn delete_workspace(&self, id: String) -> Result<(), String> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as i64;
+/// File name of the database inside the app data dir.
+pub const DB_FILE: &str = "lumenote.db";
 
-        sqlx::query("UPDATE workspaces SET is_deleted = 1, updated_at = ?1 WHERE id = ?2")
-            .bind(now)
-            .bind(id)
-            .execute(&self.pool)
-            .await
-            .map(|_| ())
-            .map_err(|e| e.to_string())
+/// Managed handle to the database. The pool is absent while an encrypted
+/// database is locked, so commands go through `db()` and fail cleanly instead
+/// of touching a closed pool.
+pub struct DbState {
+    db: RwLock<Option<Arc<Db>>>,
+    /// Key the open database was unlocked with, needed to reopen it after the
+    /// file is swapped (restore). Cleared when the database locks.
+    passphrase: RwLock<Option<String>>,
+    /// Signalled whenever the database is (re)opened after being locked.
+    pub opened: Notify,
+    last_activity: AtomicI64,
+    pub app_dir: PathBuf,
+}
+
+impl DbState {
+    pub fn new(app_dir: PathBuf, db: Option<Db>) -> Self {
+        Self {
+            db: RwLock::new(db.map(Arc::new)),
+            passphrase: RwLock::new(None),
+            opened: Notify::new(),
+            last_activity: AtomicI64::new(now_millis()),
+            app_dir,
+        }
     }
 
-    pub async fn get_sync_data(
-        &self,
-        since: i64,
-    ) -> Result<(Vec<NoteRecord>, Vec<FolderRecord>, Vec<WorkspaceRecord>), String> {
-        let notes = sqlx::query_as::<_, NoteRecord>("SELECT * FROM notes WHERE updated_at > ?1")
-            .bind(since)
-            .fetch_all(&self.pool)
-            .await
-            .map_err(|e| e.to_string())?;
+    pub fn path(&self) -> PathBuf {
+        self.app_dir.join(DB_FILE)
+    }
 
-        let folders =
-            sqlx::query_as::<_, FolderRecord>("SELECT * FROM folders WHERE updated_at > ?1")
-                .bind(since)
-                .fetch_all(&self.pool)
-                .await
-                .map_err(|e| e.to_string())?;
+    /// Returns the open database and records user activity for auto-lock.
+    pub fn db(&self) -> Result<Arc<Db>, AppError> {
+        let db = self.peek().ok_or(AppError::Locked {
+            target: LockTarget::Database,
+        })?;
+        self.last_activity.store(now_millis(), Ordering::Relaxed);
+        Ok(db)
+    }
 
-        let workspaces =
-            sqlx::query_as::<_, WorkspaceRecord>("SELECT * FROM workspaces WHERE updated_at > ?1")
-                .bind(since)
-                .fetch_all(&self.pool)
-                .await
-                .map_err(|e| e.to_string())?;
+    /// Like `db()` but does not count as activity; used by background tasks.
+    pub fn peek(&self) -> Option<Arc<Db>> {
+        self.db.read().unwrap().clone()
+    }
 
-        Ok((notes, folders, workspaces))
+    pub fn open(&self, db: Db, passphrase: Option<&str>) {
+        *self.passphrase.write().unwrap() = passphrase.map(str::to_string);
+        *self.db.write().unwrap() = Some(Arc::new(db));
+        self.last_activity.store(now_millis(), Ordering::Relaxed);
+        self.opened.notify_one();
     }
 
-    pub async fn search_notes(&self, query: String) -> Result<Vec<SearchResult>, String> {
-        // Prepare FTS query (add * for prefix matching)
-        let fts_query = format!("{}*", query.replace("\"", "\"\""));
+    /// Takes the database out and closes its pool. In-flight commands keep
+    /// their `Arc` and finish before the pool is actually released.
+    pub async fn close(&self) {
+        let db = self.db.write().unwrap().take();
+        *self.passphrase.write().unwrap() = None;
+        if let Some(db) = db {
+            db.pool.close().await;
+        }
+    }
 
-        sqlx::query_as::<_, SearchResult>(
-            "SELECT id, title, snippet(notes_fts, 2, '<mark>', '</mark>', '...', 20) as snippet
-             FROM notes_fts
-             WHERE notes_fts MATCH ?1
-             ORDER BY rank
-             LIMIT 20",
-        )
-        .bind(fts_query)
-        .fetch_all(&self.pool)
-        .await
-        .map_err(|e| e.to_string())
+    pub fn passphrase(&self) -> Option<String> {
+        self.passphrase.read().unwrap().clone()
+    }
+
+    pub fn idle_millis(&self) -> i64 {
+        now_millis() - self.last_activity.load(Ordering::Relaxed)
     }
 }
 
-pub struct DbState {
-    pub db: Db,
+fn existed(
+    deleted: Result<bool, AppError>,
+    entity: Entity,
+    id: &str,
+) -> Result<Option<i32>, AppError> {
+    match deleted? {
+        true => Ok(None),
+        false => Err(AppError::not_found(entity, id)),
+    }
 }
 
-pub async fn init_db(app_dir: std::path::PathBuf) -> Result<Pool<Sqlite>, sqlx::Error> {
-    let db_path = app_dir.join("lumenote.db");
-    let db_url = format!("sqlite:{}", db_path.to_str().unwrap());
+pub(crate) fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
 
-    if !db_path.exists() {
-        std::fs::File::create(&db_path).unwrap();
+/// Connects to a database file, keying every connection when a passphrase is
+/// given (SQLCipher). A wrong key only surfaces on the first query.
+pub async fn connect(path: &Path, passphrase: Option<&str>) -> Result<Pool<Sqlite>, sqlx::Error> {
+    let mut options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true);
+    if let Some(passphrase) = passphrase {
+        options = options.pragma("key", format!("'{}'", passphrase.replace('\'', "''")));
     }
 
-    let pool = SqlitePoolOptions::new()
+    SqlitePoolOptions::new()
         .max_connections(5)
-        .connect(&db_url)
-        .await?;
-
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS folders (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL,
-            parent_id TEXT,
-            workspace_id TEXT NOT NULL,
-            created_at INTEGER NOT NULL,
-            updated_at INTEGER NOT NULL DEFAULT 0,
-            version INTEGER NOT NULL DEFAULT 1,
-            is_deleted BOOLEAN NOT NULL DEFAULT 0,
-            color TEXT,
-            FOREIGN KEY(parent_id) REFERENCES folders(id) ON DELETE CASCADE
-        )",
-    )
-    .execute(&pool)
-    .await?;
-
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS notes (
-            id TEXT PRIMARY KEY,
-            title TEXT NOT NULL,
-            content TEXT NOT NULL,
-            folder_id TEXT,
-            workspace_id TEXT NOT NULL,
-            created_at INTEGER NOT NULL,
-            updated_at INTEGER NOT NULL,
-            version INTEGER NOT NULL DEFAULT 1,
-            is_deleted BOOLEAN NOT NULL DEFAULT 0,
-            FOREIGN KEY(folder_id) REFERENCES folders(id) ON DELETE CASCADE
-        )",
-    )
-    .execute(&pool)
-    .await?;
-
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS workspaces (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL,
-            color TEXT NOT NULL,
-            created_at INTEGER NOT NULL,
-            updated_at INTEGER NOT NULL DEFAULT 0,
-            version INTEGER NOT NULL DEFAULT 1,
-            is_deleted BOOLEAN NOT NULL DEFAULT 0
-        )",
-    )
-    .execute(&pool)
-    .await?;
-
-    // Migration: Add columns to notes if they don't exist
-    let note_info: Vec<(i64, String, String, i64, Option<String>, i64)> =
-        sqlx::query_as("PRAGMA table_info(notes)")
-            .fetch_all(&pool)
-            .await?;
-
-    if !note_info.iter().any(|c| c.1 == "version") {
-        sqlx::query("ALTER TABLE notes ADD COLUMN version INTEGER NOT NULL DEFAULT 1")
-            .execute(&pool)
-            .await?;
-    }
-    if !note_info.iter().any(|c| c.1 == "is_deleted") {
-        sqlx::query("ALTER TABLE notes ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT 0")
-            .execute(&pool)
-            .await?;
-    }
+        .connect_with(options)
+        .await
+}
 
-    // Migration: Add columns to folders if they don't exist
-    let folder_info: Vec<(i64, String, String, i64, Option<String>, i64)> =
-        sqlx::query_as("PRAGMA table_info(folders)")
-            .fetch_all(&pool)
-            .await?;
+pub async fn init_db(app_dir: PathBuf) -> Result<Pool<Sqlite>, sqlx::Error> {
+    open_db(&app_dir, None).await
+}
 
-    if !folder_info.iter().any(|c| c.1 == "updated_at") {
-        sqlx::query("ALTER TABLE folders ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0")
-            .execute(&pool)
+/// Opens the database and migrates it to the current schema, backing it up
+/// into `backups/` first when an existing database needs upgrading.
+pub async fn open_db(
+    app_dir: &Path,
+    passphrase: Option<&str>,
+) -> Result<Pool<Sqlite>, sqlx::Error> {
+    let pool = connect(&app_dir.join(DB_FILE), passphrase).await?;
+    crate::migrations::run(&pool, Some(&crate::backup::backup_dir(app_dir))).await?;
+
+    // Make sure there is always at least one workspace to show
+    let workspace_count: (i64,) =
+        sqlx::query_as("SELECT count(*) FROM workspaces WHERE is_deleted = 0")
+            .fetch_one(&pool)
             .await?;
-        // Initialize updated_at with created_at for existing folders
-        sqlx::query("UPDATE folders SET updated_at = created_at WHERE updated_at = 0")
-            .execute(&pool)
-            .await?;
-    }
-    if !folder_info.iter().any(|c| c.1 == "is_deleted") {
-        sqlx::query("ALTER TABLE folders ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT 0")
-            .execute(&pool)
-            .await?;
-    }
-    if !folder_info.iter().any(|c| c.1 == "version") {
-        sqlx::query("ALTER TABLE folders ADD COLUMN version INTEGER NOT NULL DEFAULT 1")
-            .execute(&pool)
-            .await?;
-    }
 
-    // Migration: Check if workspaces table exists and add default workspace if empty
-    let workspace_count: (i64,) = sqlx::query_as("SELECT count(*) FROM workspaces WHERE is_deleted = 0")
-        .fetch_one(&pool)
-        .await
-        .unwrap_or((0,));
-    
     if workspace_count.0 == 0 {
         // Insert default workspace if no workspaces exist
         let default_id = "default".to_string();
         let default_name = "Lumenote".to_string();
         let default_color = "#4F7DF3".to_string();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as i64;
-        
+        let now = now_millis();
+
         sqlx::query(
             "INSERT OR IGNORE INTO workspaces (id, name, color, created_at, updated_at, version, is_deleted)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
@@ -546,129 +1320,110 @@ pub async fn init_db(app_dir: std::path::PathBuf) -> Result<Pool<Sqlite>, sqlx::
         .await?;
     }
 
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS changes (
-            id TEXT PRIMARY KEY,
-            note_id TEXT NOT NULL,
-            old_content TEXT,
-            new_content TEXT,
-            timestamp INTEGER NOT NULL,
-            version INTEGER NOT NULL,
-            FOREIGN KEY(note_id) REFERENCES notes(id) ON DELETE CASCADE
-        )",
-    )
-    .execute(&pool)
-    .await?;
-
-    // FTS5 Table for search
-    sqlx::query(
-        "CREATE VIRTUAL TABLE IF NOT EXISTS notes_fts USING fts5(
-            id UNINDEXED,
-            title,
-            content,
-            tokenize='unicode61'
-        )",
-    )
-    .execute(&pool)
-    .await?;
-
-    // Triggers to keep FTS in sync
-    sqlx::query(
-        "CREATE TRIGGER IF NOT EXISTS notes_ai AFTER INSERT ON notes BEGIN
-            INSERT INTO notes_fts(id, title, content) VALUES (new.id, new.title, new.content);
-        END;",
-    )
-    .execute(&pool)
-    .await?;
-
-    sqlx::query(
-        "CREATE TRIGGER IF NOT EXISTS notes_ad AFTER DELETE ON notes BEGIN
-            DELETE FROM notes_fts WHERE id = old.id;
-        END;",
-    )
-    .execute(&pool)
-    .await?;
-
-    sqlx::query(
-        "CREATE TRIGGER IF NOT EXISTS notes_au AFTER UPDATE ON notes BEGIN
-            UPDATE notes_fts SET title = new.title, content = new.content WHERE id = new.id;
-        END;",
-    )
-    .execute(&pool)
-    .await?;
-
-    // Sync existing data if FTS is empty
-    let count: (i64,) = sqlx::query_as("SELECT count(*) FROM notes_fts")
-        .fetch_one(&pool)
-        .await
-        .unwrap_or((0,));
-
-    if count.0 == 0 {
-        sqlx::query(
-            "INSERT INTO notes_fts(id, title, content) SELECT id, title, content FROM notes",
-        )
-        .execute(&pool)
-        .await?;
-    }
-
     Ok(pool)
 }
 
 #[tauri::command]
-pub async fn get_notes(state: tauri::State<'_, DbState>) -> Result<Vec<NoteRecord>, String> {
-    state.db.get_notes().await
+pub async fn get_notes(state: tauri::State<'_, DbState>) -> Result<Vec<NoteRecord>, AppError> {
+    state.db()?.get_notes().await
 }
 
 #[tauri::command]
-pub async fn upsert_note(state: tauri::State<'_, DbState>, note: NoteRecord) -> Result<(), String> {
-    state.db.upsert_note(note).await
+pub async fn upsert_note(
+    state: tauri::State<'_, DbState>,
+    note: NoteRecord,
+) -> Result<(), AppError> {
+    state.db()?.upsert_note(note).await
 }
 
 #[tauri::command]
-pub async fn delete_note(state: tauri::State<'_, DbState>, id: String) -> Result<(), String> {
-    state.db.delete_note(id).await
+pub async fn delete_note(state: tauri::State<'_, DbState>, id: String) -> Result<(), AppError> {
+    state.db()?.delete_note(id).await
 }
 
 #[tauri::command]
-pub async fn get_folders(state: tauri::State<'_, DbState>) -> Result<Vec<FolderRecord>, String> {
-    state.db.get_folders().await
+pub async fn get_folders(state: tauri::State<'_, DbState>) -> Result<Vec<FolderRecord>, AppError> {
+    state.db()?.get_folders().await
 }
 
 #[tauri::command]
 pub async fn upsert_folder(
     state: tauri::State<'_, DbState>,
     folder: FolderRecord,
-) -> Result<(), String> {
-    state.db.upsert_folder(folder).await
+) -> Result<(), AppError> {
+    state.db()?.upsert_folder(folder).await
+}
+
+#[tauri::command]
+pub async fn delete_folder(state: tauri::State<'_, DbState>, id: String) -> Result<(), AppError> {
+    state.db()?.delete_folder(id).await
+}
+
+#[tauri::command]
+pub async fn move_items(
+    state: tauri::State<'_, DbState>,
+    note_ids: Vec<String>,
+    folder_ids: Vec<String>,
+    workspace_id: String,
+    folder_id: Option<String>,
+) -> Result<(), AppError> {
+    state
+        .db()?
+        .move_items(&note_ids, &folder_ids, &workspace_id, folder_id.as_deref())
+        .await
+}
+
+#[tauri::command]
+pub async fn duplicate_note(
+    state: tauri::State<'_, DbState>,
+    id: String,
+) -> Result<NoteRecord, AppError> {
+    state.db()?.duplicate_note(&id).await
+}
+
+#[tauri::command]
+pub async fn copy_folder_tree(
+    state: tauri::State<'_, DbState>,
+    id: String,
+    workspace_id: String,
+    folder_id: Option<String>,
+) -> Result<FolderRecord, AppError> {
+    state
+        .db()?
+        .copy_folder_tree(&id, &workspace_id, folder_id.as_deref())
+        .await
 }
 
 #[tauri::command]
-pub async fn delete_folder(state: tauri::State<'_, DbState>, id: String) -> Result<(), String> {
-    state.db.delete_folder(id).await
+pub async fn apply_batch(
+    state: tauri::State<'_, DbState>,
+    operations: Vec<BatchOp>,
+) -> Result<BatchResult, AppError> {
+    state.db()?.apply_batch(operations).await
 }
 
 #[tauri::command]
 pub async fn search_notes(
     state: tauri::State<'_, DbState>,
     query: String,
-) -> Result<Vec<SearchResult>, String> {
-    state.db.search_notes(query).await
+) -> Result<Vec<SearchResult>, AppError> {
+    state.db()?.search_notes(query).await
 }
 
 #[tauri::command]
 pub async fn apply_remote_update_note(
     state: tauri::State<'_, DbState>,
     note: NoteRecord,
-) -> Result<(), String> {
-    state.db.apply_remote_update_note(note).await
+) -> Result<(), AppError> {
+    state.db()?.apply_remote_update_note(note).await
 }
 
 #[tauri::command]
 pub async fn apply_remote_update_folder(
     state: tauri::State<'_, DbState>,
     folder: FolderRecord,
-) -> Result<(), String> {
-    state.db.apply_remote_update_folder(folder).await
+) -> Result<(), AppError> {
+    state.db()?.apply_remote_update_folder(folder).await
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -679,38 +1434,47 @@ pub struct SyncDataResponse {
 }
 
 #[tauri::command]
-pub async fn get_workspaces(state: tauri::State<'_, DbState>) -> Result<Vec<WorkspaceRecord>, String> {
-    state.db.get_workspaces().await
+pub async fn get_workspaces(
+    state: tauri::State<'_, DbState>,
+) -> Result<Vec<WorkspaceRecord>, AppError> {
+    state.db()?.get_workspaces().await
 }
 
 #[tauri::command]
 pub async fn upsert_workspace(
     state: tauri::State<'_, DbState>,
     workspace: WorkspaceRecord,
-) -> Result<(), String> {
-    state.db.upsert_workspace(workspace).await
+) -> Result<(), AppError> {
+    state.db()?.upsert_workspace(workspace).await
 }
 
 #[tauri::command]
-pub async fn delete_workspace(state: tauri::State<'_, DbState>, id: String) -> Result<(), String> {
-    state.db.delete_workspace(id).await
+pub async fn delete_workspace(
+    state: tauri::State<'_, DbState>,
+    id: String,
+) -> Result<(), AppError> {
+    state.db()?.delete_workspace(id).await
 }
 
 #[tauri::command]
 pub async fn get_sync_data(
     state: tauri::State<'_, DbState>,
     since: i64,
-) -> Result<SyncDataResponse, String> {
-    let (notes, folders, workspaces) = state.db.get_sync_data(since).await?;
-    Ok(SyncDataResponse { notes, folders, workspaces })
+) -> Result<SyncDataResponse, AppError> {
+    let (notes, folders, workspaces) = state.db()?.get_sync_data(since).await?;
+    Ok(SyncDataResponse {
+        notes,
+        folders,
+        workspaces,
+    })
 }
 
 #[tauri::command]
 pub async fn apply_remote_update_workspace(
     state: tauri::State<'_, DbState>,
     workspace: WorkspaceRecord,
-) -> Result<(), String> {
-    state.db.apply_remote_update_workspace(workspace).await
+) -> Result<(), AppError> {
+    state.db()?.apply_remote_update_workspace(workspace).await
 }
 
 #[cfg(test)]
@@ -718,60 +1482,13 @@ mod tests {
     use super::*;
 
     async fn setup_test_db() -> Db {
+        // One connection, since every in-memory connection is its own database
         let pool = SqlitePoolOptions::new()
+            .max_connections(1)
             .connect("sqlite::memory:")
             .await
             .unwrap();
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS folders (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                parent_id TEXT,
-                workspace_id TEXT NOT NULL,
-                created_at INTEGER NOT NULL,
-                updated_at INTEGER NOT NULL,
-                is_deleted BOOLEAN NOT NULL DEFAULT 0,
-                color TEXT,
-                FOREIGN KEY(parent_id) REFERENCES folders(id) ON DELETE CASCADE
-            )",
-        )
-        .execute(&pool)
-        .await
-        .unwrap();
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS notes (
-                id TEXT PRIMARY KEY,
-                title TEXT NOT NULL,
-                content TEXT NOT NULL,
-                folder_id TEXT,
-                workspace_id TEXT NOT NULL,
-                created_at INTEGER NOT NULL,
-                updated_at INTEGER NOT NULL,
-                version INTEGER NOT NULL DEFAULT 1,
-                is_deleted BOOLEAN NOT NULL DEFAULT 0,
-                FOREIGN KEY(folder_id) REFERENCES folders(id) ON DELETE CASCADE
-            )",
-        )
-        .execute(&pool)
-        .await
-        .unwrap();
-
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS changes (
-                id TEXT PRIMARY KEY,
-                note_id TEXT NOT NULL,
-                old_content TEXT,
-                new_content TEXT,
-                timestamp INTEGER NOT NULL,
-                version INTEGER NOT NULL,
-                FOREIGN KEY(note_id) REFERENCES notes(id) ON DELETE CASCADE
-            )",
-        )
-        .execute(&pool)
-        .await
-        .unwrap();
+        crate::migrations::run(&pool, None).await.unwrap();
 
         Db::new(pool)
     }
@@ -789,6 +1506,8 @@ mod tests {
             created_at: 1000,
             updated_at: 1000,
             version: 1,
+            sort_key: String::new(),
+            color: None,
             is_deleted: false,
         };
 
@@ -812,6 +1531,8 @@ mod tests {
             created_at: 1000,
             updated_at: 1000,
             version: 1,
+            sort_key: String::new(),
+            color: None,
             is_deleted: false,
         };
 
@@ -835,6 +1556,7 @@ mod tests {
             updated_at: 1000,
             version: 1,
             color: None,
+            sort_key: String::new(),
             is_deleted: false,
         };
 
@@ -858,6 +1580,8 @@ mod tests {
             created_at: 1000,
             updated_at: 1000,
             version: 1,
+            sort_key: String::new(),
+            color: None,
             is_deleted: false,
         };
 
@@ -892,4 +1616,274 @@ mod tests {
         assert_eq!(changes[1].1, "Content V2");
         assert_eq!(changes[1].2, 2);
     }
+
+    fn workspace(id: &str) -> WorkspaceRecord {
+        WorkspaceRecord {
+            id: id.to_string(),
+            name: id.to_string(),
+            color: "#4F7DF3".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            is_deleted: false,
+        }
+    }
+
+    fn folder(id: &str, parent_id: Option<&str>, workspace_id: &str) -> FolderRecord {
+        FolderRecord {
+            id: id.to_string(),
+            name: id.to_string(),
+            parent_id: parent_id.map(str::to_string),
+            workspace_id: workspace_id.to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            color: None,
+            sort_key: String::new(),
+            is_deleted: false,
+        }
+    }
+
+    fn note(id: &str, folder_id: Option<&str>, workspace_id: &str) -> NoteRecord {
+        NoteRecord {
+            id: id.to_string(),
+            title: id.to_string(),
+            content: format!("content of {}", id),
+            folder_id: folder_id.map(str::to_string),
+            workspace_id: workspace_id.to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        }
+    }
+
+    /// w1: a/b/n1, n2 at the top. w2 is empty.
+    async fn setup_tree() -> Db {
+        let db = setup_test_db().await;
+        db.upsert_workspace(workspace("w1")).await.unwrap();
+        db.upsert_workspace(workspace("w2")).await.unwrap();
+        db.upsert_folder(folder("a", None, "w1")).await.unwrap();
+        db.upsert_folder(folder("b", Some("a"), "w1"))
+            .await
+            .unwrap();
+        db.upsert_note(note("n1", Some("b"), "w1")).await.unwrap();
+        db.upsert_note(note("n2", None, "w1")).await.unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn test_move_items_carries_subtree_to_other_workspace() {
+        let db = setup_tree().await;
+        let ids = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        for target in ["a", "b"] {
+            let err = db
+                .move_items(&[], &ids(&["a"]), "w1", Some(target))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, AppError::Conflict { .. }), "{:?}", err);
+        }
+        assert_eq!(
+            db.move_items(&ids(&["n2"]), &[], "w1", Some("missing"))
+                .await
+                .unwrap_err(),
+            AppError::not_found(Entity::Folder, "missing")
+        );
+        assert!(db
+            .move_items(&ids(&["n2"]), &[], "w2", Some("b"))
+            .await
+            .is_err());
+
+        // n1 is inside the moved folder and stays in b.
+        db.move_items(&ids(&["n1", "n2"]), &ids(&["a"]), "w2", None)
+            .await
+            .unwrap();
+
+        let folders = db.get_folders().await.unwrap();
+        let notes = db.get_notes().await.unwrap();
+        let a = folders.iter().find(|f| f.id == "a").unwrap();
+        let b = folders.iter().find(|f| f.id == "b").unwrap();
+        let n1 = notes.iter().find(|n| n.id == "n1").unwrap();
+        let n2 = notes.iter().find(|n| n.id == "n2").unwrap();
+        assert_eq!(
+            (a.workspace_id.as_str(), a.parent_id.as_deref()),
+            ("w2", None)
+        );
+        assert_eq!(
+            (b.workspace_id.as_str(), b.parent_id.as_deref()),
+            ("w2", Some("a"))
+        );
+        assert_eq!(
+            (n1.workspace_id.as_str(), n1.folder_id.as_deref()),
+            ("w2", Some("b"))
+        );
+        assert_eq!(
+            (n2.workspace_id.as_str(), n2.folder_id.as_deref()),
+            ("w2", None)
+        );
+        assert!([a.version, b.version, n1.version, n2.version]
+            .iter()
+            .all(|&v| v == 2));
+        assert!([b.updated_at, n1.updated_at, n2.updated_at]
+            .iter()
+            .all(|&t| t == a.updated_at));
+
+        // Moved items go after what is already there.
+        db.move_items(&[], &ids(&["b"]), "w2", None).await.unwrap();
+        let top: Vec<String> = db
+            .get_folders()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|f| f.parent_id.is_none())
+            .map(|f| f.id)
+            .collect();
+        assert_eq!(top, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_note_lands_below_original() {
+        let db = setup_tree().await;
+        db.upsert_note(note("n3", None, "w1")).await.unwrap();
+
+        let copy = db.duplicate_note("n2").await.unwrap();
+        assert_ne!(copy.id, "n2");
+        assert_eq!(copy.content, "content of n2");
+        assert_eq!(copy.version, 1);
+
+        let top: Vec<String> = db
+            .get_notes()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|n| n.folder_id.is_none())
+            .map(|n| n.id)
+            .collect();
+        assert_eq!(top, ["n2".to_string(), copy.id.clone(), "n3".to_string()]);
+
+        let revisions: i64 = sqlx::query_scalar("SELECT count(*) FROM changes WHERE note_id = ?1")
+            .bind(&copy.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(revisions, 1);
+        assert!(db.duplicate_note("missing").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_copy_folder_tree_into_itself() {
+        let db = setup_tree().await;
+
+        let root = db.copy_folder_tree("a", "w1", Some("b")).await.unwrap();
+        assert_ne!(root.id, "a");
+        assert_eq!(root.parent_id.as_deref(), Some("b"));
+        assert_eq!(root.version, 1);
+
+        let folders = db.get_folders().await.unwrap();
+        let notes = db.get_notes().await.unwrap();
+        assert_eq!(folders.len(), 4);
+        assert_eq!(notes.len(), 3);
+        let b_copy = folders
+            .iter()
+            .find(|f| f.parent_id.as_deref() == Some(root.id.as_str()))
+            .unwrap();
+        assert_eq!(b_copy.name, "b");
+        let n1_copy = notes
+            .iter()
+            .find(|n| n.folder_id.as_deref() == Some(b_copy.id.as_str()))
+            .unwrap();
+        assert_eq!(n1_copy.content, "content of n1");
+        assert_ne!(n1_copy.id, "n1");
+
+        // The originals are untouched.
+        let a = folders.iter().find(|f| f.id == "a").unwrap();
+        assert_eq!(a.version, 1);
+        assert!(db.copy_folder_tree("a", "w3", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_apply_batch_is_all_or_nothing() {
+        let db = setup_tree().await;
+
+        let failed = db
+            .apply_batch(vec![
+                BatchOp::UpsertNote {
+                    note: note("new", None, "w1"),
+                },
+                BatchOp::DeleteFolder {
+                    id: "a".to_string(),
+                },
+                BatchOp::DeleteNote {
+                    id: "missing".to_string(),
+                },
+                BatchOp::DeleteNote {
+                    id: "n2".to_string(),
+                },
+            ])
+            .await
+            .unwrap();
+        assert!(!failed.committed);
+        let statuses: Vec<OpStatus> = failed.results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                OpStatus::RolledBack,
+                OpStatus::RolledBack,
+                OpStatus::Failed,
+                OpStatus::Skipped
+            ]
+        );
+        assert_eq!(
+            failed.results[2].error,
+            Some(AppError::not_found(Entity::Note, "missing"))
+        );
+        assert_eq!(db.get_notes().await.unwrap().len(), 2);
+        assert_eq!(db.get_folders().await.unwrap().len(), 2);
+
+        let mut edited = note("n2", None, "w1");
+        edited.content = "edited".to_string();
+        let applied = db
+            .apply_batch(vec![
+                BatchOp::UpsertNote {
+                    note: note("new", None, "w1"),
+                },
+                BatchOp::UpsertNote { note: edited },
+                BatchOp::DeleteFolder {
+                    id: "b".to_string(),
+                },
+            ])
+            .await
+            .unwrap();
+        assert!(applied.committed);
+        let versions: Vec<Option<i32>> = applied.results.iter().map(|r| r.version).collect();
+        assert_eq!(versions, [Some(1), Some(2), None]);
+        assert_eq!(db.get_notes().await.unwrap().len(), 3);
+        assert_eq!(db.get_folders().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upsert_note_keeps_changes_log_in_step() {
+        let db = setup_test_db().await;
+        sqlx::query(
+            "CREATE TRIGGER reject_changes BEFORE INSERT ON changes
+             BEGIN SELECT RAISE(ABORT, 'disk full'); END",
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let err = db
+            .upsert_note(note("n1", None, "default"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Database { .. }), "{:?}", err);
+        let notes: i64 = sqlx::query_scalar("SELECT count(*) FROM notes")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(notes, 0);
+    }
 }
@@ -0,0 +1,691 @@
+//! Document export for readers outside Lumenote: a single note or a whole
+//! folder as PDF, DOCX or EPUB. Note Markdown is parsed once into the small
+//! block model below, which each format renders on its own (see `pdf`,
+//! `docx` and `epub`). Folders become books: every note is a chapter, in the
+//! order the sidebar shows them.
+
+use crate::db::{Db, FolderRecord, NoteRecord};
+use crate::error::{AppError, Entity};
+use crate::grants::{write_atomic, Access, FileGrants};
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use serde::Deserialize;
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentFormat {
+    Pdf,
+    Docx,
+    Epub,
+}
+
+/// A run of text sharing one style.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+    pub strike: bool,
+    pub link: Option<String>,
+}
+
+impl Span {
+    pub fn plain(text: impl Into<String>) -> Self {
+        Span {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    fn same_style(&self, other: &Span) -> bool {
+        self.bold == other.bold
+            && self.italic == other.italic
+            && self.code == other.code
+            && self.strike == other.strike
+            && self.link == other.link
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Heading {
+        level: u8,
+        spans: Vec<Span>,
+    },
+    Paragraph(Vec<Span>),
+    /// `start` is set for ordered lists.
+    List {
+        start: Option<u64>,
+        items: Vec<ListItem>,
+    },
+    Quote(Vec<Block>),
+    Code {
+        lang: String,
+        text: String,
+    },
+    Table {
+        header: Vec<Vec<Span>>,
+        rows: Vec<Vec<Vec<Span>>>,
+    },
+    Rule,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    /// Set for task list items.
+    pub checked: Option<bool>,
+    pub blocks: Vec<Block>,
+}
+
+/// One note. `folders` is the folder path from the exported folder down to
+/// the note, used by formats with a nested table of contents.
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub title: String,
+    pub folders: Vec<String>,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub title: String,
+    pub chapters: Vec<Chapter>,
+}
+
+/// Plain text of a run of spans, for bookmarks and tables of contents.
+pub fn plain_text(spans: &[Span]) -> String {
+    spans.iter().map(|s| s.text.as_str()).collect()
+}
+
+/// Escapes text for XML content and attributes, dropping the control
+/// characters XML 1.0 cannot carry.
+pub fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Packs files into an in-memory ZIP, the container of both DOCX and EPUB.
+/// An entry named `mimetype` is stored uncompressed, as EPUB requires.
+pub fn pack_zip(files: &[(&str, Vec<u8>)]) -> Result<Vec<u8>, AppError> {
+    let zip_error = |e: zip::result::ZipError| AppError::Io {
+        path: None,
+        message: format!("Failed to write document: {}", e),
+    };
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, bytes) in files {
+        let method = if *name == "mimetype" {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        zip.start_file(*name, FileOptions::default().compression_method(method))
+            .map_err(zip_error)?;
+        zip.write_all(bytes).map_err(|e| AppError::io(name, e))?;
+    }
+    Ok(zip.finish().map_err(zip_error)?.into_inner())
+}
+
+/// Parses note Markdown into blocks. Footnotes are collected at the end,
+/// after a rule.
+pub fn parse(markdown: &str) -> Vec<Block> {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS;
+    let mut builder = Builder::default();
+    for event in Parser::new_ext(markdown, options) {
+        builder.event(event);
+    }
+    builder.finish()
+}
+
+enum Frame {
+    Blocks(Vec<Block>),
+    Quote(Vec<Block>),
+    List {
+        start: Option<u64>,
+        items: Vec<ListItem>,
+    },
+    Item {
+        checked: Option<bool>,
+        blocks: Vec<Block>,
+    },
+    Footnote {
+        label: String,
+        blocks: Vec<Block>,
+    },
+    Table {
+        header: Vec<Vec<Span>>,
+        rows: Vec<Vec<Vec<Span>>>,
+        row: Vec<Vec<Span>>,
+    },
+}
+
+/// Turns the flat pulldown-cmark event stream into nested blocks. Open
+/// containers live on `frames`; inline text collects in `spans` until the
+/// enclosing paragraph, heading or cell closes.
+#[derive(Default)]
+struct Builder {
+    frames: Vec<Frame>,
+    spans: Vec<Span>,
+    bold: usize,
+    italic: usize,
+    strike: usize,
+    links: Vec<String>,
+    code: Option<(String, String)>,
+    footnotes: Vec<(String, Vec<Block>)>,
+}
+
+impl Builder {
+    fn blocks(&mut self) -> &mut Vec<Block> {
+        if self.frames.is_empty() {
+            self.frames.push(Frame::Blocks(Vec::new()));
+        }
+        for frame in self.frames.iter_mut().rev() {
+            match frame {
+                Frame::Blocks(blocks)
+                | Frame::Quote(blocks)
+                | Frame::Item { blocks, .. }
+                | Frame::Footnote { blocks, .. } => return blocks,
+                _ => {}
+            }
+        }
+        unreachable!("the root frame holds blocks")
+    }
+
+    fn push_block(&mut self, block: Block) {
+        self.blocks().push(block);
+    }
+
+    fn push_span(&mut self, text: &str, code: bool) {
+        let span = Span {
+            text: text.to_string(),
+            bold: self.bold > 0,
+            italic: self.italic > 0,
+            code,
+            strike: self.strike > 0,
+            link: self.links.last().cloned(),
+        };
+        match self.spans.last_mut() {
+            Some(last) if last.same_style(&span) => last.text.push_str(text),
+            _ => self.spans.push(span),
+        }
+    }
+
+    /// Closes the pending paragraph, e.g. the text of a tight list item
+    /// before a nested list.
+    fn flush(&mut self) {
+        let spans = std::mem::take(&mut self.spans);
+        if spans.iter().any(|s| !s.text.trim().is_empty()) {
+            self.push_block(Block::Paragraph(spans));
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => match &mut self.code {
+                Some((_, code)) => code.push_str(&text),
+                None => self.push_span(&text, false),
+            },
+            Event::Code(text) => self.push_span(&text, true),
+            Event::Html(text) | Event::InlineHtml(text) => self.push_span(&text, false),
+            Event::InlineMath(text) | Event::DisplayMath(text) => self.push_span(&text, true),
+            Event::FootnoteReference(label) => self.push_span(&format!("[{}]", label), false),
+            Event::SoftBreak => self.push_span(" ", false),
+            Event::HardBreak => self.push_span("\n", false),
+            Event::Rule => {
+                self.flush();
+                self.push_block(Block::Rule);
+            }
+            Event::TaskListMarker(checked) => {
+                if let Some(Frame::Item {
+                    checked: marker, ..
+                }) = self.frames.last_mut()
+                {
+                    *marker = Some(checked);
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::Heading { .. } | Tag::HtmlBlock => self.flush(),
+            Tag::BlockQuote(_) => {
+                self.flush();
+                self.frames.push(Frame::Quote(Vec::new()));
+            }
+            Tag::CodeBlock(kind) => {
+                self.flush();
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_string()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((lang, String::new()));
+            }
+            Tag::List(start) => {
+                self.flush();
+                self.frames.push(Frame::List {
+                    start,
+                    items: Vec::new(),
+                });
+            }
+            Tag::Item => {
+                self.flush();
+                self.frames.push(Frame::Item {
+                    checked: None,
+                    blocks: Vec::new(),
+                });
+            }
+            Tag::FootnoteDefinition(label) => {
+                self.flush();
+                self.frames.push(Frame::Footnote {
+                    label: label.to_string(),
+                    blocks: Vec::new(),
+                });
+            }
+            Tag::Table(_) => {
+                self.flush();
+                self.frames.push(Frame::Table {
+                    header: Vec::new(),
+                    rows: Vec::new(),
+                    row: Vec::new(),
+                });
+            }
+            Tag::TableHead | Tag::TableRow | Tag::TableCell => self.spans.clear(),
+            Tag::Emphasis => self.italic += 1,
+            Tag::Strong => self.bold += 1,
+            Tag::Strikethrough => self.strike += 1,
+            Tag::Link { dest_url, .. } => self.links.push(dest_url.to_string()),
+            Tag::Image { .. } => {
+                self.italic += 1;
+                self.push_span("[", false);
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::HtmlBlock => self.flush(),
+            TagEnd::Heading(level) => {
+                let spans = std::mem::take(&mut self.spans);
+                self.push_block(Block::Heading {
+                    level: heading_level(level),
+                    spans,
+                });
+            }
+            TagEnd::BlockQuote(_) => {
+                self.flush();
+                if let Some(Frame::Quote(blocks)) = self.frames.pop() {
+                    self.push_block(Block::Quote(blocks));
+                }
+            }
+            TagEnd::CodeBlock => {
+                if let Some((lang, mut text)) = self.code.take() {
+                    if text.ends_with('\n') {
+                        text.pop();
+                    }
+                    self.push_block(Block::Code { lang, text });
+                }
+            }
+            TagEnd::List(_) => {
+                self.flush();
+                if let Some(Frame::List { start, items }) = self.frames.pop() {
+                    self.push_block(Block::List { start, items });
+                }
+            }
+            TagEnd::Item => {
+                self.flush();
+                if let Some(Frame::Item { checked, blocks }) = self.frames.pop() {
+                    if let Some(Frame::List { items, .. }) = self.frames.last_mut() {
+                        items.push(ListItem { checked, blocks });
+                    }
+                }
+            }
+            TagEnd::FootnoteDefinition => {
+                self.flush();
+                if let Some(Frame::Footnote { label, blocks }) = self.frames.pop() {
+                    self.footnotes.push((label, blocks));
+                }
+            }
+            TagEnd::TableCell => {
+                let cell = std::mem::take(&mut self.spans);
+                if let Some(Frame::Table { row, .. }) = self.frames.last_mut() {
+                    row.push(cell);
+                }
+            }
+            TagEnd::TableHead => {
+                if let Some(Frame::Table { header, row, .. }) = self.frames.last_mut() {
+                    *header = std::mem::take(row);
+                }
+            }
+            TagEnd::TableRow => {
+                if let Some(Frame::Table { rows, row, .. }) = self.frames.last_mut() {
+                    rows.push(std::mem::take(row));
+                }
+            }
+            TagEnd::Table => {
+                if let Some(Frame::Table { header, rows, .. }) = self.frames.pop() {
+                    self.push_block(Block::Table { header, rows });
+                }
+            }
+            TagEnd::Emphasis => self.italic = self.italic.saturating_sub(1),
+            TagEnd::Strong => self.bold = self.bold.saturating_sub(1),
+            TagEnd::Strikethrough => self.strike = self.strike.saturating_sub(1),
+            TagEnd::Link => {
+                self.links.pop();
+            }
+            TagEnd::Image => {
+                self.push_span("]", false);
+                self.italic = self.italic.saturating_sub(1);
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(mut self) -> Vec<Block> {
+        self.flush();
+        let mut blocks = match self.frames.into_iter().next() {
+            Some(Frame::Blocks(blocks)) => blocks,
+            _ => Vec::new(),
+        };
+        if !self.footnotes.is_empty() {
+            blocks.push(Block::Rule);
+            for (label, mut note) in self.footnotes {
+                let marker = Span::plain(format!("[{}] ", label));
+                match note.first_mut() {
+                    Some(Block::Paragraph(spans)) => spans.insert(0, marker),
+                    _ => note.insert(0, Block::Paragraph(vec![marker])),
+                }
+                blocks.extend(note);
+            }
+        }
+        blocks
+    }
+}
+
+fn heading_level(level: HeadingLevel) -> u8 {
+    match level {
+        HeadingLevel::H1 => 1,
+        HeadingLevel::H2 => 2,
+        HeadingLevel::H3 => 3,
+        HeadingLevel::H4 => 4,
+        HeadingLevel::H5 => 5,
+        HeadingLevel::H6 => 6,
+    }
+}
+
+fn chapter(note: &NoteRecord, folders: Vec<String>) -> Chapter {
+    Chapter {
+        title: note.title.clone(),
+        folders,
+        blocks: parse(&note.content),
+    }
+}
+
+/// Collects the notes below `parent` as chapters in sidebar order: child
+/// folders first, then the folder's own notes. `notes` and `folders` come in
+/// `sort_key` order from the database.
+fn collect_chapters(
+    parent: Option<&str>,
+    path: &[String],
+    notes: &[NoteRecord],
+    folders: &[FolderRecord],
+    out: &mut Vec<Chapter>,
+) {
+    for folder in folders.iter().filter(|f| f.parent_id.as_deref() == parent) {
+        let mut child_path = path.to_vec();
+        child_path.push(folder.name.clone());
+        collect_chapters(Some(&folder.id), &child_path, notes, folders, out);
+    }
+    for note in notes.iter().filter(|n| n.folder_id.as_deref() == parent) {
+        out.push(chapter(note, path.to_vec()));
+    }
+}
+
+/// Builds the book for a folder, or for the whole workspace when
+/// `folder_id` is `None`.
+pub fn folder_document(
+    title: &str,
+    folder_id: Option<&str>,
+    notes: &[NoteRecord],
+    folders: &[FolderRecord],
+) -> Document {
+    let mut chapters = Vec::new();
+    collect_chapters(folder_id, &[], notes, folders, &mut chapters);
+    Document {
+        title: title.to_string(),
+        chapters,
+    }
+}
+
+pub fn render(format: DocumentFormat, document: &Document) -> Result<Vec<u8>, AppError> {
+    match format {
+        DocumentFormat::Pdf => crate::pdf::render(document),
+        DocumentFormat::Docx => crate::docx::render(document),
+        DocumentFormat::Epub => crate::epub::render(document),
+    }
+}
+
+pub async fn export_note_document_logic(
+    db: &Db,
+    note_id: String,
+    format: DocumentFormat,
+    path: PathBuf,
+) -> Result<(), AppError> {
+    let note = db
+        .get_notes()
+        .await?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or_else(|| AppError::not_found(Entity::Note, note_id.as_str()))?;
+    let document = Document {
+        title: note.title.clone(),
+        chapters: vec![chapter(&note, Vec::new())],
+    };
+    write_atomic(&path, &render(format, &document)?)
+}
+
+pub async fn export_folder_document_logic(
+    db: &Db,
+    workspace_id: String,
+    folder_id: Option<String>,
+    format: DocumentFormat,
+    path: PathBuf,
+) -> Result<(), AppError> {
+    let notes: Vec<NoteRecord> = db
+        .get_notes()
+        .await?
+        .into_iter()
+        .filter(|n| n.workspace_id == workspace_id)
+        .collect();
+    let folders: Vec<FolderRecord> = db
+        .get_folders()
+        .await?
+        .into_iter()
+        .filter(|f| f.workspace_id == workspace_id)
+        .collect();
+    let title = match &folder_id {
+        Some(id) => folders
+            .iter()
+            .find(|f| &f.id == id)
+            .map(|f| f.name.clone())
+            .ok_or_else(|| AppError::not_found(Entity::Folder, id.as_str()))?,
+        None => db
+            .get_workspaces()
+            .await?
+            .into_iter()
+            .find(|w| w.id == workspace_id)
+            .map(|w| w.name)
+            .ok_or_else(|| AppError::not_found(Entity::Workspace, workspace_id.as_str()))?,
+    };
+
+    let document = folder_document(&title, folder_id.as_deref(), &notes, &folders);
+    if document.chapters.is_empty() {
+        return Err(AppError::validation(
+            "folder_id",
+            "There are no notes to export",
+        ));
+    }
+    write_atomic(&path, &render(format, &document)?)
+}
+
+#[tauri::command]
+pub async fn export_note_document(
+    state: tauri::State<'_, crate::DbState>,
+    grants: tauri::State<'_, FileGrants>,
+    note_id: String,
+    format: DocumentFormat,
+    token: String,
+) -> Result<(), AppError> {
+    let db = state.db()?;
+    let path = grants.redeem(&token, Access::Write)?;
+    export_note_document_logic(&db, note_id, format, path).await
+}
+
+/// Exports a folder as one book; `folder_id: None` exports the workspace.
+#[tauri::command]
+pub async fn export_folder_document(
+    state: tauri::State<'_, crate::DbState>,
+    grants: tauri::State<'_, FileGrants>,
+    workspace_id: String,
+    folder_id: Option<String>,
+    format: DocumentFormat,
+    token: String,
+) -> Result<(), AppError> {
+    let db = state.db()?;
+    let path = grants.redeem(&token, Access::Write)?;
+    export_folder_document_logic(&db, workspace_id, folder_id, format, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(id: &str, name: &str, parent_id: Option<&str>) -> FolderRecord {
+        FolderRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            parent_id: parent_id.map(str::to_string),
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            color: None,
+            sort_key: String::new(),
+            is_deleted: false,
+        }
+    }
+
+    fn note(id: &str, folder_id: Option<&str>) -> NoteRecord {
+        NoteRecord {
+            id: id.to_string(),
+            title: id.to_string(),
+            content: format!("# {}", id),
+            folder_id: folder_id.map(str::to_string),
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_builds_nested_blocks() {
+        let blocks = parse(
+            "# Title\n\nSome **bold** and [a link](https://x.dev).\n\n\
+             - [x] done\n- todo\n  1. nested\n\n\
+             > quoted\n\n```rust\nfn main() {}\n```\n\n\
+             | A | B |\n|---|---|\n| 1 | 2 |\n\nNote[^1].\n\n[^1]: Footnote.\n",
+        );
+
+        assert_eq!(
+            blocks[0],
+            Block::Heading {
+                level: 1,
+                spans: vec![Span::plain("Title")]
+            }
+        );
+        let Block::Paragraph(spans) = &blocks[1] else {
+            panic!("expected a paragraph, got {:?}", blocks[1]);
+        };
+        assert!(spans[1].bold && spans[1].text == "bold");
+        assert_eq!(spans[3].link.as_deref(), Some("https://x.dev"));
+
+        let Block::List { start: None, items } = &blocks[2] else {
+            panic!("expected a bullet list, got {:?}", blocks[2]);
+        };
+        assert_eq!(items[0].checked, Some(true));
+        assert!(matches!(
+            items[1].blocks[1],
+            Block::List { start: Some(1), .. }
+        ));
+
+        assert!(matches!(&blocks[3], Block::Quote(q) if q.len() == 1));
+        assert_eq!(
+            blocks[4],
+            Block::Code {
+                lang: "rust".to_string(),
+                text: "fn main() {}".to_string()
+            }
+        );
+        assert!(
+            matches!(&blocks[5], Block::Table { header, rows } if header.len() == 2 && rows.len() == 1)
+        );
+        assert_eq!(blocks[7], Block::Rule);
+        assert_eq!(
+            plain_text(match &blocks[8] {
+                Block::Paragraph(spans) => spans,
+                other => panic!("expected the footnote, got {:?}", other),
+            }),
+            "[1] Footnote."
+        );
+    }
+
+    #[test]
+    fn test_folder_chapters_follow_sidebar_order() {
+        let folders = vec![
+            folder("book", "Book", None),
+            folder("part", "Part", Some("book")),
+        ];
+        let notes = vec![
+            note("intro", Some("book")),
+            note("deep", Some("part")),
+            note("outside", None),
+        ];
+
+        let document = folder_document("Book", Some("book"), &notes, &folders);
+        let titles: Vec<&str> = document.chapters.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, ["deep", "intro"]);
+        assert_eq!(document.chapters[0].folders, ["Part"]);
+        assert!(document.chapters[1].folders.is_empty());
+
+        let workspace = folder_document("All", None, &notes, &folders);
+        assert_eq!(workspace.chapters.len(), 3);
+        assert_eq!(workspace.chapters[2].title, "outside");
+    }
+}
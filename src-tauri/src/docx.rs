@@ -0,0 +1,534 @@
+//! DOCX rendering for document export. The package is written by hand: a
+//! handful of WordprocessingML parts in a ZIP. Headings use Word's built-in
+//! heading styles so the navigation pane and generated tables of contents
+//! work, lists use real numbering, and every note starts on a new page.
+
+use crate::document::{escape_xml, pack_zip, Block, Document, ListItem, Span};
+use crate::error::AppError;
+
+const W_NS: &str = "http://schemas.openxmlformats.org/wordprocessingml/2006/main";
+const R_NS: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const REL_NS: &str = "http://schemas.openxmlformats.org/package/2006/relationships";
+
+/// Relationship ids taken by the fixed parts; hyperlinks follow.
+const FIXED_RELATIONSHIPS: usize = 2;
+
+const BULLET_LIST: usize = 0;
+const NUMBERED_LIST: usize = 1;
+
+/// Where paragraphs are being written: inside a quote, or as the
+/// continuation of a list item.
+#[derive(Clone, Copy, Default)]
+struct Context {
+    quote: bool,
+    /// Nesting depth of the enclosing list, if any.
+    list_depth: Option<usize>,
+}
+
+/// A `w:num` instance: which abstract numbering it uses and, for ordered
+/// lists, where its level restarts.
+struct Numbering {
+    abstract_id: usize,
+    level: usize,
+    start: Option<u64>,
+}
+
+#[derive(Default)]
+struct Writer {
+    body: String,
+    links: Vec<String>,
+    numberings: Vec<Numbering>,
+    page_break: bool,
+}
+
+impl Writer {
+    /// Opens a paragraph. The first paragraph of every chapter after the
+    /// first one starts a new page.
+    fn open_paragraph(
+        &mut self,
+        style: Option<&str>,
+        numbering: Option<(usize, usize)>,
+        indent: Option<usize>,
+    ) {
+        self.body.push_str("<w:p><w:pPr>");
+        if let Some(style) = style {
+            self.body
+                .push_str(&format!("<w:pStyle w:val=\"{}\"/>", style));
+        }
+        if std::mem::take(&mut self.page_break) {
+            self.body.push_str("<w:pageBreakBefore/>");
+        }
+        if let Some((level, id)) = numbering {
+            self.body.push_str(&format!(
+                "<w:numPr><w:ilvl w:val=\"{}\"/><w:numId w:val=\"{}\"/></w:numPr>",
+                level, id
+            ));
+        }
+        if let Some(depth) = indent {
+            self.body
+                .push_str(&format!("<w:ind w:left=\"{}\"/>", 720 * (depth + 1)));
+        }
+        self.body.push_str("</w:pPr>");
+    }
+
+    fn paragraph(&mut self, style: Option<&str>, spans: &[Span], context: Context) {
+        let style = style.or(if context.quote { Some("Quote") } else { None });
+        self.open_paragraph(style, None, context.list_depth);
+        self.runs(spans, false);
+        self.body.push_str("</w:p>");
+    }
+
+    fn runs(&mut self, spans: &[Span], bold: bool) {
+        for span in spans {
+            let external = span.link.as_deref().filter(|l| l.contains(':'));
+            if let Some(link) = external {
+                self.links.push(link.to_string());
+                let id = FIXED_RELATIONSHIPS + self.links.len();
+                self.body
+                    .push_str(&format!("<w:hyperlink r:id=\"rId{}\">", id));
+            }
+            self.run(span, bold, external.is_some());
+            if external.is_some() {
+                self.body.push_str("</w:hyperlink>");
+            }
+        }
+    }
+
+    fn run(&mut self, span: &Span, bold: bool, link: bool) {
+        let mut props = String::new();
+        if span.code {
+            props.push_str("<w:rStyle w:val=\"CodeChar\"/>");
+        } else if link {
+            props.push_str("<w:rStyle w:val=\"Hyperlink\"/>");
+        }
+        if span.bold || bold {
+            props.push_str("<w:b/>");
+        }
+        if span.italic {
+            props.push_str("<w:i/>");
+        }
+        if span.strike {
+            props.push_str("<w:strike/>");
+        }
+        self.body.push_str("<w:r>");
+        if !props.is_empty() {
+            self.body.push_str(&format!("<w:rPr>{}</w:rPr>", props));
+        }
+        for (i, line) in span.text.split('\n').enumerate() {
+            if i > 0 {
+                self.body.push_str("<w:br/>");
+            }
+            if !line.is_empty() {
+                self.body.push_str(&format!(
+                    "<w:t xml:space=\"preserve\">{}</w:t>",
+                    escape_xml(line)
+                ));
+            }
+        }
+        self.body.push_str("</w:r>");
+    }
+
+    /// `level` is the heading level of the chapter title, note headings
+    /// nest below it.
+    fn blocks(&mut self, blocks: &[Block], level: usize, context: Context) {
+        for block in blocks {
+            self.block(block, level, context);
+        }
+    }
+
+    fn block(&mut self, block: &Block, level: usize, context: Context) {
+        match block {
+            Block::Heading { level: h, spans } => {
+                let style = heading_style(level + usize::from(*h));
+                self.paragraph(Some(&style), spans, context);
+            }
+            Block::Paragraph(spans) => self.paragraph(None, spans, context),
+            Block::List { start, items } => {
+                let depth = context.list_depth.map_or(0, |d| d + 1).min(8);
+                let id = self.numbering(*start, depth);
+                for item in items {
+                    self.list_item(item, id, depth, level, context);
+                }
+            }
+            Block::Quote(blocks) => self.blocks(
+                blocks,
+                level,
+                Context {
+                    quote: true,
+                    ..context
+                },
+            ),
+            Block::Code { text, .. } => {
+                let span = Span {
+                    text: text.clone(),
+                    ..Default::default()
+                };
+                self.open_paragraph(Some("Code"), None, context.list_depth);
+                self.run(&span, false, false);
+                self.body.push_str("</w:p>");
+            }
+            Block::Table { header, rows } => self.table(header, rows),
+            Block::Rule => {
+                self.open_paragraph(Some("Rule"), None, context.list_depth);
+                self.body.push_str("</w:p>");
+            }
+        }
+    }
+
+    /// Registers a list instance. Bullet lists share one; every ordered
+    /// list gets its own so its numbering restarts.
+    fn numbering(&mut self, start: Option<u64>, level: usize) -> usize {
+        if start.is_none() {
+            if let Some(i) = self
+                .numberings
+                .iter()
+                .position(|n| n.abstract_id == BULLET_LIST)
+            {
+                return i + 1;
+            }
+        }
+        self.numberings.push(Numbering {
+            abstract_id: if start.is_some() {
+                NUMBERED_LIST
+            } else {
+                BULLET_LIST
+            },
+            level,
+            start,
+        });
+        self.numberings.len()
+    }
+
+    fn list_item(
+        &mut self,
+        item: &ListItem,
+        id: usize,
+        depth: usize,
+        level: usize,
+        context: Context,
+    ) {
+        let style = if context.quote {
+            "Quote"
+        } else {
+            "ListParagraph"
+        };
+        let mut rest = item.blocks.as_slice();
+        // The item's first paragraph carries the number or checkbox
+        let first = match rest.first() {
+            Some(Block::Paragraph(spans)) => {
+                rest = &rest[1..];
+                spans.as_slice()
+            }
+            _ => &[],
+        };
+        match item.checked {
+            Some(checked) => {
+                self.open_paragraph(Some(style), None, Some(depth));
+                let mark = if checked { "☒ " } else { "☐ " };
+                self.run(&Span::plain(mark), false, false);
+            }
+            None => self.open_paragraph(Some(style), Some((depth, id)), None),
+        }
+        self.runs(first, false);
+        self.body.push_str("</w:p>");
+        self.blocks(
+            rest,
+            level,
+            Context {
+                list_depth: Some(depth),
+                ..context
+            },
+        );
+    }
+
+    fn table(&mut self, header: &[Vec<Span>], rows: &[Vec<Vec<Span>>]) {
+        let columns = rows.iter().map(Vec::len).fold(header.len(), usize::max);
+        if columns == 0 {
+            return;
+        }
+        self.body.push_str(
+            "<w:tbl><w:tblPr><w:tblStyle w:val=\"TableGrid\"/>\
+             <w:tblW w:w=\"5000\" w:type=\"pct\"/></w:tblPr><w:tblGrid>",
+        );
+        for _ in 0..columns {
+            self.body
+                .push_str(&format!("<w:gridCol w:w=\"{}\"/>", 9638 / columns));
+        }
+        self.body.push_str("</w:tblGrid>");
+        for (i, row) in std::iter::once(header)
+            .chain(rows.iter().map(Vec::as_slice))
+            .enumerate()
+        {
+            self.body.push_str("<w:tr>");
+            if i == 0 {
+                self.body.push_str("<w:trPr><w:tblHeader/></w:trPr>");
+            }
+            for column in 0..columns {
+                self.body.push_str("<w:tc><w:p>");
+                if let Some(cell) = row.get(column) {
+                    self.runs(cell, i == 0);
+                }
+                self.body.push_str("</w:p></w:tc>");
+            }
+            self.body.push_str("</w:tr>");
+        }
+        self.body.push_str("</w:tbl>");
+        // Word merges a table into a directly following one
+        self.body.push_str("<w:p/>");
+    }
+}
+
+fn heading_style(level: usize) -> String {
+    format!("Heading{}", level.clamp(1, 9))
+}
+
+pub fn render(document: &Document) -> Result<Vec<u8>, AppError> {
+    let mut writer = Writer::default();
+    let mut folders: &[String] = &[];
+    for (i, chapter) in document.chapters.iter().enumerate() {
+        writer.page_break = i > 0;
+        // Headings for the folders this chapter opens
+        let shared = folders
+            .iter()
+            .zip(&chapter.folders)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, name) in chapter.folders.iter().enumerate().skip(shared) {
+            writer.paragraph(
+                Some(&heading_style(depth + 1)),
+                &[Span::plain(name.as_str())],
+                Context::default(),
+            );
+        }
+        folders = &chapter.folders;
+
+        let level = chapter.folders.len() + 1;
+        writer.paragraph(
+            Some(&heading_style(level)),
+            &[Span::plain(chapter.title.as_str())],
+            Context::default(),
+        );
+        writer.blocks(&chapter.blocks, level, Context::default());
+    }
+
+    let document_xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <w:document xmlns:w=\"{}\" xmlns:r=\"{}\"><w:body>{}\
+         <w:sectPr><w:pgSz w:w=\"11906\" w:h=\"16838\"/>\
+         <w:pgMar w:top=\"1134\" w:right=\"1134\" w:bottom=\"1134\" w:left=\"1134\" \
+         w:header=\"709\" w:footer=\"709\" w:gutter=\"0\"/></w:sectPr>\
+         </w:body></w:document>",
+        W_NS, R_NS, writer.body
+    );
+
+    let mut relationships = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <Relationships xmlns=\"{}\">\
+         <Relationship Id=\"rId1\" Type=\"{}/styles\" Target=\"styles.xml\"/>\
+         <Relationship Id=\"rId2\" Type=\"{}/numbering\" Target=\"numbering.xml\"/>",
+        REL_NS, R_NS, R_NS
+    );
+    for (i, link) in writer.links.iter().enumerate() {
+        relationships.push_str(&format!(
+            "<Relationship Id=\"rId{}\" Type=\"{}/hyperlink\" Target=\"{}\" TargetMode=\"External\"/>",
+            FIXED_RELATIONSHIPS + i + 1,
+            R_NS,
+            escape_xml(link)
+        ));
+    }
+    relationships.push_str("</Relationships>");
+
+    let core = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <cp:coreProperties \
+         xmlns:cp=\"http://schemas.openxmlformats.org/package/2006/metadata/core-properties\" \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\
+         <dc:title>{}</dc:title><dc:creator>Lumenote</dc:creator></cp:coreProperties>",
+        escape_xml(&document.title)
+    );
+
+    pack_zip(&[
+        ("[Content_Types].xml", CONTENT_TYPES.as_bytes().to_vec()),
+        ("_rels/.rels", PACKAGE_RELATIONSHIPS.as_bytes().to_vec()),
+        ("docProps/core.xml", core.into_bytes()),
+        ("word/document.xml", document_xml.into_bytes()),
+        ("word/_rels/document.xml.rels", relationships.into_bytes()),
+        ("word/styles.xml", styles_xml().into_bytes()),
+        (
+            "word/numbering.xml",
+            numbering_xml(&writer.numberings).into_bytes(),
+        ),
+    ])
+}
+
+fn styles_xml() -> String {
+    let mut headings = String::new();
+    let sizes = [36, 30, 26, 24, 22, 22, 22, 22, 22];
+    for (i, size) in sizes.iter().enumerate() {
+        headings.push_str(&format!(
+            "<w:style w:type=\"paragraph\" w:styleId=\"Heading{n}\">\
+             <w:name w:val=\"heading {n}\"/><w:basedOn w:val=\"Normal\"/>\
+             <w:next w:val=\"Normal\"/><w:qFormat/>\
+             <w:pPr><w:keepNext/><w:keepLines/><w:spacing w:before=\"{before}\" w:after=\"120\"/>\
+             <w:outlineLvl w:val=\"{level}\"/></w:pPr>\
+             <w:rPr><w:b/><w:color w:val=\"1F2328\"/><w:sz w:val=\"{size}\"/></w:rPr></w:style>",
+            n = i + 1,
+            level = i,
+            before = if i == 0 { 0 } else { 240 },
+            size = size
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <w:styles xmlns:w=\"{}\">\
+         <w:docDefaults><w:rPrDefault><w:rPr>\
+         <w:rFonts w:ascii=\"Calibri\" w:hAnsi=\"Calibri\" w:eastAsia=\"Calibri\" w:cs=\"Calibri\"/>\
+         <w:sz w:val=\"22\"/><w:szCs w:val=\"22\"/></w:rPr></w:rPrDefault>\
+         <w:pPrDefault><w:pPr><w:spacing w:after=\"120\" w:line=\"276\" w:lineRule=\"auto\"/></w:pPr></w:pPrDefault>\
+         </w:docDefaults>\
+         <w:style w:type=\"paragraph\" w:default=\"1\" w:styleId=\"Normal\"><w:name w:val=\"Normal\"/><w:qFormat/></w:style>\
+         {}\
+         <w:style w:type=\"paragraph\" w:styleId=\"ListParagraph\"><w:name w:val=\"List Paragraph\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:spacing w:after=\"40\"/><w:ind w:left=\"720\"/></w:pPr></w:style>\
+         <w:style w:type=\"paragraph\" w:styleId=\"Quote\"><w:name w:val=\"Quote\"/><w:basedOn w:val=\"Normal\"/>\
+         <w:pPr><w:pBdr><w:left w:val=\"single\" w:sz=\"18\" w:space=\"8\" w:color=\"D0D7DE\"/></w:pBdr>\
+         <w:ind w:left=\"360\"/></w:pPr><w:rPr><w:i/><w:color w:val=\"6B7280\"/></w:rPr></w:style>\
+         <w:style w:type=\"paragraph\" w:styleId=\"Code\"><w:name w:val=\"Code\"/><w:basedOn w:val=\"Normal\"/>\
+         <w:pPr><w:shd w:val=\"clear\" w:color=\"auto\" w:fill=\"F3F4F6\"/><w:spacing w:after=\"160\" w:line=\"240\" w:lineRule=\"auto\"/></w:pPr>\
+         <w:rPr><w:rFonts w:ascii=\"Consolas\" w:hAnsi=\"Consolas\" w:cs=\"Consolas\"/><w:sz w:val=\"19\"/></w:rPr></w:style>\
+         <w:style w:type=\"paragraph\" w:styleId=\"Rule\"><w:name w:val=\"Horizontal Rule\"/><w:basedOn w:val=\"Normal\"/>\
+         <w:pPr><w:pBdr><w:bottom w:val=\"single\" w:sz=\"6\" w:space=\"1\" w:color=\"D0D7DE\"/></w:pBdr></w:pPr></w:style>\
+         <w:style w:type=\"character\" w:styleId=\"CodeChar\"><w:name w:val=\"Code Char\"/>\
+         <w:rPr><w:rFonts w:ascii=\"Consolas\" w:hAnsi=\"Consolas\" w:cs=\"Consolas\"/><w:sz w:val=\"20\"/>\
+         <w:shd w:val=\"clear\" w:color=\"auto\" w:fill=\"F3F4F6\"/></w:rPr></w:style>\
+         <w:style w:type=\"character\" w:styleId=\"Hyperlink\"><w:name w:val=\"Hyperlink\"/>\
+         <w:rPr><w:color w:val=\"0563C1\"/><w:u w:val=\"single\"/></w:rPr></w:style>\
+         <w:style w:type=\"table\" w:styleId=\"TableGrid\"><w:name w:val=\"Table Grid\"/>\
+         <w:pPr><w:spacing w:after=\"0\"/></w:pPr><w:tblPr><w:tblBorders>\
+         <w:top w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"D0D7DE\"/>\
+         <w:left w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"D0D7DE\"/>\
+         <w:bottom w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"D0D7DE\"/>\
+         <w:right w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"D0D7DE\"/>\
+         <w:insideH w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"D0D7DE\"/>\
+         <w:insideV w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"D0D7DE\"/>\
+         </w:tblBorders><w:tblCellMar><w:left w:w=\"108\" w:type=\"dxa\"/><w:right w:w=\"108\" w:type=\"dxa\"/></w:tblCellMar>\
+         </w:tblPr></w:style>\
+         </w:styles>",
+        W_NS, headings
+    )
+}
+
+fn numbering_xml(numberings: &[Numbering]) -> String {
+    let bullets = ["•", "◦", "▪"];
+    let mut bullet_levels = String::new();
+    let mut number_levels = String::new();
+    for level in 0..9 {
+        let indent = format!(
+            "<w:pPr><w:ind w:left=\"{}\" w:hanging=\"360\"/></w:pPr>",
+            720 * (level + 1)
+        );
+        bullet_levels.push_str(&format!(
+            "<w:lvl w:ilvl=\"{}\"><w:start w:val=\"1\"/><w:numFmt w:val=\"bullet\"/>\
+             <w:lvlText w:val=\"{}\"/><w:lvlJc w:val=\"left\"/>{}</w:lvl>",
+            level,
+            bullets[level % bullets.len()],
+            indent
+        ));
+        number_levels.push_str(&format!(
+            "<w:lvl w:ilvl=\"{}\"><w:start w:val=\"1\"/><w:numFmt w:val=\"decimal\"/>\
+             <w:lvlText w:val=\"%{}.\"/><w:lvlJc w:val=\"left\"/>{}</w:lvl>",
+            level,
+            level + 1,
+            indent
+        ));
+    }
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+         <w:numbering xmlns:w=\"{}\">\
+         <w:abstractNum w:abstractNumId=\"{}\"><w:multiLevelType w:val=\"hybridMultilevel\"/>{}</w:abstractNum>\
+         <w:abstractNum w:abstractNumId=\"{}\"><w:multiLevelType w:val=\"hybridMultilevel\"/>{}</w:abstractNum>",
+        W_NS, BULLET_LIST, bullet_levels, NUMBERED_LIST, number_levels
+    );
+    for (i, numbering) in numberings.iter().enumerate() {
+        xml.push_str(&format!(
+            "<w:num w:numId=\"{}\"><w:abstractNumId w:val=\"{}\"/>",
+            i + 1,
+            numbering.abstract_id
+        ));
+        if let Some(start) = numbering.start {
+            xml.push_str(&format!(
+                "<w:lvlOverride w:ilvl=\"{}\"><w:startOverride w:val=\"{}\"/></w:lvlOverride>",
+                numbering.level, start
+            ));
+        }
+        xml.push_str("</w:num>");
+    }
+    xml.push_str("</w:numbering>");
+    xml
+}
+
+const CONTENT_TYPES: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+<Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\
+<Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+<Override PartName=\"/word/document.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml\"/>\
+<Override PartName=\"/word/styles.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml\"/>\
+<Override PartName=\"/word/numbering.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml\"/>\
+<Override PartName=\"/docProps/core.xml\" ContentType=\"application/vnd.openxmlformats-package.core-properties+xml\"/>\
+</Types>";
+
+const PACKAGE_RELATIONSHIPS: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+<Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"word/document.xml\"/>\
+<Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties\" Target=\"docProps/core.xml\"/>\
+</Relationships>";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{parse, Chapter};
+    use std::io::Read;
+
+    fn part(docx: &[u8], name: &str) -> String {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(docx)).unwrap();
+        let mut xml = String::new();
+        zip.by_name(name).unwrap().read_to_string(&mut xml).unwrap();
+        xml
+    }
+
+    #[test]
+    fn test_docx_uses_heading_styles_numbering_and_tables() {
+        let document = Document {
+            title: "Guides".to_string(),
+            chapters: vec![
+                Chapter {
+                    title: "Setup".to_string(),
+                    folders: Vec::new(),
+                    blocks: parse("# Install <now>\n\n1. one\n2. two\n\n3. three\n\n| a | b |\n|---|---|\n| 1 | [x](https://x.dev) |\n"),
+                },
+                Chapter {
+                    title: "Usage".to_string(),
+                    folders: Vec::new(),
+                    blocks: parse("5. five\n6. six\n"),
+                },
+            ],
+        };
+
+        let docx = render(&document).unwrap();
+        let body = part(&docx, "word/document.xml");
+        assert!(body.contains(
+            "<w:pStyle w:val=\"Heading1\"/></w:pPr><w:r><w:t xml:space=\"preserve\">Setup"
+        ));
+        assert!(body.contains("<w:pStyle w:val=\"Heading2\"/></w:pPr><w:r><w:t xml:space=\"preserve\">Install &lt;now&gt;"));
+        assert!(body.contains("<w:pStyle w:val=\"Heading1\"/><w:pageBreakBefore/>"));
+        assert_eq!(body.matches("<w:tbl>").count(), 1);
+        assert!(body.contains("<w:hyperlink r:id=\"rId3\">"));
+        assert!(part(&docx, "word/_rels/document.xml.rels").contains("Target=\"https://x.dev\""));
+
+        let numbering = part(&docx, "word/numbering.xml");
+        assert!(numbering.contains("<w:num w:numId=\"1\"><w:abstractNumId w:val=\"1\"/><w:lvlOverride w:ilvl=\"0\"><w:startOverride w:val=\"1\"/>"));
+        assert!(numbering.contains("<w:num w:numId=\"2\"><w:abstractNumId w:val=\"1\"/><w:lvlOverride w:ilvl=\"0\"><w:startOverride w:val=\"5\"/>"));
+        assert!(part(&docx, "word/styles.xml").contains("<w:outlineLvl w:val=\"8\"/>"));
+    }
+}
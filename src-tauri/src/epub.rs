@@ -0,0 +1,382 @@
+//! EPUB rendering for document export. Each note is an XHTML chapter in an
+//! EPUB 3 package; folders below the exported one become nested sections of
+//! the table of contents. An NCX table of contents is included as well for
+//! EPUB 2 readers.
+
+use crate::document::{escape_xml, pack_zip, Block, Chapter, Document, Span};
+use crate::error::AppError;
+
+fn chapter_file(index: usize) -> String {
+    format!("chapter-{:03}.xhtml", index + 1)
+}
+
+fn spans_html(spans: &[Span], out: &mut String) {
+    for span in spans {
+        let link = span.link.as_deref().filter(|l| l.contains(':'));
+        let mut open = String::new();
+        let mut close = String::new();
+        let mut wrap = |tag: &str, attrs: String| {
+            open.push_str(&format!("<{}{}>", tag, attrs));
+            close.insert_str(0, &format!("</{}>", tag));
+        };
+        if let Some(link) = link {
+            wrap("a", format!(" href=\"{}\"", escape_xml(link)));
+        }
+        if span.bold {
+            wrap("strong", String::new());
+        }
+        if span.italic {
+            wrap("em", String::new());
+        }
+        if span.strike {
+            wrap("del", String::new());
+        }
+        if span.code {
+            wrap("code", String::new());
+        }
+        let text = escape_xml(&span.text).replace('\n', "<br/>");
+        out.push_str(&open);
+        out.push_str(&text);
+        out.push_str(&close);
+    }
+}
+
+/// Renders blocks as XHTML. Note headings move down a level, below the
+/// chapter title.
+fn blocks_html(blocks: &[Block], out: &mut String) {
+    for block in blocks {
+        match block {
+            Block::Heading { level, spans } => {
+                let level = (level + 1).min(6);
+                out.push_str(&format!("<h{}>", level));
+                spans_html(spans, out);
+                out.push_str(&format!("</h{}>\n", level));
+            }
+            Block::Paragraph(spans) => {
+                out.push_str("<p>");
+                spans_html(spans, out);
+                out.push_str("</p>\n");
+            }
+            Block::List { start, items } => {
+                let tag = match start {
+                    Some(1) => {
+                        out.push_str("<ol>\n");
+                        "ol"
+                    }
+                    Some(n) => {
+                        out.push_str(&format!("<ol start=\"{}\">\n", n));
+                        "ol"
+                    }
+                    None => {
+                        out.push_str("<ul>\n");
+                        "ul"
+                    }
+                };
+                for item in items {
+                    match item.checked {
+                        Some(true) => out.push_str("<li class=\"task\">☑ "),
+                        Some(false) => out.push_str("<li class=\"task\">☐ "),
+                        None => out.push_str("<li>"),
+                    }
+                    blocks_html(&item.blocks, out);
+                    out.push_str("</li>\n");
+                }
+                out.push_str(&format!("</{}>\n", tag));
+            }
+            Block::Quote(blocks) => {
+                out.push_str("<blockquote>\n");
+                blocks_html(blocks, out);
+                out.push_str("</blockquote>\n");
+            }
+            Block::Code { lang, text } if !lang.is_empty() => out.push_str(&format!(
+                "<pre><code class=\"language-{}\">{}</code></pre>\n",
+                escape_xml(lang),
+                escape_xml(text)
+            )),
+            Block::Code { text, .. } => {
+                out.push_str(&format!("<pre><code>{}</code></pre>\n", escape_xml(text)));
+            }
+            Block::Table { header, rows } => {
+                out.push_str("<table>\n<thead><tr>");
+                for cell in header {
+                    out.push_str("<th>");
+                    spans_html(cell, out);
+                    out.push_str("</th>");
+                }
+                out.push_str("</tr></thead>\n<tbody>\n");
+                for row in rows {
+                    out.push_str("<tr>");
+                    for cell in row {
+                        out.push_str("<td>");
+                        spans_html(cell, out);
+                        out.push_str("</td>");
+                    }
+                    out.push_str("</tr>\n");
+                }
+                out.push_str("</tbody>\n</table>\n");
+            }
+            Block::Rule => out.push_str("<hr/>\n"),
+        }
+    }
+}
+
+fn chapter_xhtml(chapter: &Chapter) -> String {
+    let mut body = String::new();
+    blocks_html(&chapter.blocks, &mut body);
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n\
+         <head><meta charset=\"UTF-8\"/><title>{title}</title>\
+         <link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/></head>\n\
+         <body>\n<section epub:type=\"chapter\">\n<h1>{title}</h1>\n{body}</section>\n</body>\n</html>\n",
+        title = escape_xml(&chapter.title),
+        body = body
+    )
+}
+
+/// Walks the chapters in order, calling `open` for every folder a chapter
+/// enters, `close` for every folder it leaves and `entry` for the chapter
+/// itself. Both tables of contents are built from this walk.
+fn walk_toc(
+    chapters: &[Chapter],
+    mut open: impl FnMut(&str, usize),
+    mut close: impl FnMut(),
+    mut entry: impl FnMut(&Chapter, usize),
+) {
+    let mut folders: &[String] = &[];
+    for (i, chapter) in chapters.iter().enumerate() {
+        let shared = folders
+            .iter()
+            .zip(&chapter.folders)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for _ in shared..folders.len() {
+            close();
+        }
+        for name in &chapter.folders[shared..] {
+            open(name, i);
+        }
+        folders = &chapter.folders;
+        entry(chapter, i);
+    }
+    for _ in 0..folders.len() {
+        close();
+    }
+}
+
+fn nav_xhtml(document: &Document) -> String {
+    let toc = std::cell::RefCell::new(String::new());
+    walk_toc(
+        &document.chapters,
+        |name, _| {
+            toc.borrow_mut()
+                .push_str(&format!("<li><span>{}</span>\n<ol>\n", escape_xml(name)))
+        },
+        || toc.borrow_mut().push_str("</ol>\n</li>\n"),
+        |chapter, i| {
+            toc.borrow_mut().push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                chapter_file(i),
+                escape_xml(&chapter.title)
+            ))
+        },
+    );
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n\
+         <head><meta charset=\"UTF-8\"/><title>{title}</title>\
+         <link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/></head>\n\
+         <body>\n<nav epub:type=\"toc\" id=\"toc\">\n<h1>{title}</h1>\n<ol>\n{toc}</ol>\n</nav>\n</body>\n</html>\n",
+        title = escape_xml(&document.title),
+        toc = toc.into_inner()
+    )
+}
+
+fn toc_ncx(document: &Document, id: &str) -> String {
+    let points = std::cell::RefCell::new(String::new());
+    let count = std::cell::Cell::new(0);
+    // Points sharing a target share its play order, as the NCX spec asks
+    let point = |label: &str, index: usize| {
+        count.set(count.get() + 1);
+        format!(
+            "<navPoint id=\"nav-{}\" playOrder=\"{}\"><navLabel><text>{}</text></navLabel>\
+             <content src=\"{}\"/>",
+            count.get(),
+            index + 1,
+            escape_xml(label),
+            chapter_file(index)
+        )
+    };
+    walk_toc(
+        &document.chapters,
+        // A folder points at its first chapter
+        |name, i| {
+            points
+                .borrow_mut()
+                .push_str(&format!("{}\n", point(name, i)))
+        },
+        || points.borrow_mut().push_str("</navPoint>\n"),
+        |chapter, i| {
+            points
+                .borrow_mut()
+                .push_str(&format!("{}</navPoint>\n", point(&chapter.title, i)))
+        },
+    );
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\n\
+         <head><meta name=\"dtb:uid\" content=\"urn:uuid:{}\"/></head>\n\
+         <docTitle><text>{}</text></docTitle>\n<navMap>\n{}</navMap>\n</ncx>\n",
+        id,
+        escape_xml(&document.title),
+        points.into_inner()
+    )
+}
+
+fn content_opf(document: &Document, id: &str) -> String {
+    let now = time::OffsetDateTime::now_utc();
+    let modified = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    );
+    let mut manifest = String::new();
+    let mut spine = String::new();
+    for i in 0..document.chapters.len() {
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{n}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+            chapter_file(i),
+            n = i + 1
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{}\"/>\n", i + 1));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n\
+         <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+         <dc:identifier id=\"book-id\">urn:uuid:{id}</dc:identifier>\n\
+         <dc:title>{title}</dc:title>\n\
+         <dc:language>und</dc:language>\n\
+         <dc:creator>Lumenote</dc:creator>\n\
+         <meta property=\"dcterms:modified\">{modified}</meta>\n\
+         </metadata>\n<manifest>\n\
+         <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+         <item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>\n\
+         <item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n\
+         {manifest}</manifest>\n<spine toc=\"ncx\">\n{spine}</spine>\n</package>\n",
+        id = id,
+        title = escape_xml(&document.title),
+        modified = modified,
+        manifest = manifest,
+        spine = spine
+    )
+}
+
+pub fn render(document: &Document) -> Result<Vec<u8>, AppError> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let chapters: Vec<(String, Vec<u8>)> = document
+        .chapters
+        .iter()
+        .enumerate()
+        .map(|(i, chapter)| {
+            (
+                format!("OEBPS/{}", chapter_file(i)),
+                chapter_xhtml(chapter).into_bytes(),
+            )
+        })
+        .collect();
+
+    // The mimetype entry has to come first
+    let mut files: Vec<(&str, Vec<u8>)> = vec![
+        ("mimetype", b"application/epub+zip".to_vec()),
+        ("META-INF/container.xml", CONTAINER_XML.as_bytes().to_vec()),
+        ("OEBPS/content.opf", content_opf(document, &id).into_bytes()),
+        ("OEBPS/nav.xhtml", nav_xhtml(document).into_bytes()),
+        ("OEBPS/toc.ncx", toc_ncx(document, &id).into_bytes()),
+        ("OEBPS/style.css", STYLE_CSS.as_bytes().to_vec()),
+    ];
+    files.extend(
+        chapters
+            .iter()
+            .map(|(name, bytes)| (name.as_str(), bytes.clone())),
+    );
+    pack_zip(&files)
+}
+
+const CONTAINER_XML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+<rootfiles><rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/></rootfiles>\n\
+</container>\n";
+
+const STYLE_CSS: &str = "body { line-height: 1.5; }
+h1, h2, h3, h4, h5, h6 { line-height: 1.25; page-break-after: avoid; }
+pre { white-space: pre-wrap; background: #f3f4f6; padding: 0.6em 0.8em; border-radius: 4px; font-size: 0.85em; }
+code { font-family: monospace; }
+blockquote { margin-left: 0; padding-left: 1em; border-left: 3px solid #d0d7de; color: #555; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #d0d7de; padding: 0.3em 0.6em; }
+li.task { list-style: none; }
+li > p { margin: 0.2em 0; }
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::parse;
+    use std::io::Read;
+
+    fn chapter(title: &str, folders: &[&str]) -> Chapter {
+        Chapter {
+            title: title.to_string(),
+            folders: folders.iter().map(|f| f.to_string()).collect(),
+            blocks: parse("# Heading\n\nText & more\n"),
+        }
+    }
+
+    #[test]
+    fn test_epub_packages_chapters_with_nested_toc() {
+        let document = Document {
+            title: "Book".to_string(),
+            chapters: vec![
+                chapter("One", &["Part"]),
+                chapter("Two", &["Part", "Sub"]),
+                chapter("Three", &[]),
+            ],
+        };
+
+        let epub = render(&document).unwrap();
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(epub)).unwrap();
+        let mimetype = zip.by_index(0).unwrap();
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), zip::CompressionMethod::Stored);
+        drop(mimetype);
+
+        let mut read = |name: &str| {
+            let mut text = String::new();
+            zip.by_name(name)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+        let chapter = read("OEBPS/chapter-002.xhtml");
+        assert!(chapter.contains("<h1>Two</h1>\n<h2>Heading</h2>\n<p>Text &amp; more</p>"));
+
+        let nav = read("OEBPS/nav.xhtml");
+        assert!(nav.contains(
+            "<li><span>Part</span>\n<ol>\n<li><a href=\"chapter-001.xhtml\">One</a></li>\n\
+             <li><span>Sub</span>\n<ol>\n<li><a href=\"chapter-002.xhtml\">Two</a></li>\n\
+             </ol>\n</li>\n</ol>\n</li>\n<li><a href=\"chapter-003.xhtml\">Three</a></li>"
+        ));
+        let ncx = read("OEBPS/toc.ncx");
+        assert_eq!(ncx.matches("<navPoint ").count(), 5);
+        assert_eq!(ncx.matches("</navPoint>").count(), 5);
+        assert!(read("OEBPS/content.opf").contains("<itemref idref=\"chapter-3\"/>"));
+    }
+}
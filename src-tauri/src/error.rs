@@ -0,0 +1,267 @@
+//! Error type of the Tauri command surface. Commands return `AppError`, which
+//! serializes as `{ "kind": "not_found", "entity": "note", "id": "..." }` so
+//! the UI can pick a localized message and react to the kind; `Display` gives
+//! an English message for logs.
+
+use crate::crypto::CryptoError;
+use crate::importer::ImportError;
+use crate::sync::SyncError;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Entity {
+    Workspace,
+    Folder,
+    Note,
+    Backup,
+}
+
+impl std::fmt::Display for Entity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Entity::Workspace => "workspace",
+            Entity::Folder => "folder",
+            Entity::Note => "note",
+            Entity::Backup => "backup",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What is locked in `AppError::Locked`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockTarget {
+    /// The encrypted database has not been unlocked.
+    Database,
+    /// Sync is end-to-end encrypted and the data key is not unlocked.
+    Sync,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AppError {
+    Locked {
+        target: LockTarget,
+    },
+    NotFound {
+        entity: Entity,
+        id: String,
+    },
+    /// The request contradicts the current data, e.g. moving a folder into
+    /// its own subtree.
+    Conflict {
+        message: String,
+    },
+    /// An argument was rejected; `field` is its name as sent by the UI.
+    Validation {
+        field: String,
+        message: String,
+    },
+    Io {
+        path: Option<String>,
+        message: String,
+    },
+    Database {
+        message: String,
+    },
+    /// The archive was rejected; the payload carries a `reason` code and its
+    /// details.
+    ImportInvalid(ImportError),
+    Cancelled,
+    /// Sync has no server configured.
+    NotConfigured,
+    /// The sync server could not be reached.
+    Offline {
+        message: String,
+    },
+    /// The sync server answered with an error.
+    Remote {
+        message: String,
+    },
+    /// Anything without a more specific kind.
+    Internal {
+        message: String,
+    },
+}
+
+impl AppError {
+    pub fn not_found(entity: Entity, id: impl Into<String>) -> Self {
+        AppError::NotFound {
+            entity,
+            id: id.into(),
+        }
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        AppError::Conflict {
+            message: message.into(),
+        }
+    }
+
+    pub fn validation(field: impl Into<String>, message: impl Into<String>) -> Self {
+        AppError::Validation {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+
+    /// A failure the user can do nothing about, e.g. a background task that
+    /// panicked.
+    pub fn internal(message: impl Into<String>) -> Self {
+        AppError::Internal {
+            message: message.into(),
+        }
+    }
+
+    /// An I/O failure on a known path.
+    pub fn io(path: impl AsRef<std::path::Path>, e: std::io::Error) -> Self {
+        AppError::Io {
+            path: Some(path.as_ref().display().to_string()),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Locked {
+                target: LockTarget::Database,
+            } => write!(f, "Database is locked"),
+            AppError::Locked {
+                target: LockTarget::Sync,
+            } => write!(f, "Encrypted sync is locked: enter your passphrase"),
+            AppError::NotFound { entity, id } => write!(f, "No such {}: {}", entity, id),
+            AppError::Conflict { message } => write!(f, "{}", message),
+            AppError::Validation { field, message } => write!(f, "Invalid {}: {}", field, message),
+            AppError::Io {
+                path: Some(path),
+                message,
+            } => write!(f, "{}: {}", path, message),
+            AppError::Io {
+                path: None,
+                message,
+            } => write!(f, "{}", message),
+            AppError::Database { message } => write!(f, "Database error: {}", message),
+            AppError::ImportInvalid(e) => write!(f, "{}", e),
+            AppError::Cancelled => write!(f, "Cancelled"),
+            AppError::NotConfigured => write!(f, "Sync is not configured"),
+            AppError::Offline { message } => write!(f, "Sync server unreachable: {}", message),
+            AppError::Remote { message } | AppError::Internal { message } => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        AppError::Database {
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::Io {
+            path: None,
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<ImportError> for AppError {
+    fn from(e: ImportError) -> Self {
+        AppError::ImportInvalid(e)
+    }
+}
+
+/// A wrong passphrase or recovery key is the user's to fix; anything else
+/// means the key material itself is broken.
+impl From<CryptoError> for AppError {
+    fn from(e: CryptoError) -> Self {
+        match e {
+            CryptoError::WrongPassphrase => AppError::validation("passphrase", e.to_string()),
+            CryptoError::WrongRecoveryKey | CryptoError::InvalidRecoveryKey => {
+                AppError::validation("recovery_key", e.to_string())
+            }
+            e => AppError::internal(e.to_string()),
+        }
+    }
+}
+
+impl From<SyncError> for AppError {
+    fn from(e: SyncError) -> Self {
+        match e {
+            SyncError::NotConfigured => AppError::NotConfigured,
+            SyncError::Offline(message) => AppError::Offline { message },
+            SyncError::Server(message) => AppError::Remote { message },
+            SyncError::Local(message) => AppError::Internal { message },
+            SyncError::Locked => AppError::Locked {
+                target: LockTarget::Sync,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_kind_and_fields() {
+        let json = serde_json::to_value(AppError::not_found(Entity::Note, "n1")).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "kind": "not_found", "entity": "note", "id": "n1" })
+        );
+
+        let json = serde_json::to_value(AppError::Locked {
+            target: LockTarget::Database,
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "kind": "locked", "target": "database" })
+        );
+    }
+
+    #[test]
+    fn test_import_error_carries_reason_code() {
+        let json = serde_json::to_value(AppError::from(ImportError::TooManyEntries {
+            count: 3,
+            limit: 2,
+        }))
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "kind": "import_invalid",
+                "reason": "too_many_entries",
+                "count": 3,
+                "limit": 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_sqlx_errors_are_database_errors() {
+        let e = AppError::from(sqlx::Error::RowNotFound);
+        assert!(matches!(e, AppError::Database { .. }), "{:?}", e);
+    }
+
+    #[test]
+    fn test_only_wrong_keys_are_validation_errors() {
+        assert_eq!(
+            AppError::from(CryptoError::WrongPassphrase),
+            AppError::validation("passphrase", "Wrong passphrase")
+        );
+        let e = AppError::from(CryptoError::Decryption);
+        assert!(matches!(e, AppError::Internal { .. }), "{:?}", e);
+    }
+}
@@ -1,127 +1,1055 @@
-use crate::db::{Db, FolderRecord, NoteRecord};
+use crate::db::{Db, FolderRecord, NoteRecord, WorkspaceRecord};
+use crate::error::{AppError, Entity};
+use crate::grants::{write_atomic, write_atomic_with, Access, FileGrants};
+use crate::manifest::{
+    ExportManifest, ManifestFolder, ManifestNote, ManifestWorkspace, MANIFEST_FILE,
+    MANIFEST_VERSION,
+};
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashSet};
 use std::fs;
-use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use zip::write::{FileOptions, ZipWriter};
-use zip::CompressionMethod;
+use zip::{CompressionMethod, DateTime};
+
+/// Where an export goes: a ZIP file or straight into a directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportTarget {
+    Zip,
+    Directory,
+}
+
+impl ExportTarget {
+    /// The grant the target path has to come with.
+    pub fn access(self) -> Access {
+        match self {
+            ExportTarget::Zip => Access::Write,
+            ExportTarget::Directory => Access::Directory,
+        }
+    }
+}
+
+/// Which part of a workspace to export, e.g.
+/// `{ "kind": "items", "ids": [...] }` or `{ "kind": "tag", "tag": "work" }`.
+/// Folders the selected items live in are exported with them, so paths and
+/// the manifest stay the same as in a full export.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExportSelection {
+    /// Notes and folders by id; a folder brings everything inside it.
+    Items { ids: Vec<String> },
+    /// Notes carrying `#tag`, without the `#` and ignoring case.
+    Tag { tag: String },
+    /// Notes matching a full-text search.
+    Query { query: String },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEndings {
+    #[default]
+    Keep,
+    Lf,
+    Crlf,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    /// Everything when `None`.
+    pub selection: Option<ExportSelection>,
+    /// Prepend YAML front-matter with the note's id, title, dates and tags.
+    pub front_matter: bool,
+    pub line_endings: LineEndings,
+    /// Also export notes and folders in the trash; the manifest marks them.
+    pub include_deleted: bool,
+}
+
+/// A file or directory of an export, `path` relative to the export root.
+pub struct ExportEntry {
+    pub path: String,
+    /// `None` for directories.
+    pub bytes: Option<Vec<u8>>,
+    pub modified_at: i64,
+}
 
 pub async fn export_workspace_logic(
     db: &Db,
     workspace_id: String,
     zip_path: PathBuf,
-) -> Result<(), String> {
-    let notes = db.get_notes().await?;
-    let folders = db.get_folders().await?;
+) -> Result<(), AppError> {
+    export_logic(
+        db,
+        workspace_id,
+        ExportTarget::Zip,
+        zip_path,
+        &ExportOptions::default(),
+    )
+    .await
+}
 
-    let ws_notes: Vec<NoteRecord> = notes
-        .into_iter()
-        .filter(|n| n.workspace_id == workspace_id)
-        .collect();
-    let ws_folders: Vec<FolderRecord> = folders
+pub async fn export_logic(
+    db: &Db,
+    workspace_id: String,
+    target: ExportTarget,
+    path: PathBuf,
+    options: &ExportOptions,
+) -> Result<(), AppError> {
+    let (entries, manifest) = export_entries(db, &workspace_id, options).await?;
+    match target {
+        ExportTarget::Zip => write_zip(&path, &entries, &manifest),
+        ExportTarget::Directory => write_directory(&path, &entries, &manifest),
+    }
+}
+
+/// The files of an export and its manifest, before they are written out.
+pub async fn export_entries(
+    db: &Db,
+    workspace_id: &str,
+    options: &ExportOptions,
+) -> Result<(Vec<ExportEntry>, ExportManifest), AppError> {
+    let workspace = db
+        .get_workspaces()
+        .await?
         .into_iter()
-        .filter(|f| f.workspace_id == workspace_id)
-        .collect();
+        .find(|w| w.id == workspace_id)
+        .ok_or_else(|| AppError::not_found(Entity::Workspace, workspace_id))?;
+    let (mut notes, mut folders) = db
+        .get_workspace_items(workspace_id, options.include_deleted)
+        .await?;
 
-    // Create zip file
-    let file = File::create(&zip_path)
-        .map_err(|e| format!("Failed to create zip file {:?}: {}", zip_path, e))?;
-    let mut zip = ZipWriter::new(file);
-    let options = FileOptions::default()
-        .compression_method(CompressionMethod::Deflated)
-        .unix_permissions(0o755);
+    if let Some(selection) = &options.selection {
+        let matches = match selection {
+            ExportSelection::Query { query } if query.trim().is_empty() => {
+                return Err(AppError::validation("query", "Search query is empty"));
+            }
+            ExportSelection::Query { query } => Some(db.search_note_ids(query).await?),
+            _ => None,
+        };
+        let (note_ids, folder_ids) = select(selection, matches, &notes, &folders);
+        notes.retain(|n| note_ids.contains(&n.id));
+        folders.retain(|f| folder_ids.contains(&f.id));
+    }
 
-    // Export logic - add files to zip
-    export_folder_recursive(None, &ws_notes, &ws_folders, &mut zip, &options, "")?;
+    build_export(&workspace, &notes, &folders, options)
+}
 
-    zip.finish()
-        .map_err(|e| format!("Failed to finalize zip file: {}", e))?;
+/// Resolves a selection to the ids of the notes and folders to export,
+/// including the folders above them. `matches` holds the search hits of a
+/// query selection.
+fn select(
+    selection: &ExportSelection,
+    matches: Option<HashSet<String>>,
+    notes: &[NoteRecord],
+    folders: &[FolderRecord],
+) -> (HashSet<String>, HashSet<String>) {
+    let mut note_ids: HashSet<String> = HashSet::new();
+    let mut folder_ids: HashSet<String> = HashSet::new();
+    match selection {
+        ExportSelection::Items { ids } => {
+            let ids: HashSet<&str> = ids.iter().map(String::as_str).collect();
+            // Folders come parents first from the database only by
+            // accident, so walk down from each selected folder
+            let mut pending: Vec<&str> = folders
+                .iter()
+                .filter(|f| ids.contains(f.id.as_str()))
+                .map(|f| f.id.as_str())
+                .collect();
+            while let Some(id) = pending.pop() {
+                if folder_ids.insert(id.to_string()) {
+                    pending.extend(
+                        folders
+                            .iter()
+                            .filter(|f| f.parent_id.as_deref() == Some(id))
+                            .map(|f| f.id.as_str()),
+                    );
+                }
+            }
+            note_ids.extend(
+                notes
+                    .iter()
+                    .filter(|n| {
+                        ids.contains(n.id.as_str())
+                            || n.folder_id.as_ref().is_some_and(|f| folder_ids.contains(f))
+                    })
+                    .map(|n| n.id.clone()),
+            );
+        }
+        ExportSelection::Tag { tag } => {
+            let tag = tag.trim_start_matches('#').to_lowercase();
+            note_ids.extend(
+                notes
+                    .iter()
+                    .filter(|n| note_tags(&n.content).contains(&tag))
+                    .map(|n| n.id.clone()),
+            );
+        }
+        ExportSelection::Query { .. } => {
+            let matches = matches.unwrap_or_default();
+            note_ids.extend(
+                notes
+                    .iter()
+                    .filter(|n| matches.contains(&n.id))
+                    .map(|n| n.id.clone()),
+            );
+        }
+    }
 
-    Ok(())
+    // Keep the path down to every selected item
+    let parent_of = |id: &str| {
+        folders
+            .iter()
+            .find(|f| f.id == id)
+            .and_then(|f| f.parent_id.clone())
+    };
+    let mut pending: Vec<String> = notes
+        .iter()
+        .filter(|n| note_ids.contains(&n.id))
+        .filter_map(|n| n.folder_id.clone())
+        .chain(folder_ids.iter().filter_map(|id| parent_of(id)))
+        .collect();
+    while let Some(id) = pending.pop() {
+        if folder_ids.insert(id.clone()) {
+            pending.extend(parent_of(&id));
+        }
+    }
+    (note_ids, folder_ids)
+}
+
+/// Lowercased `#tags` of a note. Tags are words starting with `#` in the
+/// text, so headings, code and link targets never count.
+pub fn note_tags(content: &str) -> BTreeSet<String> {
+    let mut tags = BTreeSet::new();
+    let mut in_code = false;
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        // Inline code is skipped by only looking outside backticks
+        for (i, part) in line.split('`').enumerate() {
+            if i % 2 == 1 {
+                continue;
+            }
+            let mut previous = ' ';
+            for (at, c) in part.char_indices() {
+                if c == '#' && previous.is_whitespace() {
+                    let tag: String = part[at + 1..]
+                        .chars()
+                        .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '/'))
+                        .collect();
+                    // `#1` is an issue number more often than a tag
+                    if tag.chars().any(|c| !c.is_numeric()) {
+                        tags.insert(tag.to_lowercase());
+                    }
+                }
+                previous = c;
+            }
+        }
+    }
+    tags
 }
 
-fn export_folder_recursive(
-    parent_id: Option<String>,
+/// Lays the notes out as files and fills the manifest.
+fn build_export(
+    workspace: &WorkspaceRecord,
     notes: &[NoteRecord],
     folders: &[FolderRecord],
-    zip: &mut ZipWriter<File>,
-    options: &FileOptions,
-    current_path: &str,
-) -> Result<(), String> {
-    // Export notes in this folder
-    for note in notes.iter().filter(|n| n.folder_id == parent_id) {
-        let safe_title = sanitize_filename(&note.title);
-        let filename = if safe_title.is_empty() {
-            "Untitled.md".to_string()
-        } else {
-            format!("{}.md", safe_title)
-        };
+    options: &ExportOptions,
+) -> Result<(Vec<ExportEntry>, ExportManifest), AppError> {
+    let layout = ExportLayout::new(notes, folders, ".md");
+    let mut manifest = new_manifest(workspace);
+    let mut entries = Vec::new();
+
+    // Explicit directory entries, so empty folders survive the export
+    for entry in &layout.folders {
+        let folder = entry.record;
+        entries.push(ExportEntry {
+            path: entry.path.clone(),
+            bytes: None,
+            modified_at: folder.updated_at,
+        });
+        manifest.folders.push(ManifestFolder {
+            id: folder.id.clone(),
+            path: entry.path.clone(),
+            name: folder.name.clone(),
+            parent_id: folder.parent_id.clone(),
+            color: folder.color.clone(),
+            created_at: folder.created_at,
+            updated_at: folder.updated_at,
+            version: folder.version,
+            index: entry.index,
+            sort_key: folder.sort_key.clone(),
+            is_deleted: folder.is_deleted,
+        });
+    }
 
-        let zip_path = if current_path.is_empty() {
-            filename.clone()
+    for entry in &layout.notes {
+        let note = entry.record;
+        let mut content = if options.front_matter {
+            with_front_matter(note)
         } else {
-            format!("{}/{}", current_path, filename)
+            note.content.clone()
         };
+        content = normalize_line_endings(&content, options.line_endings);
+        entries.push(ExportEntry {
+            path: entry.path.clone(),
+            bytes: Some(content.into_bytes()),
+            modified_at: note.updated_at,
+        });
+        manifest.notes.push(ManifestNote {
+            id: note.id.clone(),
+            path: entry.path.clone(),
+            title: note.title.clone(),
+            folder_id: note.folder_id.clone(),
+            color: note.color.clone(),
+            created_at: note.created_at,
+            updated_at: note.updated_at,
+            version: note.version,
+            index: entry.index,
+            sort_key: note.sort_key.clone(),
+            is_deleted: note.is_deleted,
+        });
+    }
+    Ok((entries, manifest))
+}
+
+/// Adds the note's metadata to the front-matter of its content, creating
+/// the block when there is none. Keys the note already sets win.
+fn with_front_matter(note: &NoteRecord) -> String {
+    let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+    let tags: Vec<String> = note_tags(&note.content).iter().map(|t| quote(t)).collect();
+    let fields = [
+        ("id", quote(&note.id)),
+        ("title", quote(&note.title)),
+        ("created", iso_time(note.created_at)),
+        ("updated", iso_time(note.updated_at)),
+        ("tags", format!("[{}]", tags.join(", "))),
+    ];
+
+    let (existing, body) = split_front_matter(&note.content);
+    let taken: HashSet<&str> = existing
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, _)| key.trim())
+        .collect();
+    let mut out = String::from("---\n");
+    for (key, value) in fields {
+        if !taken.contains(key) {
+            out.push_str(&format!("{}: {}\n", key, value));
+        }
+    }
+    out.push_str(existing);
+    out.push_str("---\n");
+    out.push_str(body);
+    out
+}
 
-        zip.start_file(&zip_path, *options)
-            .map_err(|e| format!("Failed to add file to zip: {}", e))?;
-        zip.write_all(note.content.as_bytes())
-            .map_err(|e| format!("Failed to write content to zip: {}", e))?;
+/// Splits `---` front-matter off the content. The first part keeps its
+/// trailing newline and is empty when there is no front-matter.
+fn split_front_matter(content: &str) -> (&str, &str) {
+    let Some(rest) = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))
+    else {
+        return ("", content);
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return (&rest[..offset], &rest[offset + line.len()..]);
+        }
+        offset += line.len();
     }
+    ("", content)
+}
 
-    // Recurse into subfolders
-    for folder in folders.iter().filter(|f| f.parent_id == parent_id) {
-        let subfolder_name = sanitize_filename(&folder.name);
-        let folder_name = if subfolder_name.is_empty() {
-            "Untitled Folder"
-        } else {
-            &subfolder_name
-        };
+fn normalize_line_endings(content: &str, endings: LineEndings) -> String {
+    let lf = || content.replace("\r\n", "\n").replace('\r', "\n");
+    match endings {
+        LineEndings::Keep => content.to_string(),
+        LineEndings::Lf => lf(),
+        LineEndings::Crlf => lf().replace('\n', "\r\n"),
+    }
+}
 
-        let subfolder_path = if current_path.is_empty() {
-            folder_name.to_string()
-        } else {
-            format!("{}/{}", current_path, folder_name)
+/// Formats a millisecond Unix timestamp as RFC 3339 in UTC.
+pub fn iso_time(millis: i64) -> String {
+    let t = time::OffsetDateTime::from_unix_timestamp(millis.div_euclid(1000))
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        t.year(),
+        u8::from(t.month()),
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second()
+    )
+}
+
+fn write_zip(
+    path: &Path,
+    entries: &[ExportEntry],
+    manifest: &ExportManifest,
+) -> Result<(), AppError> {
+    // Written to a temp file and moved into place, so a failed export never
+    // leaves a truncated archive at the chosen path
+    write_atomic_with(path, |file| {
+        let mut zip = ZipWriter::new(file);
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .unix_permissions(0o755);
+
+        for entry in entries {
+            let entry_options = options.last_modified_time(zip_time(entry.modified_at));
+            match &entry.bytes {
+                None => zip
+                    .add_directory(&entry.path, entry_options)
+                    .map_err(zip_error("add folder to zip"))?,
+                Some(bytes) => {
+                    zip.start_file(&entry.path, entry_options)
+                        .map_err(zip_error("add file to zip"))?;
+                    zip.write_all(bytes)
+                        .map_err(zip_error("write content to zip"))?;
+                }
+            }
+        }
+
+        // Manifest goes last so it reflects the final entry paths
+        zip.start_file(MANIFEST_FILE, options)
+            .map_err(zip_error("add manifest to zip"))?;
+        zip.write_all(&manifest_json(manifest)?)
+            .map_err(zip_error("write manifest to zip"))?;
+
+        zip.finish().map_err(zip_error("finalize zip file"))
+    })
+}
+
+/// Writes the export into `dir`. When an earlier export went there, the
+/// files its manifest lists that this one no longer has are removed, and
+/// its folders once they are empty; nothing the manifest did not list is
+/// touched. The new manifest goes last, so an interrupted export is cleaned
+/// up by the next one.
+fn write_directory(
+    dir: &Path,
+    entries: &[ExportEntry],
+    manifest: &ExportManifest,
+) -> Result<(), AppError> {
+    let previous = fs::read(relative_path(dir, MANIFEST_FILE))
+        .ok()
+        .and_then(|raw| serde_json::from_slice::<ExportManifest>(&raw).ok());
+    for entry in entries {
+        match &entry.bytes {
+            None => {
+                let path = relative_path(dir, &entry.path);
+                fs::create_dir_all(&path).map_err(|e| AppError::io(&path, e))?;
+            }
+            Some(bytes) => write_relative(dir, &entry.path, bytes)?,
+        }
+    }
+    if let Some(previous) = previous {
+        let exported: HashSet<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        remove_stale(
+            dir,
+            &exported,
+            previous.notes.iter().map(|n| n.path.as_str()),
+            previous.folders.iter().map(|f| f.path.as_str()),
+        )?;
+    }
+    write_relative(dir, MANIFEST_FILE, &manifest_json(manifest)?)
+}
+
+/// Removes what an earlier export into `dir` wrote and this one did not:
+/// the `files`, then the `folders` once they are empty. Paths that would
+/// leave `dir` are skipped, since they come from a file on disk.
+pub(crate) fn remove_stale<'a>(
+    dir: &Path,
+    exported: &HashSet<&str>,
+    files: impl IntoIterator<Item = &'a str>,
+    folders: impl IntoIterator<Item = &'a str>,
+) -> Result<(), AppError> {
+    for path in files {
+        if exported.contains(path) || !is_plain_relative(path) {
+            continue;
+        }
+        let path = relative_path(dir, path);
+        if fs::symlink_metadata(&path).is_ok_and(|m| m.is_file()) {
+            fs::remove_file(&path).map_err(|e| AppError::io(&path, e))?;
+        }
+    }
+    // Deepest first; folders with anything else in them stay
+    let mut folders: Vec<&str> = folders
+        .into_iter()
+        .filter(|path| !exported.contains(path) && is_plain_relative(path))
+        .collect();
+    folders.sort_by_key(|path| std::cmp::Reverse(path.len()));
+    for path in folders {
+        let _ = fs::remove_dir(relative_path(dir, path));
+    }
+    Ok(())
+}
+
+/// Whether a path read back from a manifest stays inside the export: `/`
+/// separated names, none of them empty, `.`, `..` or a drive or root.
+fn is_plain_relative(path: &str) -> bool {
+    path.split('/').all(|part| {
+        let mut components = Path::new(part).components();
+        matches!(components.next(), Some(std::path::Component::Normal(_)))
+            && components.next().is_none()
+            && !part.contains(['\\', ':'])
+    })
+}
+
+pub fn manifest_json(manifest: &ExportManifest) -> Result<Vec<u8>, AppError> {
+    serde_json::to_vec_pretty(manifest)
+        .map_err(|e| AppError::internal(format!("Failed to serialize manifest: {}", e)))
+}
+
+fn relative_path(dir: &Path, path: &str) -> PathBuf {
+    path.split('/')
+        .fold(dir.to_path_buf(), |p, part| p.join(part))
+}
+
+/// Atomically writes `path`, given with `/` separators, below `dir`,
+/// creating the directories in between.
+pub fn write_relative(dir: &Path, path: &str, bytes: &[u8]) -> Result<(), AppError> {
+    let target = relative_path(dir, path);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| AppError::io(parent, e))?;
+    }
+    write_atomic(&target, bytes)
+}
+
+/// Maps a failure while writing the archive; the zip error already names the
+/// underlying I/O problem.
+fn zip_error<E: std::fmt::Display>(action: &'static str) -> impl FnOnce(E) -> AppError {
+    move |e| AppError::Io {
+        path: None,
+        message: format!("Failed to {}: {}", action, e),
+    }
+}
+
+/// Where each folder and note of a workspace lands in an export. Every export
+/// format goes through it, so their trees and file names always match.
+pub struct ExportLayout<'a> {
+    /// Parents always come before their subfolders.
+    pub folders: Vec<LaidOut<'a, FolderRecord>>,
+    pub notes: Vec<LaidOut<'a, NoteRecord>>,
+}
+
+pub struct LaidOut<'a, T> {
+    pub record: &'a T,
+    /// Relative path with `/` separators; note paths end in the extension.
+    pub path: String,
+    /// Position among siblings of the same kind.
+    pub index: usize,
+}
+
+impl<'a> ExportLayout<'a> {
+    /// Lays out the tree under the workspace root. Notes get `note_ext`
+    /// (e.g. `.md`); items whose parent is not in `folders` are left out.
+    pub fn new(notes: &'a [NoteRecord], folders: &'a [FolderRecord], note_ext: &str) -> Self {
+        let mut layout = ExportLayout {
+            folders: Vec::new(),
+            notes: Vec::new(),
         };
+        layout_folder_recursive(None, notes, folders, note_ext, "", &mut layout);
+        layout
+    }
+}
+
+fn layout_folder_recursive<'a>(
+    parent_id: Option<&str>,
+    notes: &'a [NoteRecord],
+    folders: &'a [FolderRecord],
+    note_ext: &str,
+    current_path: &str,
+    layout: &mut ExportLayout<'a>,
+) {
+    // Entry names already taken in this directory, lowercased since Windows
+    // and macOS file systems are case-insensitive
+    let mut used_names: HashSet<String> = HashSet::new();
 
-        export_folder_recursive(
-            Some(folder.id.clone()),
+    // Sidebar order, then creation time and id as a stable tie-break so
+    // duplicates get the same suffix on every export
+    let mut folder_notes: Vec<&NoteRecord> = notes
+        .iter()
+        .filter(|n| n.folder_id.as_deref() == parent_id)
+        .collect();
+    folder_notes.sort_by(|a, b| {
+        (&a.sort_key, a.created_at, &a.id).cmp(&(&b.sort_key, b.created_at, &b.id))
+    });
+    let mut subfolders: Vec<&FolderRecord> = folders
+        .iter()
+        .filter(|f| f.parent_id.as_deref() == parent_id)
+        .collect();
+    subfolders.sort_by(|a, b| {
+        (&a.sort_key, a.created_at, &a.id).cmp(&(&b.sort_key, b.created_at, &b.id))
+    });
+
+    for (index, note) in folder_notes.into_iter().enumerate() {
+        let filename = unique_name(
+            &sanitize_filename(&note.title, "Untitled"),
+            note_ext,
+            &mut used_names,
+        );
+        layout.notes.push(LaidOut {
+            record: note,
+            path: join_path(current_path, &filename),
+            index,
+        });
+    }
+
+    for (index, folder) in subfolders.into_iter().enumerate() {
+        let folder_name = unique_name(
+            &sanitize_filename(&folder.name, "Untitled Folder"),
+            "",
+            &mut used_names,
+        );
+        let subfolder_path = join_path(current_path, &folder_name);
+        layout.folders.push(LaidOut {
+            record: folder,
+            path: subfolder_path.clone(),
+            index,
+        });
+        layout_folder_recursive(
+            Some(&folder.id),
             notes,
             folders,
-            zip,
-            options,
+            note_ext,
             &subfolder_path,
-        )?;
+            layout,
+        );
     }
+}
 
-    Ok(())
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn new_manifest(workspace: &WorkspaceRecord) -> ExportManifest {
+    ExportManifest {
+        format_version: MANIFEST_VERSION,
+        workspace: ManifestWorkspace {
+            id: workspace.id.clone(),
+            name: workspace.name.clone(),
+            color: workspace.color.clone(),
+            created_at: workspace.created_at,
+            updated_at: workspace.updated_at,
+            version: workspace.version,
+        },
+        folders: Vec::new(),
+        notes: Vec::new(),
+    }
+}
+
+/// Converts a millisecond Unix timestamp into a ZIP entry time. ZIP times
+/// only cover 1980..=2107, anything outside falls back to the ZIP epoch.
+fn zip_time(millis: i64) -> DateTime {
+    time::OffsetDateTime::from_unix_timestamp(millis.div_euclid(1000))
+        .ok()
+        .and_then(|t| DateTime::try_from(t).ok())
+        .unwrap_or_default()
+}
+
+/// Longest base name kept, in bytes. Leaves room for ` (999)` and `.md`
+/// within the common 255-byte file name limit.
+const MAX_NAME_BYTES: usize = 200;
+
+/// Names Windows refuses as files or folders, with or without extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes a title safe as a file name on Windows, macOS and Linux while
+/// keeping everything that is legal there (punctuation, Unicode, emoji).
+fn sanitize_filename(name: &str, fallback: &str) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows silently drops trailing dots and spaces
+    let mut sanitized = replaced.trim().trim_end_matches(['.', ' ']).to_string();
+
+    if sanitized.len() > MAX_NAME_BYTES {
+        let mut end = MAX_NAME_BYTES;
+        while !sanitized.is_char_boundary(end) {
+            end -= 1;
+        }
+        sanitized.truncate(end);
+        sanitized = sanitized.trim_end_matches(['.', ' ']).to_string();
+    }
+
+    if sanitized.is_empty() {
+        return fallback.to_string();
+    }
+
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(stem.trim_end()))
+    {
+        sanitized.insert(stem.len(), '_');
+    }
+    sanitized
 }
 
-fn sanitize_filename(name: &str) -> String {
-    name.chars()
-        .map(|c| {
-            if c.is_alphanumeric() || c == ' ' || c == '-' || c == '_' {
-                c
+/// Returns `base + ext`, or `base (2) + ext`, `base (3) + ext`, ... when the
+/// name is already taken in the directory, and records it as used.
+fn unique_name(base: &str, ext: &str, used: &mut HashSet<String>) -> String {
+    let name = (1..)
+        .map(|n| {
+            if n == 1 {
+                format!("{}{}", base, ext)
             } else {
-                '_'
+                format!("{} ({}){}", base, n, ext)
             }
         })
-        .collect::<String>()
-        .trim()
-        .to_string()
+        .find(|candidate| !used.contains(&candidate.to_lowercase()))
+        .unwrap();
+    used.insert(name.to_lowercase());
+    name
 }
 
+/// Exports to the path granted by `pick_save_path` for a ZIP (the default)
+/// or by `pick_directory` for a directory.
 #[tauri::command]
 pub async fn export_workspace(
     state: tauri::State<'_, crate::DbState>,
+    grants: tauri::State<'_, FileGrants>,
     workspace_id: String,
-    base_path: String,
-) -> Result<(), String> {
-    let path = PathBuf::from(base_path);
-    export_workspace_logic(&state.db, workspace_id, path).await
+    token: String,
+    target: Option<ExportTarget>,
+    options: Option<ExportOptions>,
+) -> Result<(), AppError> {
+    let db = state.db()?;
+    let target = target.unwrap_or(ExportTarget::Zip);
+    let path = grants.redeem(&token, target.access())?;
+    export_logic(
+        &db,
+        workspace_id,
+        target,
+        path,
+        &options.unwrap_or_default(),
+    )
+    .await
 }
 
+/// Writes to the path granted by `pick_save_path`.
 #[tauri::command]
-pub async fn write_text_file(path: String, content: String) -> Result<(), String> {
-    fs::write(&path, content).map_err(|e| format!("Failed to write file at {}: {}", path, e))
+pub async fn write_text_file(
+    grants: tauri::State<'_, FileGrants>,
+    token: String,
+    content: String,
+) -> Result<(), AppError> {
+    let path = grants.redeem(&token, Access::Write)?;
+    write_atomic(&path, content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_db, tx};
+    use std::io::Read;
+
+    #[test]
+    fn test_sanitize_filename_keeps_legal_characters() {
+        assert_eq!(
+            sanitize_filename("Notes (v2.1) — Bob's 🎉", "Untitled"),
+            "Notes (v2.1) — Bob's 🎉"
+        );
+        assert_eq!(sanitize_filename("a/b\\c: d?", "Untitled"), "a_b_c_ d_");
+        assert_eq!(sanitize_filename("Draft...", "Untitled"), "Draft");
+        assert_eq!(sanitize_filename(" . ", "Untitled"), "Untitled");
+        assert_eq!(sanitize_filename("con", "Untitled"), "con_");
+        assert_eq!(sanitize_filename("LPT1.backup", "Untitled"), "LPT1_.backup");
+        assert_eq!(sanitize_filename("Console", "Untitled"), "Console");
+
+        let long = "é".repeat(300);
+        let truncated = sanitize_filename(&long, "Untitled");
+        assert!(truncated.len() <= MAX_NAME_BYTES);
+        assert!(truncated.chars().all(|c| c == 'é'));
+    }
+
+    #[tokio::test]
+    async fn test_export_deduplicates_names_and_keeps_empty_folders() {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+
+        let mut conn = db.pool.acquire().await.unwrap();
+        for (id, title, created_at) in [("n1", "Plan", 1), ("n2", "plan", 2), ("n3", "Plan", 3)] {
+            tx::restore_note(
+                &mut conn,
+                &NoteRecord {
+                    id: id.to_string(),
+                    title: title.to_string(),
+                    content: id.to_string(),
+                    folder_id: None,
+                    workspace_id: "default".to_string(),
+                    created_at,
+                    updated_at: created_at,
+                    version: 1,
+                    sort_key: String::new(),
+                    color: None,
+                    is_deleted: false,
+                },
+            )
+            .await
+            .unwrap();
+        }
+        tx::restore_folder(
+            &mut conn,
+            &FolderRecord {
+                id: "f1".to_string(),
+                name: "Empty".to_string(),
+                parent_id: None,
+                workspace_id: "default".to_string(),
+                created_at: 1,
+                updated_at: 1,
+                version: 1,
+                color: None,
+                sort_key: String::new(),
+                is_deleted: false,
+            },
+        )
+        .await
+        .unwrap();
+
+        let zip_path = dir.join("export.zip");
+        export_workspace_logic(&db, "default".to_string(), zip_path.clone())
+            .await
+            .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&zip_path).unwrap()).unwrap();
+        let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+        assert!(names.contains(&"Plan.md".to_string()));
+        assert!(names.contains(&"plan (2).md".to_string()));
+        assert!(names.contains(&"Plan (3).md".to_string()));
+        assert!(names.contains(&"Empty/".to_string()));
+
+        let mut content = String::new();
+        archive
+            .by_name("Plan (3).md")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "n3");
+    }
+
+    #[test]
+    fn test_note_tags_skip_headings_and_code() {
+        let content = "# Title\n#Work and #ideas/later, not a#b or #42\n`#code`\n```\n#fenced\n```\n[x](#anchor)";
+        let tags: Vec<String> = note_tags(content).into_iter().collect();
+        assert_eq!(tags, ["ideas/later", "work"]);
+    }
+
+    #[tokio::test]
+    async fn test_export_selection_to_directory() {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+
+        let mut conn = db.pool.acquire().await.unwrap();
+        for (id, name, parent_id) in [
+            ("f1", "Work", None),
+            ("f2", "Sub", Some("f1")),
+            ("f3", "Other", None),
+        ] {
+            tx::restore_folder(
+                &mut conn,
+                &FolderRecord {
+                    id: id.to_string(),
+                    name: name.to_string(),
+                    parent_id: parent_id.map(str::to_string),
+                    workspace_id: "default".to_string(),
+                    created_at: 1,
+                    updated_at: 1,
+                    version: 1,
+                    color: None,
+                    sort_key: String::new(),
+                    is_deleted: false,
+                },
+            )
+            .await
+            .unwrap();
+        }
+        for (id, content, folder_id, is_deleted) in [
+            (
+                "n1",
+                "---\ntitle: Mine\n---\nSee #work\r\nlater",
+                Some("f2"),
+                false,
+            ),
+            ("n2", "Nothing here", Some("f3"), false),
+            ("n3", "Old #work", None, true),
+        ] {
+            tx::restore_note(
+                &mut conn,
+                &NoteRecord {
+                    id: id.to_string(),
+                    title: id.to_string(),
+                    content: content.to_string(),
+                    folder_id: folder_id.map(str::to_string),
+                    workspace_id: "default".to_string(),
+                    created_at: 0,
+                    updated_at: 1_700_000_000_000,
+                    version: 1,
+                    sort_key: String::new(),
+                    color: None,
+                    is_deleted,
+                },
+            )
+            .await
+            .unwrap();
+        }
+        drop(conn);
+
+        let out = dir.join("out");
+        let options = ExportOptions {
+            selection: Some(ExportSelection::Tag {
+                tag: "#Work".to_string(),
+            }),
+            front_matter: true,
+            line_endings: LineEndings::Crlf,
+            include_deleted: false,
+        };
+        export_logic(
+            &db,
+            "default".to_string(),
+            ExportTarget::Directory,
+            out.clone(),
+            &options,
+        )
+        .await
+        .unwrap();
+
+        let content = std::fs::read_to_string(out.join("Work").join("Sub").join("n1.md")).unwrap();
+        assert_eq!(
+            content,
+            "---\r\nid: \"n1\"\r\ncreated: 1970-01-01T00:00:00Z\r\nupdated: 2023-11-14T22:13:20Z\r\n\
+             tags: [\"work\"]\r\ntitle: Mine\r\n---\r\nSee #work\r\nlater"
+        );
+        assert!(!out.join("Other").exists());
+        assert!(!out.join("n3.md").exists());
+
+        let manifest: ExportManifest =
+            serde_json::from_slice(&std::fs::read(out.join(MANIFEST_FILE)).unwrap()).unwrap();
+        assert_eq!(manifest.folders.len(), 2);
+        assert_eq!(manifest.notes.len(), 1);
+
+        // Deleted notes come along, marked, when asked for
+        let options = ExportOptions {
+            include_deleted: true,
+            ..options
+        };
+        export_logic(
+            &db,
+            "default".to_string(),
+            ExportTarget::Directory,
+            out.clone(),
+            &options,
+        )
+        .await
+        .unwrap();
+        let manifest: ExportManifest =
+            serde_json::from_slice(&std::fs::read(out.join(MANIFEST_FILE)).unwrap()).unwrap();
+        assert!(manifest.notes.iter().any(|n| n.id == "n3" && n.is_deleted));
+    }
+
+    #[tokio::test]
+    async fn test_directory_export_removes_files_it_wrote_before() {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+
+        let mut conn = db.pool.acquire().await.unwrap();
+        tx::restore_folder(
+            &mut conn,
+            &FolderRecord {
+                id: "f1".to_string(),
+                name: "Work".to_string(),
+                parent_id: None,
+                workspace_id: "default".to_string(),
+                created_at: 1,
+                updated_at: 1,
+                version: 1,
+                color: None,
+                sort_key: String::new(),
+                is_deleted: false,
+            },
+        )
+        .await
+        .unwrap();
+        let note = NoteRecord {
+            id: "n1".to_string(),
+            title: "Plan".to_string(),
+            content: String::new(),
+            folder_id: Some("f1".to_string()),
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        };
+        tx::restore_note(&mut conn, &note).await.unwrap();
+        drop(conn);
+
+        let out = dir.join("out");
+        let options = ExportOptions::default();
+        let export = || {
+            export_logic(
+                &db,
+                "default".to_string(),
+                ExportTarget::Directory,
+                out.clone(),
+                &options,
+            )
+        };
+        export().await.unwrap();
+        assert!(out.join("Work").join("Plan.md").exists());
+        std::fs::write(out.join("Work").join("mine.txt"), "kept").unwrap();
+        std::fs::write(out.join("Other.md"), "kept").unwrap();
+
+        db.upsert_note(NoteRecord {
+            title: "Roadmap".to_string(),
+            folder_id: None,
+            ..note
+        })
+        .await
+        .unwrap();
+        export().await.unwrap();
+        assert!(out.join("Roadmap.md").exists());
+        assert!(!out.join("Work").join("Plan.md").exists());
+        assert!(out.join("Work").join("mine.txt").exists());
+        assert!(out.join("Other.md").exists());
+
+        // Paths in a planted manifest never reach outside the folder
+        let outside = dir.join("outside.md");
+        std::fs::write(&outside, "kept").unwrap();
+        let mut manifest: ExportManifest =
+            serde_json::from_slice(&std::fs::read(out.join(MANIFEST_FILE)).unwrap()).unwrap();
+        manifest.notes[0].path = "../outside.md".to_string();
+        std::fs::write(out.join(MANIFEST_FILE), manifest_json(&manifest).unwrap()).unwrap();
+        export().await.unwrap();
+        assert!(outside.exists());
+    }
 }
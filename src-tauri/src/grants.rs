@@ -0,0 +1,400 @@
+//! Scoped file system access for the webview. The UI never passes raw paths
+//! to commands that touch user files: it asks the backend to show a native
+//! file dialog, gets back a short-lived single-use grant token for the path
+//! the user picked, and hands that token to the command doing the I/O.
+//! Writes are atomic (temp file + rename) and never follow symlinks or land
+//! in system directories.
+
+use crate::db::now_millis;
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri_plugin_dialog::{DialogExt, FilePath, MessageDialogButtons, MessageDialogKind};
+
+/// How long a grant stays valid after the dialog closed.
+const GRANT_TTL_MS: i64 = 5 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Creating and replacing files inside a directory.
+    Directory,
+    /// Reading files inside a directory.
+    ReadDirectory,
+}
+
+/// A path picked by the user; `path` is for display only, commands take the
+/// token.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileGrant {
+    pub token: String,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DialogFilter {
+    pub name: String,
+    pub extensions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DialogOptions {
+    pub title: Option<String>,
+    pub default_name: Option<String>,
+    pub filters: Vec<DialogFilter>,
+}
+
+struct Grant {
+    path: PathBuf,
+    access: Access,
+    expires_at: i64,
+}
+
+#[derive(Default)]
+pub struct FileGrants(Mutex<HashMap<String, Grant>>);
+
+impl FileGrants {
+    /// Records a grant for a path the user picked. Write grants are checked
+    /// up front so the user hears about a refused location right away.
+    pub fn issue(&self, path: PathBuf, access: Access) -> Result<FileGrant, AppError> {
+        match access {
+            Access::Read | Access::ReadDirectory => {}
+            Access::Write => check_write_target(&path)?,
+            Access::Directory => check_write_dir(&path)?,
+        }
+        let token = uuid::Uuid::new_v4().to_string();
+        let grant = FileGrant {
+            token: token.clone(),
+            path: path.display().to_string(),
+        };
+        let mut grants = self.0.lock().unwrap();
+        let now = now_millis();
+        grants.retain(|_, g| g.expires_at > now);
+        grants.insert(
+            token,
+            Grant {
+                path,
+                access,
+                expires_at: now + GRANT_TTL_MS,
+            },
+        );
+        Ok(grant)
+    }
+
+    /// Consumes a grant and returns its path. Tokens are single-use, so a
+    /// leaked token cannot be replayed.
+    pub fn redeem(&self, token: &str, access: Access) -> Result<PathBuf, AppError> {
+        let grant = self.0.lock().unwrap().remove(token);
+        match grant {
+            Some(g) if g.access == access && g.expires_at > now_millis() => Ok(g.path),
+            _ => Err(AppError::validation(
+                "token",
+                "File access was not granted or has expired",
+            )),
+        }
+    }
+}
+
+/// Directories no export or note should ever be written into.
+fn system_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = if cfg!(windows) {
+        [
+            "SystemRoot",
+            "ProgramFiles",
+            "ProgramFiles(x86)",
+            "ProgramData",
+        ]
+        .iter()
+        .filter_map(std::env::var_os)
+        .map(PathBuf::from)
+        .collect()
+    } else {
+        [
+            "/bin",
+            "/boot",
+            "/dev",
+            "/etc",
+            "/lib",
+            "/lib64",
+            "/proc",
+            "/sbin",
+            "/sys",
+            "/usr",
+            "/System",
+            "/Library",
+            "/private/etc",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect()
+    };
+    // Resolve links like /bin -> /usr/bin so the comparison below holds
+    for dir in dirs.clone() {
+        if let Ok(real) = dir.canonicalize() {
+            dirs.push(real);
+        }
+    }
+    dirs
+}
+
+fn check_not_system(dir: &Path) -> Result<(), AppError> {
+    let real = dir.canonicalize().map_err(|e| AppError::io(dir, e))?;
+    if system_dirs().iter().any(|d| real.starts_with(d)) {
+        return Err(AppError::validation(
+            "path",
+            "Refusing to write into a system directory",
+        ));
+    }
+    Ok(())
+}
+
+/// Refuses directories that are symlinks or inside a system directory.
+pub fn check_write_dir(dir: &Path) -> Result<(), AppError> {
+    let meta = fs::symlink_metadata(dir).map_err(|e| AppError::io(dir, e))?;
+    if meta.file_type().is_symlink() || !meta.is_dir() {
+        return Err(AppError::validation("path", "Not a directory"));
+    }
+    check_not_system(dir)
+}
+
+/// Refuses paths that are symlinks, directories, or inside a system
+/// directory. The parent directory must exist.
+pub fn check_write_target(path: &Path) -> Result<(), AppError> {
+    let refuse = |message: &str| Err(AppError::validation("path", message));
+
+    let (Some(parent), Some(_)) = (path.parent(), path.file_name()) else {
+        return refuse("Not a file path");
+    };
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => {
+            return refuse("Refusing to write through a symlink")
+        }
+        Ok(meta) if !meta.is_file() => return refuse("Not a regular file"),
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(AppError::io(path, e)),
+    }
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    check_not_system(parent)
+}
+
+/// Writes `path` through a temp file in the same directory that replaces it
+/// only once `write` succeeded, so a crash or error never leaves a truncated
+/// file behind. `write` gets the temp file and returns it when done.
+pub fn write_atomic_with<F>(path: &Path, write: F) -> Result<(), AppError>
+where
+    F: FnOnce(File) -> Result<File, AppError>,
+{
+    check_write_target(path)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name, uuid::Uuid::new_v4()));
+
+    let result = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .map_err(|e| AppError::io(&temp, e))
+        .and_then(write)
+        .and_then(|file| file.sync_all().map_err(|e| AppError::io(&temp, e)))
+        // Check again right before replacing, the target may have changed
+        // since the grant was issued
+        .and_then(|_| check_write_target(path))
+        .and_then(|_| fs::rename(&temp, path).map_err(|e| AppError::io(path, e)));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+    write_atomic_with(path, |mut file| {
+        file.write_all(bytes).map_err(|e| AppError::io(path, e))?;
+        Ok(file)
+    })
+}
+
+fn into_path(picked: Option<FilePath>) -> Result<Option<PathBuf>, AppError> {
+    picked
+        .map(|p| {
+            p.into_path()
+                .map_err(|e| AppError::validation("path", e.to_string()))
+        })
+        .transpose()
+}
+
+fn builder(
+    app: &tauri::AppHandle,
+    options: DialogOptions,
+) -> tauri_plugin_dialog::FileDialogBuilder<tauri::Wry> {
+    let mut dialog = app.dialog().file();
+    if let Some(title) = options.title {
+        dialog = dialog.set_title(title);
+    }
+    if let Some(name) = options.default_name {
+        dialog = dialog.set_file_name(name);
+    }
+    for filter in &options.filters {
+        let extensions: Vec<&str> = filter.extensions.iter().map(String::as_str).collect();
+        dialog = dialog.add_filter(&filter.name, &extensions);
+    }
+    dialog
+}
+
+/// Asks the user in a native dialog, which the webview cannot answer on
+/// their behalf. For destinations a file dialog cannot grant, such as a
+/// remote URL.
+pub async fn confirm(
+    app: &tauri::AppHandle,
+    title: &str,
+    message: String,
+    action: &str,
+) -> Result<bool, AppError> {
+    let dialog = app
+        .dialog()
+        .message(message)
+        .title(title)
+        .kind(MessageDialogKind::Warning)
+        .buttons(MessageDialogButtons::OkCancelCustom(
+            action.to_string(),
+            "Cancel".to_string(),
+        ));
+    tauri::async_runtime::spawn_blocking(move || dialog.blocking_show())
+        .await
+        .map_err(|e| AppError::internal(format!("Dialog failed: {}", e)))
+}
+
+/// Shows a save dialog and grants a single write to the chosen path.
+/// Resolves to `None` when the user cancels.
+#[tauri::command]
+pub async fn pick_save_path(
+    app: tauri::AppHandle,
+    grants: tauri::State<'_, FileGrants>,
+    options: Option<DialogOptions>,
+) -> Result<Option<FileGrant>, AppError> {
+    let dialog = builder(&app, options.unwrap_or_default());
+    let picked = tauri::async_runtime::spawn_blocking(move || dialog.blocking_save_file())
+        .await
+        .map_err(|e| AppError::internal(format!("Dialog failed: {}", e)))?;
+    into_path(picked)?
+        .map(|path| grants.issue(path, Access::Write))
+        .transpose()
+}
+
+/// Shows an open dialog and grants a single read of the chosen file.
+/// Resolves to `None` when the user cancels.
+#[tauri::command]
+pub async fn pick_open_path(
+    app: tauri::AppHandle,
+    grants: tauri::State<'_, FileGrants>,
+    options: Option<DialogOptions>,
+) -> Result<Option<FileGrant>, AppError> {
+    let dialog = builder(&app, options.unwrap_or_default());
+    let picked = tauri::async_runtime::spawn_blocking(move || dialog.blocking_pick_file())
+        .await
+        .map_err(|e| AppError::internal(format!("Dialog failed: {}", e)))?;
+    into_path(picked)?
+        .map(|path| grants.issue(path, Access::Read))
+        .transpose()
+}
+
+/// Shows a folder picker and grants writing files inside the chosen folder.
+/// Resolves to `None` when the user cancels.
+#[tauri::command]
+pub async fn pick_directory(
+    app: tauri::AppHandle,
+    grants: tauri::State<'_, FileGrants>,
+    options: Option<DialogOptions>,
+) -> Result<Option<FileGrant>, AppError> {
+    let dialog = builder(&app, options.unwrap_or_default());
+    let picked = tauri::async_runtime::spawn_blocking(move || dialog.blocking_pick_folder())
+        .await
+        .map_err(|e| AppError::internal(format!("Dialog failed: {}", e)))?;
+    into_path(picked)?
+        .map(|path| grants.issue(path, Access::Directory))
+        .transpose()
+}
+
+/// Shows a folder picker and grants reading files inside the chosen folder.
+/// Resolves to `None` when the user cancels.
+#[tauri::command]
+pub async fn pick_open_directory(
+    app: tauri::AppHandle,
+    grants: tauri::State<'_, FileGrants>,
+    options: Option<DialogOptions>,
+) -> Result<Option<FileGrant>, AppError> {
+    let dialog = builder(&app, options.unwrap_or_default());
+    let picked = tauri::async_runtime::spawn_blocking(move || dialog.blocking_pick_folder())
+        .await
+        .map_err(|e| AppError::internal(format!("Dialog failed: {}", e)))?;
+    into_path(picked)?
+        .map(|path| grants.issue(path, Access::ReadDirectory))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_grants_are_single_use_and_scoped_to_access() {
+        let grants = FileGrants::default();
+        let path = temp_dir().join("note.md");
+
+        let grant = grants.issue(path.clone(), Access::Write).unwrap();
+        assert!(grants.redeem(&grant.token, Access::Read).is_err());
+
+        let grant = grants.issue(path.clone(), Access::Write).unwrap();
+        assert_eq!(grants.redeem(&grant.token, Access::Write).unwrap(), path);
+        assert!(grants.redeem(&grant.token, Access::Write).is_err());
+        assert!(grants.redeem("made-up", Access::Write).is_err());
+    }
+
+    #[test]
+    fn test_atomic_write_replaces_file_and_leaves_no_temp() {
+        let dir = temp_dir();
+        let path = dir.join("note.md");
+        fs::write(&path, "old").unwrap();
+
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+
+        let failed = write_atomic_with(&path, |_| Err(AppError::Cancelled));
+        assert_eq!(failed, Err(AppError::Cancelled));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_refuses_symlinks_and_system_directories() {
+        let dir = temp_dir();
+        let target = dir.join("target.md");
+        fs::write(&target, "keep").unwrap();
+        let link = dir.join("link.md");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        assert!(write_atomic(&link, b"overwrite").is_err());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "keep");
+        assert!(check_write_target(Path::new("/etc/lumenote.md")).is_err());
+        assert!(check_write_target(&dir).is_err());
+        assert!(check_write_target(&dir.join("new.md")).is_ok());
+        assert!(check_write_dir(&dir).is_ok());
+        assert!(check_write_dir(Path::new("/usr/share")).is_err());
+    }
+}
@@ -0,0 +1,818 @@
+//! Git history of a workspace: the Markdown export is committed to a bare
+//! repository once per sync round that moved rows, optionally pushed to a
+//! remote, and any note can be read back or restored from any commit.
+//!
+//! Commits hold exactly the files of the ZIP export, manifest included, so a
+//! clone of the repository looks like an unpacked export and old notes are
+//! found by id through the manifest of their commit. Trees are built straight
+//! in the object database; there is no working copy to get out of step.
+//! Only repositories Lumenote created are written to, so a project's own
+//! repository is never picked up and its branches never moved.
+
+use crate::db::{now_millis, Db, DbState, NoteRecord};
+use crate::error::{AppError, Entity};
+use crate::exporter::{export_entries, manifest_json, ExportOptions};
+use crate::grants::{confirm, Access, FileGrants};
+use crate::manifest::{ExportManifest, ManifestNote, MANIFEST_FILE};
+use git2::{
+    Commit, ConfigLevel, Cred, CredentialType, Delta, Oid, PushOptions, RemoteCallbacks,
+    Repository, RepositoryInitOptions, Signature,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+use tokio::sync::Notify;
+
+const SETTING_PREFIX: &str = "history.";
+
+/// The branch every commit goes to, locally and on the remote.
+const BRANCH: &str = "refs/heads/main";
+
+/// Local config entry set on repositories Lumenote created.
+const MARKER: &str = "lumenote.history";
+
+const DEFAULT_LIMIT: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistorySettings {
+    /// Directory of the bare repository.
+    pub repo: PathBuf,
+    /// URL or path of a repository to push to after each commit.
+    #[serde(default)]
+    pub remote: Option<String>,
+    #[serde(default)]
+    pub last_run_at: Option<i64>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+/// What `configure_history` changes; see there.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryOptions {
+    pub token: Option<String>,
+    pub remote: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HistoryCommit {
+    pub id: String,
+    pub message: String,
+    pub committed_at: i64,
+}
+
+/// A note as it was in a commit.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryNote {
+    pub id: String,
+    pub title: String,
+    pub content: String,
+    pub folder_id: Option<String>,
+    /// Path of its file in the commit.
+    pub path: String,
+    pub commit: HistoryCommit,
+}
+
+/// Maps a libgit2 failure; its message already names what went wrong.
+fn git_error(action: &'static str) -> impl FnOnce(git2::Error) -> AppError {
+    move |e| AppError::Io {
+        path: None,
+        message: format!("Failed to {}: {}", action, e.message()),
+    }
+}
+
+/// Opens the repository Lumenote created in `dir`, creating a bare one when
+/// the directory is empty or missing. Refuses other non-empty directories,
+/// so picking the wrong folder never buries its files under a repository,
+/// and any repository it did not create, so committing never moves the
+/// branch of someone's project.
+fn open_repo(dir: &Path) -> Result<Repository, AppError> {
+    if let Ok(repo) = Repository::open(dir) {
+        let ours = repo.is_bare()
+            && repo
+                .config()
+                .and_then(|c| c.open_level(ConfigLevel::Local))
+                .and_then(|c| c.get_bool(MARKER))
+                .unwrap_or(false);
+        if !ours {
+            return Err(AppError::validation(
+                "token",
+                "The folder is a Git repository Lumenote did not create; pick an empty folder",
+            ));
+        }
+        return Ok(repo);
+    }
+    let empty = match fs::read_dir(dir) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(e) => return Err(AppError::io(dir, e)),
+    };
+    if !empty {
+        return Err(AppError::validation(
+            "token",
+            "The folder is neither empty nor a Git repository",
+        ));
+    }
+    let repo = Repository::init_opts(
+        dir,
+        RepositoryInitOptions::new().bare(true).initial_head("main"),
+    )
+    .map_err(git_error("create the repository"))?;
+    repo.config()
+        .and_then(|mut c| c.set_bool(MARKER, true))
+        .map_err(git_error("create the repository"))?;
+    Ok(repo)
+}
+
+/// A directory of the tree being committed.
+#[derive(Default)]
+struct TreeDir {
+    files: BTreeMap<String, Oid>,
+    dirs: BTreeMap<String, TreeDir>,
+}
+
+impl TreeDir {
+    fn insert(&mut self, path: &str, blob: Oid) {
+        match path.split_once('/') {
+            Some((dir, rest)) => self
+                .dirs
+                .entry(dir.to_string())
+                .or_default()
+                .insert(rest, blob),
+            None => {
+                self.files.insert(path.to_string(), blob);
+            }
+        }
+    }
+
+    fn write(&self, repo: &Repository) -> Result<Oid, git2::Error> {
+        let mut builder = repo.treebuilder(None)?;
+        for (name, dir) in &self.dirs {
+            builder.insert(name, dir.write(repo)?, 0o040000)?;
+        }
+        for (name, blob) in &self.files {
+            builder.insert(name, *blob, 0o100644)?;
+        }
+        builder.write()
+    }
+}
+
+fn head(repo: &Repository) -> Option<Commit<'_>> {
+    repo.find_reference(BRANCH).ok()?.peel_to_commit().ok()
+}
+
+fn to_history_commit(commit: &Commit) -> HistoryCommit {
+    HistoryCommit {
+        id: commit.id().to_string(),
+        message: commit.message().unwrap_or_default().trim_end().to_string(),
+        committed_at: commit.time().seconds() * 1000,
+    }
+}
+
+/// "Update 2 notes" followed by one line per added, changed or removed note
+/// file. A commit that only touches the manifest moved or renamed folders.
+fn commit_message(repo: &Repository, old: Option<&git2::Tree>, new: &git2::Tree) -> String {
+    let mut lines = Vec::new();
+    if let Ok(diff) = repo.diff_tree_to_tree(old, Some(new), None) {
+        for delta in diff.deltas() {
+            let (verb, file) = match delta.status() {
+                Delta::Added => ("Added", delta.new_file()),
+                Delta::Deleted => ("Deleted", delta.old_file()),
+                _ => ("Changed", delta.new_file()),
+            };
+            let path = file
+                .path()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if path != MANIFEST_FILE {
+                lines.push(format!("{} {}", verb, path));
+            }
+        }
+    }
+    let subject = match lines.len() {
+        0 => "Update folders".to_string(),
+        1 => "Update 1 note".to_string(),
+        n => format!("Update {} notes", n),
+    };
+    if lines.is_empty() {
+        subject
+    } else {
+        format!("{}\n\n{}\n", subject, lines.join("\n"))
+    }
+}
+
+/// Commits the files on top of the branch. Returns `None` when they match
+/// the last commit.
+fn commit_files(
+    repo: &Repository,
+    files: &[(String, Vec<u8>)],
+) -> Result<Option<HistoryCommit>, AppError> {
+    let mut root = TreeDir::default();
+    for (path, bytes) in files {
+        let blob = repo.blob(bytes).map_err(git_error("store a note"))?;
+        root.insert(path, blob);
+    }
+    let tree_id = root.write(repo).map_err(git_error("store the tree"))?;
+    let tree = repo
+        .find_tree(tree_id)
+        .map_err(git_error("read the tree"))?;
+
+    let parent = head(repo);
+    let parent_tree = parent.as_ref().and_then(|c| c.tree().ok());
+    if parent_tree.as_ref().is_some_and(|t| t.id() == tree_id) {
+        return Ok(None);
+    }
+    let message = commit_message(repo, parent_tree.as_ref(), &tree);
+    let signature = repo
+        .signature()
+        .or_else(|_| Signature::now("Lumenote", "lumenote@localhost"))
+        .map_err(git_error("sign the commit"))?;
+    let parents: Vec<&Commit> = parent.iter().collect();
+    let id = repo
+        .commit(
+            Some(BRANCH),
+            &signature,
+            &signature,
+            &message,
+            &tree,
+            &parents,
+        )
+        .map_err(git_error("commit"))?;
+    let commit = repo.find_commit(id).map_err(git_error("read the commit"))?;
+    Ok(Some(to_history_commit(&commit)))
+}
+
+/// Pushes the branch. Credentials come from the SSH agent or the Git
+/// credential helpers, as for the command line. Never forces: if the remote
+/// moved on, the push fails until someone merges there.
+fn push(repo: &Repository, url: &str) -> Result<(), AppError> {
+    let mut remote = repo
+        .remote_anonymous(url)
+        .map_err(git_error("open the remote"))?;
+    let config = repo.config().map_err(git_error("read the Git config"))?;
+    let mut rejected = None;
+    {
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(|url, username, allowed| {
+            if allowed.contains(CredentialType::SSH_KEY) {
+                Cred::ssh_key_from_agent(username.unwrap_or("git"))
+            } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                Cred::credential_helper(&config, url, username)
+            } else {
+                Cred::default()
+            }
+        });
+        callbacks.push_update_reference(|_, status| {
+            rejected = status.map(str::to_string);
+            Ok(())
+        });
+        let mut options = PushOptions::new();
+        options.remote_callbacks(callbacks);
+        remote
+            .push(&[format!("{0}:{0}", BRANCH)], Some(&mut options))
+            .map_err(git_error("push"))?;
+    }
+    match rejected {
+        Some(reason) => Err(AppError::conflict(format!(
+            "The remote rejected the push: {}",
+            reason
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Commits the workspace's current export and pushes it when a remote is
+/// set. The push also runs when nothing changed, so one that failed before
+/// is retried.
+pub async fn commit_workspace(
+    db: &Db,
+    workspace_id: &str,
+    settings: &HistorySettings,
+) -> Result<Option<HistoryCommit>, AppError> {
+    let (entries, manifest) = export_entries(db, workspace_id, &ExportOptions::default()).await?;
+    // Directories are left out: Git only stores them with files inside, and
+    // the manifest lists every folder anyway
+    let mut files: Vec<(String, Vec<u8>)> = entries
+        .into_iter()
+        .filter_map(|entry| Some((entry.path, entry.bytes?)))
+        .collect();
+    files.push((MANIFEST_FILE.to_string(), manifest_json(&manifest)?));
+
+    let settings = settings.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let repo = open_repo(&settings.repo)?;
+        let commit = commit_files(&repo, &files)?;
+        if let Some(url) = settings.remote.as_deref().filter(|u| !u.trim().is_empty()) {
+            push(&repo, url)?;
+        }
+        Ok(commit)
+    })
+    .await
+    .map_err(|e| AppError::internal(format!("Git task failed: {}", e)))?
+}
+
+fn find_commit<'r>(repo: &'r Repository, commit_id: &str) -> Result<Commit<'r>, AppError> {
+    repo.revparse_single(commit_id)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| AppError::validation("commit_id", format!("no commit {}", commit_id)))
+}
+
+fn read_manifest(repo: &Repository, commit: &Commit) -> Option<ExportManifest> {
+    let tree = commit.tree().ok()?;
+    let entry = tree.get_path(Path::new(MANIFEST_FILE)).ok()?;
+    let blob = repo.find_blob(entry.id()).ok()?;
+    serde_json::from_slice(blob.content()).ok()
+}
+
+/// The note's manifest entry and the blob of its file in a commit.
+fn note_in(repo: &Repository, commit: &Commit, note_id: &str) -> Option<(ManifestNote, Oid)> {
+    let note = read_manifest(repo, commit)?
+        .notes
+        .into_iter()
+        .find(|n| n.id == note_id)?;
+    let blob = commit
+        .tree()
+        .ok()?
+        .get_path(Path::new(&note.path))
+        .ok()?
+        .id();
+    Some((note, blob))
+}
+
+/// Commits on the branch, newest first. With a note, only the commits that
+/// added, changed, moved or removed it.
+fn log(
+    repo: &Repository,
+    note_id: Option<&str>,
+    limit: usize,
+) -> Result<Vec<HistoryCommit>, AppError> {
+    let Some(head) = head(repo) else {
+        return Ok(Vec::new());
+    };
+    let mut walk = repo.revwalk().map_err(git_error("read the history"))?;
+    walk.push(head.id())
+        .map_err(git_error("read the history"))?;
+    let mut commits = Vec::new();
+    for id in walk {
+        if commits.len() >= limit {
+            break;
+        }
+        let id = id.map_err(git_error("read the history"))?;
+        let commit = repo
+            .find_commit(id)
+            .map_err(git_error("read the history"))?;
+        if let Some(note_id) = note_id {
+            let state = |c: &Commit| note_in(repo, c, note_id).map(|(n, blob)| (n.path, blob));
+            let before = commit.parent(0).ok().and_then(|p| state(&p));
+            if state(&commit) == before {
+                continue;
+            }
+        }
+        commits.push(to_history_commit(&commit));
+    }
+    Ok(commits)
+}
+
+fn read_note(repo: &Repository, commit_id: &str, note_id: &str) -> Result<HistoryNote, AppError> {
+    let commit = find_commit(repo, commit_id)?;
+    let (note, blob) = note_in(repo, &commit, note_id)
+        .ok_or_else(|| AppError::not_found(Entity::Note, note_id))?;
+    let blob = repo.find_blob(blob).map_err(git_error("read the note"))?;
+    Ok(HistoryNote {
+        id: note.id,
+        title: note.title,
+        content: String::from_utf8_lossy(blob.content()).to_string(),
+        folder_id: note.folder_id,
+        path: note.path,
+        commit: to_history_commit(&commit),
+    })
+}
+
+/// Runs a read against the workspace's repository off the async runtime.
+async fn with_repo<T, F>(db: &Db, workspace_id: &str, read: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Repository) -> Result<T, AppError> + Send + 'static,
+{
+    let settings = load_settings(db, workspace_id)
+        .await?
+        .ok_or_else(|| AppError::validation("workspace_id", "The workspace has no history"))?;
+    tauri::async_runtime::spawn_blocking(move || read(&open_repo(&settings.repo)?))
+        .await
+        .map_err(|e| AppError::internal(format!("Git task failed: {}", e)))?
+}
+
+pub async fn read_note_logic(
+    db: &Db,
+    workspace_id: &str,
+    commit_id: String,
+    note_id: String,
+) -> Result<HistoryNote, AppError> {
+    with_repo(db, workspace_id, move |repo| {
+        read_note(repo, &commit_id, &note_id)
+    })
+    .await
+}
+
+async fn live_folder(
+    db: &Db,
+    workspace_id: &str,
+    folder_id: Option<&str>,
+) -> Result<bool, AppError> {
+    let Some(folder_id) = folder_id else {
+        return Ok(true);
+    };
+    let found: Option<(i64,)> = sqlx::query_as(
+        "SELECT 1 FROM folders WHERE id = ?1 AND workspace_id = ?2 AND is_deleted = 0",
+    )
+    .bind(folder_id)
+    .bind(workspace_id)
+    .fetch_optional(&db.pool)
+    .await?;
+    Ok(found.is_some())
+}
+
+/// Brings back a note's title and content from a commit as a new revision,
+/// taking it out of the trash if needed. A note that still exists stays
+/// where it is; one that is gone returns to its old folder, or to the
+/// workspace root when that folder is gone too.
+pub async fn restore_note_logic(
+    db: &Db,
+    workspace_id: &str,
+    commit_id: String,
+    note_id: String,
+) -> Result<NoteRecord, AppError> {
+    let old = read_note_logic(db, workspace_id, commit_id, note_id).await?;
+    let existing: Option<NoteRecord> = sqlx::query_as("SELECT * FROM notes WHERE id = ?1")
+        .bind(&old.id)
+        .fetch_optional(&db.pool)
+        .await?;
+    let mut note = match existing {
+        Some(note) if note.workspace_id != workspace_id => {
+            return Err(AppError::conflict(
+                "The note now belongs to another workspace",
+            ));
+        }
+        Some(note) => note,
+        None => NoteRecord {
+            id: old.id.clone(),
+            title: String::new(),
+            content: String::new(),
+            folder_id: old.folder_id.clone(),
+            workspace_id: workspace_id.to_string(),
+            created_at: 0,
+            updated_at: 0,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        },
+    };
+    if !live_folder(db, workspace_id, note.folder_id.as_deref()).await? {
+        note.folder_id = match live_folder(db, workspace_id, old.folder_id.as_deref()).await? {
+            true => old.folder_id.clone(),
+            false => None,
+        };
+    }
+    note.title = old.title;
+    note.content = old.content;
+    note.is_deleted = false;
+    let id = note.id.clone();
+    db.upsert_note(note).await?;
+    sqlx::query_as("SELECT * FROM notes WHERE id = ?1")
+        .bind(&id)
+        .fetch_one(&db.pool)
+        .await
+        .map_err(AppError::from)
+}
+
+async fn load_all(db: &Db) -> Result<Vec<(String, HistorySettings)>, AppError> {
+    let rows: Vec<(String, String)> =
+        sqlx::query_as("SELECT key, value FROM settings WHERE key LIKE 'history.%' ORDER BY key")
+            .fetch_all(&db.pool)
+            .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let workspace_id = key.strip_prefix(SETTING_PREFIX)?.to_string();
+            Some((workspace_id, serde_json::from_str(&value).ok()?))
+        })
+        .collect())
+}
+
+async fn load_settings(db: &Db, workspace_id: &str) -> Result<Option<HistorySettings>, AppError> {
+    Ok(db
+        .get_setting(&format!("{}{}", SETTING_PREFIX, workspace_id))
+        .await?
+        .and_then(|value| serde_json::from_str(&value).ok()))
+}
+
+async fn save_settings(
+    db: &Db,
+    workspace_id: &str,
+    settings: &HistorySettings,
+) -> Result<(), AppError> {
+    let value = serde_json::to_string(settings)
+        .map_err(|e| AppError::internal(format!("Failed to serialize history settings: {}", e)))?;
+    db.set_setting(&format!("{}{}", SETTING_PREFIX, workspace_id), &value)
+        .await
+}
+
+/// Commits history-enabled workspaces after sync rounds.
+#[derive(Default)]
+pub struct HistoryService {
+    /// Woken by the sync loop after each round that moved rows.
+    pub batch: Notify,
+    /// Held during a commit, so passes and `configure_history` never overlap.
+    running: tokio::sync::Mutex<()>,
+}
+
+impl HistoryService {
+    /// Commits the workspace and records the outcome in its settings. Reads
+    /// the settings only once no other commit or configuration change is in
+    /// flight.
+    async fn run(&self, db: &Db, workspace_id: &str) -> Result<Option<HistoryCommit>, AppError> {
+        let _running = self.running.lock().await;
+        let mut settings = load_settings(db, workspace_id)
+            .await?
+            .ok_or_else(|| AppError::validation("workspace_id", "The workspace has no history"))?;
+        let result = commit_workspace(db, workspace_id, &settings).await;
+        settings.last_run_at = Some(now_millis());
+        settings.last_error = result.as_ref().err().map(|e| e.to_string());
+        save_settings(db, workspace_id, &settings).await?;
+        result
+    }
+
+    /// Commits every workspace with history once. Failures are kept in each
+    /// workspace's `last_error`.
+    async fn run_all(&self, db: &Db) {
+        for (workspace_id, _) in load_all(db).await.unwrap_or_default() {
+            let _ = self.run(db, &workspace_id).await;
+        }
+    }
+}
+
+/// Starts the loop that commits after each sync batch. Uses `peek` like the
+/// sync loop, so it never keeps the database from locking; a batch that
+/// arrives while the database is locked is committed with the next one.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let history = app.state::<HistoryService>();
+            history.batch.notified().await;
+            if let Some(db) = app.state::<DbState>().peek() {
+                history.run_all(&db).await;
+            }
+        }
+    });
+}
+
+/// Sets up the workspace's history, or turns it off without options. The
+/// token comes from `pick_directory` and may be left out to keep the current
+/// repository while changing the remote. A new remote is only
+/// stored once the user confirmed it in a native dialog, since every note is
+/// pushed there in plain text.
+#[tauri::command]
+pub async fn configure_history(
+    app: AppHandle,
+    state: tauri::State<'_, DbState>,
+    grants: tauri::State<'_, FileGrants>,
+    history: tauri::State<'_, HistoryService>,
+    workspace_id: String,
+    options: Option<HistoryOptions>,
+) -> Result<Option<HistorySettings>, AppError> {
+    let db = state.db()?;
+    if !db
+        .get_workspaces()
+        .await?
+        .iter()
+        .any(|w| w.id == workspace_id)
+    {
+        return Err(AppError::not_found(
+            Entity::Workspace,
+            workspace_id.as_str(),
+        ));
+    }
+    let _running = history.running.lock().await;
+    let current = load_settings(&db, &workspace_id).await?;
+    let Some(mut options) = options else {
+        sqlx::query("DELETE FROM settings WHERE key = ?1")
+            .bind(format!("{}{}", SETTING_PREFIX, workspace_id))
+            .execute(&db.pool)
+            .await?;
+        return Ok(None);
+    };
+    options.remote = options
+        .remote
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty());
+    let current_remote = current.as_ref().and_then(|c| c.remote.as_deref());
+    if let Some(remote) = options.remote.as_deref() {
+        if Some(remote) != current_remote {
+            let message = format!(
+                "Every note of this workspace will be pushed unencrypted to {}. Only continue if you trust that repository.",
+                remote
+            );
+            if !confirm(&app, "Push history to a remote?", message, "Push").await? {
+                return Err(AppError::Cancelled);
+            }
+        }
+    }
+    let repo = match (options.token, current) {
+        (Some(token), _) => grants.redeem(&token, Access::Directory)?,
+        (None, Some(current)) => current.repo,
+        (None, None) => {
+            return Err(AppError::validation(
+                "token",
+                "Pick a folder for the repository",
+            ));
+        }
+    };
+    let checked = repo.clone();
+    tauri::async_runtime::spawn_blocking(move || open_repo(&checked).map(|_| ()))
+        .await
+        .map_err(|e| AppError::internal(format!("Git task failed: {}", e)))??;
+    let settings = HistorySettings {
+        repo,
+        remote: options.remote,
+        last_run_at: None,
+        last_error: None,
+    };
+    save_settings(&db, &workspace_id, &settings).await?;
+    Ok(Some(settings))
+}
+
+#[tauri::command]
+pub async fn get_history_settings(
+    state: tauri::State<'_, DbState>,
+    workspace_id: String,
+) -> Result<Option<HistorySettings>, AppError> {
+    let db = state.db()?;
+    load_settings(&db, &workspace_id).await
+}
+
+#[tauri::command]
+pub async fn list_history(
+    state: tauri::State<'_, DbState>,
+    workspace_id: String,
+    note_id: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<HistoryCommit>, AppError> {
+    let db = state.db()?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+    with_repo(&db, &workspace_id, move |repo| {
+        log(repo, note_id.as_deref(), limit)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn get_history_note(
+    state: tauri::State<'_, DbState>,
+    workspace_id: String,
+    commit_id: String,
+    note_id: String,
+) -> Result<HistoryNote, AppError> {
+    let db = state.db()?;
+    read_note_logic(&db, &workspace_id, commit_id, note_id).await
+}
+
+#[tauri::command]
+pub async fn restore_note_from_history(
+    state: tauri::State<'_, DbState>,
+    workspace_id: String,
+    commit_id: String,
+    note_id: String,
+) -> Result<NoteRecord, AppError> {
+    let db = state.db()?;
+    restore_note_logic(&db, &workspace_id, commit_id, note_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_db;
+
+    async fn setup() -> (Db, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+        (db, dir)
+    }
+
+    fn new_note(title: &str, content: &str) -> NoteRecord {
+        NoteRecord {
+            id: uuid::Uuid::new_v4().to_string(),
+            title: title.to_string(),
+            content: content.to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commits_only_changes_and_pushes() {
+        let (db, dir) = setup().await;
+        let remote = dir.join("remote.git");
+        Repository::init_bare(&remote).unwrap();
+        let settings = HistorySettings {
+            repo: dir.join("history"),
+            remote: Some(remote.display().to_string()),
+            last_run_at: None,
+            last_error: None,
+        };
+        let mut note = new_note("Plan", "v1");
+        db.upsert_note(note.clone()).await.unwrap();
+
+        let first = commit_workspace(&db, "default", &settings)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.message, "Update 1 note\n\nAdded Plan.md");
+        assert_eq!(
+            commit_workspace(&db, "default", &settings).await.unwrap(),
+            None
+        );
+
+        note.content = "v2".to_string();
+        db.upsert_note(note.clone()).await.unwrap();
+        db.upsert_note(new_note("Other", "x")).await.unwrap();
+        let second = commit_workspace(&db, "default", &settings)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(second.message.starts_with("Update 2 notes\n\n"));
+        assert!(second.message.contains("Changed Plan.md"));
+        assert!(second.message.contains("Added Other.md"));
+
+        let pushed = Repository::open(&remote).unwrap();
+        let pushed_head = pushed.find_reference(BRANCH).unwrap().target().unwrap();
+        assert_eq!(pushed_head.to_string(), second.id);
+    }
+
+    #[tokio::test]
+    async fn test_restores_note_from_an_old_commit() {
+        let (db, dir) = setup().await;
+        let history = HistoryService::default();
+        let settings = HistorySettings {
+            repo: dir.join("history"),
+            remote: None,
+            last_run_at: None,
+            last_error: None,
+        };
+        save_settings(&db, "default", &settings).await.unwrap();
+        let mut note = new_note("Plan", "v1");
+        db.upsert_note(note.clone()).await.unwrap();
+        let first = history.run(&db, "default").await.unwrap().unwrap();
+        db.upsert_note(new_note("Other", "x")).await.unwrap();
+        history.run(&db, "default").await.unwrap().unwrap();
+        note.content = "v2".to_string();
+        db.upsert_note(note.clone()).await.unwrap();
+        let third = history.run(&db, "default").await.unwrap().unwrap();
+        db.delete_note(note.id.clone()).await.unwrap();
+
+        let commits = with_repo(&db, "default", {
+            let id = note.id.clone();
+            move |repo| log(repo, Some(&id), 10)
+        })
+        .await
+        .unwrap();
+        assert_eq!(commits, vec![third, first.clone()]);
+
+        let restored = restore_note_logic(&db, "default", first.id, note.id.clone())
+            .await
+            .unwrap();
+        assert_eq!(restored.content, "v1");
+        assert!(!restored.is_deleted);
+        assert!(db
+            .get_notes()
+            .await
+            .unwrap()
+            .iter()
+            .any(|n| n.id == note.id));
+    }
+
+    #[tokio::test]
+    async fn test_refuses_repositories_it_did_not_create() {
+        let (_db, dir) = setup().await;
+        let project = dir.join("project");
+        Repository::init(&project).unwrap();
+        assert!(open_repo(&project).is_err());
+        assert!(open_repo(&project.join(".git")).is_err());
+        let bare = dir.join("bare.git");
+        Repository::init_bare(&bare).unwrap();
+        assert!(open_repo(&bare).is_err());
+
+        let ours = dir.join("history");
+        open_repo(&ours).unwrap();
+        assert!(open_repo(&ours).is_ok());
+    }
+}
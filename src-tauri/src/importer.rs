@@ -1,203 +1,1485 @@
-use crate::db::{Db, FolderRecord, NoteRecord, WorkspaceRecord};
+use crate::db::{now_millis, tx, Db, FolderRecord, NoteRecord, WorkspaceRecord};
+use crate::error::{AppError, Entity};
+use crate::grants::{Access, FileGrants};
+use crate::manifest::{ExportManifest, MANIFEST_FILE};
+use crate::ordering;
+use serde::{Deserialize, Serialize};
+use sqlx::{Sqlite, SqliteConnection, Transaction};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::Emitter;
 use zip::ZipArchive;
 
+/// Event emitted after every imported note, payload is `ImportProgress`.
+pub const IMPORT_PROGRESS_EVENT: &str = "import-progress";
+
 #[derive(Debug)]
 struct ZipEntry {
     name: String,
     path: String,
     is_dir: bool,
+    /// Decoded Markdown; `None` for directories.
     content: Option<String>,
+    modified_at: Option<i64>,
 }
 
-pub async fn import_workspace_logic(
-    db: &Db,
-    zip_path: PathBuf,
-    workspace_name: Option<String>,
-) -> Result<String, String> {
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportProgress {
+    pub job_id: String,
+    pub processed: usize,
+    pub total: usize,
+    pub current_path: String,
+}
+
+/// Progress reporting, cancellation and safety limits for one running import.
+#[derive(Default)]
+pub struct ImportControl {
+    pub job_id: String,
+    pub limits: ImportLimits,
+    cancelled: Arc<AtomicBool>,
+    on_progress: Option<Box<dyn Fn(ImportProgress) + Send + Sync>>,
+}
+
+impl ImportControl {
+    pub fn new(
+        job_id: String,
+        cancelled: Arc<AtomicBool>,
+        on_progress: impl Fn(ImportProgress) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            job_id,
+            limits: ImportLimits::default(),
+            cancelled,
+            on_progress: Some(Box::new(on_progress)),
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<(), AppError> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            Err(AppError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn report(&self, processed: usize, total: usize, current_path: &str) {
+        if let Some(ref on_progress) = self.on_progress {
+            on_progress(ImportProgress {
+                job_id: self.job_id.clone(),
+                processed,
+                total,
+                current_path: current_path.to_string(),
+            });
+        }
+    }
+}
+
+/// Cancellation flags of the imports currently running, keyed by job id.
+#[derive(Default)]
+pub struct ImportJobs {
+    jobs: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl ImportJobs {
+    fn start(
+        &self,
+        app: &tauri::AppHandle,
+        job_id: Option<String>,
+        limits: Option<ImportLimits>,
+    ) -> ImportControl {
+        let job_id = job_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.jobs
+            .lock()
+            .unwrap()
+            .insert(job_id.clone(), cancelled.clone());
+
+        let app = app.clone();
+        let mut control = ImportControl::new(job_id, cancelled, move |progress| {
+            let _ = app.emit(IMPORT_PROGRESS_EVENT, progress);
+        });
+        control.limits = limits.map(ImportLimits::clamped).unwrap_or_default();
+        control
+    }
+
+    fn finish(&self, job_id: &str) {
+        self.jobs.lock().unwrap().remove(job_id);
+    }
+
+    pub fn cancel(&self, job_id: &str) -> bool {
+        match self.jobs.lock().unwrap().get(job_id) {
+            Some(flag) => {
+                flag.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// What to do when an imported note lands next to an existing note with the
+/// same title (same workspace and folder).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateStrategy {
+    /// Leave the existing note untouched and drop the imported one.
+    Skip,
+    /// Replace the existing note's content, recording a revision in `changes`.
+    Overwrite,
+    /// Import alongside the existing note as `Title (2)`, `Title (3)`, ...
+    #[default]
+    KeepBoth,
+    /// Update the note whose id matches the manifest entry. Entries without a
+    /// manifest id, or whose id is unknown here, fall back to keep-both.
+    MergeById,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub workspace_id: String,
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub renamed: usize,
+}
+
+/// Safety limits applied while reading an archive, so a crafted ZIP cannot
+/// exhaust memory or disk. Callers may only tighten the defaults; see
+/// `clamped`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportLimits {
+    /// Maximum number of entries in the archive (files and directories).
+    pub max_entries: usize,
+    /// Maximum total uncompressed size of the entries that get imported.
+    pub max_total_bytes: u64,
+    /// Maximum uncompressed size of one entry, which is held in memory
+    /// while it is imported.
+    pub max_entry_bytes: u64,
+    /// Maximum number of path components of an entry.
+    pub max_depth: usize,
+    /// Maximum uncompressed/compressed size ratio of a single entry.
+    pub max_compression_ratio: u64,
+}
+
+impl Default for ImportLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            max_total_bytes: 512 * 1024 * 1024,
+            max_entry_bytes: 16 * 1024 * 1024,
+            max_depth: 32,
+            max_compression_ratio: 100,
+        }
+    }
+}
+
+impl ImportLimits {
+    /// The limits with every value no looser than the default, so limits
+    /// coming from the webview can only make an import stricter.
+    pub fn clamped(self) -> Self {
+        let default = Self::default();
+        Self {
+            max_entries: self.max_entries.min(default.max_entries),
+            max_total_bytes: self.max_total_bytes.min(default.max_total_bytes),
+            max_entry_bytes: self.max_entry_bytes.min(default.max_entry_bytes),
+            max_depth: self.max_depth.min(default.max_depth),
+            max_compression_ratio: self
+                .max_compression_ratio
+                .min(default.max_compression_ratio),
+        }
+    }
+}
+
+/// Entries smaller than this are not ratio-checked: short, repetitive
+/// Markdown legitimately compresses very well.
+const RATIO_CHECK_MIN_BYTES: u64 = 1024 * 1024;
+
+/// Why an archive was rejected. Serialized with a `reason` code inside
+/// `AppError::ImportInvalid`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ImportError {
+    /// The file could not be opened or is not a readable ZIP.
+    Archive {
+        message: String,
+    },
+    InvalidManifest {
+        message: String,
+    },
+    /// No Markdown file or folder in the archive.
+    Empty,
+    TooManyEntries {
+        count: usize,
+        limit: usize,
+    },
+    TooLarge {
+        limit: u64,
+    },
+    EntryTooLarge {
+        path: String,
+        limit: u64,
+    },
+    PathTooDeep {
+        path: String,
+        limit: usize,
+    },
+    /// Likely a ZIP bomb.
+    CompressionRatio {
+        path: String,
+        ratio: u64,
+        limit: u64,
+    },
+    /// Absolute path, `..` component or control characters in an entry name.
+    UnsafePath {
+        path: String,
+    },
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Archive { message } => write!(f, "{}", message),
+            ImportError::InvalidManifest { message } => {
+                write!(f, "Invalid {}: {}", MANIFEST_FILE, message)
+            }
+            ImportError::Empty => write!(
+                f,
+                "ZIP archive must contain at least one .md file or folder"
+            ),
+            ImportError::TooManyEntries { count, limit } => write!(
+                f,
+                "ZIP archive has {} entries, more than the limit of {}",
+                count, limit
+            ),
+            ImportError::TooLarge { limit } => write!(
+                f,
+                "ZIP archive content exceeds the limit of {} bytes",
+                limit
+            ),
+            ImportError::EntryTooLarge { path, limit } => write!(
+                f,
+                "Entry '{}' is larger than the limit of {} bytes",
+                path, limit
+            ),
+            ImportError::PathTooDeep { path, limit } => {
+                write!(f, "Path '{}' is nested deeper than {} levels", path, limit)
+            }
+            ImportError::CompressionRatio { path, ratio, limit } => write!(
+                f,
+                "Entry '{}' has a compression ratio of {}:1 (limit {}:1)",
+                path, ratio, limit
+            ),
+            ImportError::UnsafePath { path } => write!(f, "Unsafe path in ZIP archive: '{}'", path),
+        }
+    }
+}
+
+/// Everything an import needs from the archive, read before the import
+/// transaction starts. `ImportLimits` bound how much of it is held in memory.
+struct ImportArchive {
+    entries: Vec<ZipEntry>,
+    manifest: Option<ExportManifest>,
+}
+
+/// Reads an entry while charging its real size against the byte budget.
+/// Stops one byte past whichever limit is closer, so neither a lying header
+/// nor a huge entry gets buffered.
+fn read_limited(
+    file: impl Read,
+    remaining: &mut u64,
+    limits: &ImportLimits,
+    name: &str,
+) -> Result<Vec<u8>, ImportError> {
+    let mut bytes = Vec::new();
+    file.take((*remaining).min(limits.max_entry_bytes) + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| ImportError::Archive {
+            message: format!("Failed to read file content {}: {}", name, e),
+        })?;
+    if bytes.len() as u64 > limits.max_entry_bytes {
+        return Err(ImportError::EntryTooLarge {
+            path: name.to_string(),
+            limit: limits.max_entry_bytes,
+        });
+    }
+    if bytes.len() as u64 > *remaining {
+        return Err(ImportError::TooLarge {
+            limit: limits.max_total_bytes,
+        });
+    }
+    *remaining -= bytes.len() as u64;
+    Ok(bytes)
+}
+
+/// Decodes Markdown that is not necessarily UTF-8: a BOM wins, then UTF-8,
+/// then the encoding guessed from the bytes. Never fails; undecodable bytes
+/// become U+FFFD.
+fn decode_text(bytes: Vec<u8>) -> String {
+    if let Some((encoding, bom_len)) = encoding_rs::Encoding::for_bom(&bytes) {
+        return encoding
+            .decode_without_bom_handling(&bytes[bom_len..])
+            .0
+            .into_owned();
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => {
+            let bytes = e.into_bytes();
+            let mut detector = chardetng::EncodingDetector::new();
+            detector.feed(&bytes, true);
+            detector
+                .guess(None, true)
+                .decode_without_bom_handling(&bytes)
+                .0
+                .into_owned()
+        }
+    }
+}
+
+/// Turns an entry name into a relative `a/b/c` path. Backslashes count as
+/// separators and `.`/empty components are dropped; absolute paths, drive
+/// letters, `..` and control characters are rejected.
+fn normalize_entry_path(raw: &str, limits: &ImportLimits) -> Result<String, ImportError> {
+    let unsafe_path = || ImportError::UnsafePath {
+        path: raw.to_string(),
+    };
+    let unified = raw.replace('\\', "/");
+    if unified.starts_with('/') || unified.as_bytes().get(1) == Some(&b':') {
+        return Err(unsafe_path());
+    }
+
+    let mut parts = Vec::new();
+    for part in unified.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => return Err(unsafe_path()),
+            p if p.chars().any(char::is_control) => return Err(unsafe_path()),
+            p => parts.push(p),
+        }
+    }
+
+    if parts.len() > limits.max_depth {
+        return Err(ImportError::PathTooDeep {
+            path: raw.to_string(),
+            limit: limits.max_depth,
+        });
+    }
+    Ok(parts.join("/"))
+}
+
+fn read_archive(zip_path: &Path, limits: &ImportLimits) -> Result<ImportArchive, ImportError> {
     // Open and validate ZIP file
-    let file = File::open(&zip_path)
-        .map_err(|e| format!("Failed to open ZIP file: {}", e))?;
-    
-    let mut archive = ZipArchive::new(file)
-        .map_err(|e| format!("Failed to read ZIP archive: {}", e))?;
+    let file = File::open(zip_path).map_err(|e| ImportError::Archive {
+        message: format!("Failed to open ZIP file: {}", e),
+    })?;
+
+    let mut archive = ZipArchive::new(file).map_err(|e| ImportError::Archive {
+        message: format!("Failed to read ZIP archive: {}", e),
+    })?;
+
+    if archive.len() > limits.max_entries {
+        return Err(ImportError::TooManyEntries {
+            count: archive.len(),
+            limit: limits.max_entries,
+        });
+    }
 
     // Extract all entries from ZIP
     let mut entries: Vec<ZipEntry> = Vec::new();
-    
+    let mut manifest: Option<ExportManifest> = None;
+    let mut declared_bytes: u64 = 0;
+    // Uncompressed bytes still allowed; charged on actual reads, since
+    // declared sizes can lie
+    let mut remaining_bytes = limits.max_total_bytes;
+
     for i in 0..archive.len() {
-        let mut file = archive.by_index(i)
-            .map_err(|e| format!("Failed to read file at index {}: {}", i, e))?;
-        
+        let file = archive.by_index(i).map_err(|e| ImportError::Archive {
+            message: format!("Failed to read file at index {}: {}", i, e),
+        })?;
+
         let name = file.name().to_string();
-        let path = name.trim_end_matches('/').to_string();
         let is_dir = file.is_dir() || name.ends_with('/');
-        
-        let content = if !is_dir && name.ends_with(".md") {
-            let mut content = String::new();
-            file.read_to_string(&mut content)
-                .map_err(|e| format!("Failed to read file content {}: {}", name, e))?;
-            Some(content)
-        } else if !is_dir {
+        let path = normalize_entry_path(&name, limits)?;
+        if path.is_empty() {
+            continue;
+        }
+        let modified_at = zip_time_millis(file.last_modified());
+
+        if !is_dir && path != MANIFEST_FILE && !path.ends_with(".md") {
             // Skip non-MD files
             continue;
-        } else {
-            None
+        }
+
+        if !is_dir {
+            if file.size() > limits.max_entry_bytes {
+                return Err(ImportError::EntryTooLarge {
+                    path,
+                    limit: limits.max_entry_bytes,
+                });
+            }
+            declared_bytes = declared_bytes.saturating_add(file.size());
+            if declared_bytes > limits.max_total_bytes {
+                return Err(ImportError::TooLarge {
+                    limit: limits.max_total_bytes,
+                });
+            }
+            let ratio = file.size() / file.compressed_size().max(1);
+            if file.size() >= RATIO_CHECK_MIN_BYTES && ratio > limits.max_compression_ratio {
+                return Err(ImportError::CompressionRatio {
+                    path,
+                    ratio,
+                    limit: limits.max_compression_ratio,
+                });
+            }
+        }
+
+        if path == MANIFEST_FILE {
+            let raw = read_limited(file, &mut remaining_bytes, limits, &name)?;
+            manifest =
+                Some(
+                    serde_json::from_slice(&raw).map_err(|e| ImportError::InvalidManifest {
+                        message: e.to_string(),
+                    })?,
+                );
+            continue;
+        }
+
+        let content = match is_dir {
+            true => None,
+            false => Some(decode_text(read_limited(
+                file,
+                &mut remaining_bytes,
+                limits,
+                &name,
+            )?)),
         };
-        
         entries.push(ZipEntry {
-            name: Path::new(&name)
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or(&name)
-                .to_string(),
+            name: path.rsplit('/').next().unwrap_or(&path).to_string(),
             path,
             is_dir,
             content,
+            modified_at,
         });
     }
 
+    // Folders that only exist in the manifest (e.g. empty ones) still get created
+    if let Some(ref mut manifest) = manifest {
+        for folder in manifest.folders.iter_mut() {
+            folder.path = normalize_entry_path(&folder.path, limits)?;
+            if !entries.iter().any(|e| e.is_dir && e.path == folder.path) {
+                entries.push(ZipEntry {
+                    name: folder.name.clone(),
+                    path: folder.path.clone(),
+                    is_dir: true,
+                    content: None,
+                    modified_at: None,
+                });
+            }
+        }
+    }
+
+    // Many archivers omit directory entries, so derive them from file paths
+    let implied_dirs: Vec<String> = entries
+        .iter()
+        .filter(|e| !e.is_dir)
+        .flat_map(|e| {
+            let parts: Vec<&str> = e.path.split('/').filter(|s| !s.is_empty()).collect();
+            (1..parts.len())
+                .map(|depth| parts[..depth].join("/"))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for dir in implied_dirs {
+        if !entries.iter().any(|e| e.is_dir && e.path == dir) {
+            entries.push(ZipEntry {
+                name: dir.rsplit('/').next().unwrap_or(&dir).to_string(),
+                path: dir,
+                is_dir: true,
+                content: None,
+                modified_at: None,
+            });
+        }
+    }
+
     // Validate ZIP structure: must have at least one .md file or folder
-    let has_md_files = entries.iter().any(|e| !e.is_dir && e.content.is_some());
+    let has_md_files = entries.iter().any(|e| !e.is_dir);
     let has_folders = entries.iter().any(|e| e.is_dir);
-    
+
     if !has_md_files && !has_folders {
-        return Err("ZIP file không hợp lệ: Phải chứa ít nhất một file .md hoặc folder".to_string());
+        return Err(ImportError::Empty);
     }
 
-    // Check if all non-directory entries are .md files
-    for entry in &entries {
-        if !entry.is_dir && entry.content.is_none() {
-            return Err(format!("ZIP file không hợp lệ: File '{}' không phải là file .md", entry.name));
+    Ok(ImportArchive { entries, manifest })
+}
+
+/// Reads the archive on a blocking thread, so no ZIP I/O happens on the async
+/// runtime or while the import transaction is open.
+async fn load_archive(zip_path: &Path, limits: &ImportLimits) -> Result<ImportArchive, AppError> {
+    let path = zip_path.to_path_buf();
+    let limits = *limits;
+    let archive = tokio::task::spawn_blocking(move || read_archive(&path, &limits))
+        .await
+        .map_err(|e| AppError::internal(format!("Archive reader failed: {}", e)))??;
+    Ok(archive)
+}
+
+/// Commits the import transaction on success and rolls it back on error or
+/// cancellation, so a failed import never leaves partial data behind.
+async fn finish_transaction<T>(
+    tx: Transaction<'_, Sqlite>,
+    result: Result<T, AppError>,
+) -> Result<T, AppError> {
+    match result {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            tx.rollback().await?;
+            Err(e)
         }
     }
+}
+
+pub async fn import_workspace_logic(
+    db: &Db,
+    zip_path: PathBuf,
+    workspace_name: Option<String>,
+    control: &ImportControl,
+) -> Result<String, AppError> {
+    let archive = load_archive(&zip_path, &control.limits).await?;
+
+    let mut tx = db.pool.begin().await?;
+    let result = import_new_workspace(&mut tx, archive, &zip_path, workspace_name, control).await;
+    let workspace_id = finish_transaction(tx, result).await?;
+    db.notify_changed();
+    Ok(workspace_id)
+}
 
+async fn import_new_workspace(
+    conn: &mut SqliteConnection,
+    archive: ImportArchive,
+    zip_path: &Path,
+    workspace_name: Option<String>,
+    control: &ImportControl,
+) -> Result<String, AppError> {
     // Create workspace
-    let workspace_id = uuid::Uuid::new_v4().to_string();
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as i64;
-    
+    let now = now_millis();
+
+    let ws_meta = archive.manifest.as_ref().map(|m| m.workspace.clone());
+    let ws_meta = ws_meta.as_ref();
+    let workspace_id = match ws_meta {
+        Some(meta) => fresh_id(&meta.id, tx::has_workspace(conn, &meta.id).await?),
+        None => uuid::Uuid::new_v4().to_string(),
+    };
+
     let workspace = WorkspaceRecord {
         id: workspace_id.clone(),
-        name: workspace_name.unwrap_or_else(|| {
-            zip_path.file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("Imported Workspace")
-                .to_string()
-        }),
-        color: "#4F7DF3".to_string(),
-        created_at: now,
-        updated_at: now,
-        version: 1,
+        name: workspace_name
+            .or_else(|| ws_meta.map(|m| m.name.clone()))
+            .unwrap_or_else(|| {
+                zip_path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("Imported Workspace")
+                    .to_string()
+            }),
+        color: ws_meta
+            .map(|m| m.color.clone())
+            .unwrap_or_else(|| "#4F7DF3".to_string()),
+        created_at: ws_meta.map(|m| m.created_at).unwrap_or(now),
+        updated_at: ws_meta.map(|m| m.updated_at).unwrap_or(now),
+        version: ws_meta.map(|m| m.version).unwrap_or(1),
         is_deleted: false,
     };
-    
-    db.upsert_workspace(workspace).await?;
+
+    tx::restore_workspace(conn, &workspace).await?;
+
+    import_tree(
+        conn,
+        archive,
+        &workspace_id,
+        None,
+        DuplicateStrategy::KeepBoth,
+        control,
+    )
+    .await?;
+
+    Ok(workspace_id)
+}
+
+/// Imports a ZIP into an existing workspace, optionally below `folder_id`.
+/// Folders are matched by name and reused; notes that collide with an
+/// existing note are handled according to `strategy`.
+pub async fn import_into_workspace_logic(
+    db: &Db,
+    zip_path: PathBuf,
+    workspace_id: String,
+    folder_id: Option<String>,
+    strategy: DuplicateStrategy,
+    control: &ImportControl,
+) -> Result<ImportSummary, AppError> {
+    if !db
+        .get_workspaces()
+        .await?
+        .iter()
+        .any(|w| w.id == workspace_id)
+    {
+        return Err(AppError::not_found(Entity::Workspace, workspace_id));
+    }
+    if let Some(ref folder_id) = folder_id {
+        let target_ok = db
+            .get_folders()
+            .await?
+            .iter()
+            .any(|f| &f.id == folder_id && f.workspace_id == workspace_id);
+        if !target_ok {
+            return Err(AppError::not_found(Entity::Folder, folder_id.as_str()));
+        }
+    }
+
+    let archive = load_archive(&zip_path, &control.limits).await?;
+
+    let mut tx = db.pool.begin().await?;
+    let result = import_tree(
+        &mut tx,
+        archive,
+        &workspace_id,
+        folder_id,
+        strategy,
+        control,
+    )
+    .await;
+    let summary = finish_transaction(tx, result).await?;
+    db.notify_changed();
+    Ok(summary)
+}
+
+/// Creates the archive's folders and notes under `base_folder_id` of the
+/// given workspace, reusing folders that already exist by name.
+async fn import_tree(
+    conn: &mut SqliteConnection,
+    archive: ImportArchive,
+    workspace_id: &str,
+    base_folder_id: Option<String>,
+    strategy: DuplicateStrategy,
+    control: &ImportControl,
+) -> Result<ImportSummary, AppError> {
+    let ImportArchive { entries, manifest } = archive;
+    let now = now_millis();
+
+    let mut summary = ImportSummary {
+        workspace_id: workspace_id.to_string(),
+        ..Default::default()
+    };
+
+    // Existing records of the target workspace, kept current while importing
+    // so duplicates inside the archive itself are detected as well
+    let mut existing_folders = tx::workspace_folders(conn, workspace_id).await?;
+    let mut existing_notes = tx::workspace_notes(conn, workspace_id).await?;
 
     // Build folder structure
     let mut folder_map: HashMap<String, String> = HashMap::new(); // path -> folder_id
-    
+
     // Sort entries: directories first, then files
     let mut dir_entries: Vec<_> = entries.iter().filter(|e| e.is_dir).collect();
     let file_entries: Vec<_> = entries.iter().filter(|e| !e.is_dir).collect();
-    
+
     dir_entries.sort_by(|a, b| {
         let depth_a = a.path.matches('/').count();
         let depth_b = b.path.matches('/').count();
         depth_a.cmp(&depth_b)
     });
 
+    let total = file_entries.len();
+    control.report(0, total, "");
+
     // Create folders recursively
     for entry in dir_entries {
+        control.check_cancelled()?;
         let path_parts: Vec<&str> = entry.path.split('/').filter(|s| !s.is_empty()).collect();
         if path_parts.is_empty() {
             continue;
         }
-        
+
         let mut current_path = String::new();
-        let mut parent_id: Option<String> = None;
-        
+        let mut parent_id: Option<String> = base_folder_id.clone();
+
         for part in path_parts.iter() {
             if !current_path.is_empty() {
                 current_path.push('/');
             }
             current_path.push_str(part);
-            
+
             if let Some(existing_id) = folder_map.get(&current_path) {
                 parent_id = Some(existing_id.clone());
                 continue;
             }
-            
-            let folder_id = uuid::Uuid::new_v4().to_string();
+
+            let meta = manifest
+                .as_ref()
+                .and_then(|m| m.folder_by_path(&current_path));
+            let fallback_time = entries
+                .iter()
+                .find(|e| e.is_dir && e.path == current_path)
+                .and_then(|e| e.modified_at)
+                .unwrap_or(now);
+
+            // Reuse a matching folder of the target instead of duplicating it
+            let reused = existing_folders
+                .iter()
+                .find(|f| {
+                    strategy == DuplicateStrategy::MergeById && meta.is_some_and(|m| m.id == f.id)
+                })
+                .or_else(|| {
+                    existing_folders
+                        .iter()
+                        .find(|f| f.parent_id == parent_id && f.name == *part)
+                });
+            if let Some(existing) = reused {
+                folder_map.insert(current_path.clone(), existing.id.clone());
+                parent_id = Some(existing.id.clone());
+                continue;
+            }
+
+            let folder_id = match meta {
+                Some(meta) => fresh_id(&meta.id, tx::has_folder(conn, &meta.id).await?),
+                None => uuid::Uuid::new_v4().to_string(),
+            };
             folder_map.insert(current_path.clone(), folder_id.clone());
-            
+
             // Create folder record
             let folder = FolderRecord {
                 id: folder_id.clone(),
-                name: part.to_string(),
+                name: meta
+                    .map(|m| m.name.clone())
+                    .unwrap_or_else(|| part.to_string()),
                 parent_id: parent_id.clone(),
-                workspace_id: workspace_id.clone(),
-                created_at: now,
-                updated_at: now,
-                version: 1,
-                color: None,
-                is_deleted: false,
+                workspace_id: workspace_id.to_string(),
+                created_at: meta.map(|m| m.created_at).unwrap_or(fallback_time),
+                updated_at: meta.map(|m| m.updated_at).unwrap_or(fallback_time),
+                version: meta.map(|m| m.version).unwrap_or(1),
+                color: meta.and_then(|m| m.color.clone()),
+                sort_key: meta
+                    .map(|m| manifest_key(&m.sort_key, m.index))
+                    .unwrap_or_default(),
+                is_deleted: meta.is_some_and(|m| m.is_deleted),
             };
-            
-            db.upsert_folder(folder).await?;
+
+            tx::restore_folder(conn, &folder).await?;
+            existing_folders.push(folder);
             parent_id = Some(folder_id);
         }
     }
 
     // Create notes
-    for entry in file_entries {
-        if let Some(ref content) = entry.content {
+    for (processed, entry) in file_entries.into_iter().enumerate() {
+        control.report(processed, total, &entry.path);
+        control.check_cancelled()?;
+
+        if let Some(content) = &entry.content {
             let path_parts: Vec<&str> = entry.path.split('/').filter(|s| !s.is_empty()).collect();
-            
-            // Get note title from filename (remove .md extension)
+
+            let meta = manifest.as_ref().and_then(|m| m.note_by_path(&entry.path));
+
+            // Get note title from the manifest, else from filename (remove .md extension)
             let filename = entry.name.trim_end_matches(".md");
-            let note_title = if filename.is_empty() { "Untitled" } else { filename };
-            
+            let note_title = match meta {
+                Some(meta) => meta.title.as_str(),
+                None if filename.is_empty() => "Untitled",
+                None => filename,
+            };
+
             // Determine parent folder
             let folder_id = if path_parts.len() > 1 {
                 // Note is in a folder
                 let folder_path = path_parts[..path_parts.len() - 1].join("/");
                 folder_map.get(&folder_path).cloned()
             } else {
-                // Note is in root
-                None
+                // Note is in the import root
+                base_folder_id.clone()
+            };
+
+            // Merge by id: update the note this manifest entry was exported from
+            if strategy == DuplicateStrategy::MergeById {
+                let target = meta.and_then(|m| existing_notes.iter_mut().find(|n| n.id == m.id));
+                if let Some(existing) = target {
+                    if existing.title == note_title && existing.content == *content {
+                        summary.skipped += 1;
+                    } else {
+                        existing.title = note_title.to_string();
+                        existing.content = content.clone();
+                        tx::upsert_note(conn, existing.clone()).await?;
+                        summary.updated += 1;
+                    }
+                    continue;
+                }
+            }
+
+            let mut title = note_title.to_string();
+            let duplicate = existing_notes
+                .iter_mut()
+                .find(|n| n.folder_id == folder_id && n.title == note_title);
+            if let Some(existing) = duplicate {
+                match strategy {
+                    DuplicateStrategy::Skip => {
+                        summary.skipped += 1;
+                        continue;
+                    }
+                    DuplicateStrategy::Overwrite => {
+                        if existing.content == *content {
+                            summary.skipped += 1;
+                        } else {
+                            existing.content = content.clone();
+                            tx::upsert_note(conn, existing.clone()).await?;
+                            summary.updated += 1;
+                        }
+                        continue;
+                    }
+                    DuplicateStrategy::KeepBoth | DuplicateStrategy::MergeById => {
+                        title = unique_title(&existing_notes, &folder_id, note_title);
+                        summary.renamed += 1;
+                    }
+                }
+            }
+
+            let note_id = match meta {
+                Some(meta) => fresh_id(&meta.id, tx::has_note(conn, &meta.id).await?),
+                None => uuid::Uuid::new_v4().to_string(),
             };
-            
+            let fallback_time = entry.modified_at.unwrap_or(now);
+
             let note = NoteRecord {
-                id: uuid::Uuid::new_v4().to_string(),
-                title: note_title.to_string(),
+                id: note_id,
+                title,
                 content: content.clone(),
                 folder_id,
-                workspace_id: workspace_id.clone(),
-                created_at: now,
-                updated_at: now,
-                version: 1,
-                is_deleted: false,
+                workspace_id: workspace_id.to_string(),
+                created_at: meta.map(|m| m.created_at).unwrap_or(fallback_time),
+                updated_at: meta.map(|m| m.updated_at).unwrap_or(fallback_time),
+                version: meta.map(|m| m.version).unwrap_or(1),
+                sort_key: meta
+                    .map(|m| manifest_key(&m.sort_key, m.index))
+                    .unwrap_or_default(),
+                color: meta.and_then(|m| m.color.clone()),
+                is_deleted: meta.is_some_and(|m| m.is_deleted),
             };
-            
-            db.upsert_note(note).await?;
+
+            tx::restore_note(conn, &note).await?;
+            existing_notes.push(note);
+            summary.created += 1;
         }
     }
+    control.report(total, total, "");
 
-    Ok(workspace_id)
+    Ok(summary)
+}
+
+/// Sidebar position for an item described by the manifest: its exported key,
+/// or one derived from its index when the manifest predates keys. Items
+/// without a manifest entry keep an empty key and sort by creation time.
+fn manifest_key(sort_key: &str, index: usize) -> String {
+    if ordering::is_valid_key(sort_key) {
+        sort_key.to_string()
+    } else {
+        ordering::key_at(index)
+    }
+}
+
+/// Returns `Title (2)`, `Title (3)`, ... whichever is first free in the folder.
+fn unique_title(notes: &[NoteRecord], folder_id: &Option<String>, title: &str) -> String {
+    (2..)
+        .map(|n| format!("{} ({})", title, n))
+        .find(|candidate| {
+            !notes
+                .iter()
+                .any(|n| &n.folder_id == folder_id && &n.title == candidate)
+        })
+        .unwrap()
+}
+
+/// Keeps the exported id unless it is already taken locally (e.g. importing
+/// an export of this very database), in which case a new one is generated.
+fn fresh_id(exported_id: &str, taken: bool) -> String {
+    if taken || exported_id.is_empty() {
+        uuid::Uuid::new_v4().to_string()
+    } else {
+        exported_id.to_string()
+    }
+}
+
+/// Converts a ZIP entry time (no timezone, read as UTC) into milliseconds.
+/// Returns `None` for the all-zero default some archivers write.
+fn zip_time_millis(time: zip::DateTime) -> Option<i64> {
+    if time.datepart() == zip::DateTime::default().datepart()
+        && time.timepart() == zip::DateTime::default().timepart()
+    {
+        return None;
+    }
+    time.to_time().ok().map(|t| t.unix_timestamp() * 1000)
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn import_workspace(
+    app: tauri::AppHandle,
     state: tauri::State<'_, crate::DbState>,
-    zip_path: String,
+    jobs: tauri::State<'_, ImportJobs>,
+    grants: tauri::State<'_, FileGrants>,
+    token: String,
     workspace_name: Option<String>,
-) -> Result<String, String> {
-    let path = PathBuf::from(zip_path);
-    import_workspace_logic(&state.db, path, workspace_name).await
+    job_id: Option<String>,
+    limits: Option<ImportLimits>,
+) -> Result<String, AppError> {
+    let db = state.db()?;
+    let path = grants.redeem(&token, Access::Read)?;
+    let control = jobs.start(&app, job_id, limits);
+    let result = import_workspace_logic(&db, path, workspace_name, &control).await;
+    jobs.finish(&control.job_id);
+    result
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn import_into_workspace(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, crate::DbState>,
+    jobs: tauri::State<'_, ImportJobs>,
+    grants: tauri::State<'_, FileGrants>,
+    token: String,
+    workspace_id: String,
+    folder_id: Option<String>,
+    strategy: Option<DuplicateStrategy>,
+    job_id: Option<String>,
+    limits: Option<ImportLimits>,
+) -> Result<ImportSummary, AppError> {
+    let db = state.db()?;
+    let path = grants.redeem(&token, Access::Read)?;
+    let control = jobs.start(&app, job_id, limits);
+    let result = import_into_workspace_logic(
+        &db,
+        path,
+        workspace_id,
+        folder_id,
+        strategy.unwrap_or_default(),
+        &control,
+    )
+    .await;
+    jobs.finish(&control.job_id);
+    result
+}
+
+/// Requests cancellation of a running import; it rolls back at the next entry.
+#[tauri::command]
+pub fn cancel_import(jobs: tauri::State<'_, ImportJobs>, job_id: String) -> bool {
+    jobs.cancel(&job_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_db;
+    use crate::exporter::export_workspace_logic;
+
+    async fn setup_test_db() -> (Db, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pool = init_db(dir.clone()).await.unwrap();
+        (Db::new(pool), dir)
+    }
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let files: Vec<(&str, &[u8])> = files.iter().map(|(n, c)| (*n, c.as_bytes())).collect();
+        write_zip_bytes(path, &files);
+    }
+
+    fn write_zip_bytes(path: &Path, files: &[(&str, &[u8])]) {
+        use std::io::Write;
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in files {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn archive_error(files: &[(&str, &[u8])], limits: ImportLimits) -> ImportError {
+        let path = std::env::temp_dir().join(format!("lumenote-test-{}.zip", uuid::Uuid::new_v4()));
+        write_zip_bytes(&path, files);
+        let result = read_archive(&path, &limits).map(|_| ());
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap_err()
+    }
+
+    async fn seed_note(db: &Db, id: &str, title: &str, content: &str) {
+        db.upsert_note(NoteRecord {
+            id: id.to_string(),
+            title: title.to_string(),
+            content: content.to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_export_import_round_trip_preserves_metadata() {
+        let (source, dir) = setup_test_db().await;
+
+        let mut conn = source.pool.acquire().await.unwrap();
+        tx::restore_workspace(
+            &mut conn,
+            &WorkspaceRecord {
+                id: "ws-1".to_string(),
+                name: "Projects".to_string(),
+                color: "#FF8800".to_string(),
+                created_at: 1_600_000_000_000,
+                updated_at: 1_650_000_000_000,
+                version: 3,
+                is_deleted: false,
+            },
+        )
+        .await
+        .unwrap();
+        tx::restore_folder(
+            &mut conn,
+            &FolderRecord {
+                id: "folder-1".to_string(),
+                name: "Research".to_string(),
+                parent_id: None,
+                workspace_id: "ws-1".to_string(),
+                created_at: 1_610_000_000_000,
+                updated_at: 1_620_000_000_000,
+                version: 2,
+                color: Some("#00AA00".to_string()),
+                sort_key: String::new(),
+                is_deleted: false,
+            },
+        )
+        .await
+        .unwrap();
+        tx::restore_note(
+            &mut conn,
+            &NoteRecord {
+                id: "note-1".to_string(),
+                title: "Reading list".to_string(),
+                content: "# Books".to_string(),
+                folder_id: Some("folder-1".to_string()),
+                workspace_id: "ws-1".to_string(),
+                created_at: 1_611_000_000_000,
+                updated_at: 1_621_000_000_000,
+                version: 5,
+                sort_key: "k".to_string(),
+                color: Some("#E94F37".to_string()),
+                is_deleted: false,
+            },
+        )
+        .await
+        .unwrap();
+        // Created later but moved above note-1 in the sidebar
+        tx::restore_note(
+            &mut conn,
+            &NoteRecord {
+                id: "note-2".to_string(),
+                title: "Later".to_string(),
+                content: String::new(),
+                folder_id: Some("folder-1".to_string()),
+                workspace_id: "ws-1".to_string(),
+                created_at: 1_612_000_000_000,
+                updated_at: 1_612_000_000_000,
+                version: 1,
+                sort_key: "V".to_string(),
+                color: None,
+                is_deleted: false,
+            },
+        )
+        .await
+        .unwrap();
+
+        let zip_path = dir.join("export.zip");
+        export_workspace_logic(&source, "ws-1".to_string(), zip_path.clone())
+            .await
+            .unwrap();
+
+        // Import into a fresh database: ids and metadata survive
+        let (target, _) = setup_test_db().await;
+        let ws_id =
+            import_workspace_logic(&target, zip_path.clone(), None, &ImportControl::default())
+                .await
+                .unwrap();
+        assert_eq!(ws_id, "ws-1");
+
+        let workspace = target
+            .get_workspaces()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|w| w.id == ws_id)
+            .unwrap();
+        assert_eq!(workspace.name, "Projects");
+        assert_eq!(workspace.color, "#FF8800");
+        assert_eq!(workspace.created_at, 1_600_000_000_000);
+        assert_eq!(workspace.updated_at, 1_650_000_000_000);
+
+        let folders = target.get_folders().await.unwrap();
+        assert_eq!(folders.len(), 1);
+        assert_eq!(folders[0].id, "folder-1");
+        assert_eq!(folders[0].color.as_deref(), Some("#00AA00"));
+        assert_eq!(folders[0].created_at, 1_610_000_000_000);
+
+        let notes = target.get_notes().await.unwrap();
+        let ids: Vec<&str> = notes.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids, ["note-2", "note-1"]);
+        assert_eq!(notes[1].title, "Reading list");
+        assert_eq!(notes[1].folder_id.as_deref(), Some("folder-1"));
+        assert_eq!(notes[1].created_at, 1_611_000_000_000);
+        assert_eq!(notes[1].updated_at, 1_621_000_000_000);
+        assert_eq!(notes[1].version, 5);
+        assert_eq!(notes[1].sort_key, "k");
+        assert_eq!(notes[1].color.as_deref(), Some("#E94F37"));
+
+        // Importing back into the source database must not reuse taken ids
+        let copy_id = import_workspace_logic(
+            &source,
+            zip_path,
+            Some("Copy".to_string()),
+            &ImportControl::default(),
+        )
+        .await
+        .unwrap();
+        assert_ne!(copy_id, "ws-1");
+        let notes = source.get_notes().await.unwrap();
+        assert_eq!(notes.len(), 4);
+        assert!(notes
+            .iter()
+            .any(|n| n.workspace_id == copy_id && n.id != "note-1"));
+    }
+
+    #[tokio::test]
+    async fn test_import_into_workspace_duplicate_strategies() {
+        let (db, dir) = setup_test_db().await;
+        seed_note(&db, "existing", "Plan", "old").await;
+
+        let zip_path = dir.join("update.zip");
+        write_zip(
+            &zip_path,
+            &[("Plan.md", "new"), ("Ideas/Other.md", "other")],
+        );
+
+        // Skip leaves the existing note alone, new notes and folders still land
+        let summary = import_into_workspace_logic(
+            &db,
+            zip_path.clone(),
+            "default".to_string(),
+            None,
+            DuplicateStrategy::Skip,
+            &ImportControl::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!((summary.created, summary.skipped), (1, 1));
+
+        // Overwrite updates in place and records a revision; the folder is reused
+        let summary = import_into_workspace_logic(
+            &db,
+            zip_path.clone(),
+            "default".to_string(),
+            None,
+            DuplicateStrategy::Overwrite,
+            &ImportControl::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            (summary.updated, summary.skipped, summary.created),
+            (1, 1, 0)
+        );
+        let notes = db.get_notes().await.unwrap();
+        let plan = notes.iter().find(|n| n.id == "existing").unwrap();
+        assert_eq!(plan.content, "new");
+        assert_eq!(plan.version, 2);
+        assert_eq!(db.get_folders().await.unwrap().len(), 1);
+        let revisions: (i64,) =
+            sqlx::query_as("SELECT count(*) FROM changes WHERE note_id = 'existing'")
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(revisions.0, 2);
+
+        // Keep both adds a suffixed copy
+        let summary = import_into_workspace_logic(
+            &db,
+            zip_path,
+            "default".to_string(),
+            None,
+            DuplicateStrategy::KeepBoth,
+            &ImportControl::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!((summary.created, summary.renamed), (2, 2));
+        let notes = db.get_notes().await.unwrap();
+        assert!(notes.iter().any(|n| n.title == "Plan (2)"));
+        assert!(notes.iter().any(|n| n.title == "Other (2)"));
+    }
+
+    #[tokio::test]
+    async fn test_import_into_workspace_merge_by_id() {
+        let (db, dir) = setup_test_db().await;
+        seed_note(&db, "note-1", "Renamed locally", "v1").await;
+
+        let zip_path = dir.join("colleague.zip");
+        let manifest = serde_json::json!({
+            "format_version": 1,
+            "workspace": {
+                "id": "remote-ws", "name": "Remote", "color": "#000000",
+                "created_at": 0, "updated_at": 0, "version": 1
+            },
+            "notes": [{
+                "id": "note-1", "path": "Spec.md", "title": "Spec", "folder_id": null,
+                "created_at": 0, "updated_at": 0, "version": 1, "index": 0
+            }]
+        });
+        write_zip(
+            &zip_path,
+            &[("Spec.md", "v2"), (MANIFEST_FILE, &manifest.to_string())],
+        );
+
+        let summary = import_into_workspace_logic(
+            &db,
+            zip_path,
+            "default".to_string(),
+            None,
+            DuplicateStrategy::MergeById,
+            &ImportControl::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!((summary.updated, summary.created), (1, 0));
+
+        let notes = db.get_notes().await.unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "Spec");
+        assert_eq!(notes[0].content, "v2");
+    }
+
+    #[tokio::test]
+    async fn test_manifest_without_keys_orders_by_index() {
+        let (db, dir) = setup_test_db().await;
+        let zip_path = dir.join("old.zip");
+        let manifest = serde_json::json!({
+            "format_version": 1,
+            "workspace": {
+                "id": "old-ws", "name": "Old", "color": "#000000",
+                "created_at": 0, "updated_at": 0, "version": 1
+            },
+            "notes": [{
+                "id": "a", "path": "A.md", "title": "A", "folder_id": null,
+                "created_at": 0, "updated_at": 0, "version": 1, "index": 1
+            }, {
+                "id": "b", "path": "B.md", "title": "B", "folder_id": null,
+                "created_at": 5, "updated_at": 5, "version": 1, "index": 0
+            }]
+        });
+        write_zip(
+            &zip_path,
+            &[
+                ("A.md", "a"),
+                ("B.md", "b"),
+                (MANIFEST_FILE, &manifest.to_string()),
+            ],
+        );
+
+        import_workspace_logic(&db, zip_path, None, &ImportControl::default())
+            .await
+            .unwrap();
+        let ids: Vec<String> = db
+            .get_notes()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|n| n.id)
+            .collect();
+        assert_eq!(ids, ["b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_import_reports_progress() {
+        let (db, dir) = setup_test_db().await;
+        let zip_path = dir.join("progress.zip");
+        write_zip(&zip_path, &[("A.md", "a"), ("Sub/B.md", "b")]);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let control = ImportControl::new("job".to_string(), Arc::default(), move |p| {
+            sink.lock()
+                .unwrap()
+                .push((p.processed, p.total, p.current_path));
+        });
+        import_workspace_logic(&db, zip_path, None, &control)
+            .await
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.first(), Some(&(0, 2, String::new())));
+        assert!(events.iter().any(|e| e.2 == "Sub/B.md"));
+        assert_eq!(events.last(), Some(&(2, 2, String::new())));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_import_rolls_back() {
+        let (db, dir) = setup_test_db().await;
+        let zip_path = dir.join("cancel.zip");
+        write_zip(&zip_path, &[("A.md", "a"), ("B.md", "b")]);
+
+        // Cancel as soon as the first note is reported
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let flag = cancelled.clone();
+        let control = ImportControl::new("job".to_string(), cancelled, move |p| {
+            if p.processed == 1 {
+                flag.store(true, Ordering::SeqCst);
+            }
+        });
+        let workspaces_before = db.get_workspaces().await.unwrap().len();
+
+        let result = import_workspace_logic(&db, zip_path, None, &control).await;
+        assert_eq!(result, Err(AppError::Cancelled));
+        assert_eq!(db.get_workspaces().await.unwrap().len(), workspaces_before);
+        assert!(db.get_notes().await.unwrap().is_empty());
+        assert!(db.get_folders().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rejects_unsafe_paths() {
+        for name in [
+            "../evil.md",
+            "a/../../evil.md",
+            "/etc/evil.md",
+            "C:/evil.md",
+            "..\\evil.md",
+        ] {
+            assert!(
+                matches!(
+                    archive_error(&[(name, b"x")], ImportLimits::default()),
+                    ImportError::UnsafePath { .. }
+                ),
+                "{} was accepted",
+                name
+            );
+        }
+        assert_eq!(
+            normalize_entry_path("./a\\b//c.md", &ImportLimits::default()),
+            Ok("a/b/c.md".to_string())
+        );
+    }
+
+    #[test]
+    fn test_enforces_limits() {
+        let limits = ImportLimits {
+            max_entries: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            archive_error(&[("a.md", b"a"), ("b.md", b"b"), ("c.md", b"c")], limits),
+            ImportError::TooManyEntries { count: 3, limit: 2 }
+        );
+
+        let limits = ImportLimits {
+            max_total_bytes: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            archive_error(&[("a.md", b"abc"), ("b.md", b"abc")], limits),
+            ImportError::TooLarge { limit: 4 }
+        );
+
+        let limits = ImportLimits {
+            max_depth: 2,
+            ..Default::default()
+        };
+        assert!(matches!(
+            archive_error(&[("a/b/c.md", b"x")], limits),
+            ImportError::PathTooDeep { .. }
+        ));
+
+        let limits = ImportLimits {
+            max_entry_bytes: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            archive_error(&[("a.md", b"a"), ("b.md", b"abcde")], limits),
+            ImportError::EntryTooLarge {
+                path: "b.md".to_string(),
+                limit: 4
+            }
+        );
+
+        let bomb = vec![0u8; 4 * 1024 * 1024];
+        assert!(matches!(
+            archive_error(&[("bomb.md", &bomb)], ImportLimits::default()),
+            ImportError::CompressionRatio { .. }
+        ));
+    }
+
+    #[test]
+    fn test_supplied_limits_cannot_be_looser_than_the_defaults() {
+        let loose = ImportLimits {
+            max_entries: usize::MAX,
+            max_total_bytes: u64::MAX,
+            max_entry_bytes: u64::MAX,
+            max_depth: 1000,
+            max_compression_ratio: u64::MAX,
+        }
+        .clamped();
+        let default = ImportLimits::default();
+        assert_eq!(loose.max_entries, default.max_entries);
+        assert_eq!(loose.max_total_bytes, default.max_total_bytes);
+        assert_eq!(loose.max_entry_bytes, default.max_entry_bytes);
+        assert_eq!(loose.max_depth, default.max_depth);
+        assert_eq!(loose.max_compression_ratio, default.max_compression_ratio);
+
+        let strict = ImportLimits {
+            max_entries: 5,
+            ..default
+        }
+        .clamped();
+        assert_eq!(strict.max_entries, 5);
+    }
+
+    #[tokio::test]
+    async fn test_imports_non_utf8_markdown() {
+        let (db, dir) = setup_test_db().await;
+        let zip_path = dir.join("legacy.zip");
+        // "Café" in Windows-1252, and UTF-16LE with a BOM
+        write_zip_bytes(
+            &zip_path,
+            &[
+                ("cp1252.md", b"Caf\xe9 au lait"),
+                ("utf16.md", &[0xFF, 0xFE, b'h', 0, b'i', 0]),
+            ],
+        );
+
+        import_workspace_logic(&db, zip_path, None, &ImportControl::default())
+            .await
+            .unwrap();
+
+        let notes = db.get_notes().await.unwrap();
+        let cp1252 = notes.iter().find(|n| n.title == "cp1252").unwrap();
+        assert_eq!(cp1252.content, "Café au lait");
+        let utf16 = notes.iter().find(|n| n.title == "utf16").unwrap();
+        assert_eq!(utf16.content, "hi");
+    }
 }
@@ -1,41 +1,91 @@
+mod archive;
+mod backup;
+mod crypto;
 mod db;
+mod document;
+mod docx;
+mod epub;
+mod error;
 mod exporter;
+mod grants;
+mod history;
 mod importer;
+mod maintenance;
+mod manifest;
+mod migrations;
+mod mirror;
+mod ordering;
+mod pdf;
+mod recovery;
+mod site;
+mod sync;
+mod vault;
+mod webdav;
 
+use archive::{export_full_backup, import_full_backup};
+use backup::{
+    create_backup, get_backup_settings, list_backups, restore_backup, set_backup_settings,
+    verify_backup,
+};
 use db::{
-    apply_remote_update_folder, apply_remote_update_note, apply_remote_update_workspace,
-    delete_folder, delete_note, delete_workspace, get_folders,
-    get_notes, get_sync_data, get_workspaces, init_db, search_notes, upsert_folder, upsert_note,
-    upsert_workspace, DbState,
+    apply_batch, apply_remote_update_folder, apply_remote_update_note,
+    apply_remote_update_workspace, copy_folder_tree, delete_folder, delete_note, delete_workspace,
+    duplicate_note, get_folders, get_notes, get_sync_data, get_workspaces, move_items,
+    search_notes, upsert_folder, upsert_note, upsert_workspace, DbState,
 };
+use document::{export_folder_document, export_note_document};
 use exporter::{export_workspace, write_text_file};
-use importer::import_workspace;
+use grants::{pick_directory, pick_open_directory, pick_open_path, pick_save_path, FileGrants};
+use history::{
+    configure_history, get_history_note, get_history_settings, list_history,
+    restore_note_from_history, HistoryService,
+};
+use importer::{cancel_import, import_into_workspace, import_workspace, ImportJobs};
+use maintenance::{check_database, repair_database};
+use mirror::{configure_mirror, get_mirror_status, mirror_now, MirrorService};
+use ordering::reorder;
+use recovery::{
+    get_recovery_status, open_database_read_only, recover_from_backup, retry_open_database,
+    start_fresh_database, RecoveryState,
+};
+use site::export_site;
+use sync::{
+    change_encryption_passphrase, configure_sync, enable_encryption, export_recovery_key,
+    get_encryption_status, get_sync_status, lock_encryption, reset_passphrase_with_recovery_key,
+    sync_now, unlock_encryption, SyncService,
+};
 use tauri::Manager;
+use vault::{
+    disable_database_encryption, enable_database_encryption, get_database_status, lock_database,
+    set_auto_lock, unlock_database,
+};
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .setup(|app| {
             let app_handle = app.handle();
-            let app_dir = app_handle
-                .path()
-                .app_data_dir()
-                .expect("failed to get app data dir");
-
-            // Ensure app directory exists
-            if !app_dir.exists() {
-                std::fs::create_dir_all(&app_dir).expect("failed to create app data dir");
-            }
+            let app_dir = app_handle.path().app_data_dir()?;
 
-            tauri::async_runtime::block_on(async move {
-                let pool = init_db(app_dir).await.expect("failed to init db");
-                app_handle.manage(DbState {
-                    db: db::Db::new(pool),
-                });
-            });
+            // A database that cannot be opened starts the app in recovery mode
+            // instead of aborting; an encrypted one stays locked until the user
+            // enters the passphrase.
+            let (db, recovery) = tauri::async_runtime::block_on(recovery::open_at_startup(&app_dir));
+            app_handle.manage(DbState::new(app_dir, db));
+            app_handle.manage(RecoveryState::new(recovery));
+            sync::start(app_handle);
+            vault::start(app_handle);
+            backup::start(app_handle);
+            mirror::start(app_handle);
+            history::start(app_handle);
 
             Ok(())
         })
+        .manage(ImportJobs::default())
+        .manage(FileGrants::default())
+        .manage(SyncService::default())
+        .manage(MirrorService::default())
+        .manage(HistoryService::default())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
@@ -46,17 +96,70 @@ pub fn run() {
             get_folders,
             upsert_folder,
             delete_folder,
+            move_items,
+            apply_batch,
+            duplicate_note,
+            copy_folder_tree,
             get_workspaces,
             upsert_workspace,
             delete_workspace,
             search_notes,
             export_workspace,
             import_workspace,
+            import_into_workspace,
+            cancel_import,
             write_text_file,
+            pick_save_path,
+            pick_open_path,
+            pick_directory,
+            pick_open_directory,
+            export_site,
+            export_note_document,
+            export_folder_document,
             get_sync_data,
             apply_remote_update_note,
             apply_remote_update_folder,
-            apply_remote_update_workspace
+            apply_remote_update_workspace,
+            sync_now,
+            get_sync_status,
+            configure_sync,
+            get_encryption_status,
+            enable_encryption,
+            unlock_encryption,
+            lock_encryption,
+            change_encryption_passphrase,
+            reset_passphrase_with_recovery_key,
+            export_recovery_key,
+            get_database_status,
+            unlock_database,
+            lock_database,
+            enable_database_encryption,
+            disable_database_encryption,
+            set_auto_lock,
+            list_backups,
+            create_backup,
+            verify_backup,
+            restore_backup,
+            get_backup_settings,
+            set_backup_settings,
+            export_full_backup,
+            import_full_backup,
+            configure_mirror,
+            get_mirror_status,
+            mirror_now,
+            configure_history,
+            get_history_settings,
+            list_history,
+            get_history_note,
+            restore_note_from_history,
+            check_database,
+            repair_database,
+            reorder,
+            get_recovery_status,
+            retry_open_database,
+            open_database_read_only,
+            recover_from_backup,
+            start_fresh_database
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,507 @@
+//! Health check and repair for the local database: SQLite integrity, search
+//! index drift, dangling references and folder cycles. Soft-deleted rows are
+//! ignored throughout, since the app never shows them.
+
+use crate::backup::{self, BackupKind};
+use crate::db::{now_millis, Db, DbState};
+use crate::error::AppError;
+use serde::Serialize;
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+
+/// Name of the folder that orphaned notes and folders are moved into.
+pub const RECOVERED_FOLDER: &str = "Recovered";
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    /// Output of `PRAGMA integrity_check`, empty when it reports "ok".
+    pub integrity_errors: Vec<String>,
+    /// Notes that search cannot find.
+    pub fts_missing: Vec<String>,
+    /// Search entries that are duplicated, outdated or point at no note.
+    pub fts_stale: Vec<String>,
+    /// Notes whose folder does not exist (or is deleted, or in another workspace).
+    pub orphan_notes: Vec<String>,
+    /// Folders whose parent does not exist (or is deleted, or in another workspace).
+    pub orphan_folders: Vec<String>,
+    /// Notes and folders whose workspace does not exist at all.
+    pub missing_workspace_notes: Vec<String>,
+    pub missing_workspace_folders: Vec<String>,
+    /// Each entry lists the folders of one parent cycle.
+    pub folder_cycles: Vec<Vec<String>>,
+    /// Live notes in a deleted workspace; reported only, they stay hidden.
+    pub notes_in_deleted_workspaces: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RepairSummary {
+    /// Backup taken before anything was changed.
+    pub backup: String,
+    pub cycles_broken: usize,
+    pub items_recovered: usize,
+    pub fts_rebuilt: bool,
+    /// State after the repair.
+    pub report: HealthReport,
+}
+
+async fn ids(conn: &mut SqliteConnection, sql: &str) -> Result<Vec<String>, AppError> {
+    let rows: Vec<(String,)> = sqlx::query_as(sql).fetch_all(&mut *conn).await?;
+    Ok(rows.into_iter().map(|(id,)| id).collect())
+}
+
+const ORPHAN_NOTES: &str = "SELECT n.id FROM notes n
+     WHERE n.is_deleted = 0 AND n.folder_id IS NOT NULL
+       AND n.workspace_id IN (SELECT id FROM workspaces)
+       AND NOT EXISTS (SELECT 1 FROM folders f WHERE f.id = n.folder_id
+                       AND f.is_deleted = 0 AND f.workspace_id = n.workspace_id)
+     ORDER BY n.id";
+
+const ORPHAN_FOLDERS: &str = "SELECT c.id FROM folders c
+     WHERE c.is_deleted = 0 AND c.parent_id IS NOT NULL
+       AND c.workspace_id IN (SELECT id FROM workspaces)
+       AND NOT EXISTS (SELECT 1 FROM folders p WHERE p.id = c.parent_id
+                       AND p.is_deleted = 0 AND p.workspace_id = c.workspace_id)
+     ORDER BY c.id";
+
+pub async fn check(conn: &mut SqliteConnection) -> Result<HealthReport, AppError> {
+    let integrity: Vec<(String,)> = sqlx::query_as("PRAGMA integrity_check")
+        .fetch_all(&mut *conn)
+        .await?;
+    let integrity_errors = integrity
+        .into_iter()
+        .map(|(line,)| line)
+        .filter(|line| line != "ok")
+        .collect();
+
+    let mut report = HealthReport {
+        integrity_errors,
+        fts_missing: ids(
+            conn,
+            "SELECT id FROM notes WHERE id NOT IN (SELECT id FROM notes_fts) ORDER BY id",
+        )
+        .await?,
+        fts_stale: ids(
+            conn,
+            "SELECT id FROM notes_fts WHERE id NOT IN (SELECT id FROM notes)
+             UNION
+             SELECT id FROM notes_fts GROUP BY id HAVING count(*) > 1
+             UNION
+             SELECT n.id FROM notes n JOIN notes_fts f ON f.id = n.id
+              WHERE f.title IS NOT n.title OR f.content IS NOT n.content
+             ORDER BY 1",
+        )
+        .await?,
+        orphan_notes: ids(conn, ORPHAN_NOTES).await?,
+        orphan_folders: ids(conn, ORPHAN_FOLDERS).await?,
+        missing_workspace_notes: ids(
+            conn,
+            "SELECT id FROM notes WHERE is_deleted = 0
+               AND workspace_id NOT IN (SELECT id FROM workspaces) ORDER BY id",
+        )
+        .await?,
+        missing_workspace_folders: ids(
+            conn,
+            "SELECT id FROM folders WHERE is_deleted = 0
+               AND workspace_id NOT IN (SELECT id FROM workspaces) ORDER BY id",
+        )
+        .await?,
+        folder_cycles: folder_cycles(conn).await?,
+        notes_in_deleted_workspaces: ids(
+            conn,
+            "SELECT n.id FROM notes n JOIN workspaces w ON w.id = n.workspace_id
+             WHERE n.is_deleted = 0 AND w.is_deleted = 1 ORDER BY n.id",
+        )
+        .await?,
+        healthy: false,
+    };
+    report.healthy = report.integrity_errors.is_empty()
+        && report.fts_missing.is_empty()
+        && report.fts_stale.is_empty()
+        && report.orphan_notes.is_empty()
+        && report.orphan_folders.is_empty()
+        && report.missing_workspace_notes.is_empty()
+        && report.missing_workspace_folders.is_empty()
+        && report.folder_cycles.is_empty();
+    Ok(report)
+}
+
+/// Follows every live folder's parent chain; a chain that comes back to a
+/// folder already on it is a cycle. Each cycle is reported once, starting at
+/// its smallest id.
+async fn folder_cycles(conn: &mut SqliteConnection) -> Result<Vec<Vec<String>>, AppError> {
+    let rows: Vec<(String, Option<String>)> =
+        sqlx::query_as("SELECT id, parent_id FROM folders WHERE is_deleted = 0 ORDER BY id")
+            .fetch_all(&mut *conn)
+            .await?;
+    let parents: HashMap<&str, Option<&str>> = rows
+        .iter()
+        .map(|(id, parent)| (id.as_str(), parent.as_deref()))
+        .collect();
+
+    // 0 = unvisited, 1 = on the current chain, 2 = done
+    let mut state: HashMap<&str, u8> = HashMap::new();
+    let mut cycles = Vec::new();
+    for (start, _) in &rows {
+        let mut chain: Vec<&str> = Vec::new();
+        let mut current = Some(start.as_str());
+        while let Some(id) = current {
+            match state.get(id).copied().unwrap_or(0) {
+                0 => {
+                    state.insert(id, 1);
+                    chain.push(id);
+                    current = parents
+                        .get(id)
+                        .copied()
+                        .flatten()
+                        .filter(|p| parents.contains_key(p));
+                }
+                1 => {
+                    let from = chain.iter().position(|c| *c == id).unwrap();
+                    let mut cycle: Vec<String> =
+                        chain[from..].iter().map(|s| s.to_string()).collect();
+                    let min = (0..cycle.len()).min_by_key(|&i| &cycle[i]).unwrap();
+                    cycle.rotate_left(min);
+                    cycles.push(cycle);
+                    break;
+                }
+                _ => break,
+            }
+        }
+        for id in chain {
+            state.insert(id, 2);
+        }
+    }
+    Ok(cycles)
+}
+
+/// Returns the id of the workspace's root "Recovered" folder, creating it if needed.
+async fn recovered_folder(
+    conn: &mut SqliteConnection,
+    workspace_id: &str,
+    now: i64,
+) -> Result<String, AppError> {
+    let existing: Option<(String,)> = sqlx::query_as(
+        "SELECT id FROM folders WHERE workspace_id = ?1 AND name = ?2
+           AND parent_id IS NULL AND is_deleted = 0 LIMIT 1",
+    )
+    .bind(workspace_id)
+    .bind(RECOVERED_FOLDER)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some((id,)) = existing {
+        return Ok(id);
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO folders (id, name, parent_id, workspace_id, created_at, updated_at, version, is_deleted)
+         VALUES (?1, ?2, NULL, ?3, ?4, ?4, 1, 0)",
+    )
+    .bind(&id)
+    .bind(RECOVERED_FOLDER)
+    .bind(workspace_id)
+    .bind(now)
+    .execute(&mut *conn)
+    .await?;
+    Ok(id)
+}
+
+async fn workspace_of(
+    conn: &mut SqliteConnection,
+    table: &str,
+    id: &str,
+) -> Result<String, AppError> {
+    let (workspace_id,): (String,) =
+        sqlx::query_as(&format!("SELECT workspace_id FROM {} WHERE id = ?1", table))
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await?;
+    Ok(workspace_id)
+}
+
+/// Fixes what `check` finds, in one transaction. Every row it moves gets a
+/// new version and timestamp so the fix syncs to other devices.
+async fn repair_in(conn: &mut SqliteConnection) -> Result<(usize, usize, bool), AppError> {
+    let now = now_millis();
+    let report = check(conn).await?;
+
+    // Break each cycle at the folder whose parent changed last: that edit
+    // closed the loop.
+    for cycle in &report.folder_cycles {
+        let mut latest: Option<(i64, &String)> = None;
+        for id in cycle {
+            let (updated_at,): (i64,) =
+                sqlx::query_as("SELECT updated_at FROM folders WHERE id = ?1")
+                    .bind(id)
+                    .fetch_one(&mut *conn)
+                    .await?;
+            match latest {
+                Some((at, _)) if at > updated_at => {}
+                _ => latest = Some((updated_at, id)),
+            }
+        }
+        if let Some((_, id)) = latest {
+            sqlx::query(
+                "UPDATE folders SET parent_id = NULL, updated_at = ?1, version = version + 1 WHERE id = ?2",
+            )
+            .bind(now)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    // Rows without a workspace move, whole subtrees at once, into the first
+    // workspace; any of them left dangling are then recovered below.
+    let homeless = report.missing_workspace_notes.len() + report.missing_workspace_folders.len();
+    if homeless > 0 {
+        let target: Option<(String,)> = sqlx::query_as(
+            "SELECT id FROM workspaces WHERE is_deleted = 0 ORDER BY created_at ASC LIMIT 1",
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        if let Some((target,)) = target {
+            for table in ["notes", "folders"] {
+                sqlx::query(&format!(
+                    "UPDATE {} SET workspace_id = ?1, updated_at = ?2, version = version + 1
+                     WHERE is_deleted = 0 AND workspace_id NOT IN (SELECT id FROM workspaces)",
+                    table
+                ))
+                .bind(&target)
+                .bind(now)
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+    }
+
+    let mut recovered = 0;
+    for id in ids(conn, ORPHAN_FOLDERS).await? {
+        let workspace_id = workspace_of(conn, "folders", &id).await?;
+        let folder = recovered_folder(conn, &workspace_id, now).await?;
+        sqlx::query(
+            "UPDATE folders SET parent_id = ?1, updated_at = ?2, version = version + 1 WHERE id = ?3",
+        )
+        .bind(&folder)
+        .bind(now)
+        .bind(&id)
+        .execute(&mut *conn)
+        .await?;
+        recovered += 1;
+    }
+    for id in ids(conn, ORPHAN_NOTES).await? {
+        let workspace_id = workspace_of(conn, "notes", &id).await?;
+        let folder = recovered_folder(conn, &workspace_id, now).await?;
+        sqlx::query(
+            "UPDATE notes SET folder_id = ?1, updated_at = ?2, version = version + 1 WHERE id = ?3",
+        )
+        .bind(&folder)
+        .bind(now)
+        .bind(&id)
+        .execute(&mut *conn)
+        .await?;
+        recovered += 1;
+    }
+
+    let fts_rebuilt = !report.fts_missing.is_empty() || !report.fts_stale.is_empty();
+    if fts_rebuilt {
+        sqlx::raw_sql(
+            "DELETE FROM notes_fts;
+             INSERT INTO notes_fts(id, title, content) SELECT id, title, content FROM notes;
+             INSERT INTO notes_fts(notes_fts) VALUES('optimize');",
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    sqlx::raw_sql("REINDEX; ANALYZE;")
+        .execute(&mut *conn)
+        .await?;
+
+    Ok((report.folder_cycles.len(), recovered, fts_rebuilt))
+}
+
+pub async fn repair(db: &Db, app_dir: &std::path::Path) -> Result<RepairSummary, AppError> {
+    let backup = backup::create(db, app_dir, BackupKind::PreRepair).await?;
+
+    let mut tx = db.pool.begin().await?;
+    let (cycles_broken, items_recovered, fts_rebuilt) = repair_in(&mut tx).await?;
+    tx.commit().await?;
+
+    // VACUUM cannot run inside a transaction.
+    sqlx::query("VACUUM").execute(&db.pool).await?;
+
+    let mut conn = db.pool.acquire().await?;
+    let report = check(&mut conn).await?;
+    drop(conn);
+    if cycles_broken + items_recovered > 0 {
+        db.notify_changed();
+    }
+
+    Ok(RepairSummary {
+        backup: backup.file_name,
+        cycles_broken,
+        items_recovered,
+        fts_rebuilt,
+        report,
+    })
+}
+
+#[tauri::command]
+pub async fn check_database(state: tauri::State<'_, DbState>) -> Result<HealthReport, AppError> {
+    let db = state.db()?;
+    let mut conn = db.pool.acquire().await?;
+    check(&mut conn).await
+}
+
+/// Backs the database up, then repairs it and returns the new health report.
+#[tauri::command]
+pub async fn repair_database(state: tauri::State<'_, DbState>) -> Result<RepairSummary, AppError> {
+    let db = state.db()?;
+    repair(&db, &state.app_dir).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_db, FolderRecord, NoteRecord};
+
+    fn folder(id: &str, parent_id: Option<&str>, workspace_id: &str) -> FolderRecord {
+        FolderRecord {
+            id: id.to_string(),
+            name: id.to_string(),
+            parent_id: parent_id.map(str::to_string),
+            workspace_id: workspace_id.to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            color: None,
+            sort_key: String::new(),
+            is_deleted: false,
+        }
+    }
+
+    fn note(id: &str, folder_id: Option<&str>, workspace_id: &str) -> NoteRecord {
+        NoteRecord {
+            id: id.to_string(),
+            title: id.to_string(),
+            content: format!("body of {}", id),
+            folder_id: folder_id.map(str::to_string),
+            workspace_id: workspace_id.to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        }
+    }
+
+    async fn setup() -> (Db, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        (Db::new(init_db(dir.clone()).await.unwrap()), dir)
+    }
+
+    async fn report(db: &Db) -> HealthReport {
+        let mut conn = db.pool.acquire().await.unwrap();
+        check(&mut conn).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fresh_database_is_healthy() {
+        let (db, dir) = setup().await;
+        db.upsert_folder(folder("a", None, "default"))
+            .await
+            .unwrap();
+        db.upsert_note(note("n1", Some("a"), "default"))
+            .await
+            .unwrap();
+        let report = report(&db).await;
+        assert!(report.healthy, "{:?}", report);
+        db.pool.close().await;
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_detects_and_repairs_problems() {
+        let (db, dir) = setup().await;
+        db.upsert_folder(folder("a", None, "default"))
+            .await
+            .unwrap();
+        db.upsert_folder(folder("b", Some("a"), "default"))
+            .await
+            .unwrap();
+        db.upsert_folder(folder("c", Some("b"), "default"))
+            .await
+            .unwrap();
+        db.upsert_folder(folder("gone", None, "default"))
+            .await
+            .unwrap();
+        db.upsert_note(note("in-gone", Some("gone"), "default"))
+            .await
+            .unwrap();
+        db.upsert_folder(folder("child-of-gone", Some("gone"), "default"))
+            .await
+            .unwrap();
+        db.upsert_note(note("homeless", None, "nowhere"))
+            .await
+            .unwrap();
+        db.upsert_note(note("searchable", None, "default"))
+            .await
+            .unwrap();
+        db.delete_folder("gone".to_string()).await.unwrap();
+
+        // a -> b -> c -> a
+        sqlx::query(
+            "UPDATE folders SET parent_id = 'c', updated_at = 9999999999999 WHERE id = 'a'",
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        sqlx::query("DELETE FROM notes_fts WHERE id = 'searchable'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let before = report(&db).await;
+        assert!(!before.healthy);
+        assert_eq!(before.folder_cycles, vec![vec!["a", "c", "b"]]);
+        assert_eq!(before.orphan_notes, vec!["in-gone"]);
+        assert_eq!(before.orphan_folders, vec!["child-of-gone"]);
+        assert_eq!(before.missing_workspace_notes, vec!["homeless"]);
+        assert_eq!(before.fts_missing, vec!["searchable"]);
+
+        let summary = repair(&db, &dir).await.unwrap();
+        assert!(summary.report.healthy, "{:?}", summary.report);
+        assert_eq!(summary.cycles_broken, 1);
+        assert_eq!(summary.items_recovered, 2);
+        assert!(summary.fts_rebuilt);
+        assert!(dir.join("backups").join(&summary.backup).exists());
+
+        let folders = db.get_folders().await.unwrap();
+        let recovered = folders.iter().find(|f| f.name == RECOVERED_FOLDER).unwrap();
+        let a = folders.iter().find(|f| f.id == "a").unwrap();
+        assert_eq!(a.parent_id, None);
+        assert!(a.version > 1);
+        let child = folders.iter().find(|f| f.id == "child-of-gone").unwrap();
+        assert_eq!(child.parent_id.as_deref(), Some(recovered.id.as_str()));
+
+        let notes = db.get_notes().await.unwrap();
+        let orphan = notes.iter().find(|n| n.id == "in-gone").unwrap();
+        assert_eq!(orphan.folder_id.as_deref(), Some(recovered.id.as_str()));
+        let homeless = notes.iter().find(|n| n.id == "homeless").unwrap();
+        assert_eq!(homeless.workspace_id, "default");
+        assert_eq!(
+            db.search_notes("searchable".to_string())
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        db.pool.close().await;
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
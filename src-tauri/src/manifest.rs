@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+/// File name of the manifest written at the root of every exported ZIP.
+pub const MANIFEST_FILE: &str = "lumenote.json";
+
+/// Bumped whenever the manifest layout changes in a non-additive way.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Metadata that plain Markdown files cannot carry (ids, timestamps, colors,
+/// ordering). The exporter writes it next to the notes and the importer uses
+/// it, when present, to make an export → import round trip lossless.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExportManifest {
+    pub format_version: u32,
+    pub workspace: ManifestWorkspace,
+    #[serde(default)]
+    pub folders: Vec<ManifestFolder>,
+    #[serde(default)]
+    pub notes: Vec<ManifestNote>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ManifestWorkspace {
+    pub id: String,
+    pub name: String,
+    pub color: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub version: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ManifestFolder {
+    pub id: String,
+    /// Directory path inside the ZIP, without a trailing slash.
+    pub path: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub color: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub version: i32,
+    /// Position among its siblings at export time.
+    pub index: usize,
+    /// Manual position among its siblings, see `ordering`. Missing from
+    /// manifests written before it was added; `index` stands in then.
+    #[serde(default)]
+    pub sort_key: String,
+    /// In the trash; only exported when asked for.
+    #[serde(default)]
+    pub is_deleted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ManifestNote {
+    pub id: String,
+    /// Path of the `.md` entry inside the ZIP.
+    pub path: String,
+    pub title: String,
+    pub folder_id: Option<String>,
+    #[serde(default)]
+    pub color: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub version: i32,
+    /// Position among its siblings at export time.
+    pub index: usize,
+    /// See `ManifestFolder::sort_key`.
+    #[serde(default)]
+    pub sort_key: String,
+    /// In the trash; only exported when asked for.
+    #[serde(default)]
+    pub is_deleted: bool,
+}
+
+impl ExportManifest {
+    pub fn folder_by_path(&self, path: &str) -> Option<&ManifestFolder> {
+        self.folders.iter().find(|f| f.path == path)
+    }
+
+    pub fn note_by_path(&self, path: &str) -> Option<&ManifestNote> {
+        self.notes.iter().find(|n| n.path == path)
+    }
+}
@@ -0,0 +1,393 @@
+//! Numbered schema migrations for the local database. Each migration is an
+//! embedded SQL file applied in its own transaction and recorded in
+//! `schema_version`, so a database is always at exactly one known version.
+
+use crate::db::now_millis;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use std::path::Path;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every migration, in order. Append only: released migrations must never be
+/// edited, since databases in the wild have already applied them.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "settings",
+        sql: include_str!("../migrations/0002_settings.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "ordering_and_note_colors",
+        sql: include_str!("../migrations/0003_ordering_and_note_colors.sql"),
+    },
+];
+
+/// Schema version this build of the app expects.
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Returns the applied version: 0 for an empty or unversioned database.
+pub async fn current_version(conn: &mut SqliteConnection) -> Result<i64, sqlx::Error> {
+    if !table_exists(conn, "schema_version").await? {
+        return Ok(0);
+    }
+    let (version,): (Option<i64>,) = sqlx::query_as("SELECT MAX(version) FROM schema_version")
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(version.unwrap_or(0))
+}
+
+async fn table_exists(conn: &mut SqliteConnection, name: &str) -> Result<bool, sqlx::Error> {
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1")
+            .bind(name)
+            .fetch_one(&mut *conn)
+            .await?;
+    Ok(count > 0)
+}
+
+/// Brings the database up to date. Refuses databases written by a newer app
+/// version, and when there is anything to upgrade and `backup_dir` is given,
+/// first snapshots the database there with `VACUUM INTO`.
+pub async fn run(pool: &Pool<Sqlite>, backup_dir: Option<&Path>) -> Result<(), sqlx::Error> {
+    run_migrations(pool, MIGRATIONS, backup_dir).await
+}
+
+async fn run_migrations(
+    pool: &Pool<Sqlite>,
+    migrations: &[Migration],
+    backup_dir: Option<&Path>,
+) -> Result<(), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let current = current_version(&mut conn).await?;
+    let latest = migrations.last().map(|m| m.version).unwrap_or(0);
+    if current > latest {
+        return Err(sqlx::Error::Configuration(
+            format!(
+                "The database was created by a newer version of Lumenote (schema {}, this version supports {}). Please update the app.",
+                current, latest
+            )
+            .into(),
+        ));
+    }
+    if current == latest {
+        return Ok(());
+    }
+
+    let legacy = current == 0 && table_exists(&mut conn, "notes").await?;
+    if let Some(dir) = backup_dir {
+        if legacy || current > 0 {
+            backup(&mut conn, dir, current).await?;
+        }
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        if legacy && migration.version == 1 {
+            adopt_legacy(&mut tx).await?;
+        }
+        sqlx::raw_sql(migration.sql).execute(&mut *tx).await?;
+        sqlx::query("INSERT INTO schema_version (version, name, applied_at) VALUES (?1, ?2, ?3)")
+            .bind(migration.version)
+            .bind(migration.name)
+            .bind(now_millis())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+/// Databases from before versioning were upgraded by adding columns ad hoc,
+/// so older ones may lack some. Add whatever is missing so that they match
+/// the tables migration 1 would have created.
+async fn adopt_legacy(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    let note_columns = columns(conn, "notes").await?;
+    if !note_columns.iter().any(|c| c == "version") {
+        sqlx::query("ALTER TABLE notes ADD COLUMN version INTEGER NOT NULL DEFAULT 1")
+            .execute(&mut *conn)
+            .await?;
+    }
+    if !note_columns.iter().any(|c| c == "is_deleted") {
+        sqlx::query("ALTER TABLE notes ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT 0")
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    let folder_columns = columns(conn, "folders").await?;
+    if folder_columns.is_empty() {
+        return Ok(());
+    }
+    if !folder_columns.iter().any(|c| c == "updated_at") {
+        sqlx::query("ALTER TABLE folders ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0")
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("UPDATE folders SET updated_at = created_at WHERE updated_at = 0")
+            .execute(&mut *conn)
+            .await?;
+    }
+    if !folder_columns.iter().any(|c| c == "is_deleted") {
+        sqlx::query("ALTER TABLE folders ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT 0")
+            .execute(&mut *conn)
+            .await?;
+    }
+    if !folder_columns.iter().any(|c| c == "version") {
+        sqlx::query("ALTER TABLE folders ADD COLUMN version INTEGER NOT NULL DEFAULT 1")
+            .execute(&mut *conn)
+            .await?;
+    }
+    if !folder_columns.iter().any(|c| c == "color") {
+        sqlx::query("ALTER TABLE folders ADD COLUMN color TEXT")
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn columns(conn: &mut SqliteConnection, table: &str) -> Result<Vec<String>, sqlx::Error> {
+    let info: Vec<(i64, String, String, i64, Option<String>, i64)> =
+        sqlx::query_as(&format!("PRAGMA table_info({})", table))
+            .fetch_all(&mut *conn)
+            .await?;
+    Ok(info.into_iter().map(|c| c.1).collect())
+}
+
+/// Writes a consistent copy of the database (same key, if encrypted) that the
+/// user can restore if an upgrade goes wrong.
+async fn backup(conn: &mut SqliteConnection, dir: &Path, from: i64) -> Result<(), sqlx::Error> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("pre-migration-v{}-{}.db", from, now_millis()));
+    sqlx::query("VACUUM INTO ?1")
+        .bind(path.to_string_lossy())
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{connect, init_db, Db, NoteRecord, DB_FILE};
+    use std::path::PathBuf;
+
+    /// Schemas the app created before migrations were versioned, oldest first.
+    const LEGACY_SCHEMAS: &[&str] = &[
+        // First release: no workspaces table, no soft deletes or versions
+        "CREATE TABLE folders (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            parent_id TEXT,
+            workspace_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            color TEXT
+        );
+        CREATE TABLE notes (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL,
+            content TEXT NOT NULL,
+            folder_id TEXT,
+            workspace_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        );",
+        // Workspaces, versions and full-text search, before `settings`
+        "CREATE TABLE folders (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            parent_id TEXT,
+            workspace_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL DEFAULT 0,
+            version INTEGER NOT NULL DEFAULT 1,
+            is_deleted BOOLEAN NOT NULL DEFAULT 0,
+            color TEXT
+        );
+        CREATE TABLE notes (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL,
+            content TEXT NOT NULL,
+            folder_id TEXT,
+            workspace_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            version INTEGER NOT NULL DEFAULT 1,
+            is_deleted BOOLEAN NOT NULL DEFAULT 0
+        );
+        CREATE TABLE workspaces (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            color TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL DEFAULT 0,
+            version INTEGER NOT NULL DEFAULT 1,
+            is_deleted BOOLEAN NOT NULL DEFAULT 0
+        );
+        CREATE TABLE changes (
+            id TEXT PRIMARY KEY,
+            note_id TEXT NOT NULL,
+            old_content TEXT,
+            new_content TEXT,
+            timestamp INTEGER NOT NULL,
+            version INTEGER NOT NULL
+        );
+        CREATE VIRTUAL TABLE notes_fts USING fts5(id UNINDEXED, title, content, tokenize='unicode61');",
+    ];
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn seed(dir: &Path, schema: &str) {
+        let pool = connect(&dir.join(DB_FILE), None).await.unwrap();
+        sqlx::raw_sql(schema).execute(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO folders (id, name, parent_id, workspace_id, created_at) VALUES ('f1', 'Old folder', NULL, 'default', 500)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO notes (id, title, content, folder_id, workspace_id, created_at, updated_at)
+             VALUES ('n1', 'Old note', 'written long ago', 'f1', 'default', 500, 600)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+    }
+
+    fn backups(dir: &Path) -> usize {
+        std::fs::read_dir(dir.join("backups"))
+            .map(|entries| entries.count())
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn test_upgrades_every_legacy_schema() {
+        for schema in LEGACY_SCHEMAS {
+            let dir = temp_dir();
+            seed(&dir, schema).await;
+
+            let db = Db::new(init_db(dir.clone()).await.unwrap());
+            let mut conn = db.pool.acquire().await.unwrap();
+            assert_eq!(current_version(&mut conn).await.unwrap(), latest_version());
+            drop(conn);
+            assert_eq!(backups(&dir), 1);
+
+            let folders = db.get_folders().await.unwrap();
+            assert_eq!(folders.len(), 1);
+            assert_eq!(folders[0].version, 1);
+            assert!(!folders[0].is_deleted);
+            let notes = db.get_notes().await.unwrap();
+            assert_eq!(notes[0].content, "written long ago");
+            assert_eq!(db.search_notes("long".to_string()).await.unwrap().len(), 1);
+            assert_eq!(db.get_workspaces().await.unwrap().len(), 1);
+            db.set_setting("k", "v").await.unwrap();
+
+            db.pool.close().await;
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fresh_database_needs_no_backup_and_reopens_cleanly() {
+        let dir = temp_dir();
+        let pool = init_db(dir.clone()).await.unwrap();
+        pool.close().await;
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+        assert_eq!(backups(&dir), 0);
+
+        db.upsert_note(NoteRecord {
+            id: "n1".to_string(),
+            title: "New".to_string(),
+            content: "fresh".to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        })
+        .await
+        .unwrap();
+        assert_eq!(db.search_notes("fresh".to_string()).await.unwrap().len(), 1);
+
+        db.pool.close().await;
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_newer_database() {
+        let dir = temp_dir();
+        let pool = init_db(dir.clone()).await.unwrap();
+        sqlx::query(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (?1, 'future', 0)",
+        )
+        .bind(latest_version() + 1)
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+
+        let err = init_db(dir.clone()).await.unwrap_err().to_string();
+        assert!(err.contains("newer version"), "{}", err);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_failed_migration_rolls_back_and_keeps_version() {
+        let dir = temp_dir();
+        let pool = connect(&dir.join(DB_FILE), None).await.unwrap();
+        let migrations = [
+            Migration {
+                version: 1,
+                name: "one",
+                sql: "CREATE TABLE a (id TEXT PRIMARY KEY);",
+            },
+            Migration {
+                version: 2,
+                name: "two",
+                sql: "CREATE TABLE b (id TEXT PRIMARY KEY); INSERT INTO missing VALUES (1);",
+            },
+        ];
+
+        assert!(
+            run_migrations(&pool, &migrations, Some(&dir.join("backups")))
+                .await
+                .is_err()
+        );
+        let mut conn = pool.acquire().await.unwrap();
+        assert_eq!(current_version(&mut conn).await.unwrap(), 1);
+        assert!(table_exists(&mut conn, "a").await.unwrap());
+        assert!(!table_exists(&mut conn, "b").await.unwrap());
+        drop(conn);
+
+        pool.close().await;
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
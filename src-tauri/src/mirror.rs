@@ -0,0 +1,1162 @@
+//! Two-way mirror of a workspace and a directory of Markdown files, so notes
+//! can be searched, versioned and edited with other tools. Folders map to
+//! directories and notes to `.md` files laid out like the Markdown export.
+//!
+//! Each pass compares both sides with what they looked like after the last
+//! pass (kept in the `mirror.<workspace id>` setting): edits on disk go
+//! through `upsert_note`, so they get revisions and search indexing, and edits
+//! in the app are written out. Every file ends in an id marker, so a note
+//! stays the same note when its file is renamed or moved; files that lost it
+//! are recognized by content. When both sides changed, the app keeps its
+//! version and the file's becomes a separate "(conflict)" note.
+
+use crate::db::{now_millis, tx, Db, DbState, FolderRecord, NoteRecord};
+use crate::error::{AppError, Entity};
+use crate::exporter::{write_relative, ExportLayout};
+use crate::grants::{Access, FileGrants};
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tokio::sync::Notify;
+
+const SETTING_PREFIX: &str = "mirror.";
+
+/// How often the database is checked for changes to write out.
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Wait after a file event, so a burst of writes is applied in one pass.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+const MARKER_START: &str = "<!-- lumenote:";
+const MARKER_END: &str = " -->";
+
+/// A mirrored workspace and both sides as of the last pass.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MirrorState {
+    pub dir: PathBuf,
+    #[serde(default)]
+    notes: HashMap<String, Mirrored>,
+    #[serde(default)]
+    folders: HashMap<String, Mirrored>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Mirrored {
+    /// Relative path with `/` separators.
+    path: String,
+    /// `content_hash` of the note's content; unused for folders.
+    #[serde(default)]
+    hash: u64,
+    updated_at: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MirrorSummary {
+    /// Notes and folders changed in the app from edits on disk.
+    pub imported: usize,
+    /// Files written or removed for changes made in the app.
+    pub exported: usize,
+    pub conflicts: usize,
+    /// Markdown files skipped because they are not UTF-8 text; their notes
+    /// are left as they are.
+    pub unreadable: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorStatus {
+    pub workspace_id: String,
+    pub dir: String,
+    pub last_pass_at: Option<i64>,
+    pub last_summary: Option<MirrorSummary>,
+    pub last_error: Option<String>,
+}
+
+/// A Markdown file found on disk.
+struct DiskFile {
+    path: String,
+    /// Id from the marker, if the file still has one.
+    id: Option<String>,
+    body: String,
+    hash: u64,
+}
+
+/// FNV-1a; stored in the settings, so it must not change between builds
+/// the way `DefaultHasher` may.
+fn content_hash(content: &str) -> u64 {
+    content.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn render(note: &NoteRecord) -> String {
+    format!(
+        "{}\n\n{}{}{}\n",
+        note.content, MARKER_START, note.id, MARKER_END
+    )
+}
+
+/// Splits a file into the note content and the id from its marker. A marker
+/// that was moved or edited around is still found and cut out, taking the
+/// blank lines before it along.
+fn parse(text: &str) -> (String, Option<String>) {
+    let text = text.replace("\r\n", "\n");
+    let Some(start) = text.rfind(MARKER_START) else {
+        return (text, None);
+    };
+    let Some(len) = text[start..].find(MARKER_END) else {
+        return (text, None);
+    };
+    let id = text[start + MARKER_START.len()..start + len]
+        .trim()
+        .to_string();
+    let end = start + len + MARKER_END.len();
+    let body = match (text[..start].strip_suffix("\n\n"), &text[end..]) {
+        (Some(body), "\n") => body.to_string(),
+        _ => format!("{}{}", text[..start].trim_end(), text[end..].trim_end()),
+    };
+    (body, Some(id).filter(|id| !id.is_empty()))
+}
+
+fn parent_dir(path: &str) -> &str {
+    path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+fn file_stem(path: &str) -> &str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name.len().checked_sub(3) {
+        Some(end) if name.is_char_boundary(end) && name[end..].eq_ignore_ascii_case(".md") => {
+            &name[..end]
+        }
+        _ => name,
+    }
+}
+
+fn absolute(dir: &Path, path: &str) -> PathBuf {
+    path.split('/')
+        .fold(dir.to_path_buf(), |p, part| p.join(part))
+}
+
+/// Files and directories found below the mirror root.
+struct Scan {
+    files: Vec<DiskFile>,
+    dirs: BTreeSet<String>,
+    /// Markdown files that could not be read as UTF-8 text.
+    unreadable: BTreeSet<String>,
+}
+
+/// Markdown files and directories below `dir`, skipping hidden entries
+/// (`.git`, editor swap files) and anything that isn't a plain file. A file
+/// that cannot be read as text is listed in `unreadable` instead of failing
+/// the whole pass.
+fn scan(dir: &Path) -> Result<Scan, AppError> {
+    let mut scan = Scan {
+        files: Vec::new(),
+        dirs: BTreeSet::new(),
+        unreadable: BTreeSet::new(),
+    };
+    let mut pending = vec![String::new()];
+    while let Some(rel) = pending.pop() {
+        let path = absolute(dir, &rel);
+        let entries = fs::read_dir(&path).map_err(|e| AppError::io(&path, e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| AppError::io(&path, e))?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+            let child = if rel.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", rel, name)
+            };
+            let kind = entry
+                .file_type()
+                .map_err(|e| AppError::io(entry.path(), e))?;
+            if kind.is_dir() {
+                scan.dirs.insert(child.clone());
+                pending.push(child);
+            } else if kind.is_file() && name.to_lowercase().ends_with(".md") {
+                let Ok(text) = fs::read_to_string(entry.path()) else {
+                    eprintln!("Mirror skips unreadable file {}", entry.path().display());
+                    scan.unreadable.insert(child);
+                    continue;
+                };
+                let (body, id) = parse(&text);
+                scan.files.push(DiskFile {
+                    path: child,
+                    id,
+                    hash: content_hash(&body),
+                    body,
+                });
+            }
+        }
+    }
+    Ok(scan)
+}
+
+/// `updated_at` of a row just written in the pass's transaction.
+async fn stamp(conn: &mut SqliteConnection, table: &str, id: &str) -> Result<i64, AppError> {
+    sqlx::query_scalar(&format!("SELECT updated_at FROM {} WHERE id = ?1", table))
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(AppError::from)
+}
+
+/// Folders of the workspace by directory path, created on demand.
+struct FolderPaths<'a> {
+    workspace_id: &'a str,
+    ids: HashMap<String, String>,
+    imported: usize,
+}
+
+impl FolderPaths<'_> {
+    /// The folder for directory `path`, creating it and its parents in the
+    /// app when they don't exist yet and recording them in `state`. `None`
+    /// for the mirror root.
+    async fn ensure(
+        &mut self,
+        conn: &mut SqliteConnection,
+        state: &mut MirrorState,
+        path: &str,
+    ) -> Result<Option<String>, AppError> {
+        let mut parent_id = None;
+        let mut current = String::new();
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            current = if current.is_empty() {
+                part.to_string()
+            } else {
+                format!("{}/{}", current, part)
+            };
+            if let Some(id) = self.ids.get(&current) {
+                parent_id = Some(id.clone());
+                continue;
+            }
+            let id = uuid::Uuid::new_v4().to_string();
+            tx::upsert_folder(
+                conn,
+                FolderRecord {
+                    id: id.clone(),
+                    name: part.to_string(),
+                    parent_id: parent_id.clone(),
+                    workspace_id: self.workspace_id.to_string(),
+                    created_at: 0,
+                    updated_at: 0,
+                    version: 1,
+                    color: None,
+                    sort_key: String::new(),
+                    is_deleted: false,
+                },
+            )
+            .await?;
+            state.folders.insert(
+                id.clone(),
+                Mirrored {
+                    path: current.clone(),
+                    hash: 0,
+                    updated_at: stamp(conn, "folders", &id).await?,
+                },
+            );
+            self.imported += 1;
+            self.ids.insert(current.clone(), id.clone());
+            parent_id = Some(id);
+        }
+        Ok(parent_id)
+    }
+}
+
+/// Brings the workspace and its directory in line and updates `state` to
+/// what both look like afterwards.
+///
+/// Edits from disk are applied in one transaction that also saves the state
+/// they lead to, file by file, so a pass that fails while writing out never
+/// imports the same files twice.
+///
+/// A directory that has gone missing or empty since the last pass (an
+/// unmounted drive, say) fails the pass instead of deleting every note.
+pub async fn mirror_pass(
+    db: &Db,
+    workspace_id: &str,
+    state: &mut MirrorState,
+) -> Result<MirrorSummary, AppError> {
+    let dir = state.dir.clone();
+    let mirrored_before = !state.notes.is_empty() || !state.folders.is_empty();
+    if mirrored_before && !dir.is_dir() {
+        return Err(AppError::Io {
+            path: Some(dir.display().to_string()),
+            message: "The mirror folder is missing".to_string(),
+        });
+    }
+    fs::create_dir_all(&dir).map_err(|e| AppError::io(&dir, e))?;
+    let mut summary = MirrorSummary::default();
+
+    let (notes, folders) = db.get_workspace_items(workspace_id, false).await?;
+    // Only what is laid out is mirrored; a live note in a deleted folder
+    // counts as gone
+    let live_layout = ExportLayout::new(&notes, &folders, ".md");
+    let live: HashMap<&str, &NoteRecord> = live_layout
+        .notes
+        .iter()
+        .map(|n| (n.record.id.as_str(), n.record))
+        .collect();
+    let live_paths: HashMap<&str, &str> = live_layout
+        .notes
+        .iter()
+        .map(|n| (n.record.id.as_str(), n.path.as_str()))
+        .collect();
+    let live_folders: HashMap<&str, &FolderRecord> =
+        folders.iter().map(|f| (f.id.as_str(), f)).collect();
+    let Scan {
+        files,
+        dirs,
+        unreadable,
+    } = scan(&dir)?;
+    if !state.notes.is_empty() && files.is_empty() && unreadable.is_empty() {
+        return Err(AppError::conflict(
+            "The mirror folder is empty; turn mirroring off to stop mirroring instead of deleting every note",
+        ));
+    }
+    summary.unreadable = unreadable.iter().cloned().collect();
+
+    // Which note each file belongs to: its marker, else its known path,
+    // else the content of a note whose file disappeared. Files at the path
+    // the state knows for their marker go first, so they win over copies
+    let known_paths: HashMap<&str, &str> = state
+        .notes
+        .iter()
+        .map(|(id, m)| (m.path.as_str(), id.as_str()))
+        .collect();
+    let mut ordered: Vec<&DiskFile> = files.iter().collect();
+    ordered.sort_by_key(|f| known_paths.get(f.path.as_str()).copied() != f.id.as_deref());
+    let mut by_note: HashMap<String, &DiskFile> = HashMap::new();
+    let mut unmatched: Vec<&DiskFile> = Vec::new();
+    for file in ordered {
+        let id = file
+            .id
+            .clone()
+            .filter(|id| state.notes.contains_key(id) || live.contains_key(id.as_str()))
+            .or_else(|| known_paths.get(file.path.as_str()).map(|id| id.to_string()));
+        match id {
+            Some(id) if !by_note.contains_key(&id) => {
+                by_note.insert(id, file);
+            }
+            // The note's new file, written by a pass that failed before it
+            // removed the old one
+            Some(id)
+                if live_paths.get(id.as_str()) == Some(&file.path.as_str())
+                    && live
+                        .get(id.as_str())
+                        .is_some_and(|n| n.content == file.body) => {}
+            _ => unmatched.push(file),
+        }
+    }
+    unmatched.retain(|file| {
+        let renamed = state
+            .notes
+            .iter()
+            .find(|(id, m)| m.hash == file.hash && !by_note.contains_key(*id))
+            .map(|(id, _)| id.clone());
+        match renamed {
+            Some(id) => {
+                by_note.insert(id, file);
+                false
+            }
+            None => true,
+        }
+    });
+
+    let mut conn = db.pool.begin().await?;
+    let mut after = state.clone();
+    let now = now_millis();
+
+    // Folders first, so notes can be put into them
+    let mut paths = FolderPaths {
+        workspace_id,
+        ids: HashMap::new(),
+        imported: 0,
+    };
+    for (id, mirrored) in &state.folders {
+        if dirs.contains(&mirrored.path) && live_folders.contains_key(id.as_str()) {
+            paths.ids.insert(mirrored.path.clone(), id.clone());
+        }
+    }
+    let known_dirs: HashSet<&str> = state.folders.values().map(|m| m.path.as_str()).collect();
+    // Parents first, so a renamed parent is known before its subfolders
+    let mut vanished: Vec<(&String, &Mirrored)> = state.folders.iter().collect();
+    vanished.sort_by(|a, b| a.1.path.cmp(&b.1.path));
+    for (id, mirrored) in vanished {
+        let Some(folder) = live_folders.get(id.as_str()) else {
+            continue;
+        };
+        if dirs.contains(&mirrored.path) {
+            continue;
+        }
+        // A directory that vanished while one of its notes showed up in a
+        // new directory was renamed or moved
+        let moved_to = by_note
+            .iter()
+            .filter(|(note_id, _)| {
+                live.get(note_id.as_str())
+                    .is_some_and(|n| n.folder_id.as_deref() == Some(id.as_str()))
+            })
+            .map(|(_, file)| parent_dir(&file.path))
+            .find(|d| !d.is_empty() && !known_dirs.contains(d) && !paths.ids.contains_key(*d));
+        if let Some(new_path) = moved_to {
+            let parent_id = paths
+                .ensure(&mut conn, &mut after, parent_dir(new_path))
+                .await?;
+            let name = new_path.rsplit('/').next().unwrap_or(new_path);
+            tx::upsert_folder(
+                &mut conn,
+                FolderRecord {
+                    name: name.to_string(),
+                    parent_id,
+                    ..(*folder).clone()
+                },
+            )
+            .await?;
+            after.folders.insert(
+                id.clone(),
+                Mirrored {
+                    path: new_path.to_string(),
+                    hash: 0,
+                    updated_at: stamp(&mut conn, "folders", id).await?,
+                },
+            );
+            paths.ids.insert(new_path.to_string(), id.clone());
+            summary.imported += 1;
+        } else if folder.updated_at == mirrored.updated_at {
+            tx::delete_folder(&mut conn, id, now).await?;
+            after.folders.remove(id);
+            summary.imported += 1;
+        }
+        // Otherwise it changed in the app too and is written out again
+    }
+    for path in &dirs {
+        paths.ensure(&mut conn, &mut after, path).await?;
+    }
+
+    // Notes
+    let mut conflicts: Vec<(&DiskFile, Option<String>)> = Vec::new();
+    for (id, note) in &live {
+        let mirrored = state.notes.get(*id);
+        let app_changed = mirrored.is_none_or(|m| m.updated_at != note.updated_at);
+        let Some(file) = by_note.get(*id) else {
+            // An unreadable file is still there; the note is left alone
+            let kept = mirrored.is_some_and(|m| unreadable.contains(&m.path));
+            if mirrored.is_some() && !app_changed && !kept {
+                tx::delete_note(&mut conn, id, now).await?;
+                after.notes.remove(*id);
+                summary.imported += 1;
+            }
+            continue;
+        };
+        let edited = mirrored.is_none_or(|m| m.hash != file.hash) && file.body != note.content;
+        let moved = mirrored.is_some_and(|m| m.path != file.path);
+        if !edited && !moved {
+            continue;
+        }
+        let mut updated = (*note).clone();
+        if moved {
+            updated.title = file_stem(&file.path).to_string();
+            updated.folder_id = paths
+                .ensure(&mut conn, &mut after, parent_dir(&file.path))
+                .await?;
+        }
+        if edited && app_changed {
+            conflicts.push((file, updated.folder_id.clone()));
+        } else if edited {
+            updated.content = file.body.clone();
+        }
+        // The file is accounted for either way; a note that was not saved
+        // here keeps counting as changed in the app, so it is written out
+        let updated_at = if moved || !app_changed {
+            tx::upsert_note(&mut conn, updated).await?;
+            summary.imported += 1;
+            stamp(&mut conn, "notes", id).await?
+        } else {
+            mirrored.map_or(0, |m| m.updated_at)
+        };
+        after.notes.insert(
+            id.to_string(),
+            Mirrored {
+                path: file.path.clone(),
+                hash: file.hash,
+                updated_at,
+            },
+        );
+    }
+    // Files of notes deleted in the app are removed below, unless they were
+    // edited since; those come back as new notes
+    for (id, mirrored) in &state.notes {
+        if let Some(file) = by_note.get(id) {
+            if !live.contains_key(id.as_str()) && file.hash != mirrored.hash {
+                unmatched.push(file);
+                after.notes.remove(id);
+            }
+        }
+    }
+    for (file, folder_id) in conflicts {
+        tx::upsert_note(
+            &mut conn,
+            new_note(
+                workspace_id,
+                format!("{} (conflict)", file_stem(&file.path)),
+                file.body.clone(),
+                folder_id,
+            ),
+        )
+        .await?;
+        summary.conflicts += 1;
+    }
+    let imported_paths: Vec<&str> = unmatched.iter().map(|f| f.path.as_str()).collect();
+    for file in unmatched {
+        let folder_id = paths
+            .ensure(&mut conn, &mut after, parent_dir(&file.path))
+            .await?;
+        let note = new_note(
+            workspace_id,
+            file_stem(&file.path).to_string(),
+            file.body.clone(),
+            folder_id,
+        );
+        let id = note.id.clone();
+        tx::upsert_note(&mut conn, note).await?;
+        after.notes.insert(
+            id.clone(),
+            Mirrored {
+                path: file.path.clone(),
+                hash: file.hash,
+                updated_at: stamp(&mut conn, "notes", &id).await?,
+            },
+        );
+        summary.imported += 1;
+    }
+    summary.imported += paths.imported;
+    let changed = summary.imported + summary.conflicts > 0;
+    if changed {
+        tx::set_setting(
+            &mut conn,
+            &format!("{}{}", SETTING_PREFIX, workspace_id),
+            &state_json(&after)?,
+        )
+        .await?;
+    }
+    conn.commit().await?;
+    if changed {
+        db.notify_changed();
+    }
+    *state = after;
+
+    // Write the app's side out, now including what came from disk
+    let (notes, folders) = db.get_workspace_items(workspace_id, false).await?;
+    let layout = ExportLayout::new(&notes, &folders, ".md");
+    let mut next = MirrorState {
+        dir: dir.clone(),
+        ..MirrorState::default()
+    };
+    for entry in &layout.folders {
+        let path = absolute(&dir, &entry.path);
+        if !path.is_dir() {
+            fs::create_dir_all(&path).map_err(|e| AppError::io(&path, e))?;
+            summary.exported += 1;
+        }
+        next.folders.insert(
+            entry.record.id.clone(),
+            Mirrored {
+                path: entry.path.clone(),
+                hash: 0,
+                updated_at: entry.record.updated_at,
+            },
+        );
+    }
+    let current: HashMap<&str, &DiskFile> = files.iter().map(|f| (f.path.as_str(), f)).collect();
+    let mut written: HashSet<&str> = HashSet::new();
+    for entry in &layout.notes {
+        let note = entry.record;
+        // Never written over; the note stays as the state knows it
+        if unreadable.contains(&entry.path) {
+            if let Some(mirrored) = state.notes.get(&note.id) {
+                written.insert(&mirrored.path);
+                next.notes.insert(note.id.clone(), mirrored.clone());
+            }
+            continue;
+        }
+        let up_to_date = current
+            .get(entry.path.as_str())
+            .is_some_and(|f| f.id.as_deref() == Some(note.id.as_str()) && f.body == note.content);
+        if !up_to_date {
+            write_relative(&dir, &entry.path, render(note).as_bytes())?;
+            summary.exported += 1;
+        }
+        written.insert(&entry.path);
+        next.notes.insert(
+            note.id.clone(),
+            Mirrored {
+                path: entry.path.clone(),
+                hash: content_hash(&note.content),
+                updated_at: note.updated_at,
+            },
+        );
+    }
+
+    // Remove files this mirror wrote that no longer belong to a note at that
+    // path; files it never wrote are left alone
+    let stale: BTreeSet<&str> = state
+        .notes
+        .values()
+        .map(|m| m.path.as_str())
+        .chain(by_note.values().map(|f| f.path.as_str()))
+        .chain(
+            files
+                .iter()
+                .filter(|f| f.id.is_some())
+                .map(|f| f.path.as_str()),
+        )
+        .chain(imported_paths)
+        .filter(|path| !written.contains(path) && current.contains_key(path))
+        .collect();
+    for path in stale {
+        let path = absolute(&dir, path);
+        fs::remove_file(&path).map_err(|e| AppError::io(&path, e))?;
+        summary.exported += 1;
+    }
+    // Deepest first; directories with other files in them stay
+    let mut old_dirs: Vec<&str> = state
+        .folders
+        .values()
+        .map(|m| m.path.as_str())
+        .filter(|path| !next.folders.values().any(|m| m.path == *path))
+        .collect();
+    old_dirs.sort_by_key(|path| std::cmp::Reverse(path.len()));
+    for path in old_dirs {
+        if fs::remove_dir(absolute(&dir, path)).is_ok() {
+            summary.exported += 1;
+        }
+    }
+
+    *state = next;
+    Ok(summary)
+}
+
+fn new_note(
+    workspace_id: &str,
+    title: String,
+    content: String,
+    folder_id: Option<String>,
+) -> NoteRecord {
+    NoteRecord {
+        id: uuid::Uuid::new_v4().to_string(),
+        title,
+        content,
+        folder_id,
+        workspace_id: workspace_id.to_string(),
+        created_at: 0,
+        updated_at: 0,
+        version: 1,
+        sort_key: String::new(),
+        color: None,
+        is_deleted: false,
+    }
+}
+
+async fn load_states(db: &Db) -> Result<Vec<(String, MirrorState)>, AppError> {
+    let rows: Vec<(String, String)> =
+        sqlx::query_as("SELECT key, value FROM settings WHERE key LIKE 'mirror.%' ORDER BY key")
+            .fetch_all(&db.pool)
+            .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let workspace_id = key.strip_prefix(SETTING_PREFIX)?.to_string();
+            Some((workspace_id, serde_json::from_str(&value).ok()?))
+        })
+        .collect())
+}
+
+async fn load_state(db: &Db, workspace_id: &str) -> Result<Option<MirrorState>, AppError> {
+    Ok(db
+        .get_setting(&format!("{}{}", SETTING_PREFIX, workspace_id))
+        .await?
+        .and_then(|value| serde_json::from_str(&value).ok()))
+}
+
+fn state_json(state: &MirrorState) -> Result<String, AppError> {
+    serde_json::to_string(state)
+        .map_err(|e| AppError::internal(format!("Failed to serialize mirror state: {}", e)))
+}
+
+async fn save_state(db: &Db, workspace_id: &str, state: &MirrorState) -> Result<(), AppError> {
+    db.set_setting(
+        &format!("{}{}", SETTING_PREFIX, workspace_id),
+        &state_json(state)?,
+    )
+    .await
+}
+
+/// Changes whenever a note or folder of the workspace does, deleted or not.
+async fn fingerprint(db: &Db, workspace_id: &str) -> Result<(i64, i64), AppError> {
+    sqlx::query_as(
+        "SELECT
+            (SELECT count(*) FROM notes WHERE workspace_id = ?1)
+              + (SELECT count(*) FROM folders WHERE workspace_id = ?1),
+            max(
+              (SELECT coalesce(max(updated_at), 0) FROM notes WHERE workspace_id = ?1),
+              (SELECT coalesce(max(updated_at), 0) FROM folders WHERE workspace_id = ?1)
+            )",
+    )
+    .bind(workspace_id)
+    .fetch_one(&db.pool)
+    .await
+    .map_err(AppError::from)
+}
+
+#[derive(Default)]
+struct Watched {
+    /// Dropping it stops watching.
+    watcher: Option<notify::RecommendedWatcher>,
+    dir: PathBuf,
+    /// Files changed since the last pass.
+    dirty: bool,
+    fingerprint: Option<(i64, i64)>,
+    status: Option<MirrorStatus>,
+}
+
+/// Runs mirror passes for every mirrored workspace, on file events and on
+/// changes in the app.
+#[derive(Default)]
+pub struct MirrorService {
+    pub wake: Notify,
+    watched: Mutex<HashMap<String, Watched>>,
+    /// Held during a pass, so the loop and `mirror_now` never overlap.
+    running: tokio::sync::Mutex<()>,
+}
+
+impl MirrorService {
+    fn mark_dirty(&self, workspace_id: &str) {
+        if let Some(watched) = self.watched.lock().unwrap().get_mut(workspace_id) {
+            watched.dirty = true;
+        }
+        self.wake.notify_one();
+    }
+
+    /// Keeps a watcher on every mirrored directory and drops the others.
+    fn watch(&self, app: &AppHandle, states: &[(String, MirrorState)]) {
+        let mut watched = self.watched.lock().unwrap();
+        watched.retain(|id, w| states.iter().any(|(ws, s)| ws == id && s.dir == w.dir));
+        for (workspace_id, state) in states {
+            let entry = watched
+                .entry(workspace_id.clone())
+                .or_insert_with(|| Watched {
+                    dir: state.dir.clone(),
+                    dirty: true,
+                    ..Watched::default()
+                });
+            if entry.watcher.is_some() {
+                continue;
+            }
+            let app = app.clone();
+            let id = workspace_id.clone();
+            let watcher =
+                notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                    if event.is_ok_and(|e| !matches!(e.kind, EventKind::Access(_))) {
+                        app.state::<MirrorService>().mark_dirty(&id);
+                    }
+                })
+                .and_then(|mut watcher| {
+                    watcher.watch(&state.dir, RecursiveMode::Recursive)?;
+                    Ok(watcher)
+                });
+            match watcher {
+                Ok(watcher) => entry.watcher = Some(watcher),
+                // Retried on the next round; changes in the app still go out
+                Err(e) => eprintln!("Cannot watch {}: {}", state.dir.display(), e),
+            }
+        }
+    }
+
+    /// Runs a pass if files or the workspace changed since the last one.
+    async fn run_if_due(&self, db: &Db, workspace_id: &str) {
+        let current = fingerprint(db, workspace_id).await.ok();
+        let due = {
+            let watched = self.watched.lock().unwrap();
+            watched
+                .get(workspace_id)
+                .is_none_or(|w| w.dirty || w.fingerprint != current)
+        };
+        if !due {
+            return;
+        }
+        if let Some(w) = self.watched.lock().unwrap().get_mut(workspace_id) {
+            w.dirty = false;
+        }
+        let result = self.run(db, workspace_id).await;
+        let current = fingerprint(db, workspace_id).await.ok();
+        if let Some(w) = self.watched.lock().unwrap().get_mut(workspace_id) {
+            w.fingerprint = current;
+        }
+        if let Err(e) = result {
+            eprintln!("Mirror of workspace {} failed: {}", workspace_id, e);
+        }
+    }
+
+    /// Runs a pass from the state saved after the last one. Reads the state
+    /// only once no other pass or configuration change is in flight.
+    async fn run(&self, db: &Db, workspace_id: &str) -> Result<MirrorSummary, AppError> {
+        let _running = self.running.lock().await;
+        let mut state = load_state(db, workspace_id)
+            .await?
+            .ok_or_else(|| AppError::validation("workspace_id", "The workspace is not mirrored"))?;
+        let result = match mirror_pass(db, workspace_id, &mut state).await {
+            Ok(summary) => save_state(db, workspace_id, &state).await.map(|_| summary),
+            Err(e) => Err(e),
+        };
+        let status = MirrorStatus {
+            workspace_id: workspace_id.to_string(),
+            dir: state.dir.display().to_string(),
+            last_pass_at: Some(now_millis()),
+            last_summary: result.as_ref().ok().cloned(),
+            last_error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        if let Some(w) = self.watched.lock().unwrap().get_mut(workspace_id) {
+            w.status = Some(status);
+        }
+        result
+    }
+
+    fn status(&self, workspace_id: &str, state: &MirrorState) -> MirrorStatus {
+        self.watched
+            .lock()
+            .unwrap()
+            .get(workspace_id)
+            .and_then(|w| w.status.clone())
+            .unwrap_or_else(|| MirrorStatus {
+                workspace_id: workspace_id.to_string(),
+                dir: state.dir.display().to_string(),
+                last_pass_at: None,
+                last_summary: None,
+                last_error: None,
+            })
+    }
+}
+
+/// Starts the mirror loop. Uses `peek` like the sync loop, so mirroring
+/// never keeps the database from locking.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let mirror = app.state::<MirrorService>();
+            let state = app.state::<DbState>();
+            let Some(db) = state.peek() else {
+                state.opened.notified().await;
+                continue;
+            };
+            match load_states(&db).await {
+                Ok(states) => {
+                    mirror.watch(&app, &states);
+                    for (workspace_id, _) in states {
+                        mirror.run_if_due(&db, &workspace_id).await;
+                    }
+                }
+                Err(e) => eprintln!("Cannot read mirror settings: {}", e),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                _ = mirror.wake.notified() => {
+                    tokio::time::sleep(WATCH_DEBOUNCE).await;
+                }
+                _ = state.opened.notified() => {}
+            }
+        }
+    });
+}
+
+/// Mirrors the workspace into the directory granted by `pick_directory`, or
+/// stops mirroring it without a token. Files already on disk are kept and
+/// imported by the first pass.
+#[tauri::command]
+pub async fn configure_mirror(
+    state: tauri::State<'_, DbState>,
+    grants: tauri::State<'_, FileGrants>,
+    mirror: tauri::State<'_, MirrorService>,
+    workspace_id: String,
+    token: Option<String>,
+) -> Result<Option<MirrorStatus>, AppError> {
+    let db = state.db()?;
+    if !db
+        .get_workspaces()
+        .await?
+        .iter()
+        .any(|w| w.id == workspace_id)
+    {
+        return Err(AppError::not_found(
+            Entity::Workspace,
+            workspace_id.as_str(),
+        ));
+    }
+    let key = format!("{}{}", SETTING_PREFIX, workspace_id);
+    let _running = mirror.running.lock().await;
+    let Some(token) = token else {
+        sqlx::query("DELETE FROM settings WHERE key = ?1")
+            .bind(&key)
+            .execute(&db.pool)
+            .await?;
+        mirror.wake.notify_one();
+        return Ok(None);
+    };
+    let dir = grants.redeem(&token, Access::Directory)?;
+    let mirror_state = MirrorState {
+        dir,
+        ..MirrorState::default()
+    };
+    save_state(&db, &workspace_id, &mirror_state).await?;
+    mirror.wake.notify_one();
+    Ok(Some(mirror.status(&workspace_id, &mirror_state)))
+}
+
+#[tauri::command]
+pub async fn get_mirror_status(
+    state: tauri::State<'_, DbState>,
+    mirror: tauri::State<'_, MirrorService>,
+) -> Result<Vec<MirrorStatus>, AppError> {
+    let db = state.db()?;
+    Ok(load_states(&db)
+        .await?
+        .iter()
+        .map(|(workspace_id, s)| mirror.status(workspace_id, s))
+        .collect())
+}
+
+/// Runs a pass right away instead of waiting for the next change.
+#[tauri::command]
+pub async fn mirror_now(
+    state: tauri::State<'_, DbState>,
+    mirror: tauri::State<'_, MirrorService>,
+    workspace_id: String,
+) -> Result<MirrorSummary, AppError> {
+    let db = state.db()?;
+    mirror.run(&db, &workspace_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_db;
+
+    async fn setup() -> (Db, MirrorState) {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+        let state = MirrorState {
+            dir: dir.join("mirror"),
+            ..MirrorState::default()
+        };
+        (db, state)
+    }
+
+    async fn note(db: &Db, title: &str) -> NoteRecord {
+        db.get_notes()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|n| n.title == title)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_strips_marker() {
+        let note = new_note("default", "T".to_string(), "Body\n".to_string(), None);
+        assert_eq!(
+            parse(&render(&note)),
+            ("Body\n".to_string(), Some(note.id.clone()))
+        );
+        assert_eq!(
+            parse("Edited\r\n<!-- lumenote:abc -->\r\nmore"),
+            ("Edited\nmore".to_string(), Some("abc".to_string()))
+        );
+        assert_eq!(parse("No marker"), ("No marker".to_string(), None));
+    }
+
+    #[tokio::test]
+    async fn test_mirror_applies_edits_and_renames_from_disk() {
+        let (db, mut state) = setup().await;
+        db.upsert_note(new_note(
+            "default",
+            "Plan".to_string(),
+            "v1".to_string(),
+            None,
+        ))
+        .await
+        .unwrap();
+        let first = mirror_pass(&db, "default", &mut state).await.unwrap();
+        assert_eq!(first.exported, 1);
+        let file = state.dir.join("Plan.md");
+        let id = note(&db, "Plan").await.id;
+        assert!(fs::read_to_string(&file).unwrap().contains(&id));
+
+        // Nothing changed: nothing to do
+        let idle = mirror_pass(&db, "default", &mut state).await.unwrap();
+        assert_eq!(idle, MirrorSummary::default());
+
+        // Edit and rename into a new directory outside the app
+        let text = fs::read_to_string(&file).unwrap().replace("v1", "v2");
+        fs::create_dir_all(state.dir.join("Work")).unwrap();
+        fs::write(state.dir.join("Work").join("Roadmap.md"), text).unwrap();
+        fs::remove_file(&file).unwrap();
+        fs::write(state.dir.join("Fresh.md"), "From disk").unwrap();
+        mirror_pass(&db, "default", &mut state).await.unwrap();
+
+        let moved = note(&db, "Roadmap").await;
+        assert_eq!(moved.id, id);
+        assert_eq!(moved.content, "v2");
+        let folder = db.get_folders().await.unwrap();
+        assert_eq!(folder.len(), 1);
+        assert_eq!(moved.folder_id.as_deref(), Some(folder[0].id.as_str()));
+        let revisions: i64 = sqlx::query_scalar("SELECT count(*) FROM changes WHERE note_id = ?1")
+            .bind(&id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(revisions, 2);
+
+        // The new file became a note and got its marker
+        let fresh = note(&db, "Fresh").await;
+        assert_eq!(fresh.content, "From disk");
+        assert!(fs::read_to_string(state.dir.join("Fresh.md"))
+            .unwrap()
+            .contains(&fresh.id));
+
+        // Deleting the file deletes the note
+        fs::remove_file(state.dir.join("Fresh.md")).unwrap();
+        mirror_pass(&db, "default", &mut state).await.unwrap();
+        assert!(!db
+            .get_notes()
+            .await
+            .unwrap()
+            .iter()
+            .any(|n| n.id == fresh.id));
+    }
+
+    #[tokio::test]
+    async fn test_mirror_keeps_both_sides_of_a_conflict() {
+        let (db, mut state) = setup().await;
+        db.upsert_note(new_note(
+            "default",
+            "Plan".to_string(),
+            "v1".to_string(),
+            None,
+        ))
+        .await
+        .unwrap();
+        mirror_pass(&db, "default", &mut state).await.unwrap();
+        // The app's save has to get a newer timestamp than the last pass saw
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let file = state.dir.join("Plan.md");
+        let text = fs::read_to_string(&file).unwrap().replace("v1", "disk");
+        fs::write(&file, text).unwrap();
+        let mut in_app = note(&db, "Plan").await;
+        in_app.content = "app".to_string();
+        db.upsert_note(in_app).await.unwrap();
+
+        let summary = mirror_pass(&db, "default", &mut state).await.unwrap();
+        assert_eq!(summary.conflicts, 1);
+        assert_eq!(note(&db, "Plan").await.content, "app");
+        assert_eq!(note(&db, "Plan (conflict)").await.content, "disk");
+        assert!(fs::read_to_string(&file).unwrap().starts_with("app\n"));
+        assert!(state.dir.join("Plan (conflict).md").exists());
+    }
+
+    #[tokio::test]
+    async fn test_mirror_skips_files_that_are_not_text() {
+        let (db, mut state) = setup().await;
+        db.upsert_note(new_note(
+            "default",
+            "Plan".to_string(),
+            "v1".to_string(),
+            None,
+        ))
+        .await
+        .unwrap();
+        mirror_pass(&db, "default", &mut state).await.unwrap();
+        let file = state.dir.join("Plan.md");
+        fs::write(&file, b"caf\xe9").unwrap();
+        fs::write(state.dir.join("Other.md"), b"\xff\xfe").unwrap();
+
+        let summary = mirror_pass(&db, "default", &mut state).await.unwrap();
+        assert_eq!(summary.unreadable, vec!["Other.md", "Plan.md"]);
+        assert_eq!(note(&db, "Plan").await.content, "v1");
+        assert_eq!(fs::read(&file).unwrap(), b"caf\xe9");
+        assert_eq!(db.get_notes().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_pass_does_not_import_files_twice() {
+        let (db, mut state) = setup().await;
+        db.upsert_folder(FolderRecord {
+            id: "work".to_string(),
+            name: "Work".to_string(),
+            parent_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            version: 1,
+            color: None,
+            sort_key: String::new(),
+            is_deleted: false,
+        })
+        .await
+        .unwrap();
+        save_state(&db, "default", &state).await.unwrap();
+        fs::create_dir_all(&state.dir).unwrap();
+        fs::write(state.dir.join("Fresh.md"), "From disk").unwrap();
+        // A file where the folder's directory goes fails the writing out
+        fs::write(state.dir.join("Work"), "in the way").unwrap();
+        assert!(mirror_pass(&db, "default", &mut state).await.is_err());
+
+        fs::remove_file(state.dir.join("Work")).unwrap();
+        let mut state = load_state(&db, "default").await.unwrap().unwrap();
+        mirror_pass(&db, "default", &mut state).await.unwrap();
+        let fresh: Vec<NoteRecord> = db
+            .get_notes()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|n| n.title == "Fresh")
+            .collect();
+        assert_eq!(fresh.len(), 1);
+        assert!(fs::read_to_string(state.dir.join("Fresh.md"))
+            .unwrap()
+            .contains(&fresh[0].id));
+    }
+
+    #[tokio::test]
+    async fn test_missing_or_emptied_folder_deletes_nothing() {
+        let (db, mut state) = setup().await;
+        db.upsert_note(new_note(
+            "default",
+            "Plan".to_string(),
+            "v1".to_string(),
+            None,
+        ))
+        .await
+        .unwrap();
+        mirror_pass(&db, "default", &mut state).await.unwrap();
+
+        fs::remove_dir_all(&state.dir).unwrap();
+        assert!(mirror_pass(&db, "default", &mut state).await.is_err());
+        assert!(!state.dir.exists());
+
+        fs::create_dir_all(&state.dir).unwrap();
+        assert!(mirror_pass(&db, "default", &mut state).await.is_err());
+        assert_eq!(note(&db, "Plan").await.content, "v1");
+    }
+}
@@ -0,0 +1,466 @@
+//! Manual order of notes and folders in the sidebar. Each row carries a
+//! fractional `sort_key`: moving an item picks a key between its new
+//! neighbours, so only the moved row is written (and synced), never its
+//! siblings.
+
+use crate::db::{now_millis, Db, DbState};
+use crate::error::{AppError, Entity};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+
+/// Key digits in ascending byte order, so SQLite's default (binary) collation
+/// sorts keys the same way `key_between` does.
+const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemKind {
+    Note,
+    Folder,
+}
+
+impl ItemKind {
+    fn table(self) -> &'static str {
+        match self {
+            ItemKind::Note => "notes",
+            ItemKind::Folder => "folders",
+        }
+    }
+
+    fn parent_column(self) -> &'static str {
+        match self {
+            ItemKind::Note => "folder_id",
+            ItemKind::Folder => "parent_id",
+        }
+    }
+
+    fn entity(self) -> Entity {
+        match self {
+            ItemKind::Note => Entity::Note,
+            ItemKind::Folder => Entity::Folder,
+        }
+    }
+}
+
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && !key.ends_with('0') && key.bytes().all(|b| DIGITS.contains(&b))
+}
+
+/// Key of the `index`-th item in a list numbered from scratch, for data that
+/// only knows positions (e.g. old export manifests). Fixed-width digits plus
+/// a middle digit, so keys sort by index and leave room on both sides.
+pub fn key_at(index: usize) -> String {
+    let mut key = vec![DIGITS[DIGITS.len() / 2]];
+    let mut rest = index;
+    for _ in 0..4 {
+        key.push(DIGITS[rest % DIGITS.len()]);
+        rest /= DIGITS.len();
+    }
+    key.reverse();
+    String::from_utf8(key).unwrap()
+}
+
+fn digit_value(digit: u8) -> usize {
+    DIGITS.iter().position(|&d| d == digit).unwrap_or(0)
+}
+
+/// Returns a key that sorts strictly between `after` and `before`; `None`
+/// means the start or end of the list. Keys never end in the lowest digit,
+/// which guarantees there is always room for another key below any key.
+pub fn key_between(after: Option<&str>, before: Option<&str>) -> Result<String, AppError> {
+    for key in [after, before].into_iter().flatten() {
+        if !is_valid_key(key) {
+            return Err(AppError::validation(
+                "sort_key",
+                format!("{:?} is not a valid key", key),
+            ));
+        }
+    }
+    if let (Some(a), Some(b)) = (after, before) {
+        if a >= b {
+            return Err(AppError::validation(
+                "sort_key",
+                format!("keys out of order: {:?} >= {:?}", a, b),
+            ));
+        }
+    }
+    Ok(midpoint(after.unwrap_or(""), before))
+}
+
+fn midpoint(a: &str, b: Option<&str>) -> String {
+    if let Some(b) = b {
+        // Keep the shared prefix; a missing digit in `a` counts as zero.
+        let shared = b
+            .bytes()
+            .zip(a.bytes().chain(std::iter::repeat(DIGITS[0])))
+            .take_while(|(x, y)| x == y)
+            .count();
+        if shared > 0 {
+            let rest = midpoint(a.get(shared..).unwrap_or(""), Some(&b[shared..]));
+            return format!("{}{}", &b[..shared], rest);
+        }
+    }
+
+    let low = a.bytes().next().map_or(0, digit_value);
+    let high = b
+        .and_then(|b| b.bytes().next())
+        .map_or(DIGITS.len(), digit_value);
+    if high - low > 1 {
+        return (DIGITS[(low + high) / 2] as char).to_string();
+    }
+    match b {
+        // `b` has more digits, so its first digit alone already sorts before it.
+        Some(b) if b.len() > 1 => b[..1].to_string(),
+        _ => format!(
+            "{}{}",
+            DIGITS[low] as char,
+            midpoint(a.get(1..).unwrap_or(""), None)
+        ),
+    }
+}
+
+/// Key that places a new item after its last sibling.
+pub async fn append_key(
+    conn: &mut SqliteConnection,
+    kind: ItemKind,
+    workspace_id: &str,
+    parent_id: Option<&str>,
+) -> Result<String, AppError> {
+    let last = sqlx::query_scalar::<_, Option<String>>(&format!(
+        "SELECT max(sort_key) FROM {} WHERE workspace_id = ?1 AND {} IS ?2 AND is_deleted = 0",
+        kind.table(),
+        kind.parent_column()
+    ))
+    .bind(workspace_id)
+    .bind(parent_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    // A damaged key among the siblings must not block saving; `reorder`
+    // renumbers the list the next time it is touched.
+    let last = last.filter(|k| is_valid_key(k));
+    Ok(key_between(last.as_deref(), None).unwrap_or_else(|_| midpoint("", None)))
+}
+
+/// Key to store when a note or folder is saved. `existing` is the stored
+/// parent and key of the row, if any. Saving never changes an item's place
+/// among its siblings; an item that moved to another parent goes to the end
+/// there, and a new item keeps a valid key it came with (a restore or copy)
+/// or goes to the end.
+pub async fn key_for_save(
+    conn: &mut SqliteConnection,
+    kind: ItemKind,
+    workspace_id: &str,
+    parent_id: Option<&str>,
+    existing: Option<(Option<String>, String)>,
+    requested: String,
+) -> Result<String, AppError> {
+    match existing {
+        Some((old_parent, key)) if old_parent.as_deref() == parent_id => Ok(key),
+        Some(_) => append_key(conn, kind, workspace_id, parent_id).await,
+        None if is_valid_key(&requested) => Ok(requested),
+        None => append_key(conn, kind, workspace_id, parent_id).await,
+    }
+}
+
+async fn set_key(
+    conn: &mut SqliteConnection,
+    kind: ItemKind,
+    id: &str,
+    key: &str,
+    now: i64,
+) -> Result<(), AppError> {
+    sqlx::query(&format!(
+        "UPDATE {} SET sort_key = ?1, updated_at = ?2, version = version + 1 WHERE id = ?3",
+        kind.table()
+    ))
+    .bind(key)
+    .bind(now)
+    .bind(id)
+    .execute(&mut *conn)
+    .await
+    .map(|_| ())
+    .map_err(AppError::from)
+}
+
+/// Key for a slot directly after `after_id` (or at the top when `None`) among
+/// the live items of one workspace and parent, leaving out `exclude` (the item
+/// being moved).
+///
+/// Rows from before manual ordering have no key, and two devices can pick the
+/// same key for concurrent moves; in those cases the siblings are renumbered
+/// once in their current order first.
+pub async fn key_after(
+    conn: &mut SqliteConnection,
+    kind: ItemKind,
+    workspace_id: &str,
+    parent_id: Option<&str>,
+    after_id: Option<&str>,
+    exclude: Option<&str>,
+    now: i64,
+) -> Result<String, AppError> {
+    let mut siblings: Vec<(String, String)> = sqlx::query_as(&format!(
+        "SELECT id, sort_key FROM {} WHERE workspace_id = ?1 AND {} IS ?2 AND is_deleted = 0 AND id IS NOT ?3
+         ORDER BY sort_key, created_at",
+        kind.table(),
+        kind.parent_column()
+    ))
+    .bind(workspace_id)
+    .bind(parent_id)
+    .bind(exclude)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let position = match after_id {
+        Some(after_id) => {
+            siblings
+                .iter()
+                .position(|(sibling, _)| sibling == after_id)
+                .ok_or_else(|| {
+                    AppError::validation(
+                        "after_id",
+                        format!("{} is not in the same folder", after_id),
+                    )
+                })?
+                + 1
+        }
+        None => 0,
+    };
+
+    let ordered = siblings.iter().all(|(_, key)| is_valid_key(key))
+        && siblings.windows(2).all(|pair| pair[0].1 < pair[1].1);
+    if !ordered {
+        let mut previous: Option<String> = None;
+        for (sibling, key) in siblings.iter_mut() {
+            let next = key_between(previous.as_deref(), None)?;
+            set_key(conn, kind, sibling, &next, now).await?;
+            *key = next.clone();
+            previous = Some(next);
+        }
+    }
+
+    let after = position.checked_sub(1).map(|i| siblings[i].1.as_str());
+    let before = siblings.get(position).map(|(_, key)| key.as_str());
+    key_between(after, before)
+}
+
+/// Moves an item directly after `after_id` among its siblings (same workspace
+/// and parent), or to the top when `after_id` is `None`, and returns its new
+/// key.
+pub async fn move_after(
+    db: &Db,
+    kind: ItemKind,
+    id: &str,
+    after_id: Option<&str>,
+) -> Result<String, AppError> {
+    let mut tx = db.pool.begin().await?;
+
+    let (workspace_id, parent_id): (String, Option<String>) = sqlx::query_as(&format!(
+        "SELECT workspace_id, {} FROM {} WHERE id = ?1 AND is_deleted = 0",
+        kind.parent_column(),
+        kind.table()
+    ))
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::not_found(kind.entity(), id))?;
+
+    let now = now_millis();
+    let key = key_after(
+        &mut tx,
+        kind,
+        &workspace_id,
+        parent_id.as_deref(),
+        after_id,
+        Some(id),
+        now,
+    )
+    .await?;
+    set_key(&mut tx, kind, id, &key, now).await?;
+
+    tx.commit().await?;
+    db.notify_changed();
+    Ok(key)
+}
+
+#[tauri::command]
+pub async fn reorder(
+    state: tauri::State<'_, DbState>,
+    kind: ItemKind,
+    id: String,
+    after_id: Option<String>,
+) -> Result<String, AppError> {
+    let db = state.db()?;
+    move_after(&db, kind, &id, after_id.as_deref()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_db, tx, FolderRecord, NoteRecord};
+
+    #[test]
+    fn test_keys_stay_ordered_under_repeated_inserts() {
+        // Always inserting at the same spot is the worst case for key length.
+        let mut keys = vec![key_between(None, None).unwrap()];
+        for _ in 0..200 {
+            let first = keys[0].clone();
+            keys.insert(0, key_between(None, Some(&first)).unwrap());
+        }
+        for _ in 0..200 {
+            let last = keys.last().unwrap().clone();
+            keys.push(key_between(Some(&last), None).unwrap());
+        }
+        for _ in 0..200 {
+            let (a, b) = (keys[100].clone(), keys[101].clone());
+            keys.insert(101, key_between(Some(&a), Some(&b)).unwrap());
+        }
+
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(keys.iter().all(|k| is_valid_key(k)));
+        // Each worst-case insert costs about a sixth of a digit.
+        let longest = keys.iter().map(|k| k.len()).max().unwrap();
+        assert!(longest <= 64, "{}", longest);
+    }
+
+    #[test]
+    fn test_key_at_sorts_by_index() {
+        let keys: Vec<String> = [0, 1, 61, 62, 5000].into_iter().map(key_at).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(keys.iter().all(|k| is_valid_key(k)));
+        assert!(key_between(None, Some(&keys[0])).is_ok());
+    }
+
+    #[test]
+    fn test_key_between_rejects_bad_input() {
+        assert!(key_between(Some("b"), Some("a")).is_err());
+        assert!(key_between(Some("a"), Some("a")).is_err());
+        assert!(key_between(Some(""), None).is_err());
+        assert!(key_between(Some("a0"), None).is_err());
+        assert!(key_between(Some("a-"), None).is_err());
+        assert_eq!(key_between(Some("a"), Some("a1")).unwrap(), "a0V");
+    }
+
+    fn note(id: &str, created_at: i64) -> NoteRecord {
+        NoteRecord {
+            id: id.to_string(),
+            title: id.to_string(),
+            content: String::new(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at,
+            updated_at: created_at,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        }
+    }
+
+    async fn order(db: &Db) -> Vec<String> {
+        db.get_notes()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|n| n.id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_reorder_writes_only_the_moved_row() {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+
+        for id in ["a", "b", "c"] {
+            db.upsert_note(note(id, 1)).await.unwrap();
+        }
+        assert_eq!(order(&db).await, ["a", "b", "c"]);
+
+        move_after(&db, ItemKind::Note, "c", None).await.unwrap();
+        assert_eq!(order(&db).await, ["c", "a", "b"]);
+        move_after(&db, ItemKind::Note, "a", Some("b"))
+            .await
+            .unwrap();
+        assert_eq!(order(&db).await, ["c", "b", "a"]);
+
+        // a and c moved once each, b was never rewritten.
+        let notes = db.get_notes().await.unwrap();
+        let version = |id: &str| notes.iter().find(|n| n.id == id).unwrap().version;
+        assert_eq!(version("b"), 1);
+        assert_eq!(version("a"), 2);
+        assert_eq!(version("c"), 2);
+
+        // A later save keeps the position.
+        db.upsert_note(note("c", 1)).await.unwrap();
+        assert_eq!(order(&db).await, ["c", "b", "a"]);
+        assert!(move_after(&db, ItemKind::Note, "a", Some("missing"))
+            .await
+            .is_err());
+
+        db.pool.close().await;
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_reorder_numbers_legacy_rows_in_creation_order() {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+
+        let mut conn = db.pool.acquire().await.unwrap();
+        for (id, created_at) in [("old", 1), ("older", 0), ("newest", 3)] {
+            tx::restore_note(&mut conn, &note(id, created_at))
+                .await
+                .unwrap();
+        }
+        for id in ["f1", "f2"] {
+            tx::restore_folder(
+                &mut conn,
+                &FolderRecord {
+                    id: id.to_string(),
+                    name: id.to_string(),
+                    parent_id: None,
+                    workspace_id: "default".to_string(),
+                    created_at: 1,
+                    updated_at: 1,
+                    version: 1,
+                    color: None,
+                    sort_key: "V".to_string(),
+                    is_deleted: false,
+                },
+            )
+            .await
+            .unwrap();
+        }
+        drop(conn);
+        assert_eq!(order(&db).await, ["older", "old", "newest"]);
+
+        move_after(&db, ItemKind::Note, "older", Some("newest"))
+            .await
+            .unwrap();
+        assert_eq!(order(&db).await, ["old", "newest", "older"]);
+        assert!(db
+            .get_notes()
+            .await
+            .unwrap()
+            .iter()
+            .all(|n| is_valid_key(&n.sort_key)));
+
+        // Equal keys (two devices moved at once) are renumbered as well.
+        move_after(&db, ItemKind::Folder, "f1", Some("f2"))
+            .await
+            .unwrap();
+        let folders: Vec<String> = db
+            .get_folders()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|f| f.id)
+            .collect();
+        assert_eq!(folders, ["f2", "f1"]);
+
+        db.pool.close().await;
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,863 @@
+//! PDF rendering for document export. Pages are A4, text is set in a system
+//! sans-serif font (or the bundled DejaVu Sans when none is found) embedded
+//! into the file so non-Latin notes print too, and note titles and headings
+//! become nested bookmarks. printpdf only knows one bookmark per page, so the
+//! outline is added to the finished file with lopdf.
+
+use crate::document::{plain_text, Block, Document, ListItem, Span};
+use crate::error::AppError;
+use fontdb::{Family, Query, Stretch, Style, Weight};
+use printpdf::lopdf::{self, Dictionary, Object, ObjectId, StringFormat};
+use printpdf::path::PaintMode;
+use printpdf::{
+    Actions, Color, IndirectFontRef, Line, LinkAnnotation, Mm, PdfDocument, PdfDocumentReference,
+    PdfLayerReference, Point, Rect, Rgb,
+};
+use std::collections::HashMap;
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+/// Millimetres per point.
+const PT: f32 = 25.4 / 72.0;
+const TITLE_SIZE: f32 = 22.0;
+const HEADING_SIZES: [f32; 6] = [18.0, 15.0, 13.0, 12.0, 11.0, 11.0];
+const BODY_SIZE: f32 = 11.0;
+const CODE_SIZE: f32 = 9.0;
+const LEADING: f32 = 1.45;
+const INDENT: f32 = 6.0;
+
+const TEXT: (f32, f32, f32) = (0.12, 0.14, 0.16);
+const MUTED: (f32, f32, f32) = (0.42, 0.45, 0.5);
+const LINK: (f32, f32, f32) = (0.19, 0.35, 0.8);
+const SHADE: (f32, f32, f32) = (0.95, 0.95, 0.96);
+const BORDER: (f32, f32, f32) = (0.8, 0.81, 0.83);
+
+const SANS: &[&str] = &[
+    "Arial",
+    "Helvetica",
+    "Segoe UI",
+    "DejaVu Sans",
+    "Liberation Sans",
+    "Noto Sans",
+];
+const MONO: &[&str] = &[
+    "Consolas",
+    "Menlo",
+    "DejaVu Sans Mono",
+    "Liberation Mono",
+    "Noto Sans Mono",
+    "Courier New",
+];
+/// Last resort when no sans-serif font is installed.
+const BUNDLED: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Face {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+    Mono,
+}
+
+impl Face {
+    fn of(span: &Span, bold: bool) -> Face {
+        match (span.code, span.bold || bold, span.italic) {
+            (true, _, _) => Face::Mono,
+            (false, true, true) => Face::BoldItalic,
+            (false, true, false) => Face::Bold,
+            (false, false, true) => Face::Italic,
+            (false, false, false) => Face::Regular,
+        }
+    }
+}
+
+/// Font files for the five faces, looked up among the installed fonts.
+fn system_fonts() -> [Vec<u8>; 5] {
+    let mut db = fontdb::Database::new();
+    db.load_system_fonts();
+    fonts_in(&db)
+}
+
+/// Tries the named families first, then whatever the system maps the
+/// generic family to. Faces that are missing fall back to the regular one,
+/// and that to the bundled font.
+fn fonts_in(db: &fontdb::Database) -> [Vec<u8>; 5] {
+    let find = |names: &[&str], generic: Family, weight: Weight, style: Style| {
+        let named = names.iter().map(|name| Family::Name(name));
+        named.chain([generic]).find_map(|family| {
+            let id = db.query(&Query {
+                families: &[family],
+                weight,
+                stretch: Stretch::Normal,
+                style,
+            })?;
+            // printpdf can only embed the first face of a collection
+            db.with_face_data(id, |data, index| (index == 0).then(|| data.to_vec()))
+                .flatten()
+        })
+    };
+
+    let regular = find(SANS, Family::SansSerif, Weight::NORMAL, Style::Normal)
+        .unwrap_or_else(|| BUNDLED.to_vec());
+    let or_regular = |face: Option<Vec<u8>>| face.unwrap_or_else(|| regular.clone());
+    [
+        regular.clone(),
+        or_regular(find(SANS, Family::SansSerif, Weight::BOLD, Style::Normal)),
+        or_regular(find(SANS, Family::SansSerif, Weight::NORMAL, Style::Italic)),
+        or_regular(find(SANS, Family::SansSerif, Weight::BOLD, Style::Italic)),
+        or_regular(find(MONO, Family::Monospace, Weight::NORMAL, Style::Normal)),
+    ]
+}
+
+fn rgb((r, g, b): (f32, f32, f32)) -> Color {
+    Color::Rgb(Rgb::new(r, g, b, None))
+}
+
+/// A word or space laid out on a line.
+struct Piece {
+    text: String,
+    face: Face,
+    size: f32,
+    width: f32,
+    link: Option<String>,
+    strike: bool,
+}
+
+struct Bookmark {
+    level: usize,
+    title: String,
+    page: usize,
+    y: f32,
+}
+
+/// Lays blocks out top to bottom, starting a new page whenever the next
+/// line does not fit. `y` is the top of the next line in millimetres from
+/// the bottom edge, as PDF counts.
+struct Writer {
+    doc: PdfDocumentReference,
+    files: [Vec<u8>; 5],
+    /// Fonts are embedded on first use, so a PDF without code does not
+    /// carry the monospace font.
+    fonts: [Option<IndirectFontRef>; 5],
+    widths: HashMap<(Face, char), f32>,
+    layer: PdfLayerReference,
+    page: usize,
+    y: f32,
+    color: (f32, f32, f32),
+    bookmarks: Vec<Bookmark>,
+}
+
+impl Writer {
+    fn new(title: &str, files: [Vec<u8>; 5]) -> Self {
+        let (doc, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Text");
+        let layer = doc.get_page(page).get_layer(layer);
+        Writer {
+            doc,
+            files,
+            fonts: Default::default(),
+            widths: HashMap::new(),
+            layer,
+            page: 0,
+            y: PAGE_HEIGHT - MARGIN,
+            color: TEXT,
+            bookmarks: Vec::new(),
+        }
+    }
+
+    fn new_page(&mut self) {
+        let (page, layer) = self.doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Text");
+        self.layer = self.doc.get_page(page).get_layer(layer);
+        self.page += 1;
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    fn at_page_top(&self) -> bool {
+        self.y >= PAGE_HEIGHT - MARGIN
+    }
+
+    /// Moves to a new page unless `height` still fits on this one.
+    fn ensure(&mut self, height: f32) {
+        if self.y - height < MARGIN && !self.at_page_top() {
+            self.new_page();
+        }
+    }
+
+    fn space(&mut self, height: f32) {
+        if !self.at_page_top() {
+            self.y -= height;
+        }
+    }
+
+    fn font(&mut self, face: Face) -> Result<IndirectFontRef, AppError> {
+        let slot = face as usize;
+        if let Some(font) = &self.fonts[slot] {
+            return Ok(font.clone());
+        }
+        let font = self
+            .doc
+            .add_external_font(self.files[slot].as_slice())
+            .map_err(|e| AppError::internal(e.to_string()))?;
+        self.fonts[slot] = Some(font.clone());
+        Ok(font)
+    }
+
+    /// Advance of `c` in ems; zero for characters the font lacks, which
+    /// printpdf drops as well.
+    fn char_width(&mut self, face: Face, c: char) -> f32 {
+        if let Some(width) = self.widths.get(&(face, c)) {
+            return *width;
+        }
+        let width = ttf_parser::Face::parse(&self.files[face as usize], 0)
+            .ok()
+            .and_then(|f| {
+                let advance = f.glyph_hor_advance(f.glyph_index(c)?)?;
+                Some(advance as f32 / f32::from(f.units_per_em()))
+            })
+            .unwrap_or(0.0);
+        self.widths.insert((face, c), width);
+        width
+    }
+
+    fn text_width(&mut self, face: Face, text: &str, size: f32) -> f32 {
+        let ems: f32 = text.chars().map(|c| self.char_width(face, c)).sum();
+        ems * size * PT
+    }
+
+    fn has_glyph(&mut self, face: Face, c: char) -> bool {
+        self.char_width(face, c) > 0.0
+    }
+
+    fn piece(&mut self, text: &str, span: &Span, face: Face, size: f32) -> Piece {
+        let size = if span.code { size * 0.9 } else { size };
+        Piece {
+            text: text.to_string(),
+            face,
+            size,
+            width: self.text_width(face, text, size),
+            link: span.link.clone(),
+            strike: span.strike,
+        }
+    }
+
+    /// Word-wraps spans into lines at most `width` wide. Words longer than
+    /// a line are broken between characters.
+    fn wrap(&mut self, spans: &[Span], size: f32, bold: bool, width: f32) -> Vec<Vec<Piece>> {
+        let mut lines = vec![Vec::new()];
+        let mut used = 0.0;
+        for span in spans {
+            let face = Face::of(span, bold);
+            let text = span.text.replace('\t', "    ");
+            for (n, hard_line) in text.split('\n').enumerate() {
+                if n > 0 {
+                    lines.push(Vec::new());
+                    used = 0.0;
+                }
+                for word in split_words(hard_line) {
+                    let mut piece = self.piece(word, span, face, size);
+                    let is_space = word.trim().is_empty();
+                    if is_space {
+                        if !lines.last().unwrap().is_empty() {
+                            used += piece.width;
+                            lines.last_mut().unwrap().push(piece);
+                        }
+                        continue;
+                    }
+                    if used + piece.width > width && !lines.last().unwrap().is_empty() {
+                        trim_line(lines.last_mut().unwrap());
+                        lines.push(Vec::new());
+                        used = 0.0;
+                    }
+                    while piece.width > width && piece.text.chars().count() > 1 {
+                        let (head, tail) = self.split_to_fit(&piece, width - used);
+                        lines.last_mut().unwrap().push(head);
+                        lines.push(Vec::new());
+                        used = 0.0;
+                        piece = tail;
+                    }
+                    used += piece.width;
+                    lines.last_mut().unwrap().push(piece);
+                }
+            }
+        }
+        for line in &mut lines {
+            trim_line(line);
+        }
+        lines
+    }
+
+    fn split_to_fit(&mut self, piece: &Piece, width: f32) -> (Piece, Piece) {
+        let mut used = 0.0;
+        let mut cut = 0;
+        for (i, c) in piece.text.char_indices() {
+            let w = self.char_width(piece.face, c) * piece.size * PT;
+            if used + w > width && i > 0 {
+                break;
+            }
+            used += w;
+            cut = i + c.len_utf8();
+        }
+        let part = |this: &mut Self, text: &str| Piece {
+            text: text.to_string(),
+            face: piece.face,
+            size: piece.size,
+            width: this.text_width(piece.face, text, piece.size),
+            link: piece.link.clone(),
+            strike: piece.strike,
+        };
+        let head = part(self, &piece.text[..cut]);
+        let tail = part(self, &piece.text[cut..]);
+        (head, tail)
+    }
+
+    fn line_height(line: &[Piece], size: f32) -> f32 {
+        line.iter().map(|p| p.size).fold(size, f32::max) * PT * LEADING
+    }
+
+    fn draw_text(
+        &mut self,
+        text: &str,
+        face: Face,
+        size: f32,
+        x: f32,
+        baseline: f32,
+    ) -> Result<(), AppError> {
+        let font = self.font(face)?;
+        self.layer.use_text(text, size, Mm(x), Mm(baseline), &font);
+        Ok(())
+    }
+
+    fn draw_line(&self, from: (f32, f32), to: (f32, f32), color: (f32, f32, f32), thickness: f32) {
+        self.layer.set_outline_color(rgb(color));
+        self.layer.set_outline_thickness(thickness);
+        self.layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(from.0), Mm(from.1)), false),
+                (Point::new(Mm(to.0), Mm(to.1)), false),
+            ],
+            is_closed: false,
+        });
+    }
+
+    fn fill_rect(&self, x: f32, y: f32, width: f32, height: f32, color: (f32, f32, f32)) {
+        self.layer.set_fill_color(rgb(color));
+        self.layer.add_rect(
+            Rect::new(Mm(x), Mm(y), Mm(x + width), Mm(y + height)).with_mode(PaintMode::Fill),
+        );
+    }
+
+    /// Draws one laid out line with its top at `top`.
+    fn draw_pieces(
+        &mut self,
+        line: &[Piece],
+        x: f32,
+        top: f32,
+        height: f32,
+    ) -> Result<(), AppError> {
+        let baseline = top - height * 0.78;
+        let mut cursor = x;
+        for piece in line {
+            if !piece.text.trim().is_empty() {
+                let color = if piece.link.is_some() {
+                    LINK
+                } else {
+                    self.color
+                };
+                self.layer.set_fill_color(rgb(color));
+                self.draw_text(&piece.text, piece.face, piece.size, cursor, baseline)?;
+                if piece.strike {
+                    let y = baseline + piece.size * PT * 0.3;
+                    self.draw_line((cursor, y), (cursor + piece.width, y), color, 0.6);
+                }
+                if let Some(link) = &piece.link {
+                    let rect = Rect::new(
+                        Mm(cursor),
+                        Mm(top - height),
+                        Mm(cursor + piece.width),
+                        Mm(top),
+                    );
+                    self.layer.add_link_annotation(LinkAnnotation::new(
+                        rect,
+                        Some(printpdf::BorderArray::Solid([0.0, 0.0, 0.0])),
+                        None,
+                        Actions::uri(link.clone()),
+                        None,
+                    ));
+                }
+            }
+            cursor += piece.width;
+        }
+        Ok(())
+    }
+
+    /// Wraps and draws spans, breaking pages between lines.
+    fn text(&mut self, spans: &[Span], size: f32, bold: bool, x: f32) -> Result<(), AppError> {
+        let width = PAGE_WIDTH - MARGIN - x;
+        for line in self.wrap(spans, size, bold, width) {
+            let height = Self::line_height(&line, size);
+            self.ensure(height);
+            self.draw_pieces(&line, x, self.y, height)?;
+            self.y -= height;
+        }
+        Ok(())
+    }
+
+    fn bookmark(&mut self, level: usize, title: String) {
+        self.bookmarks.push(Bookmark {
+            level,
+            title,
+            page: self.page,
+            y: self.y,
+        });
+    }
+
+    /// `level` is the outline depth of the enclosing chapter.
+    fn blocks(&mut self, blocks: &[Block], x: f32, level: usize) -> Result<(), AppError> {
+        for block in blocks {
+            self.block(block, x, level)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: &Block, x: f32, level: usize) -> Result<(), AppError> {
+        match block {
+            Block::Heading { level: h, spans } => {
+                let size = HEADING_SIZES[usize::from(*h).clamp(1, 6) - 1];
+                self.space(size * PT * 0.8);
+                // Keep the heading together with the first lines below it
+                self.ensure(size * PT * LEADING + 3.0 * BODY_SIZE * PT * LEADING);
+                self.bookmark(level + usize::from(*h), plain_text(spans));
+                self.text(spans, size, true, x)?;
+                self.y -= 1.5;
+            }
+            Block::Paragraph(spans) => {
+                self.text(spans, BODY_SIZE, false, x)?;
+                self.y -= 2.5;
+            }
+            Block::List { start, items } => {
+                for (i, item) in items.iter().enumerate() {
+                    let marker = self.marker(*start, i, item);
+                    self.list_item(&marker, item, x, level)?;
+                }
+                self.y -= 1.0;
+            }
+            Block::Quote(blocks) => {
+                let (page, top) = (self.page, self.y);
+                let color = std::mem::replace(&mut self.color, MUTED);
+                self.blocks(blocks, x + INDENT, level)?;
+                self.color = color;
+                let top = if self.page == page {
+                    top
+                } else {
+                    PAGE_HEIGHT - MARGIN
+                };
+                self.draw_line((x + 1.5, top), (x + 1.5, self.y + 2.5), BORDER, 1.5);
+            }
+            Block::Code { text, .. } => self.code(text, x)?,
+            Block::Table { header, rows } => {
+                let columns = rows.iter().map(Vec::len).fold(header.len(), usize::max);
+                if columns > 0 {
+                    self.table_row(header, columns, x, true)?;
+                    for row in rows {
+                        self.table_row(row, columns, x, false)?;
+                    }
+                }
+                self.y -= 3.0;
+            }
+            Block::Rule => {
+                self.ensure(5.0);
+                self.draw_line(
+                    (x, self.y - 2.5),
+                    (PAGE_WIDTH - MARGIN, self.y - 2.5),
+                    BORDER,
+                    0.5,
+                );
+                self.y -= 5.0;
+            }
+        }
+        Ok(())
+    }
+
+    fn marker(&mut self, start: Option<u64>, index: usize, item: &ListItem) -> String {
+        match (item.checked, start) {
+            (Some(true), _) if self.has_glyph(Face::Regular, '☑') => "☑".to_string(),
+            (Some(false), _) if self.has_glyph(Face::Regular, '☐') => "☐".to_string(),
+            (Some(true), _) => "[x]".to_string(),
+            (Some(false), _) => "[ ]".to_string(),
+            (None, Some(start)) => format!("{}.", start + index as u64),
+            (None, None) if self.has_glyph(Face::Regular, '•') => "•".to_string(),
+            (None, None) => "-".to_string(),
+        }
+    }
+
+    fn list_item(
+        &mut self,
+        marker: &str,
+        item: &ListItem,
+        x: f32,
+        level: usize,
+    ) -> Result<(), AppError> {
+        let height = BODY_SIZE * PT * LEADING;
+        self.ensure(height);
+        let baseline = self.y - height * 0.78;
+        let indent = self.text_width(Face::Regular, marker, BODY_SIZE) + 2.0;
+        let indent = indent.max(INDENT);
+        self.layer.set_fill_color(rgb(self.color));
+        self.draw_text(marker, Face::Regular, BODY_SIZE, x, baseline)?;
+        if item.blocks.is_empty() {
+            self.y -= height;
+        }
+        for block in &item.blocks {
+            self.block(block, x + indent, level)?;
+            // Items of tight lists are single paragraphs; close them up
+            if matches!(block, Block::Paragraph(_)) {
+                self.y += 1.5;
+            }
+        }
+        Ok(())
+    }
+
+    fn code(&mut self, text: &str, x: f32) -> Result<(), AppError> {
+        let width = PAGE_WIDTH - MARGIN - x;
+        let height = CODE_SIZE * PT * LEADING;
+        self.ensure(2.0 + height);
+        self.fill_rect(x, self.y - 2.0, width, 2.0, SHADE);
+        self.y -= 2.0;
+        for source_line in text.split('\n') {
+            // Code keeps its spaces, so long lines break between characters
+            let source_line = source_line.replace('\t', "    ");
+            let mut piece = Piece {
+                width: self.text_width(Face::Mono, &source_line, CODE_SIZE),
+                text: source_line,
+                face: Face::Mono,
+                size: CODE_SIZE,
+                link: None,
+                strike: false,
+            };
+            loop {
+                let (line, rest) = if piece.width > width - 6.0 && piece.text.chars().count() > 1 {
+                    let (head, tail) = self.split_to_fit(&piece, width - 6.0);
+                    (head, Some(tail))
+                } else {
+                    (piece, None)
+                };
+                self.ensure(height);
+                self.fill_rect(x, self.y - height, width, height, SHADE);
+                self.draw_pieces(std::slice::from_ref(&line), x + 3.0, self.y, height)?;
+                self.y -= height;
+                match rest {
+                    Some(rest) => piece = rest,
+                    None => break,
+                }
+            }
+        }
+        self.ensure(2.0);
+        self.fill_rect(x, self.y - 2.0, width, 2.0, SHADE);
+        self.y -= 5.0;
+        Ok(())
+    }
+
+    fn table_row(
+        &mut self,
+        cells: &[Vec<Span>],
+        columns: usize,
+        x: f32,
+        header: bool,
+    ) -> Result<(), AppError> {
+        let column_width = (PAGE_WIDTH - MARGIN - x) / columns as f32;
+        let padding = 1.5;
+        let wrapped: Vec<Vec<Vec<Piece>>> = (0..columns)
+            .map(|i| {
+                let spans = cells.get(i).map(Vec::as_slice).unwrap_or_default();
+                self.wrap(
+                    spans,
+                    BODY_SIZE * 0.95,
+                    header,
+                    column_width - 2.0 * padding,
+                )
+            })
+            .collect();
+        let height = wrapped
+            .iter()
+            .map(|lines| {
+                lines
+                    .iter()
+                    .map(|line| Self::line_height(line, BODY_SIZE * 0.95))
+                    .sum::<f32>()
+            })
+            .fold(0.0, f32::max)
+            + 2.0 * padding;
+
+        self.ensure(height);
+        let top = self.y;
+        if header {
+            self.fill_rect(
+                x,
+                top - height,
+                column_width * columns as f32,
+                height,
+                SHADE,
+            );
+        }
+        for (i, lines) in wrapped.iter().enumerate() {
+            let mut y = top - padding;
+            for line in lines {
+                let line_height = Self::line_height(line, BODY_SIZE * 0.95);
+                self.draw_pieces(line, x + i as f32 * column_width + padding, y, line_height)?;
+                y -= line_height;
+            }
+        }
+        let right = x + column_width * columns as f32;
+        self.draw_line((x, top), (right, top), BORDER, 0.5);
+        self.draw_line((x, top - height), (right, top - height), BORDER, 0.5);
+        for i in 0..=columns {
+            let edge = x + i as f32 * column_width;
+            self.draw_line((edge, top), (edge, top - height), BORDER, 0.5);
+        }
+        self.y -= height;
+        Ok(())
+    }
+}
+
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut in_space = None;
+    for (i, c) in text.char_indices() {
+        let space = c == ' ';
+        if in_space.is_some_and(|s| s != space) {
+            words.push(&text[start..i]);
+            start = i;
+        }
+        in_space = Some(space);
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+fn trim_line(line: &mut Vec<Piece>) {
+    while line.last().is_some_and(|p| p.text.trim().is_empty()) {
+        line.pop();
+    }
+}
+
+pub fn render(document: &Document) -> Result<Vec<u8>, AppError> {
+    render_with(document, system_fonts())
+}
+
+fn render_with(document: &Document, fonts: [Vec<u8>; 5]) -> Result<Vec<u8>, AppError> {
+    let mut writer = Writer::new(&document.title, fonts);
+    let mut folders: &[String] = &[];
+    for (i, chapter) in document.chapters.iter().enumerate() {
+        if i > 0 {
+            writer.new_page();
+        }
+        // Bookmark the folders this chapter opens
+        let shared = folders
+            .iter()
+            .zip(&chapter.folders)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, name) in chapter.folders.iter().enumerate().skip(shared) {
+            writer.bookmark(depth, name.clone());
+        }
+        folders = &chapter.folders;
+
+        let level = chapter.folders.len();
+        writer.bookmark(level, chapter.title.clone());
+        writer.text(
+            &[Span::plain(chapter.title.as_str())],
+            TITLE_SIZE,
+            true,
+            MARGIN,
+        )?;
+        writer.y -= 4.0;
+        writer.blocks(&chapter.blocks, MARGIN, level)?;
+    }
+
+    let bookmarks = std::mem::take(&mut writer.bookmarks);
+    let bytes = writer
+        .doc
+        .save_to_bytes()
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    add_outline(&bytes, &bookmarks)
+}
+
+/// PDF text string in UTF-16 so titles in any script survive.
+fn pdf_string(text: &str) -> Object {
+    let mut bytes = vec![0xFE, 0xFF];
+    for unit in text.encode_utf16() {
+        bytes.extend(unit.to_be_bytes());
+    }
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// Writes the bookmarks as the document outline. Bookmarks come in reading
+/// order; each one nests under the closest earlier bookmark with a lower
+/// level.
+fn add_outline(bytes: &[u8], bookmarks: &[Bookmark]) -> Result<Vec<u8>, AppError> {
+    if bookmarks.is_empty() {
+        return Ok(bytes.to_vec());
+    }
+    let mut pdf =
+        lopdf::Document::load_mem(bytes).map_err(|e| AppError::internal(e.to_string()))?;
+    let pages: Vec<ObjectId> = pdf.get_pages().into_values().collect();
+    let root = pdf.new_object_id();
+    let ids: Vec<ObjectId> = bookmarks.iter().map(|_| pdf.new_object_id()).collect();
+
+    // children[0] holds the top level, children[i + 1] those of bookmark i
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); bookmarks.len() + 1];
+    let mut parents = Vec::with_capacity(bookmarks.len());
+    let mut stack: Vec<usize> = Vec::new();
+    for (i, bookmark) in bookmarks.iter().enumerate() {
+        while stack
+            .last()
+            .is_some_and(|&open| bookmarks[open].level >= bookmark.level)
+        {
+            stack.pop();
+        }
+        let parent = stack.last().map(|&p| p + 1).unwrap_or(0);
+        children[parent].push(i);
+        parents.push(parent);
+        stack.push(i);
+    }
+    let id_of = |slot: usize| if slot == 0 { root } else { ids[slot - 1] };
+    let descendants = |i: usize| {
+        bookmarks[i + 1..]
+            .iter()
+            .take_while(|b| b.level > bookmarks[i].level)
+            .count()
+    };
+
+    for (i, bookmark) in bookmarks.iter().enumerate() {
+        let page = pages.get(bookmark.page).copied().unwrap_or(pages[0]);
+        let mut item = Dictionary::new();
+        item.set("Title", pdf_string(&bookmark.title));
+        item.set("Parent", Object::Reference(id_of(parents[i])));
+        item.set(
+            "Dest",
+            Object::Array(vec![
+                Object::Reference(page),
+                Object::Name(b"XYZ".to_vec()),
+                Object::Null,
+                Object::Real(bookmark.y / PT),
+                Object::Null,
+            ]),
+        );
+        let siblings = &children[parents[i]];
+        let position = siblings.iter().position(|&s| s == i).unwrap();
+        if position > 0 {
+            item.set("Prev", Object::Reference(ids[siblings[position - 1]]));
+        }
+        if let Some(&next) = siblings.get(position + 1) {
+            item.set("Next", Object::Reference(ids[next]));
+        }
+        if let (Some(&first), Some(&last)) = (children[i + 1].first(), children[i + 1].last()) {
+            item.set("First", Object::Reference(ids[first]));
+            item.set("Last", Object::Reference(ids[last]));
+            item.set("Count", Object::Integer(descendants(i) as i64));
+        }
+        pdf.objects.insert(ids[i], Object::Dictionary(item));
+    }
+
+    let mut outlines = Dictionary::new();
+    outlines.set("Type", Object::Name(b"Outlines".to_vec()));
+    outlines.set("First", Object::Reference(ids[children[0][0]]));
+    outlines.set("Last", Object::Reference(ids[*children[0].last().unwrap()]));
+    outlines.set("Count", Object::Integer(bookmarks.len() as i64));
+    pdf.objects.insert(root, Object::Dictionary(outlines));
+
+    let catalog = pdf
+        .catalog_mut()
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    catalog.set("Outlines", Object::Reference(root));
+    catalog.set("PageMode", Object::Name(b"UseOutlines".to_vec()));
+    pdf.prune_objects();
+
+    let mut out = Vec::new();
+    pdf.save_to(&mut out)
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{parse, Chapter};
+
+    fn outline_titles(
+        pdf: &lopdf::Document,
+        first: ObjectId,
+        out: &mut Vec<(usize, String)>,
+        depth: usize,
+    ) {
+        let mut next = Some(first);
+        while let Some(id) = next {
+            let item = pdf.get_dictionary(id).unwrap();
+            let title = item.get(b"Title").unwrap().as_str().unwrap();
+            let units: Vec<u16> = title[2..]
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            out.push((depth, String::from_utf16(&units).unwrap()));
+            if let Ok(child) = item.get(b"First").and_then(Object::as_reference) {
+                outline_titles(pdf, child, out, depth + 1);
+            }
+            next = item.get(b"Next").and_then(Object::as_reference).ok();
+        }
+    }
+
+    #[test]
+    fn test_pdf_has_a_page_per_chapter_and_nested_bookmarks() {
+        let long = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(120);
+        let document = Document {
+            title: "Handbook".to_string(),
+            chapters: vec![
+                Chapter {
+                    title: "Welcome".to_string(),
+                    folders: Vec::new(),
+                    blocks: parse(&format!(
+                        "# Getting started\n\n{}\n\n## Ünïcödé\n\n- one\n- two\n\n```\ncode\n```\n\n| a | b |\n|---|---|\n| 1 | 2 |\n",
+                        long
+                    )),
+                },
+                Chapter {
+                    title: "Deep".to_string(),
+                    folders: vec!["Guides".to_string()],
+                    blocks: parse("# Inside\n\nText with a [link](https://example.com)."),
+                },
+            ],
+        };
+
+        // No system fonts, so every face is the bundled one
+        let bytes = render_with(&document, fonts_in(&fontdb::Database::new())).unwrap();
+        let pdf = lopdf::Document::load_mem(&bytes).unwrap();
+        assert!(pdf.get_pages().len() >= 3);
+
+        let catalog = pdf.catalog().unwrap();
+        let outlines = pdf
+            .get_dictionary(catalog.get(b"Outlines").unwrap().as_reference().unwrap())
+            .unwrap();
+        let mut titles = Vec::new();
+        outline_titles(
+            &pdf,
+            outlines.get(b"First").unwrap().as_reference().unwrap(),
+            &mut titles,
+            0,
+        );
+        assert_eq!(
+            titles,
+            [
+                (0, "Welcome".to_string()),
+                (1, "Getting started".to_string()),
+                (2, "Ünïcödé".to_string()),
+                (0, "Guides".to_string()),
+                (1, "Deep".to_string()),
+                (2, "Inside".to_string()),
+            ]
+        );
+    }
+}
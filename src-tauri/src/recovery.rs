@@ -0,0 +1,363 @@
+//! Startup without panics. When the database cannot be opened the app starts
+//! in recovery mode: the database stays closed (commands fail as if it were
+//! locked) and the UI offers to retry, restore a backup, open the file
+//! read-only, or start over with an empty database after moving the broken
+//! file aside.
+
+use crate::backup;
+use crate::db::{init_db, now_millis, open_db, Db, DbState, DB_FILE};
+use crate::error::AppError;
+use serde::Serialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Pool, Sqlite};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// Another process holds a lock on the file; retrying may help.
+    Busy,
+    /// The file is not a database or is damaged.
+    Corrupt,
+    /// The database was written by a newer version of the app.
+    Incompatible,
+    /// The file or the app data directory cannot be read or written.
+    Inaccessible,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Recovery {
+    pub problem: Problem,
+    pub error: AppError,
+    /// Whether the broken database is currently open read-only.
+    pub read_only: bool,
+}
+
+impl Recovery {
+    fn new(problem: Problem, error: AppError) -> Self {
+        Self {
+            problem,
+            error,
+            read_only: false,
+        }
+    }
+
+    fn from_open_error(e: sqlx::Error) -> Self {
+        Self::new(classify(&e), e.into())
+    }
+}
+
+/// Why startup failed, until the user resolves it. Empty in normal operation.
+#[derive(Default)]
+pub struct RecoveryState(Mutex<Option<Recovery>>);
+
+impl RecoveryState {
+    pub fn new(recovery: Option<Recovery>) -> Self {
+        Self(Mutex::new(recovery))
+    }
+
+    pub fn get(&self) -> Option<Recovery> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn set(&self, recovery: Option<Recovery>) {
+        *self.0.lock().unwrap() = recovery;
+    }
+
+    /// Keeps the original problem but reports the latest error.
+    fn failed(&self, e: sqlx::Error) -> AppError {
+        let recovery = Recovery::from_open_error(e);
+        let error = recovery.error.clone();
+        self.set(Some(recovery));
+        error
+    }
+}
+
+pub fn classify(e: &sqlx::Error) -> Problem {
+    // Migrations report a schema from the future as a configuration error
+    if matches!(e, sqlx::Error::Configuration(_)) {
+        return Problem::Incompatible;
+    }
+    if matches!(e, sqlx::Error::Io(_)) {
+        return Problem::Inaccessible;
+    }
+    // Extended result codes carry the primary code in the low byte
+    let code = e
+        .as_database_error()
+        .and_then(|d| d.code())
+        .and_then(|c| c.parse::<i32>().ok())
+        .map(|c| c & 0xff);
+    match code {
+        // SQLITE_BUSY, SQLITE_LOCKED
+        Some(5) | Some(6) => Problem::Busy,
+        // SQLITE_CORRUPT, SQLITE_NOTADB
+        Some(11) | Some(26) => Problem::Corrupt,
+        // SQLITE_PERM, SQLITE_READONLY, SQLITE_IOERR, SQLITE_CANTOPEN
+        Some(3) | Some(8) | Some(10) | Some(14) => Problem::Inaccessible,
+        _ => Problem::Other,
+    }
+}
+
+/// Opens the database at startup. Never fails: problems come back as a
+/// `Recovery` instead. An encrypted database is left closed until unlocked.
+pub async fn open_at_startup(app_dir: &Path) -> (Option<Db>, Option<Recovery>) {
+    if let Err(e) = fs::create_dir_all(app_dir) {
+        let error = AppError::io(app_dir, e);
+        return (None, Some(Recovery::new(Problem::Inaccessible, error)));
+    }
+    if crate::vault::is_encrypted(&app_dir.join(DB_FILE)) {
+        return (None, None);
+    }
+    match init_db(app_dir.to_path_buf()).await {
+        Ok(pool) => (Some(Db::new(pool)), None),
+        Err(e) => (None, Some(Recovery::from_open_error(e))),
+    }
+}
+
+/// Renames the database and its WAL and shared-memory files to
+/// `lumenote.db.broken-<millis>` and returns the new name of the database.
+fn move_aside(app_dir: &Path) -> Result<String, AppError> {
+    let name = format!("{}.broken-{}", DB_FILE, now_millis());
+    for suffix in ["", "-wal", "-shm"] {
+        let from = app_dir.join(format!("{}{}", DB_FILE, suffix));
+        if from.exists() {
+            fs::rename(&from, app_dir.join(format!("{}{}", name, suffix)))
+                .map_err(|e| AppError::io(&from, e))?;
+        }
+    }
+    Ok(name)
+}
+
+/// Opens a database file without ever writing to it, and fails unless its
+/// notes can be read.
+async fn connect_read_only(path: &Path) -> Result<Pool<Sqlite>, sqlx::Error> {
+    let options = SqliteConnectOptions::new().filename(path).read_only(true);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect_with(options)
+        .await?;
+    let readable = sqlx::query("SELECT count(*) FROM notes")
+        .fetch_one(&pool)
+        .await;
+    if let Err(e) = readable {
+        pool.close().await;
+        return Err(e);
+    }
+    Ok(pool)
+}
+
+/// Tries the normal open again, e.g. after another process let go of the file.
+pub async fn retry(state: &DbState, recovery: &RecoveryState) -> Result<(), AppError> {
+    state.close().await;
+    let pool = open_db(&state.app_dir, None)
+        .await
+        .map_err(|e| recovery.failed(e))?;
+    state.open(Db::new(pool), None);
+    recovery.set(None);
+    Ok(())
+}
+
+/// Opens the broken database read-only so its notes can be read and exported.
+pub async fn open_read_only(state: &DbState, recovery: &RecoveryState) -> Result<(), AppError> {
+    let Some(mut current) = recovery.get() else {
+        return Err(AppError::conflict("The database opened normally"));
+    };
+    state.close().await;
+    let pool = connect_read_only(&state.path()).await?;
+    state.open(Db::new(pool), None);
+    current.read_only = true;
+    recovery.set(Some(current));
+    Ok(())
+}
+
+/// Replaces the broken database with a backup. The broken file is moved
+/// aside, not deleted, and the backup is checked before anything moves.
+pub async fn restore(
+    state: &DbState,
+    recovery: &RecoveryState,
+    file_name: &str,
+) -> Result<String, AppError> {
+    let path = backup::backup_path(&state.app_dir, file_name)?;
+    let temp = state.app_dir.join(format!("{}.restoring", DB_FILE));
+    fs::copy(&path, &temp).map_err(|e| AppError::io(&temp, e))?;
+    if let Err(e) = connect_read_only(&temp).await {
+        let _ = fs::remove_file(&temp);
+        return Err(AppError::validation(
+            "file_name",
+            format!("backup cannot be opened: {}", e),
+        ));
+    }
+
+    state.close().await;
+    let moved = move_aside(&state.app_dir)?;
+    fs::rename(&temp, state.path()).map_err(|e| AppError::io(&temp, e))?;
+    let pool = open_db(&state.app_dir, None)
+        .await
+        .map_err(|e| recovery.failed(e))?;
+    state.open(Db::new(pool), None);
+    recovery.set(None);
+    Ok(moved)
+}
+
+/// Moves the broken database aside and starts with an empty one. Returns the
+/// file name the broken database was moved to.
+pub async fn start_fresh(state: &DbState, recovery: &RecoveryState) -> Result<String, AppError> {
+    state.close().await;
+    let moved = move_aside(&state.app_dir)?;
+    let pool = open_db(&state.app_dir, None)
+        .await
+        .map_err(|e| recovery.failed(e))?;
+    state.open(Db::new(pool), None);
+    recovery.set(None);
+    Ok(moved)
+}
+
+#[tauri::command]
+pub fn get_recovery_status(recovery: tauri::State<'_, RecoveryState>) -> Option<Recovery> {
+    recovery.get()
+}
+
+#[tauri::command]
+pub async fn retry_open_database(
+    state: tauri::State<'_, DbState>,
+    recovery: tauri::State<'_, RecoveryState>,
+) -> Result<(), AppError> {
+    retry(&state, &recovery).await
+}
+
+#[tauri::command]
+pub async fn open_database_read_only(
+    state: tauri::State<'_, DbState>,
+    recovery: tauri::State<'_, RecoveryState>,
+) -> Result<(), AppError> {
+    open_read_only(&state, &recovery).await
+}
+
+/// Returns the name the broken database was moved to inside the data dir.
+#[tauri::command]
+pub async fn recover_from_backup(
+    state: tauri::State<'_, DbState>,
+    recovery: tauri::State<'_, RecoveryState>,
+    file_name: String,
+) -> Result<String, AppError> {
+    restore(&state, &recovery, &file_name).await
+}
+
+/// Returns the name the broken database was moved to inside the data dir.
+#[tauri::command]
+pub async fn start_fresh_database(
+    state: tauri::State<'_, DbState>,
+    recovery: tauri::State<'_, RecoveryState>,
+) -> Result<String, AppError> {
+    start_fresh(&state, &recovery).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::BackupKind;
+    use crate::db::NoteRecord;
+    use std::path::PathBuf;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn note(id: &str) -> NoteRecord {
+        NoteRecord {
+            id: id.to_string(),
+            title: id.to_string(),
+            content: "kept".to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        }
+    }
+
+    /// A file with a valid SQLite header and garbage after it.
+    fn corrupt(path: &Path) {
+        let mut bytes = b"SQLite format 3\0".to_vec();
+        bytes.extend(std::iter::repeat_n(0xA5, 8192));
+        fs::write(path, bytes).unwrap();
+    }
+
+    async fn start(dir: &Path) -> (DbState, RecoveryState) {
+        let (db, recovery) = open_at_startup(dir).await;
+        (
+            DbState::new(dir.to_path_buf(), db),
+            RecoveryState::new(recovery),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_database_starts_fresh_and_keeps_broken_file() {
+        let dir = temp_dir();
+        corrupt(&dir.join(DB_FILE));
+
+        let (state, recovery) = start(&dir).await;
+        let problem = recovery.get().expect("recovery mode").problem;
+        assert_eq!(problem, Problem::Corrupt);
+        assert!(state.db().is_err());
+
+        let moved = start_fresh(&state, &recovery).await.unwrap();
+        assert!(recovery.get().is_none());
+        assert!(dir.join(&moved).is_file());
+        let workspaces = state.db().unwrap().get_workspaces().await.unwrap();
+        assert_eq!(workspaces.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_restore_backup_replaces_broken_database() {
+        let dir = temp_dir();
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+        db.upsert_note(note("saved")).await.unwrap();
+        let backup = backup::create(&db, &dir, BackupKind::Manual).await.unwrap();
+        db.pool.close().await;
+        corrupt(&dir.join(DB_FILE));
+
+        let (state, recovery) = start(&dir).await;
+        assert!(recovery.get().is_some());
+        assert!(restore(&state, &recovery, "manual-1.db").await.is_err());
+        assert!(recovery.get().is_some());
+
+        restore(&state, &recovery, &backup.file_name).await.unwrap();
+        assert!(recovery.get().is_none());
+        let notes = state.db().unwrap().get_notes().await.unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].id, "saved");
+    }
+
+    #[tokio::test]
+    async fn test_newer_database_opens_read_only() {
+        let dir = temp_dir();
+        let pool = init_db(dir.clone()).await.unwrap();
+        Db::new(pool.clone()).upsert_note(note("n1")).await.unwrap();
+        sqlx::query(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (999, 'future', 0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+
+        let (state, recovery) = start(&dir).await;
+        assert_eq!(recovery.get().unwrap().problem, Problem::Incompatible);
+
+        open_read_only(&state, &recovery).await.unwrap();
+        assert!(recovery.get().unwrap().read_only);
+        let db = state.db().unwrap();
+        assert_eq!(db.get_notes().await.unwrap().len(), 1);
+        let err = db.upsert_note(note("n2")).await.unwrap_err();
+        assert!(matches!(err, AppError::Database { .. }), "{:?}", err);
+    }
+}
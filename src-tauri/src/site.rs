@@ -0,0 +1,1103 @@
+//! Static website export. Every note is rendered to an HTML page at the same
+//! place the ZIP export puts its Markdown file, with a navigation sidebar,
+//! links between notes rewritten to the generated pages, local images and
+//! media from a folder the user granted copied into the site and a
+//! client-side search index. The result works from any static host or
+//! straight from disk, so nothing is fetched at runtime: the tree and search
+//! index ship as a script.
+
+use crate::db::{Db, FolderRecord, NoteRecord, WorkspaceRecord};
+use crate::error::{AppError, Entity};
+use crate::exporter::{remove_stale, write_relative, ExportLayout, ExportTarget};
+use crate::grants::{write_atomic_with, Access, FileGrants};
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+/// A generated file, `path` relative to the site root with `/` separators.
+pub struct SiteFile {
+    pub path: String,
+    pub bytes: Vec<u8>,
+}
+
+const ATTACHMENTS_DIR: &str = "assets/files";
+
+/// Lists what a directory export wrote, so the next export into the same
+/// directory can remove pages that no longer exist.
+const SITE_MANIFEST: &str = "lumenote-site.json";
+
+#[derive(Serialize, Deserialize)]
+struct SiteManifest {
+    files: Vec<String>,
+}
+
+/// Image and media types copied into the site. Links to anything else on
+/// this machine are left out so an export cannot publish arbitrary files.
+const ATTACHMENT_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "avif", "svg", "bmp", "ico", "mp3", "m4a", "ogg", "oga",
+    "wav", "flac", "mp4", "m4v", "webm", "ogv", "mov",
+];
+
+/// What `export_site` reports back.
+#[derive(Debug, Serialize)]
+pub struct SiteSummary {
+    pub pages: usize,
+    pub attachments: usize,
+    /// Local links left out of the site, as written in the notes: outside
+    /// the granted folder, not media, or unreadable.
+    pub skipped_links: Vec<String>,
+}
+
+/// The rendered site and the local links that were left out of it.
+pub struct Site {
+    pub files: Vec<SiteFile>,
+    /// Note pages plus the index page.
+    pub pages: usize,
+    pub skipped_links: Vec<String>,
+}
+
+/// Highlighting classes are prefixed so they never clash with note HTML.
+const HIGHLIGHT_CLASSES: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum NavItem {
+    Folder {
+        name: String,
+        color: Option<String>,
+        children: Vec<NavItem>,
+    },
+    Note {
+        title: String,
+        path: String,
+    },
+}
+
+#[derive(Serialize)]
+struct SearchEntry {
+    title: String,
+    path: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+struct SiteData<'a> {
+    title: &'a str,
+    tree: &'a [NavItem],
+    search: Vec<SearchEntry>,
+}
+
+pub async fn export_site_logic(
+    db: &Db,
+    workspace_id: String,
+    target: ExportTarget,
+    path: PathBuf,
+    attachments_dir: Option<&Path>,
+) -> Result<SiteSummary, AppError> {
+    let workspace = db
+        .get_workspaces()
+        .await?
+        .into_iter()
+        .find(|w| w.id == workspace_id)
+        .ok_or_else(|| AppError::not_found(Entity::Workspace, workspace_id.as_str()))?;
+    let notes: Vec<NoteRecord> = db
+        .get_notes()
+        .await?
+        .into_iter()
+        .filter(|n| n.workspace_id == workspace_id)
+        .collect();
+    let folders: Vec<FolderRecord> = db
+        .get_folders()
+        .await?
+        .into_iter()
+        .filter(|f| f.workspace_id == workspace_id)
+        .collect();
+
+    let site = build_site(&workspace, &notes, &folders, attachments_dir)?;
+    match target {
+        ExportTarget::Zip => write_zip(&path, &site.files)?,
+        ExportTarget::Directory => write_directory(&path, &site.files)?,
+    }
+    Ok(SiteSummary {
+        pages: site.pages,
+        attachments: site
+            .files
+            .iter()
+            .filter(|f| f.path.starts_with(ATTACHMENTS_DIR))
+            .count(),
+        skipped_links: site.skipped_links,
+    })
+}
+
+/// Renders the whole site in memory. Local files are copied only from
+/// inside `attachments_dir`; without one every local link is left out.
+pub fn build_site(
+    workspace: &WorkspaceRecord,
+    notes: &[NoteRecord],
+    folders: &[FolderRecord],
+    attachments_dir: Option<&Path>,
+) -> Result<Site, AppError> {
+    let pages = ExportLayout::new(notes, folders, ".html");
+    // The Markdown layout tells which page a `.md` link in a note points to
+    let sources = ExportLayout::new(notes, folders, ".md");
+    let page_for_source: HashMap<String, &str> = sources
+        .notes
+        .iter()
+        .zip(&pages.notes)
+        .map(|(source, page)| (source.path.to_lowercase(), page.path.as_str()))
+        .collect();
+
+    let mut renderer = Renderer::new(attachments_dir);
+    let mut files = Vec::new();
+    let mut search = Vec::new();
+    for (source, page) in sources.notes.iter().zip(&pages.notes) {
+        let note = page.record;
+        let rendered = renderer.render(&note.content, &source.path, &page.path, &page_for_source);
+        let body = format!(
+            "<article class=\"note\">\n<h1 class=\"note-title\">{}</h1>\n{}</article>\n",
+            escape_html(&note.title),
+            rendered.html
+        );
+        files.push(SiteFile {
+            path: page.path.clone(),
+            bytes: page_html(&note.title, &workspace.name, &page.path, &body).into_bytes(),
+        });
+        search.push(SearchEntry {
+            title: note.title.clone(),
+            path: page.path.clone(),
+            text: rendered.text,
+        });
+    }
+
+    let tree = nav_tree(None, &pages);
+    let mut index_body = format!(
+        "<article class=\"note\">\n<h1 class=\"note-title\">{}</h1>\n",
+        escape_html(&workspace.name)
+    );
+    static_tree(&tree, &mut index_body);
+    index_body.push_str("</article>\n");
+    files.push(SiteFile {
+        path: "index.html".to_string(),
+        bytes: page_html(&workspace.name, &workspace.name, "index.html", &index_body).into_bytes(),
+    });
+
+    let data = SiteData {
+        title: &workspace.name,
+        tree: &tree,
+        search,
+    };
+    let data_json = serde_json::to_string(&data)
+        .map_err(|e| AppError::internal(format!("Failed to serialize site data: {}", e)))?;
+    files.push(SiteFile {
+        path: "assets/site-data.js".to_string(),
+        bytes: format!("window.LUMENOTE_SITE = {};\n", data_json).into_bytes(),
+    });
+    files.push(SiteFile {
+        path: "assets/site.js".to_string(),
+        bytes: SITE_JS.as_bytes().to_vec(),
+    });
+    files.push(SiteFile {
+        path: "assets/style.css".to_string(),
+        bytes: format!("{}\n{}", SITE_CSS, renderer.highlight_css()?).into_bytes(),
+    });
+    let (attachments, skipped_links) = renderer.attachments.finish();
+    files.extend(attachments);
+    Ok(Site {
+        files,
+        pages: pages.notes.len() + 1,
+        skipped_links,
+    })
+}
+
+fn write_zip(path: &Path, files: &[SiteFile]) -> Result<(), AppError> {
+    let zip_error = |e: zip::result::ZipError| AppError::Io {
+        path: Some(path.display().to_string()),
+        message: e.to_string(),
+    };
+    write_atomic_with(path, |file| {
+        let mut zip = ZipWriter::new(file);
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for file in files {
+            zip.start_file(&file.path, options).map_err(zip_error)?;
+            zip.write_all(&file.bytes)
+                .map_err(|e| AppError::io(&file.path, e))?;
+        }
+        zip.finish().map_err(zip_error)
+    })
+}
+
+/// Writes into the chosen directory. Files an earlier site export listed in
+/// its manifest that this one no longer has are removed, and their folders
+/// once they are empty; anything else in the directory is left alone.
+fn write_directory(dir: &Path, files: &[SiteFile]) -> Result<(), AppError> {
+    let previous = fs::read(dir.join(SITE_MANIFEST))
+        .ok()
+        .and_then(|raw| serde_json::from_slice::<SiteManifest>(&raw).ok());
+    for file in files {
+        write_relative(dir, &file.path, &file.bytes)?;
+    }
+    if let Some(previous) = previous {
+        let exported: HashSet<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        let folders = previous
+            .files
+            .iter()
+            .flat_map(|path| path.match_indices('/').map(|(end, _)| &path[..end]));
+        remove_stale(
+            dir,
+            &exported,
+            previous.files.iter().map(String::as_str),
+            folders,
+        )?;
+    }
+    let manifest = SiteManifest {
+        files: files.iter().map(|f| f.path.clone()).collect(),
+    };
+    let json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| AppError::internal(format!("Failed to serialize site manifest: {}", e)))?;
+    write_relative(dir, SITE_MANIFEST, &json)
+}
+
+fn nav_tree(parent_id: Option<&str>, pages: &ExportLayout) -> Vec<NavItem> {
+    let mut items: Vec<NavItem> = pages
+        .folders
+        .iter()
+        .filter(|f| f.record.parent_id.as_deref() == parent_id)
+        .map(|f| NavItem::Folder {
+            name: f.record.name.clone(),
+            color: f.record.color.clone(),
+            children: nav_tree(Some(&f.record.id), pages),
+        })
+        .collect();
+    items.extend(
+        pages
+            .notes
+            .iter()
+            .filter(|n| n.record.folder_id.as_deref() == parent_id)
+            .map(|n| NavItem::Note {
+                title: n.record.title.clone(),
+                path: n.path.clone(),
+            }),
+    );
+    items
+}
+
+/// The tree as nested lists, so the index page works without scripts.
+fn static_tree(items: &[NavItem], out: &mut String) {
+    out.push_str("<ul class=\"tree\">\n");
+    for item in items {
+        match item {
+            NavItem::Folder { name, children, .. } => {
+                out.push_str(&format!("<li class=\"folder\">{}", escape_html(name)));
+                static_tree(children, out);
+                out.push_str("</li>\n");
+            }
+            NavItem::Note { title, path } => out.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                escape_html(&encode_path(path)),
+                escape_html(title)
+            )),
+        }
+    }
+    out.push_str("</ul>\n");
+}
+
+fn page_html(title: &str, site_title: &str, path: &str, body: &str) -> String {
+    let root = relative_root(path);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title} · {site_title}</title>
+<link rel="stylesheet" href="{root}assets/style.css">
+</head>
+<body data-root="{root}" data-path="{path}">
+<aside id="sidebar">
+<a class="site-title" href="{root}index.html">{site_title}</a>
+<input id="search" type="search" placeholder="Search" autocomplete="off">
+<nav id="nav"></nav>
+</aside>
+<main>
+{body}</main>
+<script src="{root}assets/site-data.js"></script>
+<script src="{root}assets/site.js"></script>
+</body>
+</html>
+"#,
+        title = escape_html(title),
+        site_title = escape_html(site_title),
+        root = root,
+        path = escape_html(path),
+        body = body,
+    )
+}
+
+/// `../` once per directory level of `path`, to get back to the site root.
+fn relative_root(path: &str) -> String {
+    "../".repeat(path.matches('/').count())
+}
+
+/// Relative href from the page at `from` to the site file at `to`.
+fn relative_href(from: &str, to: &str) -> String {
+    format!("{}{}", relative_root(from), encode_path(to))
+}
+
+/// Percent-encodes a site path for use in an href, keeping `/`.
+fn encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Resolves `link` (relative, `/`-separated, may contain `..`) against the
+/// directory of `from`. `None` when it climbs above the site root.
+fn resolve_relative(from: &str, link: &str) -> Option<String> {
+    let mut parts: Vec<&str> = from.split('/').collect();
+    parts.pop();
+    for part in link.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Local files referenced by notes, copied into the site once each.
+#[derive(Default)]
+struct Attachments {
+    /// Canonical granted folder; files are only copied from inside it.
+    root: Option<PathBuf>,
+    by_source: HashMap<PathBuf, String>,
+    used_names: HashSet<String>,
+    files: Vec<SiteFile>,
+    skipped: Vec<String>,
+}
+
+impl Attachments {
+    fn new(root: Option<&Path>) -> Self {
+        Self {
+            root: root.and_then(|root| fs::canonicalize(root).ok()),
+            ..Self::default()
+        }
+    }
+
+    /// Copies the file into the site and returns its site path, or `None`
+    /// when it is not media, not a readable regular file, or resolves
+    /// (through `..` or symlinks) to somewhere outside the granted folder.
+    fn add(&mut self, source: PathBuf) -> Option<String> {
+        let ext = source.extension()?.to_str()?.to_lowercase();
+        if !ATTACHMENT_EXTENSIONS.contains(&ext.as_str()) {
+            return None;
+        }
+        let source = fs::canonicalize(source).ok()?;
+        if !source.starts_with(self.root.as_ref()?) {
+            return None;
+        }
+        if let Some(path) = self.by_source.get(&source) {
+            return Some(path.clone());
+        }
+        if !fs::metadata(&source).ok()?.is_file() {
+            return None;
+        }
+        let bytes = fs::read(&source).ok()?;
+        let name = source.file_name()?.to_string_lossy().into_owned();
+        let (stem, ext) = match name.rfind('.') {
+            Some(dot) if dot > 0 => (&name[..dot], &name[dot..]),
+            _ => (name.as_str(), ""),
+        };
+        let unique = (1..)
+            .map(|n| match n {
+                1 => format!("{}{}", stem, ext),
+                n => format!("{}-{}{}", stem, n, ext),
+            })
+            .find(|candidate| !self.used_names.contains(&candidate.to_lowercase()))
+            .unwrap();
+        self.used_names.insert(unique.to_lowercase());
+        let path = format!("{}/{}", ATTACHMENTS_DIR, unique);
+        self.by_source.insert(source, path.clone());
+        self.files.push(SiteFile {
+            path: path.clone(),
+            bytes,
+        });
+        Some(path)
+    }
+
+    fn skip(&mut self, dest: &str) {
+        if !self.skipped.iter().any(|s| s == dest) {
+            self.skipped.push(dest.to_string());
+        }
+    }
+
+    fn finish(self) -> (Vec<SiteFile>, Vec<String>) {
+        (self.files, self.skipped)
+    }
+}
+
+/// A local file a link points at, if it is one: `file://` URLs and
+/// absolute paths. Notes live in the database, so relative paths to
+/// anything but another note have nothing to resolve against.
+fn local_file(dest: &str) -> Option<PathBuf> {
+    if let Some(rest) = dest.strip_prefix("file://") {
+        // file:///C:/x on Windows, file:///home/x elsewhere
+        let path = percent_decode(rest);
+        let path = if cfg!(windows) {
+            path.trim_start_matches('/').to_string()
+        } else {
+            path
+        };
+        return Some(PathBuf::from(path));
+    }
+    let path = Path::new(dest);
+    path.is_absolute().then(|| path.to_path_buf())
+}
+
+fn has_scheme(dest: &str) -> bool {
+    match dest.find(':') {
+        // A drive letter (C:\) is not a scheme
+        Some(i) if i > 1 => dest[..i]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')),
+        _ => false,
+    }
+}
+
+struct Rendered {
+    html: String,
+    /// Plain text for the search index.
+    text: String,
+}
+
+struct Renderer {
+    syntaxes: SyntaxSet,
+    attachments: Attachments,
+}
+
+impl Renderer {
+    fn new(attachments_dir: Option<&Path>) -> Self {
+        Self {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            attachments: Attachments::new(attachments_dir),
+        }
+    }
+
+    fn highlight_css(&self) -> Result<String, AppError> {
+        let themes = ThemeSet::load_defaults();
+        let theme = &themes.themes["InspiredGitHub"];
+        css_for_theme_with_class_style(theme, HIGHLIGHT_CLASSES)
+            .map_err(|e| AppError::internal(format!("Failed to build highlight styles: {}", e)))
+    }
+
+    /// Renders one note. `source` is the note's path in the Markdown export,
+    /// which relative `.md` links are resolved against, `page` its path in
+    /// the site.
+    fn render(
+        &mut self,
+        markdown: &str,
+        source: &str,
+        page: &str,
+        page_for_source: &HashMap<String, &str>,
+    ) -> Rendered {
+        let options = Options::ENABLE_TABLES
+            | Options::ENABLE_FOOTNOTES
+            | Options::ENABLE_STRIKETHROUGH
+            | Options::ENABLE_TASKLISTS
+            | Options::ENABLE_GFM;
+        let mut events: Vec<Event> = Vec::new();
+        let mut text = String::new();
+        let mut code: Option<(String, String)> = None;
+        let mut heading: Option<(Tag, Vec<Event>, String)> = None;
+        let mut slugs: HashMap<String, usize> = HashMap::new();
+
+        for event in Parser::new_ext(markdown, options) {
+            // Code blocks are collected whole and highlighted
+            if let Some((lang, body)) = &mut code {
+                match event {
+                    Event::Text(t) => body.push_str(&t),
+                    Event::End(TagEnd::CodeBlock) => {
+                        text.push_str(body);
+                        text.push('\n');
+                        events.push(Event::Html(self.highlight(lang, body).into()));
+                        code = None;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            let event = match event {
+                Event::Start(Tag::CodeBlock(kind)) => {
+                    let lang = match kind {
+                        CodeBlockKind::Fenced(info) => {
+                            info.split_whitespace().next().unwrap_or("").to_string()
+                        }
+                        CodeBlockKind::Indented => String::new(),
+                    };
+                    code = Some((lang, String::new()));
+                    continue;
+                }
+                Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }) => Event::Start(Tag::Link {
+                    link_type,
+                    dest_url: self.rewrite(dest_url, source, page, page_for_source),
+                    title,
+                    id,
+                }),
+                Event::Start(Tag::Image {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }) => Event::Start(Tag::Image {
+                    link_type,
+                    dest_url: self.rewrite(dest_url, source, page, page_for_source),
+                    title,
+                    id,
+                }),
+                event => event,
+            };
+
+            if let Event::Text(t) | Event::Code(t) = &event {
+                text.push_str(t);
+                text.push(' ');
+            }
+
+            // Headings get GitHub-style ids so `Note.md#section` links work
+            match event {
+                Event::Start(tag @ Tag::Heading { .. }) => {
+                    heading = Some((tag, Vec::new(), String::new()));
+                }
+                Event::End(TagEnd::Heading(level)) => {
+                    if let Some((
+                        Tag::Heading {
+                            id, classes, attrs, ..
+                        },
+                        inner,
+                        title,
+                    )) = heading.take()
+                    {
+                        let id = id.unwrap_or_else(|| unique_slug(&title, &mut slugs).into());
+                        events.push(Event::Start(Tag::Heading {
+                            level,
+                            id: Some(id),
+                            classes,
+                            attrs,
+                        }));
+                        events.extend(inner);
+                        events.push(Event::End(TagEnd::Heading(level)));
+                    }
+                }
+                event => match &mut heading {
+                    Some((_, inner, title)) => {
+                        if let Event::Text(t) | Event::Code(t) = &event {
+                            title.push_str(t);
+                        }
+                        inner.push(event);
+                    }
+                    None => events.push(event),
+                },
+            }
+        }
+
+        let mut html_out = String::new();
+        html::push_html(&mut html_out, events.into_iter());
+        Rendered {
+            html: html_out,
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+        }
+    }
+
+    fn highlight(&self, lang: &str, code: &str) -> String {
+        let syntax = (!lang.is_empty())
+            .then(|| self.syntaxes.find_syntax_by_token(lang))
+            .flatten();
+        let highlighted = syntax.and_then(|syntax| {
+            let mut generator = ClassedHTMLGenerator::new_with_class_style(
+                syntax,
+                &self.syntaxes,
+                HIGHLIGHT_CLASSES,
+            );
+            for line in LinesWithEndings::from(code) {
+                generator
+                    .parse_html_for_line_which_includes_newline(line)
+                    .ok()?;
+            }
+            Some(generator.finalize())
+        });
+        let class = if lang.is_empty() {
+            String::new()
+        } else {
+            format!(" class=\"language-{}\"", escape_html(lang))
+        };
+        format!(
+            "<pre class=\"code\"><code{}>{}</code></pre>\n",
+            class,
+            highlighted.unwrap_or_else(|| escape_html(code))
+        )
+    }
+
+    /// Points links at other notes to their pages and local files at their
+    /// copies in the site. Local files that cannot be copied lose their
+    /// link so no path on this machine is published; anything else is left
+    /// as written.
+    fn rewrite<'a>(
+        &mut self,
+        dest: CowStr<'a>,
+        source: &str,
+        page: &str,
+        page_for_source: &HashMap<String, &str>,
+    ) -> CowStr<'a> {
+        if let Some(file) = local_file(&dest) {
+            return match self.attachments.add(file) {
+                Some(path) => relative_href(page, &path).into(),
+                None => {
+                    self.attachments.skip(&dest);
+                    "".into()
+                }
+            };
+        }
+        if has_scheme(&dest) || dest.starts_with('#') {
+            return dest;
+        }
+        let (path, fragment) = match dest.find('#') {
+            Some(i) => (&dest[..i], &dest[i..]),
+            None => (&dest[..], ""),
+        };
+        let target = percent_decode(path);
+        if !target.to_lowercase().ends_with(".md") {
+            return dest;
+        }
+        match resolve_relative(source, &target)
+            .and_then(|resolved| page_for_source.get(&resolved.to_lowercase()))
+        {
+            Some(target_page) => format!("{}{}", relative_href(page, target_page), fragment).into(),
+            None => dest,
+        }
+    }
+}
+
+/// GitHub's heading anchors: lowercase, punctuation dropped, spaces to `-`,
+/// repeated titles numbered.
+fn unique_slug(title: &str, seen: &mut HashMap<String, usize>) -> String {
+    let slug: String = title
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' | '-' => Some('-'),
+            c if c.is_alphanumeric() || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect();
+    let count = seen.entry(slug.clone()).or_insert(0);
+    *count += 1;
+    match *count {
+        1 => slug,
+        n => format!("{}-{}", slug, n - 1),
+    }
+}
+
+/// Exports the website to the grant from `pick_save_path` (ZIP) or
+/// `pick_directory` (directory). `attachments_token` comes from
+/// `pick_open_directory` and names the folder local images may be copied
+/// from.
+#[tauri::command]
+pub async fn export_site(
+    state: tauri::State<'_, crate::DbState>,
+    grants: tauri::State<'_, FileGrants>,
+    workspace_id: String,
+    target: ExportTarget,
+    token: String,
+    attachments_token: Option<String>,
+) -> Result<SiteSummary, AppError> {
+    let db = state.db()?;
+    let path = grants.redeem(&token, target.access())?;
+    let attachments_dir = attachments_token
+        .map(|token| grants.redeem(&token, Access::ReadDirectory))
+        .transpose()?;
+    export_site_logic(&db, workspace_id, target, path, attachments_dir.as_deref()).await
+}
+
+const SITE_CSS: &str = r#":root {
+  --bg: #ffffff; --surface: #f7f7f8; --text: #1f2328; --muted: #6b7280;
+  --accent: #4f7df3; --border: #e5e7eb;
+  font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", Inter, sans-serif;
+}
+@media (prefers-color-scheme: dark) {
+  :root { --bg: #17181b; --surface: #1f2024; --text: #e6e6e6; --muted: #9ca3af; --border: #2d2f34; }
+}
+* { box-sizing: border-box; }
+body { margin: 0; display: flex; min-height: 100vh; background: var(--bg); color: var(--text); }
+#sidebar { width: 280px; flex-shrink: 0; padding: 20px 16px; background: var(--surface);
+  border-right: 1px solid var(--border); position: sticky; top: 0; height: 100vh; overflow-y: auto; }
+.site-title { display: block; font-weight: 600; color: var(--text); text-decoration: none; margin-bottom: 12px; }
+#search { width: 100%; padding: 6px 10px; border: 1px solid var(--border); border-radius: 8px;
+  background: var(--bg); color: var(--text); margin-bottom: 12px; }
+#nav ul, .tree { list-style: none; padding-left: 14px; margin: 0; }
+#nav > ul { padding-left: 0; }
+#nav a { color: var(--text); text-decoration: none; font-size: 14px; display: block; padding: 3px 6px; border-radius: 6px; }
+#nav a:hover { background: var(--border); }
+#nav a.active { background: var(--accent); color: #fff; }
+#nav summary { cursor: pointer; font-size: 13px; color: var(--muted); padding: 3px 0; }
+.search-result small { display: block; color: var(--muted); font-size: 12px; }
+main { flex: 1; min-width: 0; padding: 40px 48px; }
+.note { max-width: 760px; margin: 0 auto; line-height: 1.65; }
+.note a { color: var(--accent); }
+.note img { max-width: 100%; }
+.note table { border-collapse: collapse; }
+.note th, .note td { border: 1px solid var(--border); padding: 6px 10px; }
+.note blockquote { margin: 0; padding-left: 14px; border-left: 3px solid var(--border); color: var(--muted); }
+.note code { font-family: ui-monospace, SFMono-Regular, Menlo, monospace; font-size: 0.9em; }
+.note pre.code { background: var(--surface); border: 1px solid var(--border); border-radius: 8px; padding: 12px 16px; overflow-x: auto; }
+.note li:has(> input[type=checkbox]) { list-style: none; margin-left: -1.3em; }
+@media (max-width: 720px) { body { flex-direction: column; } #sidebar { width: auto; height: auto; position: static; } main { padding: 24px; } }
+"#;
+
+const SITE_JS: &str = r#"(function () {
+  var site = window.LUMENOTE_SITE;
+  var root = document.body.dataset.root;
+  var current = document.body.dataset.path;
+  var nav = document.getElementById('nav');
+  var search = document.getElementById('search');
+
+  function href(path) {
+    return root + encodeURI(path).replace(/#/g, '%23').replace(/\?/g, '%3F');
+  }
+
+  function link(title, path, detail) {
+    var a = document.createElement('a');
+    a.href = href(path);
+    a.textContent = title;
+    if (path === current) a.className = 'active';
+    if (detail) {
+      a.className += ' search-result';
+      var small = document.createElement('small');
+      small.textContent = detail;
+      a.appendChild(small);
+    }
+    return a;
+  }
+
+  function tree(items) {
+    var ul = document.createElement('ul');
+    items.forEach(function (item) {
+      var li = document.createElement('li');
+      if (item.type === 'folder') {
+        var details = document.createElement('details');
+        var summary = document.createElement('summary');
+        summary.textContent = item.name;
+        if (item.color) summary.style.color = item.color;
+        details.appendChild(summary);
+        details.appendChild(tree(item.children));
+        details.open = contains(item, current);
+        li.appendChild(details);
+      } else {
+        li.appendChild(link(item.title, item.path));
+      }
+      ul.appendChild(li);
+    });
+    return ul;
+  }
+
+  function contains(folder, path) {
+    return folder.children.some(function (child) {
+      return child.type === 'folder' ? contains(child, path) : child.path === path;
+    });
+  }
+
+  function snippet(text, index) {
+    var start = Math.max(0, index - 40);
+    return (start > 0 ? '…' : '') + text.slice(start, index + 80);
+  }
+
+  function showResults(query) {
+    var words = query.toLowerCase().split(/\s+/).filter(Boolean);
+    var ul = document.createElement('ul');
+    site.search.forEach(function (entry) {
+      var haystack = (entry.title + ' ' + entry.text).toLowerCase();
+      if (!words.every(function (w) { return haystack.indexOf(w) >= 0; })) return;
+      var li = document.createElement('li');
+      var at = entry.text.toLowerCase().indexOf(words[0]);
+      li.appendChild(link(entry.title, entry.path, at >= 0 ? snippet(entry.text, at) : ''));
+      ul.appendChild(li);
+    });
+    nav.replaceChildren(ul);
+  }
+
+  function render() {
+    var query = search.value.trim();
+    if (query) showResults(query);
+    else nav.replaceChildren(tree(site.tree));
+  }
+
+  search.addEventListener('input', render);
+  render();
+})();
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, title: &str, folder_id: Option<&str>, content: &str) -> NoteRecord {
+        NoteRecord {
+            id: id.to_string(),
+            title: title.to_string(),
+            content: content.to_string(),
+            folder_id: folder_id.map(str::to_string),
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        }
+    }
+
+    fn file<'a>(files: &'a [SiteFile], path: &str) -> &'a str {
+        let file = files
+            .iter()
+            .find(|f| f.path == path)
+            .unwrap_or_else(|| panic!("missing {}", path));
+        std::str::from_utf8(&file.bytes).unwrap()
+    }
+
+    #[test]
+    fn test_site_renders_gfm_links_and_search_index() {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("diagram.png");
+        fs::write(&image, b"png").unwrap();
+
+        let workspace = WorkspaceRecord {
+            id: "default".to_string(),
+            name: "Handbook".to_string(),
+            color: "#4F7DF3".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            is_deleted: false,
+        };
+        let folders = vec![FolderRecord {
+            id: "f1".to_string(),
+            name: "Guides".to_string(),
+            parent_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            color: None,
+            sort_key: String::new(),
+            is_deleted: false,
+        }];
+        let notes = vec![
+            note(
+                "n1",
+                "Start here",
+                None,
+                &format!(
+                    "See [setup](Guides/Set%20up.md#install) and ![d]({}).\n\n\
+                     | a | b |\n|---|---|\n| 1 | 2 |\n\n- [x] done\n\nText[^1]\n\n[^1]: Note\n",
+                    image.display()
+                ),
+            ),
+            note(
+                "n2",
+                "Set up",
+                Some("f1"),
+                "## Install\n\n```rust\nfn main() {}\n```\n\n[back](../Start%20here.md)",
+            ),
+        ];
+
+        let files = build_site(&workspace, &notes, &folders, Some(&dir))
+            .unwrap()
+            .files;
+
+        let start = file(&files, "Start here.html");
+        assert!(
+            start.contains("href=\"Guides/Set%20up.html#install\""),
+            "{}",
+            start
+        );
+        assert!(start.contains("<table>"));
+        assert!(start.contains("type=\"checkbox\""));
+        assert!(start.contains("footnote"));
+        assert!(start.contains("src=\"assets/files/diagram.png\""));
+        assert_eq!(file(&files, "assets/files/diagram.png"), "png");
+
+        let setup = file(&files, "Guides/Set up.html");
+        assert!(setup.contains("<h2 id=\"install\">"), "{}", setup);
+        assert!(setup.contains("href=\"../Start%20here.html\""));
+        assert!(setup.contains("hl-"), "code was not highlighted: {}", setup);
+        assert!(setup.contains("href=\"../assets/style.css\""));
+
+        let index = file(&files, "index.html");
+        assert!(index.contains("href=\"Guides/Set%20up.html\""));
+        let data = file(&files, "assets/site-data.js");
+        assert!(
+            data.contains("\"text\":\"Install fn main() {} back\""),
+            "{}",
+            data
+        );
+        assert!(file(&files, "assets/style.css").contains(".hl-"));
+    }
+
+    #[test]
+    fn test_site_copies_only_media_from_the_granted_folder() {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        let granted = dir.join("granted");
+        let elsewhere = dir.join("elsewhere");
+        fs::create_dir_all(&granted).unwrap();
+        fs::create_dir_all(&elsewhere).unwrap();
+        fs::write(granted.join("photo.JPG"), b"jpg").unwrap();
+        fs::write(granted.join("id_rsa"), b"secret").unwrap();
+        fs::write(elsewhere.join("leak.png"), b"png").unwrap();
+        let escape = granted.join("..").join("elsewhere").join("leak.png");
+
+        let workspace = WorkspaceRecord {
+            id: "default".to_string(),
+            name: "Handbook".to_string(),
+            color: "#4F7DF3".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            is_deleted: false,
+        };
+        let content = format!(
+            "![a]({})\n\n[b]({})\n\n![c]({})\n\n![d]({})\n",
+            granted.join("photo.JPG").display(),
+            granted.join("id_rsa").display(),
+            elsewhere.join("leak.png").display(),
+            escape.display()
+        );
+        let notes = vec![note("n1", "Photos", None, &content)];
+
+        let site = build_site(&workspace, &notes, &[], Some(&granted)).unwrap();
+        let page = file(&site.files, "Photos.html");
+        assert!(page.contains("src=\"assets/files/photo.JPG\""), "{}", page);
+        assert_eq!(site.skipped_links.len(), 3, "{:?}", site.skipped_links);
+        assert!(!page.contains(&*elsewhere.to_string_lossy()), "{}", page);
+        assert!(!page.contains("id_rsa"), "{}", page);
+        let copied: Vec<&str> = site
+            .files
+            .iter()
+            .filter(|f| f.path.starts_with(ATTACHMENTS_DIR))
+            .map(|f| f.path.as_str())
+            .collect();
+        assert_eq!(copied, vec!["assets/files/photo.JPG"]);
+
+        // Without a grant nothing local is copied
+        let site = build_site(&workspace, &notes, &[], None).unwrap();
+        assert_eq!(site.skipped_links.len(), 4);
+        assert!(!file(&site.files, "Photos.html").contains("photo.JPG"));
+    }
+
+    #[test]
+    fn test_directory_export_removes_pages_it_wrote_before() {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("README.md"), b"mine").unwrap();
+
+        let workspace = WorkspaceRecord {
+            id: "default".to_string(),
+            name: "Handbook".to_string(),
+            color: "#4F7DF3".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            is_deleted: false,
+        };
+        let folders = vec![FolderRecord {
+            id: "f1".to_string(),
+            name: "Guides".to_string(),
+            parent_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            color: None,
+            sort_key: String::new(),
+            is_deleted: false,
+        }];
+        let notes = vec![
+            note("n1", "Start here", None, "Hello"),
+            note("n2", "Set up", Some("f1"), "Install"),
+            note("n3", "Gone", Some("deleted"), "Left out"),
+        ];
+
+        let site = build_site(&workspace, &notes, &folders, None).unwrap();
+        assert_eq!(site.pages, 3);
+        write_directory(&dir, &site.files).unwrap();
+        assert!(dir.join("Guides").join("Set up.html").is_file());
+
+        let site = build_site(&workspace, &notes[..1], &[], None).unwrap();
+        assert_eq!(site.pages, 2);
+        write_directory(&dir, &site.files).unwrap();
+        assert!(dir.join("Start here.html").is_file());
+        assert!(!dir.join("Guides").exists());
+        assert!(dir.join("README.md").is_file());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,1643 @@
+use crate::crypto::{self, CryptoError, DataKey, KdfParams, KeyEnvelope};
+use crate::db::{now_millis, tx, Db, DbState, FolderRecord, NoteRecord, WorkspaceRecord};
+use crate::error::AppError;
+use crate::history::HistoryService;
+use crate::webdav::{self, WebDavTransport};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::Notify;
+
+/// Event emitted with a [`SyncStatus`] payload whenever the sync state changes.
+pub const SYNC_STATUS_EVENT: &str = "sync-status";
+
+const BACKEND_SETTING: &str = "sync.backend";
+const URL_SETTING: &str = "sync.url";
+const USERNAME_SETTING: &str = "sync.username";
+const KEY_SETTING: &str = "sync.key";
+const INTERVAL_SETTING: &str = "sync.interval_secs";
+/// Time of the last successful round. For the Lumenote server this is its
+/// clock, which is also the cursor sent back as `last_sync_time`.
+const LAST_SYNC_SETTING: &str = "sync.last_sync_time";
+/// Local clock up to which local rows have been pushed.
+const PUSHED_UNTIL_SETTING: &str = "sync.pushed_until";
+/// Set when encryption is turned on: the next round pushes every row again
+/// with `replace`, so the ciphertext overwrites the plaintext copies without
+/// touching any timestamp.
+const REUPLOAD_SETTING: &str = "sync.reupload";
+/// Serialized `KeyEnvelope`, exactly as stored on the server.
+const ENVELOPE_SETTING: &str = "sync.key_envelope";
+/// Received rows that could not be decrypted, or sit inside one that could
+/// not, kept as they arrived under `sync.quarantine.<kind>.<id>` instead of
+/// stopping every round.
+const QUARANTINE_PREFIX: &str = "sync.quarantine.";
+/// Why a row inside a set-aside workspace or folder is set aside as well.
+const PARENT_SET_ASIDE: &str = "Its workspace or folder was set aside";
+
+const MIN_PASSPHRASE_LEN: usize = 8;
+
+pub const DEFAULT_INTERVAL_SECS: u64 = 300;
+const MIN_INTERVAL_SECS: u64 = 30;
+/// Quiet period after a local write before pushing it, so a burst of edits
+/// goes out as one round.
+const CHANGE_DEBOUNCE: Duration = Duration::from_secs(3);
+const BACKOFF_BASE_SECS: u64 = 5;
+const BACKOFF_MAX_SECS: u64 = 15 * 60;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where the data is synced through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncBackend {
+    /// The Lumenote server in `server/`.
+    #[default]
+    Server,
+    /// A WebDAV collection, e.g. a Nextcloud folder; see `webdav`.
+    Webdav,
+}
+
+impl SyncBackend {
+    fn as_str(self) -> &'static str {
+        match self {
+            SyncBackend::Server => "server",
+            SyncBackend::Webdav => "webdav",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncConfig {
+    pub backend: SyncBackend,
+    pub url: String,
+    /// WebDAV login; unused by the Lumenote server.
+    pub username: String,
+    /// Sync key for the Lumenote server, password for WebDAV.
+    pub key: String,
+    pub interval_secs: u64,
+}
+
+impl SyncConfig {
+    pub fn is_configured(&self) -> bool {
+        !self.url.is_empty() && !self.key.is_empty()
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(MIN_INTERVAL_SECS))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncState {
+    Idle,
+    Syncing,
+    Error,
+    Offline,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncStatus {
+    pub state: SyncState,
+    pub configured: bool,
+    pub backend: SyncBackend,
+    pub url: String,
+    pub username: String,
+    pub interval_secs: u64,
+    /// Server time of the last successful round (unix millis).
+    pub last_sync_time: Option<i64>,
+    /// Rows pushed to / received from the server in the last successful round.
+    pub last_pushed: usize,
+    pub last_pulled: usize,
+    pub last_error: Option<String>,
+    /// When the next automatic attempt is due after a failure (unix millis).
+    pub next_retry_at: Option<i64>,
+}
+
+impl Default for SyncStatus {
+    fn default() -> Self {
+        Self {
+            state: SyncState::Idle,
+            configured: false,
+            backend: SyncBackend::Server,
+            url: String::new(),
+            username: String::new(),
+            interval_secs: DEFAULT_INTERVAL_SECS,
+            last_sync_time: None,
+            last_pushed: 0,
+            last_pulled: 0,
+            last_error: None,
+            next_retry_at: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    NotConfigured,
+    /// The server could not be reached; retried with backoff.
+    Offline(String),
+    /// The server answered with an error or an unreadable body.
+    Server(String),
+    /// Reading or writing the local database failed.
+    Local(String),
+    /// The account is end-to-end encrypted and the data key is not unlocked.
+    Locked,
+}
+
+impl std::fmt::Display for SyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncError::NotConfigured => write!(f, "Sync is not configured"),
+            SyncError::Offline(e) => write!(f, "Sync server unreachable: {}", e),
+            SyncError::Locked => write!(f, "Encrypted sync is locked: enter your passphrase"),
+            SyncError::Server(e) | SyncError::Local(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for SyncError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() || e.is_timeout() {
+            SyncError::Offline(e.to_string())
+        } else {
+            SyncError::Server(e.to_string())
+        }
+    }
+}
+
+impl From<AppError> for SyncError {
+    fn from(e: AppError) -> Self {
+        SyncError::Local(e.to_string())
+    }
+}
+
+impl From<sqlx::Error> for SyncError {
+    fn from(e: sqlx::Error) -> Self {
+        AppError::from(e).into()
+    }
+}
+
+impl From<CryptoError> for SyncError {
+    fn from(e: CryptoError) -> Self {
+        SyncError::Local(e.to_string())
+    }
+}
+
+/// Rows going out or coming in during a round.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncRows {
+    #[serde(default)]
+    pub notes: Vec<NoteRecord>,
+    #[serde(default)]
+    pub folders: Vec<FolderRecord>,
+    #[serde(default)]
+    pub workspaces: Vec<WorkspaceRecord>,
+}
+
+impl SyncRows {
+    pub fn len(&self) -> usize {
+        self.notes.len() + self.folders.len() + self.workspaces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// What a transport brings back from a round.
+#[derive(Debug)]
+pub struct Pulled {
+    /// Stored under `SyncTransport::cursor_setting` and passed back as
+    /// `since` next round.
+    pub cursor: i64,
+    /// When the round happened, shown as the last sync time.
+    pub time: i64,
+    pub rows: SyncRows,
+}
+
+/// Carries rows and the key envelope between this device and the others.
+/// Rows are already encrypted when the account is; transports only move
+/// them, and the round applies what comes back with last-writer-wins.
+pub trait SyncTransport: Send + Sync {
+    /// Setting that keeps `Pulled::cursor` between rounds.
+    fn cursor_setting(&self) -> &'static str;
+
+    /// The raw key envelope stored with the account, if any.
+    fn fetch_envelope(&self) -> impl Future<Output = Result<Option<String>, SyncError>> + Send;
+
+    /// Replaces the envelope, refusing with [`key_changed`] when the stored
+    /// one is no longer `previous`.
+    fn store_envelope(
+        &self,
+        envelope: &str,
+        previous: Option<&str>,
+    ) -> impl Future<Output = Result<(), SyncError>> + Send;
+
+    /// Sends local rows and returns everyone else's rows since `since`. With
+    /// `replace`, the rows also win over stored ones with the same
+    /// `updated_at`.
+    fn exchange(
+        &self,
+        since: i64,
+        rows: SyncRows,
+        replace: bool,
+    ) -> impl Future<Output = Result<Pulled, SyncError>> + Send;
+}
+
+pub fn key_changed() -> SyncError {
+    SyncError::Server("The encryption key was changed on another device; try again".to_string())
+}
+
+#[derive(Serialize)]
+struct SyncRequest {
+    last_sync_time: i64,
+    replace: bool,
+    #[serde(flatten)]
+    rows: SyncRows,
+}
+
+#[derive(Deserialize)]
+struct SyncResponse {
+    server_time: i64,
+    #[serde(default)]
+    notes: Vec<RemoteNote>,
+    #[serde(default)]
+    folders: Vec<RemoteFolder>,
+    #[serde(default)]
+    workspaces: Vec<RemoteWorkspace>,
+}
+
+// Server rows may omit `created_at` and carry no version.
+
+#[derive(Deserialize)]
+struct RemoteNote {
+    id: String,
+    title: String,
+    content: String,
+    folder_id: Option<String>,
+    workspace_id: String,
+    created_at: Option<i64>,
+    updated_at: i64,
+    version: Option<i32>,
+    #[serde(default)]
+    sort_key: String,
+    #[serde(default)]
+    color: Option<String>,
+    #[serde(default)]
+    is_deleted: bool,
+}
+
+#[derive(Deserialize)]
+struct RemoteFolder {
+    id: String,
+    name: String,
+    parent_id: Option<String>,
+    workspace_id: String,
+    created_at: Option<i64>,
+    updated_at: i64,
+    version: Option<i32>,
+    #[serde(default)]
+    color: Option<String>,
+    #[serde(default)]
+    sort_key: String,
+    #[serde(default)]
+    is_deleted: bool,
+}
+
+#[derive(Deserialize)]
+struct RemoteWorkspace {
+    id: String,
+    name: String,
+    color: String,
+    created_at: Option<i64>,
+    updated_at: i64,
+    version: Option<i32>,
+    #[serde(default)]
+    is_deleted: bool,
+}
+
+impl From<RemoteNote> for NoteRecord {
+    fn from(n: RemoteNote) -> Self {
+        NoteRecord {
+            id: n.id,
+            title: n.title,
+            content: n.content,
+            folder_id: n.folder_id,
+            workspace_id: n.workspace_id,
+            created_at: n.created_at.unwrap_or(n.updated_at),
+            updated_at: n.updated_at,
+            version: n.version.unwrap_or(1),
+            sort_key: n.sort_key,
+            color: n.color,
+            is_deleted: n.is_deleted,
+        }
+    }
+}
+
+impl From<RemoteFolder> for FolderRecord {
+    fn from(f: RemoteFolder) -> Self {
+        FolderRecord {
+            id: f.id,
+            name: f.name,
+            parent_id: f.parent_id,
+            workspace_id: f.workspace_id,
+            created_at: f.created_at.unwrap_or(f.updated_at),
+            updated_at: f.updated_at,
+            version: f.version.unwrap_or(1),
+            color: f.color,
+            sort_key: f.sort_key,
+            is_deleted: f.is_deleted,
+        }
+    }
+}
+
+impl From<RemoteWorkspace> for WorkspaceRecord {
+    fn from(w: RemoteWorkspace) -> Self {
+        WorkspaceRecord {
+            id: w.id,
+            name: w.name,
+            color: w.color,
+            created_at: w.created_at.unwrap_or(w.updated_at),
+            updated_at: w.updated_at,
+            version: w.version.unwrap_or(1),
+            is_deleted: w.is_deleted,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncOutcome {
+    pub server_time: i64,
+    pub pushed: usize,
+    pub pulled: usize,
+    /// Pulled rows that failed to decrypt and were set aside.
+    pub quarantined: usize,
+}
+
+async fn setting_i64(db: &Db, key: &str) -> Result<Option<i64>, AppError> {
+    Ok(db.get_setting(key).await?.and_then(|v| v.parse().ok()))
+}
+
+pub async fn load_config(db: &Db) -> Result<SyncConfig, AppError> {
+    let backend = match db.get_setting(BACKEND_SETTING).await?.as_deref() {
+        Some("webdav") => SyncBackend::Webdav,
+        _ => SyncBackend::Server,
+    };
+    Ok(SyncConfig {
+        backend,
+        url: db.get_setting(URL_SETTING).await?.unwrap_or_default(),
+        username: db.get_setting(USERNAME_SETTING).await?.unwrap_or_default(),
+        key: db.get_setting(KEY_SETTING).await?.unwrap_or_default(),
+        interval_secs: setting_i64(db, INTERVAL_SETTING)
+            .await?
+            .map(|v| v as u64)
+            .unwrap_or(DEFAULT_INTERVAL_SECS),
+    })
+}
+
+/// Stores the configuration. Pointing at another server or account resets
+/// the watermarks, so the next round pushes and pulls everything.
+pub async fn save_config(db: &Db, config: &SyncConfig) -> Result<(), AppError> {
+    let previous = load_config(db).await?;
+    let mut conn = db.pool.begin().await?;
+    tx::set_setting(&mut conn, BACKEND_SETTING, config.backend.as_str()).await?;
+    tx::set_setting(&mut conn, URL_SETTING, &config.url).await?;
+    tx::set_setting(&mut conn, USERNAME_SETTING, &config.username).await?;
+    tx::set_setting(&mut conn, KEY_SETTING, &config.key).await?;
+    tx::set_setting(
+        &mut conn,
+        INTERVAL_SETTING,
+        &config.interval_secs.to_string(),
+    )
+    .await?;
+    if previous.backend != config.backend
+        || previous.url != config.url
+        || previous.username != config.username
+        || previous.key != config.key
+    {
+        for watermark in [
+            LAST_SYNC_SETTING,
+            PUSHED_UNTIL_SETTING,
+            webdav::CURSOR_SETTING,
+        ] {
+            tx::set_setting(&mut conn, watermark, "0").await?;
+        }
+    }
+    Ok(conn.commit().await?)
+}
+
+/// Runs one round trip through the configured backend.
+pub async fn sync_once(
+    db: &Db,
+    client: &reqwest::Client,
+    config: &SyncConfig,
+    key: Option<&DataKey>,
+) -> Result<SyncOutcome, SyncError> {
+    if !config.is_configured() {
+        return Err(SyncError::NotConfigured);
+    }
+    sync_with(db, &transport(client, config), key).await
+}
+
+/// Runs one round: pushes local rows changed since the last round, applies
+/// the rows that came back in a single transaction and advances the
+/// watermarks in that same transaction.
+pub async fn sync_with<T: SyncTransport>(
+    db: &Db,
+    transport: &T,
+    key: Option<&DataKey>,
+) -> Result<SyncOutcome, SyncError> {
+    // Another device may have turned encryption on; never push plaintext or
+    // store ciphertext once it has.
+    let encrypted = match db.get_setting(ENVELOPE_SETTING).await? {
+        Some(_) => true,
+        None => match transport.fetch_envelope().await? {
+            Some(raw) => {
+                db.set_setting(ENVELOPE_SETTING, &raw).await?;
+                true
+            }
+            None => false,
+        },
+    };
+    let key = match (encrypted, key) {
+        (true, None) => return Err(SyncError::Locked),
+        (true, Some(key)) => Some(key),
+        (false, _) => None,
+    };
+
+    let since = setting_i64(db, transport.cursor_setting())
+        .await?
+        .unwrap_or(0);
+    let replace = db.get_setting(REUPLOAD_SETTING).await?.as_deref() == Some("1");
+    let pushed_until = match replace {
+        true => 0,
+        false => setting_i64(db, PUSHED_UNTIL_SETTING).await?.unwrap_or(0),
+    };
+
+    // Captured before reading, so edits made while the request is in flight
+    // are newer than the stored watermark and go out next round.
+    let started_at = now_millis();
+    let (mut notes, mut folders, mut workspaces) = db.get_sync_data(pushed_until).await?;
+    let pushed = notes.len() + folders.len() + workspaces.len();
+    if let Some(key) = key {
+        for note in &mut notes {
+            crypto::encrypt_note(key, note)?;
+        }
+        for folder in &mut folders {
+            crypto::encrypt_folder(key, folder)?;
+        }
+        for workspace in &mut workspaces {
+            crypto::encrypt_workspace(key, workspace)?;
+        }
+    }
+
+    let remote = transport
+        .exchange(
+            since,
+            SyncRows {
+                notes,
+                folders,
+                workspaces,
+            },
+            replace,
+        )
+        .await?;
+    let pulled = remote.rows.len();
+
+    let mut conn = db.pool.begin().await?;
+    // The server sends rows in no particular order; check references once
+    // the whole round is in
+    sqlx::query("PRAGMA defer_foreign_keys = ON")
+        .execute(&mut *conn)
+        .await?;
+    // Ids set aside this round; rows inside them are set aside too, or they
+    // would point at nothing
+    let mut set_aside = HashSet::new();
+    // Parents before children, so folders and notes land in existing workspaces.
+    for workspace in remote.rows.workspaces {
+        let mut plain = workspace.clone();
+        if let Some(Err(e)) = key.map(|key| crypto::decrypt_workspace(key, &mut plain)) {
+            let reason = e.to_string();
+            quarantine(&mut conn, "workspace", &workspace.id, &workspace, &reason).await?;
+            set_aside.insert(workspace.id);
+            continue;
+        }
+        tx::apply_remote_workspace(&mut conn, &plain).await?;
+    }
+    for folder in parents_first(remote.rows.folders) {
+        let mut plain = folder.clone();
+        let decrypted = match key.map(|key| crypto::decrypt_folder(key, &mut plain)) {
+            Some(Err(e)) => Err(e.to_string()),
+            _ if set_aside.contains(&folder.workspace_id) => Err(PARENT_SET_ASIDE.to_string()),
+            _ if folder
+                .parent_id
+                .as_ref()
+                .is_some_and(|p| set_aside.contains(p)) =>
+            {
+                Err(PARENT_SET_ASIDE.to_string())
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = decrypted {
+            quarantine(&mut conn, "folder", &folder.id, &folder, &e).await?;
+            set_aside.insert(folder.id);
+            continue;
+        }
+        tx::apply_remote_folder(&mut conn, &plain).await?;
+    }
+    let mut quarantined = set_aside.len();
+    for note in remote.rows.notes {
+        let mut plain = note.clone();
+        let decrypted = match key.map(|key| crypto::decrypt_note(key, &mut plain)) {
+            Some(Err(e)) => Err(e.to_string()),
+            _ if set_aside.contains(&note.workspace_id) => Err(PARENT_SET_ASIDE.to_string()),
+            _ if note
+                .folder_id
+                .as_ref()
+                .is_some_and(|f| set_aside.contains(f)) =>
+            {
+                Err(PARENT_SET_ASIDE.to_string())
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = decrypted {
+            quarantine(&mut conn, "note", &note.id, &note, &e).await?;
+            quarantined += 1;
+            continue;
+        }
+        tx::apply_remote_note(&mut conn, &plain).await?;
+    }
+    tx::set_setting(&mut conn, LAST_SYNC_SETTING, &remote.time.to_string()).await?;
+    tx::set_setting(
+        &mut conn,
+        transport.cursor_setting(),
+        &remote.cursor.to_string(),
+    )
+    .await?;
+    tx::set_setting(
+        &mut conn,
+        PUSHED_UNTIL_SETTING,
+        &(started_at - 1).to_string(),
+    )
+    .await?;
+    if replace {
+        tx::set_setting(&mut conn, REUPLOAD_SETTING, "0").await?;
+    }
+    conn.commit().await?;
+
+    Ok(SyncOutcome {
+        server_time: remote.time,
+        pushed,
+        pulled,
+        quarantined,
+    })
+}
+
+/// Orders pulled folders so each comes after its parent when both are in
+/// the batch. A cycle is left as it is.
+fn parents_first(mut pending: Vec<FolderRecord>) -> Vec<FolderRecord> {
+    let mut ordered = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let waiting: HashSet<String> = pending.iter().map(|f| f.id.clone()).collect();
+        let (ready, rest): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .partition(|f| f.parent_id.as_ref().is_none_or(|p| !waiting.contains(p)));
+        if ready.is_empty() {
+            ordered.extend(rest);
+            break;
+        }
+        ordered.extend(ready);
+        pending = rest;
+    }
+    ordered
+}
+
+/// Sets aside a pulled row that failed to decrypt, as it was received, so
+/// one bad row does not stop every round.
+async fn quarantine<T: Serialize>(
+    conn: &mut SqliteConnection,
+    kind: &str,
+    id: &str,
+    row: &T,
+    reason: &str,
+) -> Result<(), SyncError> {
+    eprintln!("Quarantined {} {} from sync: {}", kind, id, reason);
+    let value = serde_json::to_string(row)
+        .map_err(|e| AppError::internal(format!("Failed to serialize {}: {}", kind, e)))?;
+    tx::set_setting(
+        conn,
+        &format!("{}{}.{}", QUARANTINE_PREFIX, kind, id),
+        &value,
+    )
+    .await?;
+    Ok(())
+}
+
+/// The backend `config` points at.
+pub enum Transport<'a> {
+    Server(ServerTransport<'a>),
+    Webdav(WebDavTransport<'a>),
+}
+
+pub fn transport<'a>(client: &'a reqwest::Client, config: &'a SyncConfig) -> Transport<'a> {
+    match config.backend {
+        SyncBackend::Server => Transport::Server(ServerTransport { client, config }),
+        SyncBackend::Webdav => Transport::Webdav(WebDavTransport::new(client, config)),
+    }
+}
+
+impl SyncTransport for Transport<'_> {
+    fn cursor_setting(&self) -> &'static str {
+        match self {
+            Transport::Server(t) => t.cursor_setting(),
+            Transport::Webdav(t) => t.cursor_setting(),
+        }
+    }
+
+    async fn fetch_envelope(&self) -> Result<Option<String>, SyncError> {
+        match self {
+            Transport::Server(t) => t.fetch_envelope().await,
+            Transport::Webdav(t) => t.fetch_envelope().await,
+        }
+    }
+
+    async fn store_envelope(
+        &self,
+        envelope: &str,
+        previous: Option<&str>,
+    ) -> Result<(), SyncError> {
+        match self {
+            Transport::Server(t) => t.store_envelope(envelope, previous).await,
+            Transport::Webdav(t) => t.store_envelope(envelope, previous).await,
+        }
+    }
+
+    async fn exchange(
+        &self,
+        since: i64,
+        rows: SyncRows,
+        replace: bool,
+    ) -> Result<Pulled, SyncError> {
+        match self {
+            Transport::Server(t) => t.exchange(since, rows, replace).await,
+            Transport::Webdav(t) => t.exchange(since, rows, replace).await,
+        }
+    }
+}
+
+/// The Lumenote server: one `POST /sync` per round, keyed by the sync key.
+pub struct ServerTransport<'a> {
+    client: &'a reqwest::Client,
+    config: &'a SyncConfig,
+}
+
+impl ServerTransport<'_> {
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.config.url.trim_end_matches('/'), path)
+    }
+}
+
+impl SyncTransport for ServerTransport<'_> {
+    /// The server's clock doubles as the cursor.
+    fn cursor_setting(&self) -> &'static str {
+        LAST_SYNC_SETTING
+    }
+
+    /// Servers that predate encryption answer 404, which means "not
+    /// encrypted".
+    async fn fetch_envelope(&self) -> Result<Option<String>, SyncError> {
+        let response = self
+            .client
+            .get(self.url("keys"))
+            .header("X-Sync-Key", &self.config.key)
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(SyncError::Server(format!(
+                "Fetching the encryption key failed ({})",
+                response.status()
+            )));
+        }
+        Ok(response.json::<KeysPayload>().await?.envelope)
+    }
+
+    async fn store_envelope(
+        &self,
+        envelope: &str,
+        previous: Option<&str>,
+    ) -> Result<(), SyncError> {
+        let response = self
+            .client
+            .put(self.url("keys"))
+            .header("X-Sync-Key", &self.config.key)
+            .json(&KeysPayload {
+                envelope: Some(envelope.to_string()),
+                previous: previous.map(str::to_string),
+            })
+            .send()
+            .await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            reqwest::StatusCode::CONFLICT => Err(key_changed()),
+            status => Err(SyncError::Server(format!(
+                "Storing the encryption key failed ({})",
+                status
+            ))),
+        }
+    }
+
+    async fn exchange(
+        &self,
+        since: i64,
+        rows: SyncRows,
+        replace: bool,
+    ) -> Result<Pulled, SyncError> {
+        let response = self
+            .client
+            .post(self.url("sync"))
+            .header("X-Sync-Key", &self.config.key)
+            .json(&SyncRequest {
+                last_sync_time: since,
+                replace,
+                rows,
+            })
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(SyncError::Server(format!(
+                "Sync failed ({}): {}",
+                status, body
+            )));
+        }
+        let remote: SyncResponse = response.json().await?;
+        Ok(Pulled {
+            cursor: remote.server_time,
+            time: remote.server_time,
+            rows: SyncRows {
+                notes: remote.notes.into_iter().map(NoteRecord::from).collect(),
+                folders: remote.folders.into_iter().map(FolderRecord::from).collect(),
+                workspaces: remote
+                    .workspaces
+                    .into_iter()
+                    .map(WorkspaceRecord::from)
+                    .collect(),
+            },
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeysPayload {
+    envelope: Option<String>,
+    /// Envelope the client last saw; the server refuses the write if its copy
+    /// differs, so two devices cannot silently overwrite each other's key.
+    #[serde(default)]
+    previous: Option<String>,
+}
+
+/// Delay before the next attempt after `failures` consecutive failed rounds.
+pub fn backoff_delay(failures: u32) -> Duration {
+    let exp = failures.saturating_sub(1).min(16);
+    Duration::from_secs((BACKOFF_BASE_SECS << exp).min(BACKOFF_MAX_SECS))
+}
+
+/// Managed state shared by the background loop and the sync commands.
+pub struct SyncService {
+    client: reqwest::Client,
+    status: Mutex<SyncStatus>,
+    /// Wakes the background loop early, e.g. after the configuration changed.
+    wake: Notify,
+    /// Keeps timer-driven rounds and `sync_now` from overlapping.
+    round: tokio::sync::Mutex<()>,
+    /// Unlocked end-to-end encryption key; only ever held in memory.
+    data_key: Mutex<Option<DataKey>>,
+}
+
+impl Default for SyncService {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            status: Mutex::new(SyncStatus::default()),
+            wake: Notify::new(),
+            round: tokio::sync::Mutex::new(()),
+            data_key: Mutex::new(None),
+        }
+    }
+}
+
+impl SyncService {
+    pub fn status(&self) -> SyncStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn update(&self, app: &AppHandle, f: impl FnOnce(&mut SyncStatus)) {
+        let status = {
+            let mut status = self.status.lock().unwrap();
+            f(&mut status);
+            status.clone()
+        };
+        let _ = app.emit(SYNC_STATUS_EVENT, status);
+    }
+
+    /// Refreshes the configuration part of the status from the database.
+    async fn load(&self, app: &AppHandle, db: &Db) -> Result<SyncConfig, AppError> {
+        let config = load_config(db).await?;
+        let last_sync_time = setting_i64(db, LAST_SYNC_SETTING).await?.filter(|t| *t > 0);
+        self.update(app, |s| {
+            s.configured = config.is_configured();
+            s.backend = config.backend;
+            s.url = config.url.clone();
+            s.username = config.username.clone();
+            s.interval_secs = config.interval_secs;
+            s.last_sync_time = last_sync_time;
+        });
+        Ok(config)
+    }
+
+    /// Runs one round and records the outcome in the status.
+    pub async fn run(&self, app: &AppHandle, db: &Db) -> Result<SyncOutcome, SyncError> {
+        let _round = self.round.lock().await;
+        let config = self.load(app, db).await?;
+        if !config.is_configured() {
+            return Err(SyncError::NotConfigured);
+        }
+
+        self.update(app, |s| s.state = SyncState::Syncing);
+        let key = self.data_key.lock().unwrap().clone();
+        let result = sync_once(db, &self.client, &config, key.as_ref()).await;
+        self.update(app, |s| match &result {
+            Ok(outcome) => {
+                s.state = SyncState::Idle;
+                s.last_sync_time = Some(outcome.server_time);
+                s.last_pushed = outcome.pushed;
+                s.last_pulled = outcome.pulled;
+                s.last_error = (outcome.quarantined > 0).then(|| {
+                    format!(
+                        "{} received rows could not be decrypted and were set aside",
+                        outcome.quarantined
+                    )
+                });
+                s.next_retry_at = None;
+            }
+            Err(e) => {
+                s.state = match e {
+                    SyncError::Offline(_) => SyncState::Offline,
+                    _ => SyncState::Error,
+                };
+                s.last_error = Some(e.to_string());
+            }
+        });
+        if result.as_ref().is_ok_and(|o| o.pushed + o.pulled > 0) {
+            app.state::<HistoryService>().batch.notify_one();
+        }
+        result
+    }
+
+    /// Drops the unlocked data key, e.g. when the local database locks.
+    pub fn forget_key(&self) {
+        *self.data_key.lock().unwrap() = None;
+    }
+
+    fn schedule_retry(&self, app: &AppHandle, delay: Option<Duration>) {
+        let at = delay.map(|d| now_millis() + d.as_millis() as i64);
+        self.update(app, |s| s.next_retry_at = at);
+    }
+}
+
+/// Starts the background loop: a round at startup, then one per interval,
+/// shortly after local changes, or on request. Failed rounds back off
+/// exponentially and ignore local changes until the backoff has elapsed.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut failures = 0u32;
+        loop {
+            let sync = app.state::<SyncService>();
+            let state = app.state::<DbState>();
+            // `peek` so that background rounds don't hold off auto-lock.
+            let Some(db) = state.peek() else {
+                state.opened.notified().await;
+                continue;
+            };
+            let db = &*db;
+
+            let interval = match sync.run(&app, db).await {
+                Ok(_) | Err(SyncError::NotConfigured) => {
+                    failures = 0;
+                    sync.schedule_retry(&app, None);
+                    load_config(db).await.unwrap_or_default().interval()
+                }
+                Err(_) => {
+                    failures = failures.saturating_add(1);
+                    let delay = backoff_delay(failures);
+                    sync.schedule_retry(&app, Some(delay));
+                    delay
+                }
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = sync.wake.notified() => {}
+                _ = state.opened.notified() => {}
+                _ = db.changes.notified(), if failures == 0 => {
+                    tokio::time::sleep(CHANGE_DEBOUNCE).await;
+                }
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn sync_now(
+    app: AppHandle,
+    state: tauri::State<'_, DbState>,
+    sync: tauri::State<'_, SyncService>,
+) -> Result<SyncStatus, AppError> {
+    let db = state.db()?;
+    sync.run(&app, &db).await?;
+    Ok(sync.status())
+}
+
+#[tauri::command]
+pub fn get_sync_status(sync: tauri::State<'_, SyncService>) -> SyncStatus {
+    sync.status()
+}
+
+/// Saves the server settings and wakes the background loop. An empty URL or
+/// key turns sync off.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn configure_sync(
+    app: AppHandle,
+    state: tauri::State<'_, DbState>,
+    sync: tauri::State<'_, SyncService>,
+    url: String,
+    key: String,
+    interval_secs: Option<u64>,
+    backend: Option<SyncBackend>,
+    username: Option<String>,
+) -> Result<SyncStatus, AppError> {
+    let db = state.db()?;
+    let config = SyncConfig {
+        backend: backend.unwrap_or_default(),
+        url: url.trim().trim_end_matches('/').to_string(),
+        username: username.unwrap_or_default().trim().to_string(),
+        key: key.trim().to_string(),
+        interval_secs: interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS),
+    };
+    save_config(&db, &config).await?;
+    sync.load(&app, &db).await?;
+    sync.wake.notify_one();
+    Ok(sync.status())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EncryptionStatus {
+    pub enabled: bool,
+    pub unlocked: bool,
+}
+
+fn check_passphrase(field: &str, passphrase: &str) -> Result<(), AppError> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(AppError::validation(
+            field,
+            format!("must be at least {} characters", MIN_PASSPHRASE_LEN),
+        ));
+    }
+    Ok(())
+}
+
+fn not_enabled() -> AppError {
+    AppError::conflict("Encryption is not enabled")
+}
+
+fn parse_envelope(raw: &str) -> Result<KeyEnvelope, AppError> {
+    let invalid = |e: &dyn std::fmt::Display| AppError::Remote {
+        message: format!("Invalid key envelope: {}", e),
+    };
+    let envelope: KeyEnvelope = serde_json::from_str(raw).map_err(|e| invalid(&e))?;
+    envelope.kdf.check().map_err(|e| invalid(&e))?;
+    Ok(envelope)
+}
+
+/// The server's copy of the envelope is authoritative for key changes, so
+/// they all start from it (and fail while offline).
+async fn server_envelope(
+    db: &Db,
+    sync: &SyncService,
+) -> Result<(SyncConfig, Option<(String, KeyEnvelope)>), AppError> {
+    let config = load_config(db).await?;
+    if !config.is_configured() {
+        return Err(AppError::NotConfigured);
+    }
+    let envelope = match transport(&sync.client, &config).fetch_envelope().await? {
+        Some(raw) => {
+            let envelope = parse_envelope(&raw)?;
+            Some((raw, envelope))
+        }
+        None => None,
+    };
+    Ok((config, envelope))
+}
+
+/// Writes a changed envelope to the server (guarded by the copy it was
+/// derived from), then locally, and keeps `key` unlocked.
+async fn replace_envelope(
+    db: &Db,
+    sync: &SyncService,
+    config: &SyncConfig,
+    previous: &str,
+    envelope: &KeyEnvelope,
+    key: DataKey,
+) -> Result<(), AppError> {
+    let raw = serde_json::to_string(envelope).map_err(|e| AppError::internal(e.to_string()))?;
+    transport(&sync.client, config)
+        .store_envelope(&raw, Some(previous))
+        .await?;
+    db.set_setting(ENVELOPE_SETTING, &raw).await?;
+    *sync.data_key.lock().unwrap() = Some(key);
+    Ok(())
+}
+
+async fn encryption_status(db: &Db, sync: &SyncService) -> Result<EncryptionStatus, AppError> {
+    Ok(EncryptionStatus {
+        enabled: db.get_setting(ENVELOPE_SETTING).await?.is_some(),
+        unlocked: sync.data_key.lock().unwrap().is_some(),
+    })
+}
+
+#[tauri::command]
+pub async fn get_encryption_status(
+    state: tauri::State<'_, DbState>,
+    sync: tauri::State<'_, SyncService>,
+) -> Result<EncryptionStatus, AppError> {
+    let db = state.db()?;
+    encryption_status(&db, &sync).await
+}
+
+/// Turns on end-to-end encryption for the sync account and returns the
+/// recovery key, which is shown once and never stored.
+#[tauri::command]
+pub async fn enable_encryption(
+    state: tauri::State<'_, DbState>,
+    sync: tauri::State<'_, SyncService>,
+    passphrase: String,
+) -> Result<String, AppError> {
+    let db = state.db()?;
+    check_passphrase("passphrase", &passphrase)?;
+    let _round = sync.round.lock().await;
+    let (config, existing) = server_envelope(&db, &sync).await?;
+    if existing.is_some() {
+        return Err(AppError::conflict(
+            "Encryption is already enabled for this account; unlock it with your passphrase",
+        ));
+    }
+
+    let (envelope, key, recovery_key) = KeyEnvelope::create(&passphrase, KdfParams::default())?;
+    let raw = serde_json::to_string(&envelope).map_err(|e| AppError::internal(e.to_string()))?;
+    transport(&sync.client, &config)
+        .store_envelope(&raw, None)
+        .await?;
+
+    // Re-upload everything so the server's plaintext copies get overwritten.
+    let mut conn = db.pool.begin().await?;
+    tx::set_setting(&mut conn, ENVELOPE_SETTING, &raw).await?;
+    tx::set_setting(&mut conn, REUPLOAD_SETTING, "1").await?;
+    conn.commit().await?;
+
+    *sync.data_key.lock().unwrap() = Some(key);
+    sync.wake.notify_one();
+    Ok(recovery_key)
+}
+
+#[tauri::command]
+pub async fn unlock_encryption(
+    state: tauri::State<'_, DbState>,
+    sync: tauri::State<'_, SyncService>,
+    passphrase: String,
+) -> Result<EncryptionStatus, AppError> {
+    let db = state.db()?;
+    let local = db.get_setting(ENVELOPE_SETTING).await?;
+    let local_key = match &local {
+        Some(raw) => parse_envelope(raw)?
+            .unlock(&passphrase)
+            .map_err(AppError::from),
+        None => Err(not_enabled()),
+    };
+
+    // The passphrase may have been changed on another device since this one
+    // last saw the envelope.
+    let key = match local_key {
+        Ok(key) => key,
+        Err(local_err) => match server_envelope(&db, &sync).await {
+            Ok((_, Some((raw, envelope)))) if Some(&raw) != local.as_ref() => {
+                let key = envelope.unlock(&passphrase)?;
+                db.set_setting(ENVELOPE_SETTING, &raw).await?;
+                key
+            }
+            _ => return Err(local_err),
+        },
+    };
+
+    *sync.data_key.lock().unwrap() = Some(key);
+    sync.wake.notify_one();
+    encryption_status(&db, &sync).await
+}
+
+#[tauri::command]
+pub async fn lock_encryption(
+    state: tauri::State<'_, DbState>,
+    sync: tauri::State<'_, SyncService>,
+) -> Result<EncryptionStatus, AppError> {
+    let db = state.db()?;
+    sync.forget_key();
+    encryption_status(&db, &sync).await
+}
+
+/// Re-wraps the data key under a new passphrase; nothing is re-encrypted.
+#[tauri::command]
+pub async fn change_encryption_passphrase(
+    state: tauri::State<'_, DbState>,
+    sync: tauri::State<'_, SyncService>,
+    current_passphrase: String,
+    new_passphrase: String,
+) -> Result<(), AppError> {
+    let db = state.db()?;
+    check_passphrase("new_passphrase", &new_passphrase)?;
+    let (config, existing) = server_envelope(&db, &sync).await?;
+    let (raw, envelope) = existing.ok_or_else(not_enabled)?;
+    let key = envelope.unlock(&current_passphrase).map_err(|e| match e {
+        CryptoError::WrongPassphrase => AppError::validation("current_passphrase", e.to_string()),
+        e => e.into(),
+    })?;
+    let updated = envelope.rewrap(&key, &new_passphrase)?;
+    replace_envelope(&db, &sync, &config, &raw, &updated, key).await
+}
+
+/// Sets a new passphrase using the recovery key, for when the passphrase is lost.
+#[tauri::command]
+pub async fn reset_passphrase_with_recovery_key(
+    state: tauri::State<'_, DbState>,
+    sync: tauri::State<'_, SyncService>,
+    recovery_key: String,
+    new_passphrase: String,
+) -> Result<(), AppError> {
+    let db = state.db()?;
+    check_passphrase("new_passphrase", &new_passphrase)?;
+    let (config, existing) = server_envelope(&db, &sync).await?;
+    let (raw, envelope) = existing.ok_or_else(not_enabled)?;
+    let key = envelope.unlock_with_recovery_key(&recovery_key)?;
+    let updated = envelope.rewrap(&key, &new_passphrase)?;
+    replace_envelope(&db, &sync, &config, &raw, &updated, key).await?;
+    sync.wake.notify_one();
+    Ok(())
+}
+
+/// Issues a fresh recovery key for export; the previous one stops working.
+#[tauri::command]
+pub async fn export_recovery_key(
+    state: tauri::State<'_, DbState>,
+    sync: tauri::State<'_, SyncService>,
+    passphrase: String,
+) -> Result<String, AppError> {
+    let db = state.db()?;
+    let (config, existing) = server_envelope(&db, &sync).await?;
+    let (raw, envelope) = existing.ok_or_else(not_enabled)?;
+    let key = envelope.unlock(&passphrase)?;
+    let (updated, recovery_key) = envelope.reset_recovery_key(&key)?;
+    replace_envelope(&db, &sync, &config, &raw, &updated, key).await?;
+    Ok(recovery_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_db;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    async fn setup_test_db() -> Db {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Db::new(init_db(dir).await.unwrap())
+    }
+
+    /// Answers one HTTP request per `(status, body)` in order and returns
+    /// the raw requests.
+    async fn serve(
+        responses: Vec<(u16, String)>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut chunk = [0u8; 4096];
+                loop {
+                    let n = socket.read(&mut chunk).await.unwrap();
+                    request.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&request).to_lowercase();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length: usize = text[..end]
+                            .lines()
+                            .find_map(|l| l.strip_prefix("content-length:"))
+                            .map(|v| v.trim().parse().unwrap())
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn request_body(request: &str) -> serde_json::Value {
+        serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..]).unwrap()
+    }
+
+    fn config(url: &str) -> SyncConfig {
+        SyncConfig {
+            url: url.to_string(),
+            key: "secret".to_string(),
+            interval_secs: DEFAULT_INTERVAL_SECS,
+            ..SyncConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff_delay_grows_and_caps() {
+        assert_eq!(backoff_delay(1), Duration::from_secs(5));
+        assert_eq!(backoff_delay(2), Duration::from_secs(10));
+        assert_eq!(backoff_delay(4), Duration::from_secs(40));
+        assert_eq!(backoff_delay(100), Duration::from_secs(BACKOFF_MAX_SECS));
+    }
+
+    #[tokio::test]
+    async fn test_sync_pushes_local_changes_and_applies_remote_rows() {
+        let db = setup_test_db().await;
+        db.upsert_note(NoteRecord {
+            id: "local".to_string(),
+            title: "Local".to_string(),
+            content: "mine".to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        })
+        .await
+        .unwrap();
+
+        let remote = serde_json::json!({
+            "server_time": 5000,
+            "notes": [{
+                "id": "remote", "title": "Remote", "content": "theirs", "folder_id": "f1",
+                "workspace_id": "default", "created_at": null, "updated_at": 4000, "is_deleted": false
+            }],
+            "folders": [{
+                "id": "f1", "name": "Shared", "parent_id": null, "workspace_id": "default",
+                "created_at": 3000, "updated_at": 4000, "is_deleted": false
+            }],
+            "workspaces": []
+        });
+        let (url, server) = serve(vec![(404, String::new()), (200, remote.to_string())]).await;
+        let outcome = sync_once(&db, &reqwest::Client::new(), &config(&url), None)
+            .await
+            .unwrap();
+        let requests = server.await.unwrap();
+
+        assert!(requests[0].starts_with("GET /keys"));
+        assert!(requests[1].to_lowercase().contains("x-sync-key: secret"));
+        let body = request_body(&requests[1]);
+        assert_eq!(body["last_sync_time"], 0);
+        assert!(body["notes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|n| n["id"] == "local"));
+        assert_eq!(outcome.server_time, 5000);
+        assert_eq!(outcome.pulled, 2);
+
+        let notes = db.get_notes().await.unwrap();
+        let pulled = notes.iter().find(|n| n.id == "remote").unwrap();
+        assert_eq!(pulled.content, "theirs");
+        assert_eq!(pulled.created_at, 4000);
+        assert_eq!(db.get_folders().await.unwrap()[0].name, "Shared");
+        assert_eq!(
+            setting_i64(&db, LAST_SYNC_SETTING).await.unwrap(),
+            Some(5000)
+        );
+
+        // Nothing changed locally since, so the next round pushes nothing.
+        let empty = serde_json::json!({ "server_time": 6000, "notes": [], "folders": [], "workspaces": [] });
+        let (url, server) = serve(vec![(404, String::new()), (200, empty.to_string())]).await;
+        let outcome = sync_once(&db, &reqwest::Client::new(), &config(&url), None)
+            .await
+            .unwrap();
+        let requests = server.await.unwrap();
+        assert_eq!(request_body(&requests[1])["last_sync_time"], 5000);
+        assert_eq!(outcome.pushed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_is_offline() {
+        let db = setup_test_db().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let err = sync_once(&db, &reqwest::Client::new(), &config(&url), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SyncError::Offline(_)), "{:?}", err);
+        assert_eq!(setting_i64(&db, LAST_SYNC_SETTING).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_changing_server_resets_watermarks() {
+        let db = setup_test_db().await;
+        save_config(&db, &config("http://a")).await.unwrap();
+        db.set_setting(LAST_SYNC_SETTING, "5000").await.unwrap();
+
+        let mut same = config("http://a");
+        same.interval_secs = 60;
+        save_config(&db, &same).await.unwrap();
+        assert_eq!(
+            setting_i64(&db, LAST_SYNC_SETTING).await.unwrap(),
+            Some(5000)
+        );
+        assert_eq!(load_config(&db).await.unwrap(), same);
+
+        save_config(&db, &config("http://b")).await.unwrap();
+        assert_eq!(setting_i64(&db, LAST_SYNC_SETTING).await.unwrap(), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_reupload_pushes_every_row_once_without_touching_it() {
+        let db = setup_test_db().await;
+        db.upsert_note(NoteRecord {
+            id: "local".to_string(),
+            title: "Local".to_string(),
+            content: "mine".to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        })
+        .await
+        .unwrap();
+        let before = db.get_notes().await.unwrap()[0].updated_at;
+        db.set_setting(PUSHED_UNTIL_SETTING, &(now_millis() + 60_000).to_string())
+            .await
+            .unwrap();
+        db.set_setting(REUPLOAD_SETTING, "1").await.unwrap();
+
+        let empty = serde_json::json!({ "server_time": 5000, "notes": [], "folders": [], "workspaces": [] });
+        let (url, server) = serve(vec![(404, String::new()), (200, empty.to_string())]).await;
+        sync_once(&db, &reqwest::Client::new(), &config(&url), None)
+            .await
+            .unwrap();
+        let body = request_body(&server.await.unwrap()[1]);
+        assert_eq!(body["replace"], true);
+        assert_eq!(body["notes"][0]["id"], "local");
+        assert_eq!(body["notes"][0]["updated_at"], before);
+        assert_eq!(db.get_notes().await.unwrap()[0].updated_at, before);
+
+        let (url, server) = serve(vec![(404, String::new()), (200, empty.to_string())]).await;
+        let outcome = sync_once(&db, &reqwest::Client::new(), &config(&url), None)
+            .await
+            .unwrap();
+        let body = request_body(&server.await.unwrap()[1]);
+        assert_eq!(body["replace"], false);
+        assert_eq!(outcome.pushed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_sync_never_sends_plaintext() {
+        let db = setup_test_db().await;
+        db.upsert_note(NoteRecord {
+            id: "local".to_string(),
+            title: "Diary".to_string(),
+            content: "dear diary".to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        })
+        .await
+        .unwrap();
+
+        let key = DataKey::generate();
+        let envelope = serde_json::json!({ "envelope": "{}" }).to_string();
+        let mut remote_note = NoteRecord {
+            id: "remote".to_string(),
+            title: "Remote".to_string(),
+            content: "from the other laptop".to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 1000,
+            updated_at: 1000,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        };
+        crypto::encrypt_note(&key, &mut remote_note).unwrap();
+        let remote = serde_json::json!({
+            "server_time": 2000, "notes": [remote_note], "folders": [], "workspaces": []
+        });
+
+        // Another device enabled encryption: the envelope shows up and the
+        // round stops until the key is unlocked.
+        let (url, server) = serve(vec![(200, envelope)]).await;
+        let err = sync_once(&db, &reqwest::Client::new(), &config(&url), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SyncError::Locked), "{:?}", err);
+        server.await.unwrap();
+        assert!(db.get_setting(ENVELOPE_SETTING).await.unwrap().is_some());
+
+        let (url, server) = serve(vec![(200, remote.to_string())]).await;
+        sync_once(&db, &reqwest::Client::new(), &config(&url), Some(&key))
+            .await
+            .unwrap();
+        let requests = server.await.unwrap();
+
+        assert!(!requests[0].contains("diary") && !requests[0].contains("Diary"));
+        let pushed: NoteRecord =
+            serde_json::from_value(request_body(&requests[0])["notes"][0].clone()).unwrap();
+        let local = db.get_notes().await.unwrap();
+        let local = local.iter().find(|n| n.id == "local").unwrap();
+        assert_eq!(pushed.updated_at, local.updated_at);
+        let mut decrypted = pushed;
+        crypto::decrypt_note(&key, &mut decrypted).unwrap();
+        assert_eq!(decrypted.content, "dear diary");
+
+        let notes = db.get_notes().await.unwrap();
+        let pulled = notes.iter().find(|n| n.id == "remote").unwrap();
+        assert_eq!(pulled.content, "from the other laptop");
+    }
+
+    #[tokio::test]
+    async fn test_rows_that_fail_to_decrypt_are_quarantined() {
+        let db = setup_test_db().await;
+        db.set_setting(ENVELOPE_SETTING, "{}").await.unwrap();
+        let key = DataKey::generate();
+        let mut good = NoteRecord {
+            id: "good".to_string(),
+            title: "Good".to_string(),
+            content: "kept".to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 1000,
+            updated_at: 1000,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        };
+        // Plaintext slipped in by the server
+        let injected = NoteRecord {
+            id: "injected".to_string(),
+            title: "Click me".to_string(),
+            ..good.clone()
+        };
+        crypto::encrypt_note(&key, &mut good).unwrap();
+        let remote = serde_json::json!({
+            "server_time": 2000, "notes": [injected, good], "folders": [], "workspaces": []
+        });
+
+        let (url, server) = serve(vec![(200, remote.to_string())]).await;
+        let outcome = sync_once(&db, &reqwest::Client::new(), &config(&url), Some(&key))
+            .await
+            .unwrap();
+        server.await.unwrap();
+
+        assert_eq!(outcome.quarantined, 1);
+        let notes = db.get_notes().await.unwrap();
+        assert!(notes.iter().any(|n| n.id == "good" && n.content == "kept"));
+        assert!(!notes.iter().any(|n| n.id == "injected"));
+        let kept = db
+            .get_setting(&format!("{}note.injected", QUARANTINE_PREFIX))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(kept.contains("Click me"));
+        assert_eq!(
+            setting_i64(&db, LAST_SYNC_SETTING).await.unwrap(),
+            Some(2000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pulled_children_may_arrive_before_their_parents() {
+        let db = setup_test_db().await;
+        db.set_setting(ENVELOPE_SETTING, "{}").await.unwrap();
+        let key = DataKey::generate();
+        let folder = |id: &str, parent_id: Option<&str>| FolderRecord {
+            id: id.to_string(),
+            name: id.to_string(),
+            parent_id: parent_id.map(str::to_string),
+            workspace_id: "default".to_string(),
+            created_at: 1000,
+            updated_at: 1000,
+            version: 1,
+            color: None,
+            sort_key: String::new(),
+            is_deleted: false,
+        };
+        let note = |id: &str, folder_id: &str| NoteRecord {
+            id: id.to_string(),
+            title: id.to_string(),
+            content: String::new(),
+            folder_id: Some(folder_id.to_string()),
+            workspace_id: "default".to_string(),
+            created_at: 1000,
+            updated_at: 1000,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        };
+        let mut folders = vec![folder("child", Some("parent")), folder("parent", None)];
+        for folder in &mut folders {
+            crypto::encrypt_folder(&key, folder).unwrap();
+        }
+        // Fails to decrypt, and takes the note inside it along
+        folders.push(folder("broken", None));
+        let mut notes = vec![note("inside", "child"), note("orphan", "broken")];
+        for note in &mut notes {
+            crypto::encrypt_note(&key, note).unwrap();
+        }
+        let remote = serde_json::json!({
+            "server_time": 2000, "notes": notes, "folders": folders, "workspaces": []
+        });
+
+        let (url, server) = serve(vec![(200, remote.to_string())]).await;
+        let outcome = sync_once(&db, &reqwest::Client::new(), &config(&url), Some(&key))
+            .await
+            .unwrap();
+        server.await.unwrap();
+
+        assert_eq!(outcome.quarantined, 2);
+        let mut folders: Vec<String> = db
+            .get_folders()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|f| f.id)
+            .collect();
+        folders.sort();
+        assert_eq!(folders, ["child", "parent"]);
+        let notes: Vec<String> = db
+            .get_notes()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|n| n.id)
+            .collect();
+        assert_eq!(notes, ["inside"]);
+        assert!(db
+            .get_setting(&format!("{}note.orphan", QUARANTINE_PREFIX))
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
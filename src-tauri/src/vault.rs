@@ -0,0 +1,402 @@
+//! Optional encryption at rest for the local database. The file is a regular
+//! SQLite database until encryption is enabled, after which it is a SQLCipher
+//! database that stays locked (no pool) until the passphrase is entered.
+
+use crate::db::{connect, open_db, Db, DbState, DB_FILE};
+use crate::error::AppError;
+use crate::sync::SyncService;
+use serde::Serialize;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+
+/// Emitted when the database is locked, manually or after inactivity.
+pub const DATABASE_LOCKED_EVENT: &str = "database-locked";
+
+const AUTO_LOCK_SETTING: &str = "vault.auto_lock_minutes";
+const AUTO_LOCK_CHECK: Duration = Duration::from_secs(15);
+const MIN_PASSPHRASE_LEN: usize = 8;
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// A plain SQLite file starts with a fixed header; a SQLCipher file is
+/// indistinguishable from random bytes. Missing or empty files count as plain.
+pub fn is_encrypted(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    match fs::File::open(path).and_then(|mut f| f.read_exact(&mut header)) {
+        Ok(()) => &header != SQLITE_HEADER,
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseStatus {
+    pub encrypted: bool,
+    pub locked: bool,
+    /// Only known while unlocked, since it is stored inside the database.
+    pub auto_lock_minutes: Option<u32>,
+}
+
+async fn status(state: &DbState) -> Result<DatabaseStatus, AppError> {
+    let db = state.peek();
+    let auto_lock_minutes = match &db {
+        Some(db) => auto_lock_minutes(db).await?,
+        None => None,
+    };
+    Ok(DatabaseStatus {
+        encrypted: is_encrypted(&state.path()),
+        locked: db.is_none(),
+        auto_lock_minutes,
+    })
+}
+
+async fn auto_lock_minutes(db: &Db) -> Result<Option<u32>, AppError> {
+    Ok(db
+        .get_setting(AUTO_LOCK_SETTING)
+        .await?
+        .and_then(|v| v.parse().ok())
+        .filter(|m| *m > 0))
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), AppError> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(AppError::validation(
+            "passphrase",
+            format!("must be at least {} characters", MIN_PASSPHRASE_LEN),
+        ));
+    }
+    Ok(())
+}
+
+/// SQLCipher only notices a wrong key on the first read, which fails with
+/// SQLITE_NOTADB.
+pub fn open_error(e: sqlx::Error) -> AppError {
+    match e.as_database_error().and_then(|d| d.code()) {
+        Some(code) if code == "26" => wrong_passphrase(),
+        _ => e.into(),
+    }
+}
+
+fn wrong_passphrase() -> AppError {
+    AppError::validation("passphrase", "Wrong passphrase")
+}
+
+/// Fails with "Wrong passphrase" unless `passphrase` opens the file.
+async fn check_key(path: &Path, passphrase: Option<&str>) -> Result<(), AppError> {
+    let pool = connect(path, passphrase).await.map_err(open_error)?;
+    let result = sqlx::query("SELECT count(*) FROM sqlite_master")
+        .fetch_one(&pool)
+        .await;
+    pool.close().await;
+    result.map(|_| ()).map_err(open_error)
+}
+
+/// Row counts of every table, used to check that a migrated copy is complete.
+async fn row_counts(conn: &mut SqliteConnection) -> Result<Vec<(String, i64)>, AppError> {
+    let tables: Vec<(String,)> = sqlx::query_as(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut counts = Vec::with_capacity(tables.len());
+    for (table,) in tables {
+        let (count,): (i64,) = sqlx::query_as(&format!(
+            "SELECT count(*) FROM \"{}\"",
+            table.replace('"', "\"\"")
+        ))
+        .fetch_one(&mut *conn)
+        .await?;
+        counts.push((table, count));
+    }
+    Ok(counts)
+}
+
+/// Copies the database into `target` under the new key (empty for plaintext)
+/// with `sqlcipher_export` and checks the copy before anything is replaced.
+pub(crate) async fn export(
+    source: &Pool<Sqlite>,
+    target: &Path,
+    to: Option<&str>,
+) -> Result<(), AppError> {
+    let mut conn = source.acquire().await?;
+    sqlx::query("ATTACH DATABASE ?1 AS migrated KEY ?2")
+        .bind(target.to_string_lossy())
+        .bind(to.unwrap_or(""))
+        .execute(&mut *conn)
+        .await?;
+    let exported = sqlx::query("SELECT sqlcipher_export('migrated')")
+        .execute(&mut *conn)
+        .await;
+    sqlx::query("DETACH DATABASE migrated")
+        .execute(&mut *conn)
+        .await?;
+    exported.map_err(|e| AppError::Database {
+        message: format!("Failed to migrate database: {}", e),
+    })?;
+    let expected = row_counts(&mut conn).await?;
+    drop(conn);
+
+    let copy = connect(target, to).await?;
+    let verified = async {
+        let mut conn = copy.acquire().await?;
+        let (check,): (String,) = sqlx::query_as("PRAGMA quick_check")
+            .fetch_one(&mut *conn)
+            .await?;
+        if check != "ok" {
+            return Err(AppError::Database {
+                message: format!("Migrated database failed its integrity check: {}", check),
+            });
+        }
+        if row_counts(&mut conn).await? != expected {
+            return Err(AppError::Database {
+                message: "Migrated database does not match the original".to_string(),
+            });
+        }
+        Ok(())
+    }
+    .await;
+    copy.close().await;
+    verified
+}
+
+/// Re-encrypts the database file under `to` (None = plaintext). The pool is
+/// closed for the duration so no write can land in the old file after the
+/// copy; the copy replaces the original with an atomic rename, and on any
+/// failure the original is reopened untouched.
+async fn migrate(state: &DbState, from: Option<&str>, to: Option<&str>) -> Result<(), AppError> {
+    let path = state.path();
+    let temp = state.app_dir.join(format!("{}.migrating", DB_FILE));
+    check_key(&path, from).await?;
+
+    state.close().await;
+    let result = async {
+        let _ = fs::remove_file(&temp);
+        let source = connect(&path, from).await?;
+        let exported = export(&source, &temp, to).await;
+        source.close().await;
+        exported?;
+        fs::File::open(&temp)
+            .and_then(|f| f.sync_all())
+            .and_then(|_| fs::rename(&temp, &path))
+            .map_err(|e| AppError::io(&path, e))
+    }
+    .await;
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    let key = if result.is_ok() { to } else { from };
+    let pool = open_db(&state.app_dir, key).await.map_err(open_error)?;
+    state.open(Db::new(pool), key);
+    result
+}
+
+async fn unlock(state: &DbState, passphrase: &str) -> Result<(), AppError> {
+    if state.peek().is_some() {
+        return Ok(());
+    }
+    let pool = open_db(&state.app_dir, Some(passphrase))
+        .await
+        .map_err(open_error)?;
+    state.open(Db::new(pool), Some(passphrase));
+    Ok(())
+}
+
+async fn lock(app: &AppHandle, state: &DbState) {
+    state.close().await;
+    if let Some(sync) = app.try_state::<SyncService>() {
+        sync.forget_key();
+    }
+    let _ = app.emit(DATABASE_LOCKED_EVENT, ());
+}
+
+/// Starts the auto-lock timer. Only user commands count as activity, so
+/// background sync does not keep the database open.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(AUTO_LOCK_CHECK).await;
+            let state = app.state::<DbState>();
+            let Some(db) = state.peek() else {
+                continue;
+            };
+            let minutes = auto_lock_minutes(&db).await.unwrap_or(None);
+            drop(db);
+            if let Some(minutes) = minutes {
+                let idle = state.idle_millis() >= i64::from(minutes) * 60_000;
+                if idle && is_encrypted(&state.path()) {
+                    lock(&app, &state).await;
+                }
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_database_status(
+    state: tauri::State<'_, DbState>,
+) -> Result<DatabaseStatus, AppError> {
+    status(&state).await
+}
+
+#[tauri::command]
+pub async fn unlock_database(
+    state: tauri::State<'_, DbState>,
+    passphrase: String,
+) -> Result<DatabaseStatus, AppError> {
+    unlock(&state, &passphrase).await?;
+    status(&state).await
+}
+
+#[tauri::command]
+pub async fn lock_database(
+    app: AppHandle,
+    state: tauri::State<'_, DbState>,
+) -> Result<DatabaseStatus, AppError> {
+    if !is_encrypted(&state.path()) {
+        return Err(AppError::conflict("Database encryption is not enabled"));
+    }
+    lock(&app, &state).await;
+    status(&state).await
+}
+
+#[tauri::command]
+pub async fn enable_database_encryption(
+    state: tauri::State<'_, DbState>,
+    passphrase: String,
+) -> Result<DatabaseStatus, AppError> {
+    check_passphrase(&passphrase)?;
+    state.db()?;
+    if is_encrypted(&state.path()) {
+        return Err(AppError::conflict("Database encryption is already enabled"));
+    }
+    migrate(&state, None, Some(&passphrase)).await?;
+    crate::backup::encrypt_plaintext(&state.app_dir, &passphrase).await?;
+    status(&state).await
+}
+
+/// Decrypts the database back to a plain SQLite file. Asks for the passphrase
+/// again even though the database is unlocked.
+#[tauri::command]
+pub async fn disable_database_encryption(
+    state: tauri::State<'_, DbState>,
+    passphrase: String,
+) -> Result<DatabaseStatus, AppError> {
+    state.db()?;
+    if !is_encrypted(&state.path()) {
+        return Err(AppError::conflict("Database encryption is not enabled"));
+    }
+    migrate(&state, Some(&passphrase), None).await?;
+    status(&state).await
+}
+
+/// Sets the inactivity timeout; `None` or 0 turns auto-lock off.
+#[tauri::command]
+pub async fn set_auto_lock(
+    state: tauri::State<'_, DbState>,
+    minutes: Option<u32>,
+) -> Result<DatabaseStatus, AppError> {
+    let db = state.db()?;
+    db.set_setting(AUTO_LOCK_SETTING, &minutes.unwrap_or(0).to_string())
+        .await?;
+    status(&state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_db, NoteRecord};
+    use crate::error::LockTarget;
+
+    async fn setup() -> DbState {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(init_db(dir.clone()).await.unwrap());
+        db.upsert_note(NoteRecord {
+            id: "n1".to_string(),
+            title: "Secret".to_string(),
+            content: "findable words".to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 1,
+            updated_at: 1,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        })
+        .await
+        .unwrap();
+        DbState::new(dir, Some(db))
+    }
+
+    #[test]
+    fn test_missing_or_empty_file_is_plain() {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(DB_FILE);
+        assert!(!is_encrypted(&path));
+        fs::write(&path, b"").unwrap();
+        assert!(!is_encrypted(&path));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_enable_lock_unlock_disable_round_trip() {
+        let state = setup().await;
+        migrate(&state, None, Some("correct horse")).await.unwrap();
+        assert!(is_encrypted(&state.path()));
+        assert!(!state.app_dir.join("lumenote.db.migrating").exists());
+        assert!(!fs::read(state.path())
+            .unwrap()
+            .windows(14)
+            .any(|w| w == b"findable words"));
+
+        let notes = state.db().unwrap().get_notes().await.unwrap();
+        assert_eq!(notes.len(), 1);
+        let hits = state
+            .db()
+            .unwrap()
+            .search_notes("findable".to_string())
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+
+        state.close().await;
+        assert_eq!(
+            state.db().err().unwrap(),
+            AppError::Locked {
+                target: LockTarget::Database
+            }
+        );
+        assert_eq!(
+            unlock(&state, "wrong horse").await.unwrap_err(),
+            wrong_passphrase()
+        );
+        unlock(&state, "correct horse").await.unwrap();
+        assert_eq!(state.db().unwrap().get_notes().await.unwrap().len(), 1);
+
+        // A wrong passphrase leaves the database encrypted and open.
+        assert_eq!(
+            migrate(&state, Some("wrong horse"), None)
+                .await
+                .unwrap_err(),
+            wrong_passphrase()
+        );
+        assert!(state.db().is_ok());
+
+        migrate(&state, Some("correct horse"), None).await.unwrap();
+        assert!(!is_encrypted(&state.path()));
+        assert_eq!(
+            state.db().unwrap().get_notes().await.unwrap()[0].title,
+            "Secret"
+        );
+
+        state.close().await;
+        let _ = fs::remove_dir_all(&state.app_dir);
+    }
+}
@@ -0,0 +1,547 @@
+//! Sync through a WebDAV collection (Nextcloud, ownCloud, a NAS...) instead
+//! of the Lumenote server. The collection holds plain files:
+//!
+//! - `log/<seq>.json`: the rows one device pushed in one round. Entries are
+//!   never changed once written, and each is created with
+//!   `If-None-Match: *`, so two devices can never take the same number; the
+//!   one that loses reads the other's entry and takes the next number.
+//! - `snapshots/<seq>.json`: every row as of entry `seq`, written every
+//!   `SNAPSHOT_EVERY` entries so a new device does not replay the whole log.
+//! - `head.json`: the last entry and snapshot, replaced with `If-Match` on
+//!   its ETag. Only a hint: readers keep going past it until an entry is
+//!   missing, so a device that stops between writing an entry and the head
+//!   loses nothing.
+//! - `keys.json`: the encryption key envelope, replaced with `If-Match`.
+//!
+//! Rows are merged with last-writer-wins on `updated_at`, like the server.
+
+use crate::db::{now_millis, FolderRecord, NoteRecord, WorkspaceRecord};
+use crate::sync::{key_changed, Pulled, SyncConfig, SyncError, SyncRows, SyncTransport};
+use reqwest::header::{ETAG, IF_MATCH, IF_NONE_MATCH};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Last log entry a device has read.
+pub const CURSOR_SETTING: &str = "sync.webdav_seq";
+
+const HEAD_FILE: &str = "head.json";
+const KEYS_FILE: &str = "keys.json";
+const LOG_DIR: &str = "log";
+const SNAPSHOT_DIR: &str = "snapshots";
+
+const SNAPSHOT_EVERY: i64 = 50;
+
+/// Entry numbers tried per round before giving up to other devices.
+const MAX_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Head {
+    seq: i64,
+    #[serde(default)]
+    snapshot: Option<i64>,
+}
+
+/// A log entry or snapshot.
+#[derive(Debug, Serialize, Deserialize)]
+struct Batch {
+    seq: i64,
+    written_at: i64,
+    /// The rows win over earlier ones with the same `updated_at`.
+    #[serde(default)]
+    replace: bool,
+    #[serde(flatten)]
+    rows: SyncRows,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Keys {
+    envelope: String,
+}
+
+/// What a PUT requires of the file it replaces.
+#[derive(Clone, Copy)]
+enum Expect<'a> {
+    /// The file must not exist yet.
+    Absent,
+    /// The file must still have this ETag.
+    Tag(&'a str),
+    /// Anything; for a file that exists on a server that sent no ETag.
+    Any,
+}
+
+impl<'a> Expect<'a> {
+    /// Expects the file to be as `get` found it.
+    fn found<T>(found: &'a Option<(T, Option<String>)>) -> Self {
+        match found {
+            None => Expect::Absent,
+            Some((_, Some(etag))) => Expect::Tag(etag),
+            Some((_, None)) => Expect::Any,
+        }
+    }
+}
+
+fn log_path(seq: i64) -> String {
+    format!("{}/{:012}.json", LOG_DIR, seq)
+}
+
+fn snapshot_path(seq: i64) -> String {
+    format!("{}/{:012}.json", SNAPSHOT_DIR, seq)
+}
+
+/// Adds `rows` to `into`, keeping the newest version of each row; on a tie
+/// the row already there stays unless `replace` is set.
+fn merge_rows<T>(into: &mut Vec<T>, rows: Vec<T>, replace: bool, key: fn(&T) -> (&str, i64)) {
+    let mut index: HashMap<String, usize> = into
+        .iter()
+        .enumerate()
+        .map(|(i, row)| (key(row).0.to_string(), i))
+        .collect();
+    for row in rows {
+        let (id, updated_at) = key(&row);
+        match index.get(id) {
+            Some(&i) if key(&into[i]).1 > updated_at => {}
+            Some(&i) if key(&into[i]).1 == updated_at && !replace => {}
+            Some(&i) => into[i] = row,
+            None => {
+                index.insert(id.to_string(), into.len());
+                into.push(row);
+            }
+        }
+    }
+}
+
+fn merge(into: &mut SyncRows, batch: Batch) {
+    let Batch { rows, replace, .. } = batch;
+    merge_rows(&mut into.notes, rows.notes, replace, |n: &NoteRecord| {
+        (n.id.as_str(), n.updated_at)
+    });
+    merge_rows(
+        &mut into.folders,
+        rows.folders,
+        replace,
+        |f: &FolderRecord| (f.id.as_str(), f.updated_at),
+    );
+    merge_rows(
+        &mut into.workspaces,
+        rows.workspaces,
+        replace,
+        |w: &WorkspaceRecord| (w.id.as_str(), w.updated_at),
+    );
+}
+
+pub struct WebDavTransport<'a> {
+    client: &'a reqwest::Client,
+    config: &'a SyncConfig,
+}
+
+impl<'a> WebDavTransport<'a> {
+    pub fn new(client: &'a reqwest::Client, config: &'a SyncConfig) -> Self {
+        Self { client, config }
+    }
+
+    fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        let url = format!("{}/{}", self.config.url.trim_end_matches('/'), path);
+        self.client
+            .request(method, url)
+            .basic_auth(&self.config.username, Some(&self.config.key))
+    }
+
+    fn failed(method: &str, path: &str, status: StatusCode) -> SyncError {
+        SyncError::Server(format!("WebDAV {} {} failed ({})", method, path, status))
+    }
+
+    /// Reads a JSON file and its ETag; `None` when it does not exist.
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<Option<(T, Option<String>)>, SyncError> {
+        let response = self.request(Method::GET, path).send().await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            status if !status.is_success() => return Err(Self::failed("GET", path, status)),
+            _ => {}
+        }
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let value = response
+            .json()
+            .await
+            .map_err(|e| SyncError::Server(format!("Unreadable WebDAV file {}: {}", path, e)))?;
+        Ok(Some((value, etag)))
+    }
+
+    /// Writes a JSON file if it is still as expected. Returns false when
+    /// another device got there first.
+    async fn put<T: Serialize>(
+        &self,
+        path: &str,
+        value: &T,
+        expect: Expect<'_>,
+    ) -> Result<bool, SyncError> {
+        let body = serde_json::to_vec(value).map_err(|e| SyncError::Local(e.to_string()))?;
+        let request = self
+            .request(Method::PUT, path)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+        let request = match expect {
+            Expect::Absent => request.header(IF_NONE_MATCH, "*"),
+            Expect::Tag(etag) => request.header(IF_MATCH, etag),
+            Expect::Any => request,
+        };
+        match request.send().await?.status() {
+            StatusCode::PRECONDITION_FAILED => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(Self::failed("PUT", path, status)),
+        }
+    }
+
+    /// Creates the collection and its folders; existing ones answer 405.
+    /// Runs before writing to a collection without a head, as some servers
+    /// answer a conditional PUT into a missing folder with 412, which would
+    /// look like losing a race.
+    async fn create_collections(&self) -> Result<(), SyncError> {
+        let mkcol = Method::from_bytes(b"MKCOL").expect("valid method");
+        for path in ["", LOG_DIR, SNAPSHOT_DIR] {
+            let status = self.request(mkcol.clone(), path).send().await?.status();
+            if !status.is_success() && status != StatusCode::METHOD_NOT_ALLOWED {
+                return Err(Self::failed("MKCOL", path, status));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads entries after `cursor` into `rows` until one is missing and
+    /// returns the last one read.
+    async fn read_log(&self, mut cursor: i64, rows: &mut SyncRows) -> Result<i64, SyncError> {
+        while let Some((batch, _)) = self.get::<Batch>(&log_path(cursor + 1)).await? {
+            merge(rows, batch);
+            cursor += 1;
+        }
+        Ok(cursor)
+    }
+
+    /// Writes a snapshot of every row up to entry `seq`, built from the
+    /// previous snapshot and the entries since.
+    async fn write_snapshot(&self, previous: Option<i64>, seq: i64) -> Result<(), SyncError> {
+        let mut rows = SyncRows::default();
+        let mut from = 0;
+        if let Some(previous) = previous {
+            if let Some((batch, _)) = self.get::<Batch>(&snapshot_path(previous)).await? {
+                rows = batch.rows;
+                from = previous;
+            }
+        }
+        for entry in from + 1..=seq {
+            if let Some((batch, _)) = self.get::<Batch>(&log_path(entry)).await? {
+                merge(&mut rows, batch);
+            }
+        }
+        let snapshot = Batch {
+            seq,
+            written_at: now_millis(),
+            replace: false,
+            rows,
+        };
+        // Another device writing the same snapshot is just as good
+        self.put(&snapshot_path(seq), &snapshot, Expect::Absent)
+            .await
+            .map(|_| ())
+    }
+}
+
+impl SyncTransport for WebDavTransport<'_> {
+    fn cursor_setting(&self) -> &'static str {
+        CURSOR_SETTING
+    }
+
+    async fn fetch_envelope(&self) -> Result<Option<String>, SyncError> {
+        Ok(self
+            .get::<Keys>(KEYS_FILE)
+            .await?
+            .map(|(keys, _)| keys.envelope))
+    }
+
+    async fn store_envelope(
+        &self,
+        envelope: &str,
+        previous: Option<&str>,
+    ) -> Result<(), SyncError> {
+        let found = self.get::<Keys>(KEYS_FILE).await?;
+        if found.as_ref().map(|(keys, _)| keys.envelope.as_str()) != previous {
+            return Err(key_changed());
+        }
+        if found.is_none() && self.get::<Head>(HEAD_FILE).await?.is_none() {
+            self.create_collections().await?;
+        }
+        let keys = Keys {
+            envelope: envelope.to_string(),
+        };
+        match self.put(KEYS_FILE, &keys, Expect::found(&found)).await? {
+            true => Ok(()),
+            false => Err(key_changed()),
+        }
+    }
+
+    async fn exchange(
+        &self,
+        since: i64,
+        rows: SyncRows,
+        replace: bool,
+    ) -> Result<Pulled, SyncError> {
+        let found = self.get::<Head>(HEAD_FILE).await?;
+        let head = found.as_ref().map(|(head, _)| head);
+        let (seq, snapshot) = head.map_or((0, None), |h| (h.seq, h.snapshot));
+
+        // An entry this device read is gone: the collection was emptied or
+        // replaced, so start over from its beginning
+        let mut cursor = since;
+        if cursor > seq && self.get::<Batch>(&log_path(cursor)).await?.is_none() {
+            cursor = 0;
+        }
+
+        let mut pulled = SyncRows::default();
+        if let Some(snapshot) = snapshot.filter(|s| *s > cursor) {
+            if let Some((batch, _)) = self.get::<Batch>(&snapshot_path(snapshot)).await? {
+                pulled = batch.rows;
+                cursor = snapshot;
+            }
+        }
+
+        let mut pushed = rows.is_empty();
+        if found.is_none() && !pushed {
+            self.create_collections().await?;
+        }
+        let mut batch = Batch {
+            seq: 0,
+            written_at: 0,
+            replace,
+            rows,
+        };
+        for _ in 0..MAX_ATTEMPTS {
+            cursor = self.read_log(cursor, &mut pulled).await?;
+            if pushed {
+                break;
+            }
+            batch.seq = cursor + 1;
+            batch.written_at = now_millis();
+            if self
+                .put(&log_path(batch.seq), &batch, Expect::Absent)
+                .await?
+            {
+                cursor = batch.seq;
+                pushed = true;
+                break;
+            }
+        }
+        if !pushed {
+            return Err(SyncError::Server(
+                "Other devices keep writing to the WebDAV folder; try again".to_string(),
+            ));
+        }
+
+        let mut next = Head {
+            seq: cursor,
+            snapshot,
+        };
+        if cursor - snapshot.unwrap_or(0) >= SNAPSHOT_EVERY {
+            self.write_snapshot(snapshot, cursor).await?;
+            next.snapshot = Some(cursor);
+        }
+        if next.seq > seq || next.snapshot != snapshot {
+            // Losing this race is fine; the head is only a hint
+            self.put(HEAD_FILE, &next, Expect::found(&found)).await?;
+        }
+
+        Ok(Pulled {
+            cursor,
+            time: now_millis(),
+            rows: pulled,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_db, Db};
+    use crate::sync::{sync_once, SyncBackend, DEFAULT_INTERVAL_SECS};
+    use dav_server::{fakels::FakeLs, memfs::MemFs, DavHandler};
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    /// Serves an in-memory WebDAV server and returns the URL of a collection
+    /// on it that does not exist yet.
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/lumenote", listener.local_addr().unwrap());
+        let dav = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(FakeLs::new())
+            .build_handler();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let dav = dav.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| {
+                        let dav = dav.clone();
+                        async move { Ok::<_, std::convert::Infallible>(dav.handle(request).await) }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        url
+    }
+
+    async fn device() -> Db {
+        let dir = std::env::temp_dir().join(format!("lumenote-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Db::new(init_db(dir).await.unwrap())
+    }
+
+    fn config(url: &str) -> SyncConfig {
+        SyncConfig {
+            backend: SyncBackend::Webdav,
+            url: url.to_string(),
+            username: "me".to_string(),
+            key: "secret".to_string(),
+            interval_secs: DEFAULT_INTERVAL_SECS,
+        }
+    }
+
+    fn note(id: &str, content: &str) -> NoteRecord {
+        NoteRecord {
+            id: id.to_string(),
+            title: id.to_string(),
+            content: content.to_string(),
+            folder_id: None,
+            workspace_id: "default".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            version: 1,
+            sort_key: String::new(),
+            color: None,
+            is_deleted: false,
+        }
+    }
+
+    async fn content(db: &Db, id: &str) -> Option<String> {
+        db.get_notes()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|n| n.id == id)
+            .map(|n| n.content)
+    }
+
+    #[tokio::test]
+    async fn test_devices_sync_through_webdav() {
+        let url = serve().await;
+        let client = reqwest::Client::new();
+        let config = config(&url);
+        let (a, b) = (device().await, device().await);
+
+        a.upsert_note(note("plan", "from a")).await.unwrap();
+        let outcome = sync_once(&a, &client, &config, None).await.unwrap();
+        assert!(outcome.pushed > 0);
+        let outcome = sync_once(&b, &client, &config, None).await.unwrap();
+        assert!(outcome.pulled > 0);
+        assert_eq!(content(&b, "plan").await.as_deref(), Some("from a"));
+
+        b.upsert_note(note("plan", "from b")).await.unwrap();
+        sync_once(&b, &client, &config, None).await.unwrap();
+        sync_once(&a, &client, &config, None).await.unwrap();
+        assert_eq!(content(&a, "plan").await.as_deref(), Some("from b"));
+
+        // Nothing new on either side: no entry is written
+        let outcome = sync_once(&a, &client, &config, None).await.unwrap();
+        assert_eq!((outcome.pushed, outcome.pulled), (0, 0));
+        let transport = WebDavTransport::new(&client, &config);
+        let (head, _) = transport.get::<Head>(HEAD_FILE).await.unwrap().unwrap();
+        assert_eq!(head.seq, 3);
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_guarded_by_etags() {
+        let url = serve().await;
+        let client = reqwest::Client::new();
+        let config = config(&url);
+        let transport = WebDavTransport::new(&client, &config);
+        transport.create_collections().await.unwrap();
+
+        let entry = |seq| Batch {
+            seq,
+            written_at: 0,
+            replace: false,
+            rows: SyncRows::default(),
+        };
+        assert!(transport
+            .put(&log_path(1), &entry(1), Expect::Absent)
+            .await
+            .unwrap());
+        assert!(!transport
+            .put(&log_path(1), &entry(1), Expect::Absent)
+            .await
+            .unwrap());
+
+        let head = Head {
+            seq: 1,
+            snapshot: None,
+        };
+        transport
+            .put(HEAD_FILE, &head, Expect::Absent)
+            .await
+            .unwrap();
+        let (_, stale) = transport.get::<Head>(HEAD_FILE).await.unwrap().unwrap();
+        let stale = stale.unwrap();
+        assert!(transport
+            .put(HEAD_FILE, &head, Expect::Tag(&stale))
+            .await
+            .unwrap());
+        assert!(!transport
+            .put(HEAD_FILE, &head, Expect::Tag(&stale))
+            .await
+            .unwrap());
+
+        transport.store_envelope("one", None).await.unwrap();
+        assert!(transport.store_envelope("two", None).await.is_err());
+        transport.store_envelope("two", Some("one")).await.unwrap();
+        assert_eq!(
+            transport.fetch_envelope().await.unwrap().as_deref(),
+            Some("two")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_device_starts_from_snapshot() {
+        let url = serve().await;
+        let client = reqwest::Client::new();
+        let config = config(&url);
+        let a = device().await;
+        for round in 0..SNAPSHOT_EVERY {
+            a.upsert_note(note(&format!("n{}", round % 3), &round.to_string()))
+                .await
+                .unwrap();
+            sync_once(&a, &client, &config, None).await.unwrap();
+        }
+        let transport = WebDavTransport::new(&client, &config);
+        let (head, _) = transport.get::<Head>(HEAD_FILE).await.unwrap().unwrap();
+        assert_eq!(head.snapshot, Some(SNAPSHOT_EVERY));
+
+        let b = device().await;
+        sync_once(&b, &client, &config, None).await.unwrap();
+        for (id, last) in [("n0", 48), ("n1", 49), ("n2", 47)] {
+            assert_eq!(content(&b, id).await, Some(last.to_string()));
+        }
+    }
+}
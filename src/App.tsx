@@ -9,6 +9,8 @@ import { Settings as SettingsIcon, Share2, Cloud, RefreshCw } from "lucide-react
 import { SharePopup } from "./components/popups/SharePopup";
 import { SettingsPopup } from "./components/popups/SettingsPopup";
 import { SyncPopup } from "./components/popups/SyncPopup";
+import { DatabaseUnlock } from "./components/popups/DatabaseUnlock";
+import { DatabaseRecovery } from "./components/popups/DatabaseRecovery";
 import { t } from "./utils/i18n";
 import { useEffect } from "react";
 
@@ -217,6 +219,8 @@ function App() {
         <SharePopup />
         <SettingsPopup />
         <SyncPopup />
+        <DatabaseUnlock />
+        <DatabaseRecovery />
       </main>
     </div>
   );
@@ -0,0 +1,144 @@
+import { useStore } from '../../store/useStore';
+import { AlertTriangle, RotateCcw, RefreshCw, Eye, FilePlus } from 'lucide-react';
+import { motion, AnimatePresence } from 'framer-motion';
+import { errorMessage, t } from '../../utils/i18n';
+import { api, BackupInfo } from '../../utils/api';
+import { useEffect, useState } from 'react';
+import { toast } from 'sonner';
+
+export const DatabaseRecovery = () => {
+    const { recovery, leaveRecovery, language } = useStore();
+
+    const [backups, setBackups] = useState<BackupInfo[]>([]);
+    const [error, setError] = useState<string | null>(null);
+    const [isWorking, setIsWorking] = useState(false);
+    const [isOpen, setIsOpen] = useState(true);
+
+    const show = !recovery?.read_only || isOpen;
+
+    useEffect(() => {
+        if (!recovery) return;
+        api.listBackups().then(setBackups).catch((e) => console.error('Failed to list backups', e));
+    }, [recovery]);
+
+    const run = async (action: () => Promise<unknown>, success?: (result: unknown) => string) => {
+        if (isWorking) return;
+        setIsWorking(true);
+        setError(null);
+        try {
+            const result = await action();
+            await leaveRecovery();
+            setIsOpen(false);
+            if (success) toast.success(success(result));
+        } catch (e) {
+            setError(errorMessage(e, language));
+        } finally {
+            setIsWorking(false);
+        }
+    };
+
+    const handleRestore = (backup: BackupInfo) => {
+        if (!window.confirm(t('recovery_restore_confirm', language))) return;
+        run(() => api.recoverFromBackup(backup.file_name),
+            (moved) => t('recovery_moved_aside', language, { file: String(moved) }));
+    };
+
+    const handleStartFresh = () => {
+        if (!window.confirm(t('recovery_start_fresh_confirm', language))) return;
+        run(() => api.startFreshDatabase(),
+            (moved) => t('recovery_moved_aside', language, { file: String(moved) }));
+    };
+
+    return (
+        <>
+            {recovery?.read_only && !isOpen && (
+                <div className="fixed top-0 inset-x-0 z-90 flex items-center justify-center gap-3 bg-amber-500/15 text-amber-700 dark:text-amber-300 text-[11px] font-medium py-1.5">
+                    <span>{t('recovery_read_only_banner', language)}</span>
+                    <button onClick={() => setIsOpen(true)} className="underline underline-offset-2">
+                        {t('recovery_resolve', language)}
+                    </button>
+                </div>
+            )}
+            <AnimatePresence>
+                {recovery && show && (
+                    <motion.div
+                        initial={{ opacity: 0 }}
+                        animate={{ opacity: 1 }}
+                        exit={{ opacity: 0 }}
+                        className="fixed inset-0 z-100 flex items-center justify-center bg-app-bg/95 backdrop-blur-md p-4"
+                    >
+                        <div className="w-full max-w-[420px] bg-app-surface border border-border-muted rounded-2xl shadow-2xl p-6 space-y-5">
+                            <div className="flex flex-col items-center text-center space-y-3">
+                                <div className="p-4 bg-red-500/10 rounded-full text-red-500 shadow-sm ring-1 ring-red-500/10">
+                                    <AlertTriangle size={32} />
+                                </div>
+                                <div>
+                                    <h3 className="text-base font-semibold text-text-primary">{t('recovery_title', language)}</h3>
+                                    <p className="text-xs text-text-muted mt-1">{t(`recovery_${recovery.problem}`, language)}</p>
+                                    <p className="text-[11px] text-text-muted mt-2 break-all">{errorMessage(recovery.error, language)}</p>
+                                </div>
+                            </div>
+
+                            <div className="space-y-2">
+                                <label className="text-[11px] font-bold text-text-muted uppercase tracking-wider ml-1">
+                                    {t('backups', language)}
+                                </label>
+                                <div className="max-h-40 overflow-y-auto bg-app-hover rounded-xl divide-y divide-border-muted">
+                                    {backups.length === 0 ? (
+                                        <div className="p-3 text-[11px] text-text-muted text-center">{t('no_backups', language)}</div>
+                                    ) : backups.map((backup) => (
+                                        <div key={backup.file_name} className="flex items-center justify-between px-3 py-2">
+                                            <div className="flex flex-col">
+                                                <span className="text-[11px] font-medium text-text-primary">{new Date(backup.created_at).toLocaleString()}</span>
+                                                <span className="text-[10px] text-text-muted">
+                                                    {backup.kind.replace('_', ' ')} · {(backup.size_bytes / 1024).toFixed(0)} KB
+                                                </span>
+                                            </div>
+                                            <button
+                                                onClick={() => handleRestore(backup)}
+                                                disabled={isWorking}
+                                                title={t('restore', language)}
+                                                className="p-1.5 rounded-lg text-text-muted hover:text-accent hover:bg-app-surface disabled:opacity-50 transition-colors"
+                                            >
+                                                <RotateCcw size={14} />
+                                            </button>
+                                        </div>
+                                    ))}
+                                </div>
+                            </div>
+
+                            {error && <p className="text-[11px] text-red-500 ml-1">{error}</p>}
+
+                            <div className="grid grid-cols-3 gap-2">
+                                <button
+                                    onClick={() => run(() => api.retryOpenDatabase())}
+                                    disabled={isWorking}
+                                    className="flex flex-col items-center gap-1.5 py-3 px-2 bg-app-hover hover:bg-accent-soft disabled:opacity-50 rounded-xl text-[11px] font-medium text-text-primary transition-all"
+                                >
+                                    <RefreshCw size={16} />
+                                    {t('recovery_retry', language)}
+                                </button>
+                                <button
+                                    onClick={() => recovery.read_only ? setIsOpen(false) : run(() => api.openDatabaseReadOnly())}
+                                    disabled={isWorking}
+                                    className="flex flex-col items-center gap-1.5 py-3 px-2 bg-app-hover hover:bg-accent-soft disabled:opacity-50 rounded-xl text-[11px] font-medium text-text-primary transition-all"
+                                >
+                                    <Eye size={16} />
+                                    {t('recovery_read_only', language)}
+                                </button>
+                                <button
+                                    onClick={handleStartFresh}
+                                    disabled={isWorking}
+                                    className="flex flex-col items-center gap-1.5 py-3 px-2 bg-app-hover hover:bg-red-500/10 hover:text-red-500 disabled:opacity-50 rounded-xl text-[11px] font-medium text-text-primary transition-all"
+                                >
+                                    <FilePlus size={16} />
+                                    {t('recovery_start_fresh', language)}
+                                </button>
+                            </div>
+                        </div>
+                    </motion.div>
+                )}
+            </AnimatePresence>
+        </>
+    );
+};
@@ -0,0 +1,80 @@
+import { useStore } from '../../store/useStore';
+import { Lock, Key } from 'lucide-react';
+import { motion, AnimatePresence } from 'framer-motion';
+import { errorMessage, t } from '../../utils/i18n';
+import { useState } from 'react';
+
+export const DatabaseUnlock = () => {
+    const { databaseLocked, unlockDatabase, language } = useStore();
+
+    const [passphrase, setPassphrase] = useState('');
+    const [error, setError] = useState<string | null>(null);
+    const [isUnlocking, setIsUnlocking] = useState(false);
+
+    const handleUnlock = async () => {
+        if (!passphrase || isUnlocking) return;
+        setIsUnlocking(true);
+        setError(null);
+        try {
+            await unlockDatabase(passphrase);
+            setPassphrase('');
+        } catch (e) {
+            setError(errorMessage(e, language));
+        } finally {
+            setIsUnlocking(false);
+        }
+    };
+
+    return (
+        <AnimatePresence>
+            {databaseLocked && (
+                <motion.div
+                    initial={{ opacity: 0 }}
+                    animate={{ opacity: 1 }}
+                    exit={{ opacity: 0 }}
+                    className="fixed inset-0 z-100 flex items-center justify-center bg-app-bg/95 backdrop-blur-md p-4"
+                >
+                    <div className="w-full max-w-[360px] bg-app-surface border border-border-muted rounded-2xl shadow-2xl p-6 space-y-6">
+                        <div className="flex flex-col items-center text-center space-y-3">
+                            <div className="p-4 bg-accent-soft rounded-full text-accent shadow-sm ring-1 ring-accent/10">
+                                <Lock size={32} />
+                            </div>
+                            <div>
+                                <h3 className="text-base font-semibold text-text-primary">{t('db_locked', language)}</h3>
+                                <p className="text-xs text-text-muted mt-1">{t('db_locked_desc', language)}</p>
+                            </div>
+                        </div>
+
+                        <div className="space-y-2">
+                            <label className="text-[11px] font-bold text-text-muted uppercase tracking-wider ml-1">
+                                {t('passphrase', language)}
+                            </label>
+                            <div className="relative group">
+                                <div className="absolute left-3 top-1/2 -translate-y-1/2 text-text-muted group-focus-within:text-accent transition-colors">
+                                    <Key size={16} />
+                                </div>
+                                <input
+                                    type="password"
+                                    autoFocus
+                                    value={passphrase}
+                                    onChange={(e) => setPassphrase(e.target.value)}
+                                    onKeyDown={(e) => e.key === 'Enter' && handleUnlock()}
+                                    className="w-full bg-app-hover border border-transparent focus:border-accent/30 focus:bg-app-surface rounded-xl py-2.5 pl-10 pr-4 text-sm text-text-primary outline-none transition-all shadow-sm"
+                                />
+                            </div>
+                            {error && <p className="text-[11px] text-red-500 ml-1">{error}</p>}
+                        </div>
+
+                        <button
+                            onClick={handleUnlock}
+                            disabled={!passphrase || isUnlocking}
+                            className="w-full py-3 px-4 bg-accent hover:bg-accent-hover disabled:opacity-50 disabled:cursor-not-allowed text-white rounded-xl text-sm font-semibold shadow-lg shadow-accent/20 transition-all"
+                        >
+                            {t('unlock', language)}
+                        </button>
+                    </div>
+                </motion.div>
+            )}
+        </AnimatePresence>
+    );
+};
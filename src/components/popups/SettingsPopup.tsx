@@ -1,13 +1,14 @@
 import { Modal } from '../ui/Modal';
 import { useStore } from '../../store/useStore';
-import { Sun, Moon, Monitor, Type, Minus, Plus, Languages } from 'lucide-react';
+import { Sun, Moon, Monitor, Type, Minus, Plus, Languages, Lock, Key, Archive, RotateCcw, Stethoscope, Download, Upload } from 'lucide-react';
 import { motion } from 'framer-motion';
-import { t } from '../../utils/i18n';
+import { errorMessage, t } from '../../utils/i18n';
 
 import { getName, getVersion } from '@tauri-apps/api/app';
 import { useState, useEffect } from 'react';
 import { check } from '@tauri-apps/plugin-updater';
 import { toast } from 'sonner';
+import { api, BackupInfo, BackupSettings, DatabaseStatus } from '../../utils/api';
 
 export const SettingsPopup = () => {
     const {
@@ -15,12 +16,143 @@ export const SettingsPopup = () => {
         theme, setTheme,
         fontPreset, setFontPreset,
         fontSize, setFontSize,
-        language, setLanguage
+        language, setLanguage,
+        initialize
     } = useStore();
 
     const [appVersion, setAppVersion] = useState<string>('');
     const [appName, setAppName] = useState<string>('Lumenote');
     const [updateStatus, setUpdateStatus] = useState<'idle' | 'checking' | 'available' | 'uptodate' | 'error'>('idle');
+    const [dbStatus, setDbStatus] = useState<DatabaseStatus | null>(null);
+    const [passphrase, setPassphrase] = useState('');
+    const [isMigrating, setIsMigrating] = useState(false);
+
+    const [backups, setBackups] = useState<BackupInfo[]>([]);
+    const [backupSettings, setBackupSettings] = useState<BackupSettings | null>(null);
+
+    useEffect(() => {
+        if (activePopup !== 'settings') return;
+        api.getDatabaseStatus().then(setDbStatus).catch((e) => console.error('Failed to read database status', e));
+        api.listBackups().then(setBackups).catch((e) => console.error('Failed to list backups', e));
+        api.getBackupSettings().then(setBackupSettings).catch((e) => console.error('Failed to read backup settings', e));
+    }, [activePopup]);
+
+    const handleBackupNow = async () => {
+        try {
+            await api.createBackup();
+            setBackups(await api.listBackups());
+        } catch (e) {
+            toast.error(errorMessage(e, language));
+        }
+    };
+
+    const handleExportFullBackup = async () => {
+        // The backup file is plain JSON, whatever protects the database
+        if (dbStatus?.encrypted && !window.confirm(t('export_full_backup_unencrypted', language))) return;
+        try {
+            const grant = await api.pickSavePath({
+                default_name: 'lumenote.lumenote-backup.zst',
+                filters: [{ name: 'Lumenote Backup', extensions: ['zst', 'lumenote-backup'] }],
+                title: t('export_full_backup', language)
+            });
+            if (!grant) return;
+            await api.exportFullBackup(grant.token, grant.path.endsWith('.zst'));
+            toast.success(t('export_success', language));
+        } catch (e) {
+            toast.error(errorMessage(e, language));
+        }
+    };
+
+    const handleImportFullBackup = async () => {
+        try {
+            const grant = await api.pickOpenPath({
+                filters: [{ name: 'Lumenote Backup', extensions: ['zst', 'lumenote-backup'] }],
+                title: t('import_full_backup', language)
+            });
+            if (!grant || !window.confirm(t('import_full_backup_confirm', language))) return;
+            await api.importFullBackup(grant.token);
+            await initialize();
+            toast.success(t('restore_success', language));
+        } catch (e) {
+            toast.error(errorMessage(e, language));
+        }
+    };
+
+    const handleBackupFrequency = async (value: string) => {
+        if (!backupSettings) return;
+        try {
+            setBackupSettings(await api.setBackupSettings({ ...backupSettings, interval_hours: Number(value) }));
+            setBackups(await api.listBackups());
+        } catch (e) {
+            toast.error(errorMessage(e, language));
+        }
+    };
+
+    const handleCheckDatabase = async () => {
+        try {
+            const report = await api.checkDatabase();
+            const count = report.integrity_errors.length + report.fts_missing.length + report.fts_stale.length
+                + report.orphan_notes.length + report.orphan_folders.length
+                + report.missing_workspace_notes.length + report.missing_workspace_folders.length
+                + report.folder_cycles.length;
+            if (report.healthy) {
+                toast.success(t('database_healthy', language));
+                return;
+            }
+            if (!window.confirm(t('repair_confirm', language, { count }))) return;
+            const summary = await api.repairDatabase();
+            setBackups(await api.listBackups());
+            await initialize();
+            if (summary.report.healthy) {
+                toast.success(t('repair_success', language));
+            } else {
+                toast.error(t('repair_incomplete', language), { description: summary.report.integrity_errors[0] });
+            }
+        } catch (e) {
+            toast.error(errorMessage(e, language));
+        }
+    };
+
+    const handleRestore = async (backup: BackupInfo) => {
+        if (!window.confirm(t('restore_confirm', language))) return;
+        try {
+            const check = await api.verifyBackup(backup.file_name);
+            if (!check.ok) {
+                toast.error(check.error ?? backup.file_name);
+                return;
+            }
+            await api.restoreBackup(backup.file_name);
+            setBackups(await api.listBackups());
+            await initialize();
+            toast.success(t('restore_success', language));
+        } catch (e) {
+            toast.error(errorMessage(e, language));
+        }
+    };
+
+    const handleToggleEncryption = async () => {
+        if (!dbStatus || !passphrase || isMigrating) return;
+        setIsMigrating(true);
+        try {
+            const status = dbStatus.encrypted
+                ? await api.disableDatabaseEncryption(passphrase)
+                : await api.enableDatabaseEncryption(passphrase);
+            setDbStatus(status);
+            setPassphrase('');
+        } catch (e) {
+            toast.error(errorMessage(e, language));
+        } finally {
+            setIsMigrating(false);
+        }
+    };
+
+    const handleAutoLock = async (value: string) => {
+        try {
+            setDbStatus(await api.setAutoLock(value === 'off' ? null : Number(value)));
+        } catch (e) {
+            toast.error(errorMessage(e, language));
+        }
+    };
 
     useEffect(() => {
         const fetchAppInfo = async () => {
@@ -182,6 +314,127 @@ export const SettingsPopup = () => {
                     </div>
                 </section>
 
+                {/* Database Encryption Section */}
+                <section>
+                    <h4 className="text-[11px] font-bold text-text-muted uppercase tracking-wider mb-4">{t('db_encryption', language)}</h4>
+                    <div className="space-y-4">
+                        <div className="flex items-center gap-3">
+                            <div className="relative group flex-1">
+                                <div className="absolute left-3 top-1/2 -translate-y-1/2 text-text-muted group-focus-within:text-accent transition-colors">
+                                    <Key size={16} />
+                                </div>
+                                <input
+                                    type="password"
+                                    value={passphrase}
+                                    onChange={(e) => setPassphrase(e.target.value)}
+                                    placeholder={t('passphrase', language)}
+                                    className="w-full bg-app-hover border border-transparent focus:border-accent/30 focus:bg-app-surface rounded-xl py-2 pl-10 pr-4 text-sm text-text-primary outline-none transition-all shadow-sm"
+                                />
+                            </div>
+                            <button
+                                onClick={handleToggleEncryption}
+                                disabled={!dbStatus || !passphrase || isMigrating}
+                                className="py-2 px-3 rounded-xl text-[11px] font-semibold bg-app-hover text-text-secondary hover:text-text-primary disabled:opacity-50 disabled:cursor-not-allowed transition-colors"
+                            >
+                                {t(dbStatus?.encrypted ? 'decrypt_db' : 'encrypt_db', language)}
+                            </button>
+                        </div>
+
+                        {dbStatus?.encrypted && (
+                            <div className="flex items-center gap-4">
+                                <div className="text-[10px] text-text-muted font-medium whitespace-nowrap">{t('auto_lock', language)}</div>
+                                <div className="flex-1">
+                                    {segmentedControl([
+                                        { id: 'off', label: t('auto_lock_off', language) },
+                                        { id: '5', label: '5m' },
+                                        { id: '15', label: '15m' },
+                                        { id: '60', label: '1h' },
+                                    ], dbStatus.auto_lock_minutes ? String(dbStatus.auto_lock_minutes) : 'off', handleAutoLock, 'autolock-slider')}
+                                </div>
+                                <button
+                                    onClick={() => api.lockDatabase().catch((e) => toast.error(errorMessage(e, language)))}
+                                    title={t('lock_now', language)}
+                                    className="p-2 hover:bg-app-hover rounded-lg text-text-muted hover:text-text-secondary transition-colors"
+                                >
+                                    <Lock size={16} />
+                                </button>
+                            </div>
+                        )}
+                    </div>
+                </section>
+
+                {/* Backups Section */}
+                <section>
+                    <h4 className="text-[11px] font-bold text-text-muted uppercase tracking-wider mb-4">{t('backups', language)}</h4>
+                    <div className="space-y-4">
+                        <div className="flex items-center gap-4">
+                            <div className="text-[10px] text-text-muted font-medium whitespace-nowrap">{t('backup_frequency', language)}</div>
+                            <div className="flex-1">
+                                {segmentedControl([
+                                    { id: '0', label: t('auto_lock_off', language) },
+                                    { id: '24', label: t('backup_daily', language) },
+                                    { id: '168', label: t('backup_weekly', language) },
+                                ], String(backupSettings?.interval_hours ?? 24), handleBackupFrequency, 'backup-slider')}
+                            </div>
+                            <button
+                                onClick={handleCheckDatabase}
+                                title={t('check_database', language)}
+                                className="p-2 hover:bg-app-hover rounded-lg text-text-muted hover:text-text-secondary transition-colors"
+                            >
+                                <Stethoscope size={16} />
+                            </button>
+                            <button
+                                onClick={handleBackupNow}
+                                title={t('backup_now', language)}
+                                className="p-2 hover:bg-app-hover rounded-lg text-text-muted hover:text-text-secondary transition-colors"
+                            >
+                                <Archive size={16} />
+                            </button>
+                            <button
+                                onClick={handleExportFullBackup}
+                                title={t('export_full_backup', language)}
+                                className="p-2 hover:bg-app-hover rounded-lg text-text-muted hover:text-text-secondary transition-colors"
+                            >
+                                <Download size={16} />
+                            </button>
+                            <button
+                                onClick={handleImportFullBackup}
+                                title={t('import_full_backup', language)}
+                                className="p-2 hover:bg-app-hover rounded-lg text-text-muted hover:text-text-secondary transition-colors"
+                            >
+                                <Upload size={16} />
+                            </button>
+                        </div>
+                        {backupSettings?.last_error && (
+                            <p className="text-[11px] text-red-500">
+                                {t('backup_failed', language, { error: backupSettings.last_error })}
+                            </p>
+                        )}
+
+                        <div className="max-h-40 overflow-y-auto rounded-xl bg-app-hover divide-y divide-border-muted/30">
+                            {backups.length === 0 ? (
+                                <div className="p-3 text-[11px] text-text-muted text-center">{t('no_backups', language)}</div>
+                            ) : backups.map((backup) => (
+                                <div key={backup.file_name} className="flex items-center justify-between px-3 py-2">
+                                    <div className="flex flex-col">
+                                        <span className="text-[11px] font-medium text-text-primary">{new Date(backup.created_at).toLocaleString()}</span>
+                                        <span className="text-[10px] text-text-muted">
+                                            {backup.kind.replace('_', ' ')} · {(backup.size_bytes / 1024).toFixed(0)} KB
+                                        </span>
+                                    </div>
+                                    <button
+                                        onClick={() => handleRestore(backup)}
+                                        title={t('restore', language)}
+                                        className="p-1.5 hover:bg-app-active rounded-lg text-text-muted hover:text-text-secondary transition-colors"
+                                    >
+                                        <RotateCcw size={14} />
+                                    </button>
+                                </div>
+                            ))}
+                        </div>
+                    </div>
+                </section>
+
                 {/* About Section */}
                 <section className="pt-4 border-t border-border-muted">
                     <div className="flex items-center justify-between">
@@ -3,9 +3,8 @@ import { useStore } from '../../store/useStore';
 import { Copy, FileText, Download, Check } from 'lucide-react';
 import { useState } from 'react';
 import { toast } from 'sonner';
-import { t } from '../../utils/i18n';
-import { save } from '@tauri-apps/plugin-dialog';
-import { invoke } from '@tauri-apps/api/core';
+import { errorMessage, t } from '../../utils/i18n';
+import { api } from '../../utils/api';
 
 export const SharePopup = () => {
     const { activePopup, setActivePopup, notes, activeNoteId, language } = useStore();
@@ -39,24 +38,21 @@ export const SharePopup = () => {
         if (!activeNote) return;
         
         try {
-            const filePath = await save({
+            const grant = await api.pickSavePath({
                 filters: [{
                     name: 'Markdown',
                     extensions: ['md']
                 }],
-                defaultPath: `${activeNote.title || 'note'}.md`
+                default_name: `${activeNote.title || 'note'}.md`
             });
 
-            if (filePath) {
-                await invoke('write_text_file', { 
-                    path: filePath, 
-                    content: activeNote.content 
-                });
+            if (grant) {
+                await api.writeTextFile(grant.token, activeNote.content);
                 toast.success(t('download_success', language));
             }
         } catch (error) {
             console.error('Failed to save file:', error);
-            const errorMsg = typeof error === 'string' ? error : (error instanceof Error ? error.message : String(error));
+            const errorMsg = errorMessage(error, language);
             toast.error(language === 'vi' ? `Lỗi khi lưu tệp: ${errorMsg}` : `Failed to save file: ${errorMsg}`);
         }
     };
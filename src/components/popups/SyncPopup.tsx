@@ -1,29 +1,34 @@
 import { Modal } from '../ui/Modal';
 import { useStore } from '../../store/useStore';
-import { Cloud, Globe, Key, RefreshCw, CheckCircle2, AlertCircle, Clock } from 'lucide-react';
+import { Cloud, Globe, Key, User, RefreshCw, CheckCircle2, AlertCircle, Clock } from 'lucide-react';
 import { motion, AnimatePresence } from 'framer-motion';
 import { t } from '../../utils/i18n';
 import { useState, useEffect } from 'react';
+import { SyncBackend } from '../../utils/api';
 
 export const SyncPopup = () => {
     const { 
         activePopup, setActivePopup,
-        syncUrl, syncKey, setSyncConfig,
+        syncUrl, syncKey, syncBackend, syncUsername, setSyncConfig,
         isSyncing, performSync,
         lastSyncedAt, hasUnsyncedChanges, language
     } = useStore();
 
     const [url, setUrl] = useState(syncUrl);
     const [key, setKey] = useState(syncKey);
+    const [backend, setBackend] = useState<SyncBackend>(syncBackend);
+    const [username, setUsername] = useState(syncUsername);
 
     // Update local state when store values change (e.g., after loading from localStorage)
     useEffect(() => {
         setUrl(syncUrl);
         setKey(syncKey);
-    }, [syncUrl, syncKey]);
+        setBackend(syncBackend);
+        setUsername(syncUsername);
+    }, [syncUrl, syncKey, syncBackend, syncUsername]);
 
     const handleSave = () => {
-        setSyncConfig(url, key);
+        setSyncConfig(url, key, backend, username);
     };
 
     const handleSync = async () => {
@@ -56,6 +61,18 @@ export const SyncPopup = () => {
 
                 {/* Form Section */}
                 <div className="space-y-4">
+                    <div className="flex gap-1 p-1 bg-app-hover rounded-xl">
+                        {(['server', 'webdav'] as const).map((option) => (
+                            <button
+                                key={option}
+                                onClick={() => setBackend(option)}
+                                className={`flex-1 py-1.5 rounded-lg text-xs font-semibold transition-colors ${backend === option ? 'bg-app-surface text-text-primary shadow-sm' : 'text-text-muted hover:text-text-secondary'}`}
+                            >
+                                {t(option === 'server' ? 'sync_backend_server' : 'sync_backend_webdav', language)}
+                            </button>
+                        ))}
+                    </div>
+
                     <div className="space-y-2">
                         <label className="text-[11px] font-bold text-text-muted uppercase tracking-wider ml-1">
                             {t('server_url', language)}
@@ -68,15 +85,34 @@ export const SyncPopup = () => {
                                 type="text"
                                 value={url}
                                 onChange={(e) => setUrl(e.target.value)}
-                                placeholder="https://sync.lumenote.com"
+                                placeholder={backend === 'webdav' ? "https://cloud.example.com/remote.php/dav/files/me/Lumenote" : "https://sync.lumenote.com"}
                                 className="w-full bg-app-hover border border-transparent focus:border-accent/30 focus:bg-app-surface rounded-xl py-2.5 pl-10 pr-4 text-sm text-text-primary outline-none transition-all shadow-sm"
                             />
                         </div>
                     </div>
 
+                    {backend === 'webdav' && (
+                        <div className="space-y-2">
+                            <label className="text-[11px] font-bold text-text-muted uppercase tracking-wider ml-1">
+                                {t('webdav_username', language)}
+                            </label>
+                            <div className="relative group">
+                                <div className="absolute left-3 top-1/2 -translate-y-1/2 text-text-muted group-focus-within:text-accent transition-colors">
+                                    <User size={16} />
+                                </div>
+                                <input
+                                    type="text"
+                                    value={username}
+                                    onChange={(e) => setUsername(e.target.value)}
+                                    className="w-full bg-app-hover border border-transparent focus:border-accent/30 focus:bg-app-surface rounded-xl py-2.5 pl-10 pr-4 text-sm text-text-primary outline-none transition-all shadow-sm"
+                                />
+                            </div>
+                        </div>
+                    )}
+
                     <div className="space-y-2">
                         <label className="text-[11px] font-bold text-text-muted uppercase tracking-wider ml-1">
-                            {t('sync_key', language)}
+                            {t(backend === 'webdav' ? 'webdav_password' : 'sync_key', language)}
                         </label>
                         <div className="relative group">
                             <div className="absolute left-3 top-1/2 -translate-y-1/2 text-text-muted group-focus-within:text-accent transition-colors">
@@ -86,7 +122,7 @@ export const SyncPopup = () => {
                                 type="password"
                                 value={key}
                                 onChange={(e) => setKey(e.target.value)}
-                                placeholder="ln_opt_xxxxxxxxxxxx"
+                                placeholder={backend === 'webdav' ? '' : "ln_opt_xxxxxxxxxxxx"}
                                 className="w-full bg-app-hover border border-transparent focus:border-accent/30 focus:bg-app-surface rounded-xl py-2.5 pl-10 pr-4 text-sm text-text-primary font-mono outline-none transition-all shadow-sm"
                             />
                         </div>
@@ -2,10 +2,9 @@ import { useStore } from "../../store/useStore";
 import { Plus, Search, X, FileText } from "lucide-react";
 import { AnimatePresence, motion } from "framer-motion";
 import { useState, useRef, useMemo, useEffect } from "react";
-import { t } from "../../utils/i18n";
-import { save, open } from "@tauri-apps/plugin-dialog";
+import { errorMessage, I18nKey, t } from "../../utils/i18n";
 import { toast } from "sonner";
-import { api } from "../../utils/api";
+import { api, DocumentFormat, ExportOptions, ExportTarget } from "../../utils/api";
 import {
     DndContext,
     closestCorners,
@@ -319,63 +318,170 @@ export const Sidebar = () => {
         setEditingId(null);
     };
 
-    const handleExportWorkspace = async (workspaceId: string) => {
+    const handleExportWorkspace = async (workspaceId: string, target: ExportTarget, options?: ExportOptions) => {
         try {
             const workspace = workspaces.find(w => w.id === workspaceId);
             const workspaceName = workspace?.name || "LUMENOTE";
             const sanitizedName = workspaceName.replace(/[^a-zA-Z0-9\s-_]/g, '').trim() || "LUMENOTE";
             const defaultFileName = `${sanitizedName}.zip`;
 
-            const selected = await save({
-                defaultPath: defaultFileName,
-                filters: [{
-                    name: 'ZIP Archive',
-                    extensions: ['zip']
-                }],
-                title: t('export', language)
-            });
-            if (selected) {
-                await api.exportWorkspace(workspaceId, selected);
+            const grant = target === 'zip'
+                ? await api.pickSavePath({
+                    default_name: defaultFileName,
+                    filters: [{
+                        name: 'ZIP Archive',
+                        extensions: ['zip']
+                    }],
+                    title: t('export', language)
+                })
+                : await api.pickDirectory({ title: t('export_markdown_folder', language) });
+            if (grant) {
+                await api.exportWorkspace(workspaceId, grant.token, target, options);
                 toast.success(t('export_success', language));
             }
         } catch (error) {
             console.error("Export failed:", error);
-            toast.error(t('export_failed', language));
+            toast.error(t('export_failed', language) + ': ' + errorMessage(error, language));
+        }
+    };
+
+    const handleExportSite = async (workspaceId: string, target: ExportTarget) => {
+        try {
+            const workspace = workspaces.find(w => w.id === workspaceId);
+            const workspaceName = workspace?.name || "LUMENOTE";
+            const sanitizedName = workspaceName.replace(/[^a-zA-Z0-9\s-_]/g, '').trim() || "LUMENOTE";
+
+            const grant = target === 'zip'
+                ? await api.pickSavePath({
+                    default_name: `${sanitizedName}-site.zip`,
+                    filters: [{
+                        name: 'ZIP Archive',
+                        extensions: ['zip']
+                    }],
+                    title: t('export_site_zip', language)
+                })
+                : await api.pickDirectory({ title: t('export_site_folder', language) });
+            if (!grant) return;
+            const attachments = window.confirm(t('export_site_attachments_confirm', language))
+                ? await api.pickOpenDirectory({ title: t('export_site_attachments_folder', language) })
+                : null;
+            const summary = await api.exportSite(workspaceId, target, grant.token, attachments?.token ?? null);
+            toast.success(t('export_success', language));
+            if (summary.skipped_links.length > 0) {
+                toast.warning(t('export_site_skipped', language, { count: summary.skipped_links.length }));
+            }
+        } catch (error) {
+            console.error("Site export failed:", error);
+            toast.error(t('export_failed', language) + ': ' + errorMessage(error, language));
+        }
+    };
+
+    const handleMirror = async (workspaceId: string) => {
+        try {
+            const current = (await api.getMirrorStatus()).find(m => m.workspace_id === workspaceId);
+            if (current) {
+                if (!window.confirm(t('mirror_stop_confirm', language, { dir: current.dir }))) return;
+                await api.configureMirror(workspaceId, null);
+                toast.success(t('mirror_stopped', language));
+                return;
+            }
+            const grant = await api.pickDirectory({ title: t('mirror_folder', language) });
+            if (grant) {
+                await api.configureMirror(workspaceId, grant.token);
+                toast.success(t('mirror_started', language));
+            }
+        } catch (error) {
+            console.error("Mirror setup failed:", error);
+            toast.error(errorMessage(error, language));
+        }
+    };
+
+    // Commits happen after each sync round that moved notes, so this only picks or drops the repository
+    const handleGitHistory = async (workspaceId: string) => {
+        try {
+            const current = await api.getHistorySettings(workspaceId);
+            if (current) {
+                if (!window.confirm(t('git_history_stop_confirm', language, { dir: current.repo }))) return;
+                await api.configureHistory(workspaceId, null);
+                toast.success(t('git_history_stopped', language));
+                return;
+            }
+            const grant = await api.pickDirectory({ title: t('git_history', language) });
+            if (grant) {
+                await api.configureHistory(workspaceId, { token: grant.token, remote: null });
+                toast.success(t('git_history_started', language));
+            }
+        } catch (error) {
+            console.error("Git history setup failed:", error);
+            toast.error(errorMessage(error, language));
+        }
+    };
+
+    const handleExportDocument = async (type: 'note' | 'folder', id: string, format: DocumentFormat) => {
+        try {
+            const name = type === 'note'
+                ? notes.find(n => n.id === id)?.title
+                : folders.find(f => f.id === id)?.name;
+            const sanitizedName = (name || "LUMENOTE").replace(/[^a-zA-Z0-9\s-_]/g, '').trim() || "LUMENOTE";
+            const filters = {
+                pdf: { name: 'PDF Document', extensions: ['pdf'] },
+                docx: { name: 'Word Document', extensions: ['docx'] },
+                epub: { name: 'EPUB Book', extensions: ['epub'] },
+            };
+            const titles: Record<DocumentFormat, I18nKey> = {
+                pdf: 'export_pdf',
+                docx: 'export_docx',
+                epub: 'export_epub',
+            };
+
+            const grant = await api.pickSavePath({
+                default_name: `${sanitizedName}.${format}`,
+                filters: [filters[format]],
+                title: t(titles[format], language)
+            });
+            if (grant) {
+                if (type === 'note') {
+                    await api.exportNoteDocument(id, format, grant.token);
+                } else {
+                    const folder = folders.find(f => f.id === id);
+                    await api.exportFolderDocument(folder?.workspaceId ?? activeWorkspaceId, id, format, grant.token);
+                }
+                toast.success(t('export_success', language));
+            }
+        } catch (error) {
+            console.error("Document export failed:", error);
+            toast.error(t('export_failed', language) + ': ' + errorMessage(error, language));
         }
     };
 
     const handleImportWorkspace = async () => {
         try {
-            const selected = await open({
+            const grant = await api.pickOpenPath({
                 filters: [{
                     name: 'ZIP Archive',
                     extensions: ['zip']
                 }],
-                title: t('import_workspace', language),
-                multiple: false
+                title: t('import_workspace', language)
             });
 
-            if (!selected || typeof selected !== 'string') {
+            if (!grant) {
                 return;
             }
 
-            const zipPath = selected;
-            
             // Extract workspace name from ZIP filename (without .zip extension)
-            const zipFileName = zipPath.split(/[/\\]/).pop() || 'Imported Workspace';
+            const zipFileName = grant.path.split(/[/\\]/).pop() || 'Imported Workspace';
             const workspaceName = zipFileName.replace(/\.zip$/i, '') || 'Imported Workspace';
             
-            const workspaceId = await api.importWorkspace(zipPath, workspaceName);
+            const workspaceId = await api.importWorkspace(grant.token, workspaceName);
             
             // Refresh data and switch to imported workspace
             await initialize();
             setActiveWorkspaceId(workspaceId);
             
             toast.success(t('import_success', language));
-        } catch (error: any) {
+        } catch (error) {
             console.error("Import failed:", error);
-            const errorMessage = error?.message || String(error);
-            toast.error(t('import_failed', language) + ': ' + errorMessage);
+            toast.error(t('import_failed', language) + ': ' + errorMessage(error, language));
         }
     };
 
@@ -618,6 +724,10 @@ export const Sidebar = () => {
                         onClose={() => setContextMenu(null)}
                         onRename={(id: string, val: string) => handleRenameStart(id, val)}
                         onExport={handleExportWorkspace}
+                        onExportSite={handleExportSite}
+                        onMirror={handleMirror}
+                        onGitHistory={handleGitHistory}
+                        onExportDocument={handleExportDocument}
                         onImport={handleImportWorkspace}
                         onInlineCreate={(id: string, name: string) => setTimeout(() => handleRenameStart(id, name), 0)}
                     />
@@ -1,24 +1,30 @@
 import { useState, useRef, useEffect, memo } from "react";
 import { motion, AnimatePresence } from "framer-motion";
-import { FileText, FolderPlus, Trash2, Edit3, Palette, LayoutGrid, Upload } from "lucide-react";
+import { FileText, FolderPlus, Trash2, Edit3, Palette, LayoutGrid, Upload, Copy, Globe, FileDown, BookOpen, FolderSync, GitCommit } from "lucide-react";
 import { HexColorPicker } from "react-colorful";
 import { useStore } from "../../../store/useStore";
 import { t } from "../../../utils/i18n";
 import { getItemDepth } from "../utils";
 import { ContextMenuType } from "../types";
+import { DocumentFormat, ExportOptions, ExportTarget } from "../../../utils/api";
 
 interface ContextMenuProps extends ContextMenuType {
     onClose: () => void;
     onRename: (id: string, val: string) => void;
-    onExport?: (id: string) => void;
+    onExport?: (workspaceId: string, target: ExportTarget, options?: ExportOptions) => void;
+    onExportSite?: (id: string, target: ExportTarget) => void;
+    onExportDocument?: (type: 'note' | 'folder', id: string, format: DocumentFormat) => void;
+    onMirror?: (workspaceId: string) => void;
+    onGitHistory?: (workspaceId: string) => void;
     onImport?: () => void;
     onInlineCreate?: (id: string, name: string) => void;
 }
 
-export const ContextMenu = memo(({ x, y, type, itemId, onClose, onRename, onExport, onImport, onInlineCreate }: ContextMenuProps) => {
+export const ContextMenu = memo(({ x, y, type, itemId, onClose, onRename, onExport, onExportSite, onExportDocument, onMirror, onGitHistory, onImport, onInlineCreate }: ContextMenuProps) => {
     const {
         folders, notes, workspaces, deleteNote, deleteFolder, deleteWorkspace,
-        addNote, addFolder, addWorkspace, setNoteColor, setFolderColor, setWorkspaceColor, language
+        addNote, addFolder, addWorkspace, setNoteColor, setFolderColor, setWorkspaceColor,
+        duplicateNote, duplicateFolder, language
     } = useStore();
     const ref = useRef<HTMLDivElement>(null);
     const [pos, setPos] = useState({ x, y });
@@ -91,9 +97,24 @@ export const ContextMenu = memo(({ x, y, type, itemId, onClose, onRename, onExpo
         onClose();
     };
 
+    const exportItems = (kind: 'note' | 'folder') => [
+        {
+            icon: <FileText size={14} />, label: t('export_markdown_item', language), action: () => {
+                const item = kind === 'note' ? notes.find(n => n.id === itemId) : folders.find(f => f.id === itemId);
+                if (item) onExport?.(item.workspaceId, 'zip', { selection: { kind: 'items', ids: [item.id] } });
+                onClose();
+            }
+        },
+        { icon: <FileDown size={14} />, label: t('export_pdf', language), action: () => { if (itemId) onExportDocument?.(kind, itemId, 'pdf'); onClose(); } },
+        { icon: <FileDown size={14} />, label: t('export_docx', language), action: () => { if (itemId) onExportDocument?.(kind, itemId, 'docx'); onClose(); } },
+        { icon: <BookOpen size={14} />, label: t('export_epub', language), action: () => { if (itemId) onExportDocument?.(kind, itemId, 'epub'); onClose(); } },
+    ];
+
     const items = type === 'note' ? [
         { icon: <Edit3 size={14} />, label: t('rename', language), action: handleRename },
         { icon: <Palette size={14} />, label: t('appearance', language), action: () => setShowColorPicker(!showColorPicker) },
+        { icon: <Copy size={14} />, label: t('duplicate', language), action: () => { if (itemId) duplicateNote(itemId); onClose(); } },
+        ...exportItems('note'),
         { icon: <Trash2 size={14} />, label: t('delete', language), action: () => { if (itemId) deleteNote(itemId); onClose(); }, danger: true },
     ] : type === 'folder' ? [
         {
@@ -116,11 +137,18 @@ export const ContextMenu = memo(({ x, y, type, itemId, onClose, onRename, onExpo
         },
         { icon: <Palette size={14} />, label: t('appearance', language), action: () => setShowColorPicker(!showColorPicker) },
         { icon: <Edit3 size={14} />, label: t('rename', language), action: handleRename },
+        { icon: <Copy size={14} />, label: t('duplicate', language), action: () => { if (itemId) duplicateFolder(itemId); onClose(); } },
+        ...exportItems('folder'),
         { icon: <Trash2 size={14} />, label: t('delete', language), action: () => { if (itemId) deleteFolder(itemId); onClose(); }, danger: true },
     ] : type === 'workspace' ? [
         { icon: <Edit3 size={14} />, label: t('rename', language), action: handleRename },
         { icon: <Palette size={14} />, label: t('appearance', language), action: () => setShowColorPicker(!showColorPicker) },
-        { icon: <FileText size={14} />, label: t('export_markdown', language), action: () => { if (itemId) onExport?.(itemId); onClose(); } },
+        { icon: <FileText size={14} />, label: t('export_markdown', language), action: () => { if (itemId) onExport?.(itemId, 'zip'); onClose(); } },
+        { icon: <FileText size={14} />, label: t('export_markdown_folder', language), action: () => { if (itemId) onExport?.(itemId, 'directory', { front_matter: true }); onClose(); } },
+        { icon: <Globe size={14} />, label: t('export_site_zip', language), action: () => { if (itemId) onExportSite?.(itemId, 'zip'); onClose(); } },
+        { icon: <Globe size={14} />, label: t('export_site_folder', language), action: () => { if (itemId) onExportSite?.(itemId, 'directory'); onClose(); } },
+        { icon: <FolderSync size={14} />, label: t('mirror_folder', language), action: () => { if (itemId) onMirror?.(itemId); onClose(); } },
+        { icon: <GitCommit size={14} />, label: t('git_history', language), action: () => { if (itemId) onGitHistory?.(itemId); onClose(); } },
         { icon: <Upload size={14} />, label: t('import_workspace', language), action: () => { onImport?.(); onClose(); } },
         { icon: <Trash2 size={14} />, label: t('delete', language), action: () => { if (itemId) deleteWorkspace(itemId); onClose(); }, danger: true },
     ] : [
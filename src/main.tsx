@@ -9,10 +9,17 @@ import { useStore } from "./store/useStore";
 
 const AppInitializer = ({ children }: { children: React.ReactNode }) => {
   const initialize = useStore((state) => state.initialize);
+  const initSync = useStore((state) => state.initSync);
+  const initVault = useStore((state) => state.initVault);
   
   useEffect(() => {
-    initialize();
-  }, [initialize]);
+    initVault().then((unlocked) => {
+      if (unlocked) {
+        initialize();
+        initSync();
+      }
+    });
+  }, [initialize, initSync, initVault]);
 
   return <>{children}</>;
 };
@@ -2,8 +2,9 @@ import { create } from 'zustand';
 import { Note, Folder, ViewMode, Workspace } from '../types';
 import { arrayMove } from '@dnd-kit/sortable';
 import { EditorView } from '@codemirror/view';
-import { api, SearchResult } from '../utils/api';
+import { api, ItemKind, Recovery, SearchResult, SyncBackend, SyncState, SyncStatus } from '../utils/api';
 import { toast } from 'sonner';
+import { errorMessage } from '../utils/i18n';
 
 interface AppState {
     notes: Note[];
@@ -22,11 +23,18 @@ interface AppState {
     fontPreset: 'sans' | 'serif' | 'mono';
     fontSize: number;
     language: 'vi' | 'en';
+    syncBackend: SyncBackend;
     syncUrl: string;
+    syncUsername: string;
     syncKey: string;
     lastSyncedAt: number | null;
     isSyncing: boolean;
+    syncState: SyncState;
+    syncError: string | null;
     hasUnsyncedChanges: boolean;
+    databaseLocked: boolean;
+    // Set while the database could not be opened, or is open read-only after it
+    recovery: Recovery | null;
 
     setNotes: (notes: Note[]) => void;
     setActiveNoteId: (id: string | null) => void;
@@ -52,15 +60,22 @@ interface AppState {
     renameFolder: (id: string, name: string) => void;
     setNoteColor: (id: string, color: string | null) => void;
     setFolderColor: (id: string, color: string | null) => void;
-    setSyncConfig: (url: string, key: string) => void;
+    setSyncConfig: (url: string, key: string, backend?: SyncBackend, username?: string) => void;
     setSyncing: (syncing: boolean) => void;
     performSync: () => Promise<void>;
+    applySyncStatus: (status: SyncStatus) => void;
+    initSync: () => Promise<void>;
     checkUnsyncedChanges: () => void;
+    initVault: () => Promise<boolean>;
+    unlockDatabase: (passphrase: string) => Promise<void>;
+    leaveRecovery: () => Promise<void>;
 
     reorderNotes: (activeId: string, overId: string) => void;
     reorderFolders: (activeId: string, overId: string) => void;
     moveNoteToFolder: (noteId: string, folderId: string | null) => void;
     moveFolderToFolder: (folderId: string, targetFolderId: string | null) => void;
+    duplicateNote: (id: string) => Promise<void>;
+    duplicateFolder: (id: string) => Promise<void>;
 
     searchNotes: (query: string) => Promise<void>;
     setSearchResults: (results: SearchResult[]) => void;
@@ -70,6 +85,30 @@ interface AppState {
 }
 
 let saveTimeout: ReturnType<typeof setTimeout> | null = null;
+let syncListener: Promise<unknown> | null = null;
+let lockListener: Promise<unknown> | null = null;
+// Last move to another folder; a reorder right after a drag waits for it so
+// the backend sees the item under its new parent
+let pendingMove: Promise<unknown> = Promise.resolve();
+
+// Saves an item's new place among its siblings (same workspace and parent)
+const persistOrder = <T extends { id: string; workspaceId: string }>(
+    kind: ItemKind,
+    items: T[],
+    id: string,
+    parentOf: (item: T) => string | null | undefined,
+) => {
+    const moved = items.find(i => i.id === id);
+    if (!moved) return;
+    const siblings = items.filter(i => i.workspaceId === moved.workspaceId && (parentOf(i) || null) === (parentOf(moved) || null));
+    const index = siblings.findIndex(i => i.id === id);
+    const afterId = index > 0 ? siblings[index - 1].id : null;
+    pendingMove
+        .catch(() => undefined)
+        .then(() => api.reorder(kind, id, afterId))
+        .then(() => setTimeout(() => useStore.getState().checkUnsyncedChanges(), 100))
+        .catch(console.error);
+};
 
 export const useStore = create<AppState>((set, get) => ({
     notes: [],
@@ -86,6 +125,8 @@ export const useStore = create<AppState>((set, get) => ({
     fontPreset: 'sans',
     fontSize: 16,
     language: 'en',
+    syncBackend: 'server',
+    syncUsername: '',
     syncUrl: (() => {
         try {
             return localStorage.getItem('lumenote_syncUrl') || '';
@@ -100,16 +141,13 @@ export const useStore = create<AppState>((set, get) => ({
             return '';
         }
     })(),
-    lastSyncedAt: (() => {
-        try {
-            const stored = localStorage.getItem('lumenote_lastSyncedAt');
-            return stored ? parseInt(stored, 10) : null;
-        } catch {
-            return null;
-        }
-    })(),
+    lastSyncedAt: null,
     isSyncing: false,
+    syncState: 'idle',
+    syncError: null,
     hasUnsyncedChanges: false,
+    databaseLocked: false,
+    recovery: null,
 
     initialize: async () => {
         try {
@@ -128,6 +166,7 @@ export const useStore = create<AppState>((set, get) => ({
                 updatedAt: r.updated_at,
                 version: r.version,
                 color: r.color || undefined,
+                sortKey: r.sort_key,
                 isExpanded: true,
             }));
 
@@ -141,6 +180,8 @@ export const useStore = create<AppState>((set, get) => ({
                 createdAt: r.created_at,
                 updatedAt: r.updated_at,
                 version: r.version,
+                sortKey: r.sort_key,
+                color: r.color || undefined,
                 is_deleted: r.is_deleted, // although they shouldn't be here
             }));
 
@@ -185,6 +226,7 @@ export const useStore = create<AppState>((set, get) => ({
                         created_at: note.createdAt,
                         updated_at: note.updatedAt,
                         version: note.version,
+                        color: note.color || null,
                         is_deleted: false,
                     }).catch(console.error);
                 }
@@ -201,118 +243,120 @@ export const useStore = create<AppState>((set, get) => ({
     setFontPreset: (fontPreset) => set({ fontPreset }),
     setFontSize: (fontSize) => set({ fontSize }),
     setLanguage: (language) => set({ language }),
-    setSyncConfig: (syncUrl, syncKey) => {
+    setSyncConfig: (syncUrl, syncKey, syncBackend = 'server', syncUsername = '') => {
         try {
             localStorage.setItem('lumenote_syncUrl', syncUrl);
             localStorage.setItem('lumenote_syncKey', syncKey);
         } catch (error) {
             console.error('Failed to save sync config to localStorage:', error);
         }
-        set({ syncUrl, syncKey });
-        // Recheck unsynced changes after config update
-        get().checkUnsyncedChanges();
+        set({ syncUrl, syncKey, syncBackend, syncUsername });
+        // The backend owns the sync loop; it picks up the new config right away
+        api.configureSync(syncUrl, syncKey, undefined, syncBackend, syncUsername)
+            .then((status) => get().applySyncStatus(status))
+            .catch((error) => console.error('Failed to configure sync:', error));
     },
     setSyncing: (isSyncing) => set({ isSyncing }),
     performSync: async () => {
-        const { syncUrl, syncKey, lastSyncedAt, setSyncing } = get();
+        const { syncUrl, syncKey } = get();
         if (!syncUrl || !syncKey) {
             console.warn('Sync skipped: missing syncUrl or syncKey', { syncUrl: !!syncUrl, syncKey: !!syncKey });
             return;
         }
 
-        console.log('Starting sync...', { syncUrl, syncKeyLength: syncKey.length, lastSyncedAt });
-        setSyncing(true);
         try {
-            // 1. Get local changes since last sync
-            const since = lastSyncedAt || 0;
-            console.log('Fetching local sync data since:', since);
-            const localData = await api.getSyncData(since);
-            console.log('Local sync data:', { notesCount: localData.notes.length, foldersCount: localData.folders.length, workspacesCount: localData.workspaces.length });
-
-            // 2. Send to server and get remote updates
-            const remoteData = await api.syncWithServer(syncUrl, syncKey, {
-                last_sync_time: since,
-                notes: localData.notes,
-                folders: localData.folders,
-                workspaces: localData.workspaces,
-            });
-
-            // 3. Apply remote updates locally
-            console.log('Applying remote updates:', { notesCount: remoteData.notes.length, foldersCount: remoteData.folders.length, workspacesCount: remoteData.workspaces?.length || 0 });
-            for (const note of remoteData.notes) {
-                // Map server Note to NoteRecord format expected by backend
-                // Server returns created_at as optional, but we need it for the backend
-                const noteRecord = {
-                    id: note.id,
-                    title: note.title,
-                    content: note.content,
-                    folder_id: note.folder_id,
-                    workspace_id: note.workspace_id,
-                    created_at: note.created_at || note.updated_at, // Use created_at or fallback to updated_at
-                    updated_at: note.updated_at,
-                    version: 1, // Default version for remote notes
-                    is_deleted: note.is_deleted || false,
-                };
-                await api.applyRemoteUpdateNote(noteRecord);
-            }
-            for (const folder of remoteData.folders) {
-                // Map server Folder to FolderRecord format expected by backend
-                const folderRecord = {
-                    id: folder.id,
-                    name: folder.name,
-                    parent_id: folder.parent_id,
-                    workspace_id: folder.workspace_id,
-                    created_at: folder.created_at || folder.updated_at, // Use created_at or fallback to updated_at
-                    updated_at: folder.updated_at,
-                    version: 1, // Default version for remote folders
-                    is_deleted: folder.is_deleted || false,
-                };
-                await api.applyRemoteUpdateFolder(folderRecord);
-            }
-            if (remoteData.workspaces) {
-                for (const workspace of remoteData.workspaces) {
-                    // Map server Workspace to WorkspaceRecord format expected by backend
-                    const workspaceRecord = {
-                        id: workspace.id,
-                        name: workspace.name,
-                        color: workspace.color,
-                        created_at: workspace.created_at || workspace.updated_at,
-                        updated_at: workspace.updated_at,
-                        version: 1, // Default version for remote workspaces
-                        is_deleted: workspace.is_deleted || false,
-                    };
-                    await api.applyRemoteUpdateWorkspace(workspaceRecord);
-                }
-            }
+            const status = await api.syncNow();
+            get().applySyncStatus(status);
+            console.log('Sync successful, server time:', status.last_sync_time);
 
-            // 4. Refresh local state
-            await get().initialize();
-
-            const newLastSyncedAt = remoteData.server_time;
-            try {
-                localStorage.setItem('lumenote_lastSyncedAt', newLastSyncedAt.toString());
-            } catch (error) {
-                console.error('Failed to save lastSyncedAt to localStorage:', error);
-            }
-            set({ lastSyncedAt: newLastSyncedAt, hasUnsyncedChanges: false });
-            console.log('Sync successful, server time:', newLastSyncedAt);
-            
             // Show success toast
             toast.success(get().language === 'vi' ? 'Đồng bộ thành công!' : 'Sync successful!');
-        } catch (error: any) {
+        } catch (error) {
             console.error('Sync failed:', error);
-            const errorMessage = error?.message || String(error);
-            console.error('Error details:', { errorMessage, syncUrl, syncKeyPresent: !!syncKey });
-            
+            const message = errorMessage(error, get().language);
+
             // Show error toast
             toast.error(get().language === 'vi' 
-                ? `Đồng bộ thất bại: ${errorMessage}` 
-                : `Sync failed: ${errorMessage}`
+                ? `Đồng bộ thất bại: ${message}` 
+                : `Sync failed: ${message}`
             );
-        } finally {
-            setSyncing(false);
         }
     },
+    applySyncStatus: (status) => {
+        const previous = get().syncState;
+        set({
+            syncState: status.state,
+            isSyncing: status.state === 'syncing',
+            syncError: status.last_error,
+            lastSyncedAt: status.last_sync_time,
+            syncUrl: status.url || get().syncUrl,
+            ...(status.configured && { syncBackend: status.backend, syncUsername: status.username }),
+        });
+
+        // Reload from the database only when the round brought in remote rows
+        if (previous === 'syncing' && status.state === 'idle') {
+            if (status.last_pulled > 0) {
+                get().initialize();
+            } else {
+                get().checkUnsyncedChanges();
+            }
+        }
+    },
+    initSync: async () => {
+        try {
+            let status = await api.getSyncStatus();
+
+            // Hand a config saved by the old webview sync loop over to the backend
+            const { syncUrl, syncKey } = get();
+            if (!status.configured && syncUrl && syncKey) {
+                status = await api.configureSync(syncUrl, syncKey);
+            }
+            get().applySyncStatus(status);
+
+            if (!syncListener) {
+                syncListener = api.onSyncStatus((s) => get().applySyncStatus(s));
+            }
+        } catch (error) {
+            console.error('Failed to initialize sync:', error);
+        }
+    },
+
+    // Resolves to false while an encrypted database waits for its passphrase
+    // or a broken one waits in recovery mode
+    initVault: async () => {
+        if (!lockListener) {
+            lockListener = api.onDatabaseLocked(() => {
+                set({ databaseLocked: true, notes: [], folders: [], activeNoteId: null, searchResults: [] });
+            });
+        }
+        try {
+            const recovery = await api.getRecoveryStatus();
+            if (recovery) {
+                set({ recovery, databaseLocked: false });
+                return false;
+            }
+            const status = await api.getDatabaseStatus();
+            set({ databaseLocked: status.locked });
+            return !status.locked;
+        } catch (error) {
+            console.error('Failed to read database status:', error);
+            return true;
+        }
+    },
+    unlockDatabase: async (passphrase) => {
+        await api.unlockDatabase(passphrase);
+        set({ databaseLocked: false });
+        await get().initialize();
+        await get().initSync();
+    },
+    // Called after a recovery action opened the database, read-only or not
+    leaveRecovery: async () => {
+        const recovery = await api.getRecoveryStatus();
+        set({ recovery });
+        await get().initialize();
+        // A read-only database cannot take remote changes
+        if (!recovery?.read_only) await get().initSync();
+    },
 
     updateNoteContent: async (id, content) => {
         // Clear any existing timeout
@@ -345,6 +389,7 @@ export const useStore = create<AppState>((set, get) => ({
                         created_at: updatedNote.createdAt,
                         updated_at: updatedNote.updatedAt,
                         version: updatedNote.version,
+                        color: updatedNote.color || null,
                         is_deleted: false,
                     });
 
@@ -360,6 +405,8 @@ export const useStore = create<AppState>((set, get) => ({
                             createdAt: r.created_at,
                             updatedAt: r.updated_at,
                     version: r.version,
+                    sortKey: r.sort_key,
+                    color: r.color || undefined,
                 })),
                 unsavedNoteIds: new Set([...get().unsavedNoteIds].filter(nodeId => nodeId !== id))
             });
@@ -402,6 +449,7 @@ export const useStore = create<AppState>((set, get) => ({
             created_at: newNote.createdAt,
             updated_at: newNote.updatedAt,
             version: newNote.version,
+            color: newNote.color || null,
             is_deleted: false,
         }).then(() => {
             // Check for unsynced changes after adding note
@@ -597,6 +645,7 @@ export const useStore = create<AppState>((set, get) => ({
                     created_at: updatedNote.createdAt,
                     updated_at: updatedNote.updatedAt,
                     version: updatedNote.version,
+                    color: updatedNote.color || null,
                     is_deleted: false,
                 }).then(() => {
                     // Refresh notes to get updated updatedAt from backend
@@ -611,6 +660,8 @@ export const useStore = create<AppState>((set, get) => ({
                                 createdAt: r.created_at,
                                 updatedAt: r.updated_at,
                                 version: r.version,
+                                sortKey: r.sort_key,
+                                color: r.color || undefined,
                             }))
                         });
                         get().checkUnsyncedChanges();
@@ -651,6 +702,7 @@ export const useStore = create<AppState>((set, get) => ({
                                 updatedAt: r.updated_at,
                                 version: r.version,
                                 color: r.color || undefined,
+                                sortKey: r.sort_key,
                                 isExpanded: state.folders.find(f => f.id === r.id)?.isExpanded ?? true,
                             }))
                         });
@@ -682,6 +734,7 @@ export const useStore = create<AppState>((set, get) => ({
                     created_at: updatedNote.createdAt,
                     updated_at: updatedNote.updatedAt,
                     version: updatedNote.version,
+                    color: updatedNote.color || null,
                     is_deleted: false,
                 });
             }
@@ -714,14 +767,18 @@ export const useStore = create<AppState>((set, get) => ({
         const oldIndex = state.notes.findIndex((n) => n.id === activeId);
         const newIndex = state.notes.findIndex((n) => n.id === overId);
         if (oldIndex === -1 || newIndex === -1 || oldIndex === newIndex) return state;
-        return { notes: arrayMove(state.notes, oldIndex, newIndex) };
+        const notes = arrayMove(state.notes, oldIndex, newIndex);
+        persistOrder('note', notes, activeId, n => n.folderId);
+        return { notes };
     }),
 
     reorderFolders: (activeId, overId) => set((state) => {
         const oldIndex = state.folders.findIndex((f) => f.id === activeId);
         const newIndex = state.folders.findIndex((f) => f.id === overId);
         if (oldIndex === -1 || newIndex === -1 || oldIndex === newIndex) return state;
-        return { folders: arrayMove(state.folders, oldIndex, newIndex) };
+        const folders = arrayMove(state.folders, oldIndex, newIndex);
+        persistOrder('folder', folders, activeId, f => f.parentId);
+        return { folders };
     }),
 
     moveNoteToFolder: (noteId, folderId) => {
@@ -729,21 +786,9 @@ export const useStore = create<AppState>((set, get) => ({
             const notes = state.notes.map((n) => n.id === noteId ? { ...n, folderId } : n);
             const updatedNote = notes.find(n => n.id === noteId);
             if (updatedNote) {
-                if (saveTimeout) {
-                    clearTimeout(saveTimeout);
-                    saveTimeout = null;
-                }
-                api.upsertNote({
-                    id: updatedNote.id,
-                    title: updatedNote.title,
-                    content: updatedNote.content,
-                    folder_id: updatedNote.folderId || null,
-                    workspace_id: updatedNote.workspaceId,
-                    created_at: updatedNote.createdAt,
-                    updated_at: updatedNote.updatedAt,
-                    version: updatedNote.version,
-                    is_deleted: false,
-                });
+                pendingMove = api.moveItems([noteId], [], updatedNote.workspaceId, folderId)
+                    .then(() => setTimeout(() => get().checkUnsyncedChanges(), 100))
+                    .catch(console.error);
             }
             return { notes };
         });
@@ -762,21 +807,53 @@ export const useStore = create<AppState>((set, get) => ({
         const folders = state.folders.map((f) => f.id === folderId ? { ...f, parentId: targetFolderId } : f);
         const updatedFolder = folders.find(f => f.id === folderId);
         if (updatedFolder) {
-            api.upsertFolder({
-                id: updatedFolder.id,
-                name: updatedFolder.name,
-                parent_id: updatedFolder.parentId || null,
-                workspace_id: updatedFolder.workspaceId,
-                created_at: updatedFolder.createdAt,
-                updated_at: updatedFolder.updatedAt,
-                version: updatedFolder.version,
-                color: updatedFolder.color || null,
-                is_deleted: false,
-            });
+            pendingMove = api.moveItems([], [folderId], updatedFolder.workspaceId, targetFolderId)
+                .then(() => setTimeout(() => get().checkUnsyncedChanges(), 100))
+                .catch(console.error);
         }
         return { folders };
     }),
 
+    duplicateNote: async (id) => {
+        // Copy what the editor shows, not the last autosave
+        await get().saveNote(id);
+        try {
+            const r = await api.duplicateNote(id);
+            const copy: Note = {
+                id: r.id,
+                title: r.title,
+                content: r.content,
+                folderId: r.folder_id,
+                workspaceId: r.workspace_id,
+                createdAt: r.created_at,
+                updatedAt: r.updated_at,
+                version: r.version,
+                sortKey: r.sort_key,
+                color: r.color || undefined,
+            };
+            set((state) => {
+                const notes = [...state.notes];
+                notes.splice(notes.findIndex(n => n.id === id) + 1, 0, copy);
+                return { notes };
+            });
+            get().setActiveNoteId(copy.id);
+            setTimeout(() => get().checkUnsyncedChanges(), 100);
+        } catch (error) {
+            console.error('Failed to duplicate note:', error);
+        }
+    },
+
+    duplicateFolder: async (id) => {
+        const folder = get().folders.find(f => f.id === id);
+        if (!folder) return;
+        try {
+            await api.copyFolderTree(id, folder.workspaceId, folder.parentId || null);
+            await get().initialize();
+        } catch (error) {
+            console.error('Failed to duplicate folder:', error);
+        }
+    },
+
     searchNotes: async (query) => {
         if (!query.trim()) {
             set({ searchResults: [] });
@@ -811,6 +888,7 @@ export const useStore = create<AppState>((set, get) => ({
                     created_at: note.createdAt,
                     updated_at: note.updatedAt,
                     version: note.version,
+                    color: note.color || null,
                     is_deleted: false,
                 });
 
@@ -825,6 +903,8 @@ export const useStore = create<AppState>((set, get) => ({
                         createdAt: r.created_at,
                         updatedAt: r.updated_at,
                         version: r.version,
+                        sortKey: r.sort_key,
+                        color: r.color || undefined,
                     })),
                     unsavedNoteIds: new Set([...get().unsavedNoteIds].filter(nodeId => nodeId !== id))
                 });
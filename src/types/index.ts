@@ -12,6 +12,7 @@ export interface Note {
     folderId?: string | null;
     workspaceId: string; // Required for all notes
     index?: number;
+    sortKey?: string;
     createdAt: number;
     updatedAt: number;
     color?: string;
@@ -24,6 +25,7 @@ export interface Folder {
     parentId?: string | null;
     workspaceId: string;
     index?: number;
+    sortKey?: string;
     isExpanded?: boolean;
     createdAt: number;
     updatedAt: number;
@@ -1,4 +1,5 @@
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 
 export interface NoteRecord {
     id: string;
@@ -9,6 +10,9 @@ export interface NoteRecord {
     created_at: number;
     updated_at: number;
     version: number;
+    // Position among siblings; the backend assigns it, see `reorder`
+    sort_key?: string;
+    color?: string | null;
     is_deleted?: boolean;
 }
 
@@ -21,9 +25,59 @@ export interface FolderRecord {
     updated_at: number;
     version: number;
     color?: string | null;
+    sort_key?: string;
     is_deleted?: boolean;
 }
 
+export type ItemKind = 'note' | 'folder';
+
+export type BatchOp =
+    | { op: 'upsert_note'; note: NoteRecord }
+    | { op: 'delete_note'; id: string }
+    | { op: 'upsert_folder'; folder: FolderRecord }
+    | { op: 'delete_folder'; id: string }
+    | { op: 'upsert_workspace'; workspace: WorkspaceRecord }
+    | { op: 'delete_workspace'; id: string };
+
+export type ImportInvalidReason =
+    | { reason: 'archive'; message: string }
+    | { reason: 'invalid_manifest'; message: string }
+    | { reason: 'empty' }
+    | { reason: 'too_many_entries'; count: number; limit: number }
+    | { reason: 'too_large'; limit: number }
+    | { reason: 'entry_too_large'; path: string; limit: number }
+    | { reason: 'path_too_deep'; path: string; limit: number }
+    | { reason: 'compression_ratio'; path: string; ratio: number; limit: number }
+    | { reason: 'unsafe_path'; path: string };
+
+// What commands reject with; see `errorMessage` for display
+export type AppError =
+    | { kind: 'locked'; target: 'database' | 'sync' }
+    | { kind: 'not_found'; entity: 'workspace' | 'folder' | 'note' | 'backup'; id: string }
+    | { kind: 'conflict'; message: string }
+    | { kind: 'validation'; field: string; message: string }
+    | { kind: 'io'; path: string | null; message: string }
+    | { kind: 'database'; message: string }
+    | ({ kind: 'import_invalid' } & ImportInvalidReason)
+    | { kind: 'cancelled' }
+    | { kind: 'not_configured' }
+    | { kind: 'offline'; message: string }
+    | { kind: 'remote'; message: string }
+    | { kind: 'internal'; message: string };
+
+export interface OpResult {
+    id: string;
+    status: 'applied' | 'rolled_back' | 'failed' | 'skipped';
+    version: number | null;
+    error: AppError | null;
+}
+
+export interface BatchResult {
+    // False when an operation failed; then nothing was written
+    committed: boolean;
+    results: OpResult[];
+}
+
 export interface WorkspaceRecord {
     id: string;
     name: string;
@@ -40,6 +94,217 @@ export interface SyncDataResponse {
     workspaces: WorkspaceRecord[];
 }
 
+export type DuplicateStrategy = 'skip' | 'overwrite' | 'keep_both' | 'merge_by_id';
+
+export interface ImportSummary {
+    workspace_id: string;
+    created: number;
+    updated: number;
+    skipped: number;
+    renamed: number;
+}
+
+export interface SiteSummary {
+    pages: number;
+    attachments: number;
+    // Local links left out of the site, as written in the notes
+    skipped_links: string[];
+}
+
+export interface ImportProgress {
+    job_id: string;
+    processed: number;
+    total: number;
+    current_path: string;
+}
+
+// Can only tighten the built-in limits; looser values are capped
+export interface ImportLimits {
+    max_entries?: number;
+    max_total_bytes?: number;
+    max_entry_bytes?: number;
+    max_depth?: number;
+    max_compression_ratio?: number;
+}
+
+export const IMPORT_PROGRESS_EVENT = 'import-progress';
+
+export type SyncState = 'idle' | 'syncing' | 'error' | 'offline';
+
+// The Lumenote server, or any WebDAV storage such as Nextcloud
+export type SyncBackend = 'server' | 'webdav';
+
+export interface SyncStatus {
+    state: SyncState;
+    configured: boolean;
+    backend: SyncBackend;
+    url: string;
+    username: string;
+    interval_secs: number;
+    last_sync_time: number | null;
+    last_pushed: number;
+    last_pulled: number;
+    last_error: string | null;
+    next_retry_at: number | null;
+}
+
+export const SYNC_STATUS_EVENT = 'sync-status';
+
+export interface EncryptionStatus {
+    enabled: boolean;
+    unlocked: boolean;
+}
+
+export interface DatabaseStatus {
+    encrypted: boolean;
+    locked: boolean;
+    auto_lock_minutes: number | null;
+}
+
+export const DATABASE_LOCKED_EVENT = 'database-locked';
+
+// Single-use permission to read or write the file the user picked in a
+// native dialog; commands that touch user files take the token, never a path
+export interface FileGrant {
+    token: string;
+    path: string;
+}
+
+export interface DialogOptions {
+    title?: string;
+    default_name?: string;
+    filters?: { name: string; extensions: string[] }[];
+}
+
+export type ExportTarget = 'zip' | 'directory';
+
+// Folders bring everything inside them; tags are matched without the '#'
+export type ExportSelection =
+    | { kind: 'items'; ids: string[] }
+    | { kind: 'tag'; tag: string }
+    | { kind: 'query'; query: string };
+
+export interface ExportOptions {
+    selection?: ExportSelection;
+    front_matter?: boolean;
+    line_endings?: 'keep' | 'lf' | 'crlf';
+    include_deleted?: boolean;
+}
+
+export type DocumentFormat = 'pdf' | 'docx' | 'epub';
+
+export interface MirrorSummary {
+    // Changes taken over from the files
+    imported: number;
+    // Files written or removed for changes made in the app
+    exported: number;
+    // Files edited on both sides; each became a separate "(conflict)" note
+    conflicts: number;
+    // Markdown files that are not UTF-8 text; skipped, their notes left alone
+    unreadable: string[];
+}
+
+export interface HistorySettings {
+    repo: string;
+    // URL or path of a repository to push to after each commit
+    remote: string | null;
+    last_run_at: number | null;
+    last_error: string | null;
+}
+
+export interface HistoryOptions {
+    // From pickDirectory; null keeps the current repository
+    token: string | null;
+    // A new remote is confirmed by the user in a native dialog; declining rejects with 'cancelled'
+    remote: string | null;
+}
+
+export interface HistoryCommit {
+    id: string;
+    message: string;
+    committed_at: number;
+}
+
+export interface HistoryNote {
+    id: string;
+    title: string;
+    content: string;
+    folder_id: string | null;
+    path: string;
+    commit: HistoryCommit;
+}
+
+export interface MirrorStatus {
+    workspace_id: string;
+    dir: string;
+    last_pass_at: number | null;
+    last_summary: MirrorSummary | null;
+    last_error: string | null;
+}
+
+export type RecoveryProblem = 'busy' | 'corrupt' | 'incompatible' | 'inaccessible' | 'other';
+
+// Present when the database could not be opened at startup
+export interface Recovery {
+    problem: RecoveryProblem;
+    error: AppError;
+    read_only: boolean;
+}
+
+export type BackupKind = 'auto' | 'manual' | 'pre_migration' | 'pre_restore' | 'pre_repair';
+
+export interface BackupInfo {
+    file_name: string;
+    kind: BackupKind;
+    created_at: number;
+    size_bytes: number;
+}
+
+export interface BackupSettings {
+    interval_hours: number;
+    retention: number;
+    // Why the last automatic backup failed; null once one succeeds
+    last_error: string | null;
+}
+
+// Records written to or restored from a `lumenote-backup` file
+export interface FullBackupSummary {
+    workspaces: number;
+    folders: number;
+    notes: number;
+    changes: number;
+    settings: number;
+}
+
+export interface BackupVerification {
+    file_name: string;
+    ok: boolean;
+    schema_version: number | null;
+    notes: number | null;
+    error: string | null;
+}
+
+export interface HealthReport {
+    healthy: boolean;
+    integrity_errors: string[];
+    fts_missing: string[];
+    fts_stale: string[];
+    orphan_notes: string[];
+    orphan_folders: string[];
+    missing_workspace_notes: string[];
+    missing_workspace_folders: string[];
+    folder_cycles: string[][];
+    notes_in_deleted_workspaces: string[];
+}
+
+export interface RepairSummary {
+    backup: string;
+    cycles_broken: number;
+    items_recovered: number;
+    fts_rebuilt: boolean;
+    report: HealthReport;
+}
+
 export interface SearchResult {
     id: string;
     title: string;
@@ -54,6 +319,18 @@ export const api = {
     getFolders: () => invoke<FolderRecord[]>('get_folders'),
     upsertFolder: (folder: FolderRecord) => invoke<void>('upsert_folder', { folder }),
     deleteFolder: (id: string) => invoke<void>('delete_folder', { id }),
+    // Runs all operations in one transaction, all or nothing
+    applyBatch: (operations: BatchOp[]) => invoke<BatchResult>('apply_batch', { operations }),
+    // Moves notes and folders (with their contents) into a folder, or the top level when folderId is null
+    moveItems: (noteIds: string[], folderIds: string[], workspaceId: string, folderId: string | null) =>
+        invoke<void>('move_items', { noteIds, folderIds, workspaceId, folderId }),
+    duplicateNote: (id: string) => invoke<NoteRecord>('duplicate_note', { id }),
+    // Resolves to the new top folder of the copy
+    copyFolderTree: (id: string, workspaceId: string, folderId: string | null) =>
+        invoke<FolderRecord>('copy_folder_tree', { id, workspaceId, folderId }),
+    // Moves an item right after a sibling (or to the top); resolves to its new sort key
+    reorder: (kind: ItemKind, id: string, afterId: string | null) =>
+        invoke<string>('reorder', { kind, id, afterId }),
 
     getWorkspaces: () => invoke<WorkspaceRecord[]>('get_workspaces'),
     upsertWorkspace: (workspace: WorkspaceRecord) => invoke<void>('upsert_workspace', { workspace }),
@@ -65,36 +342,96 @@ export const api = {
     applyRemoteUpdateWorkspace: (workspace: WorkspaceRecord) => invoke<void>('apply_remote_update_workspace', { workspace }),
 
     searchNotes: (query: string) => invoke<SearchResult[]>('search_notes', { query }),
-    exportWorkspace: (workspaceId: string, basePath: string) => invoke<void>('export_workspace', { workspaceId, basePath }),
-    importWorkspace: (zipPath: string, workspaceName?: string) => invoke<string>('import_workspace', { zipPath, workspaceName }),
-
-    // Sync Server API
-    syncWithServer: async (url: string, syncKey: string, payload: any) => {
-        console.log('Syncing with server:', { url, syncKeyLength: syncKey?.length, payloadSize: JSON.stringify(payload).length });
-        
-        const response = await fetch(`${url}/sync`, {
-            method: 'POST',
-            headers: {
-                'Content-Type': 'application/json',
-                'X-Sync-Key': syncKey,
-            },
-            body: JSON.stringify(payload),
-        });
-        
-        if (!response.ok) {
-            const errorText = await response.text().catch(() => response.statusText);
-            console.error('Sync failed:', {
-                status: response.status,
-                statusText: response.statusText,
-                errorText,
-                url,
-                syncKeyPresent: !!syncKey,
-            });
-            throw new Error(`Sync failed (${response.status}): ${errorText || response.statusText}`);
-        }
-        
-        const data = await response.json();
-        console.log('Sync response received:', { serverTime: data.server_time, notesCount: data.notes?.length, foldersCount: data.folders?.length });
-        return data;
-    }
+    // Resolve to null when the user cancels the dialog
+    pickSavePath: (options?: DialogOptions) => invoke<FileGrant | null>('pick_save_path', { options }),
+    pickOpenPath: (options?: DialogOptions) => invoke<FileGrant | null>('pick_open_path', { options }),
+    pickDirectory: (options?: DialogOptions) => invoke<FileGrant | null>('pick_directory', { options }),
+    pickOpenDirectory: (options?: DialogOptions) => invoke<FileGrant | null>('pick_open_directory', { options }),
+    writeTextFile: (token: string, content: string) => invoke<void>('write_text_file', { token, content }),
+    // The token comes from pickSavePath for 'zip' and pickDirectory for 'directory'
+    exportWorkspace: (workspaceId: string, token: string, target: ExportTarget = 'zip', options?: ExportOptions) =>
+        invoke<void>('export_workspace', { workspaceId, token, target, options }),
+    // attachmentsToken from pickOpenDirectory; without one local files are left out
+    exportSite: (workspaceId: string, target: ExportTarget, token: string, attachmentsToken: string | null = null) =>
+        invoke<SiteSummary>('export_site', { workspaceId, target, token, attachmentsToken }),
+    exportNoteDocument: (noteId: string, format: DocumentFormat, token: string) =>
+        invoke<void>('export_note_document', { noteId, format, token }),
+    // A null folderId exports the whole workspace as one book
+    exportFolderDocument: (workspaceId: string, folderId: string | null, format: DocumentFormat, token: string) =>
+        invoke<void>('export_folder_document', { workspaceId, folderId, format, token }),
+    // Token from pickDirectory; without one the workspace stops being mirrored
+    configureMirror: (workspaceId: string, token: string | null) =>
+        invoke<MirrorStatus | null>('configure_mirror', { workspaceId, token }),
+    getMirrorStatus: () => invoke<MirrorStatus[]>('get_mirror_status'),
+    mirrorNow: (workspaceId: string) => invoke<MirrorSummary>('mirror_now', { workspaceId }),
+    // Without options the workspace's history is turned off; the repository is kept
+    configureHistory: (workspaceId: string, options: HistoryOptions | null) =>
+        invoke<HistorySettings | null>('configure_history', { workspaceId, options }),
+    getHistorySettings: (workspaceId: string) =>
+        invoke<HistorySettings | null>('get_history_settings', { workspaceId }),
+    listHistory: (workspaceId: string, noteId?: string, limit?: number) =>
+        invoke<HistoryCommit[]>('list_history', { workspaceId, noteId, limit }),
+    getHistoryNote: (workspaceId: string, commitId: string, noteId: string) =>
+        invoke<HistoryNote>('get_history_note', { workspaceId, commitId, noteId }),
+    restoreNoteFromHistory: (workspaceId: string, commitId: string, noteId: string) =>
+        invoke<NoteRecord>('restore_note_from_history', { workspaceId, commitId, noteId }),
+    importWorkspace: (token: string, workspaceName?: string, jobId?: string, limits?: ImportLimits) =>
+        invoke<string>('import_workspace', { token, workspaceName, jobId, limits }),
+    importIntoWorkspace: (token: string, workspaceId: string, folderId?: string | null, strategy?: DuplicateStrategy, jobId?: string, limits?: ImportLimits) =>
+        invoke<ImportSummary>('import_into_workspace', { token, workspaceId, folderId, strategy, jobId, limits }),
+    cancelImport: (jobId: string) => invoke<boolean>('cancel_import', { jobId }),
+
+    syncNow: () => invoke<SyncStatus>('sync_now'),
+    getSyncStatus: () => invoke<SyncStatus>('get_sync_status'),
+    // For WebDAV the key is the account password
+    configureSync: (url: string, key: string, intervalSecs?: number, backend: SyncBackend = 'server', username = '') =>
+        invoke<SyncStatus>('configure_sync', { url, key, intervalSecs, backend, username }),
+    onSyncStatus: (callback: (status: SyncStatus) => void) =>
+        listen<SyncStatus>(SYNC_STATUS_EVENT, (event) => callback(event.payload)),
+
+    getEncryptionStatus: () => invoke<EncryptionStatus>('get_encryption_status'),
+    // Resolves to the recovery key, which is only shown once
+    enableEncryption: (passphrase: string) => invoke<string>('enable_encryption', { passphrase }),
+    unlockEncryption: (passphrase: string) => invoke<EncryptionStatus>('unlock_encryption', { passphrase }),
+    lockEncryption: () => invoke<EncryptionStatus>('lock_encryption'),
+    changeEncryptionPassphrase: (currentPassphrase: string, newPassphrase: string) =>
+        invoke<void>('change_encryption_passphrase', { currentPassphrase, newPassphrase }),
+    resetPassphraseWithRecoveryKey: (recoveryKey: string, newPassphrase: string) =>
+        invoke<void>('reset_passphrase_with_recovery_key', { recoveryKey, newPassphrase }),
+    exportRecoveryKey: (passphrase: string) => invoke<string>('export_recovery_key', { passphrase }),
+
+    getDatabaseStatus: () => invoke<DatabaseStatus>('get_database_status'),
+    unlockDatabase: (passphrase: string) => invoke<DatabaseStatus>('unlock_database', { passphrase }),
+    lockDatabase: () => invoke<DatabaseStatus>('lock_database'),
+    enableDatabaseEncryption: (passphrase: string) =>
+        invoke<DatabaseStatus>('enable_database_encryption', { passphrase }),
+    disableDatabaseEncryption: (passphrase: string) =>
+        invoke<DatabaseStatus>('disable_database_encryption', { passphrase }),
+    // null or 0 turns auto-lock off
+    setAutoLock: (minutes: number | null) => invoke<DatabaseStatus>('set_auto_lock', { minutes }),
+    onDatabaseLocked: (callback: () => void) => listen<null>(DATABASE_LOCKED_EVENT, () => callback()),
+
+    getRecoveryStatus: () => invoke<Recovery | null>('get_recovery_status'),
+    retryOpenDatabase: () => invoke<void>('retry_open_database'),
+    openDatabaseReadOnly: () => invoke<void>('open_database_read_only'),
+    // Both resolve to the name the broken database was moved to in the data dir
+    recoverFromBackup: (fileName: string) => invoke<string>('recover_from_backup', { fileName }),
+    startFreshDatabase: () => invoke<string>('start_fresh_database'),
+
+    listBackups: () => invoke<BackupInfo[]>('list_backups'),
+    createBackup: () => invoke<BackupInfo>('create_backup'),
+    verifyBackup: (fileName: string) => invoke<BackupVerification>('verify_backup', { fileName }),
+    // Resolves to the safety copy of the database that was replaced
+    restoreBackup: (fileName: string) => invoke<BackupInfo>('restore_backup', { fileName }),
+    getBackupSettings: () => invoke<BackupSettings>('get_backup_settings'),
+    setBackupSettings: (settings: BackupSettings) => invoke<BackupSettings>('set_backup_settings', { settings }),
+    // Token from pickSavePath; compressed backups are zstd
+    exportFullBackup: (token: string, compress: boolean) =>
+        invoke<FullBackupSummary>('export_full_backup', { token, compress }),
+    // Token from pickOpenPath; plain and compressed backups are both read
+    importFullBackup: (token: string) => invoke<FullBackupSummary>('import_full_backup', { token }),
+
+    checkDatabase: () => invoke<HealthReport>('check_database'),
+    // Takes a backup first; the summary carries the report after repairing
+    repairDatabase: () => invoke<RepairSummary>('repair_database'),
 };
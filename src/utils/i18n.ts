@@ -1,3 +1,5 @@
+import type { AppError } from './api';
+
 export type Language = 'vi' | 'en';
 
 export const translations = {
@@ -28,6 +30,7 @@ export const translations = {
     all_notes: "Tất cả ghi chú",
     trash: "Thùng rác",
     rename: "Đổi tên",
+    duplicate: "Nhân bản",
     delete: "Xoá",
     move: "Di chuyển",
     export: "Xuất file",
@@ -76,6 +79,24 @@ export const translations = {
     editor_placeholder: "Bắt đầu viết điều gì đó tuyệt vời...",
     no_results: "Không tìm thấy kết quả nào",
     export_markdown: "Xuất workspace (.zip)",
+    export_markdown_folder: "Xuất workspace vào thư mục",
+    export_markdown_item: "Xuất Markdown (.zip)",
+    export_site_zip: "Xuất trang web (.zip)",
+    export_site_folder: "Xuất trang web vào thư mục",
+    export_site_attachments_confirm: "Sao chép ảnh và tệp media cục bộ vào trang web? Bạn sẽ chọn thư mục chứa chúng.",
+    export_site_attachments_folder: "Thư mục chứa ảnh để xuất bản",
+    export_site_skipped: "{count} liên kết tới tệp cục bộ đã bị bỏ khỏi trang web",
+    git_history: "Lưu lịch sử Git...",
+    git_history_started: "Các thay đổi sẽ được lưu vào lịch sử Git sau mỗi lần đồng bộ",
+    git_history_stop_confirm: "Ngừng lưu lịch sử Git vào {dir}? Kho lưu trữ vẫn được giữ lại.",
+    git_history_stopped: "Đã ngừng lưu lịch sử Git",
+    mirror_folder: "Đồng bộ với thư mục...",
+    mirror_started: "Workspace đang được đồng bộ với thư mục",
+    mirror_stop_confirm: "Ngừng đồng bộ với {dir}? Các tệp trong thư mục vẫn được giữ lại.",
+    mirror_stopped: "Đã ngừng đồng bộ thư mục",
+    export_pdf: "Xuất PDF",
+    export_docx: "Xuất Word (.docx)",
+    export_epub: "Xuất sách EPUB",
     export_success: "Xuất dữ liệu thành công!",
     export_failed: "Xuất dữ liệu thất bại",
     import_workspace: "Nhập workspace (.zip)",
@@ -85,6 +106,10 @@ export const translations = {
     sync: "Đồng bộ",
     sync_desc: "Đồng bộ hóa",
     server_url: "Địa chỉ Server",
+    sync_backend_server: "Server Lumenote",
+    sync_backend_webdav: "WebDAV",
+    webdav_username: "Tên đăng nhập",
+    webdav_password: "Mật khẩu",
     sync_key: "Mã bảo mật (Key)",
     connect: "Kết nối",
     sync_now: "Đồng bộ ngay",
@@ -102,6 +127,73 @@ export const translations = {
     update_available: "Có bản cập nhật mới!",
     up_to_date: "Đã là bản mới nhất",
     update_error: "Lỗi kiểm tra",
+    db_encryption: "Mã hóa dữ liệu",
+    db_locked: "Dữ liệu đang bị khóa",
+    db_locked_desc: "Nhập mật khẩu để mở khóa ghi chú.",
+    passphrase: "Mật khẩu",
+    unlock: "Mở khóa",
+    lock_now: "Khóa ngay",
+    encrypt_db: "Bật mã hóa",
+    decrypt_db: "Tắt mã hóa",
+    auto_lock: "Tự động khóa",
+    auto_lock_off: "Tắt",
+    backups: "Sao lưu",
+    backup_now: "Sao lưu ngay",
+    backup_frequency: "Tần suất",
+    backup_daily: "Hằng ngày",
+    backup_weekly: "Hằng tuần",
+    backup_failed: "Lần sao lưu tự động gần nhất thất bại: {error}",
+    no_backups: "Chưa có bản sao lưu",
+    restore: "Khôi phục",
+    restore_confirm: "Khôi phục bản sao lưu này? Dữ liệu hiện tại sẽ được giữ lại thành một bản sao lưu riêng.",
+    restore_success: "Đã khôi phục bản sao lưu",
+    export_full_backup: "Xuất bản sao lưu đầy đủ",
+    export_full_backup_unencrypted: "Bản sao lưu đầy đủ không được mã hóa, kể cả khi cơ sở dữ liệu đã mã hóa. Ai có tệp này đều đọc được ghi chú và khóa đồng bộ. Vẫn xuất?",
+    import_full_backup: "Khôi phục từ bản sao lưu đầy đủ",
+    import_full_backup_confirm: "Khôi phục bản sao lưu này? Ghi chú trùng mã sẽ giữ phiên bản được sửa gần nhất.",
+    check_database: "Kiểm tra dữ liệu",
+    database_healthy: "Dữ liệu không có lỗi",
+    repair_confirm: "Phát hiện {count} vấn đề. Sửa ngay? Một bản sao lưu sẽ được tạo trước.",
+    repair_success: "Đã sửa dữ liệu",
+    repair_incomplete: "Một số lỗi không thể tự sửa",
+    err_locked_database: "Cơ sở dữ liệu đang bị khóa",
+    err_locked_sync: "Đồng bộ mã hóa đang bị khóa: hãy nhập mật khẩu",
+    err_not_found: "Không tìm thấy {entity}",
+    err_conflict: "Thao tác không hợp lệ: {message}",
+    err_validation: "Giá trị không hợp lệ ({field}): {message}",
+    err_io: "Lỗi đọc/ghi tệp: {message}",
+    err_database: "Lỗi cơ sở dữ liệu: {message}",
+    err_cancelled: "Đã hủy",
+    err_not_configured: "Chưa cấu hình đồng bộ",
+    err_offline: "Không kết nối được máy chủ đồng bộ",
+    err_remote: "Máy chủ đồng bộ báo lỗi: {message}",
+    err_import_archive: "Không đọc được tệp ZIP: {message}",
+    err_import_invalid_manifest: "Tệp mô tả trong ZIP không hợp lệ",
+    err_import_empty: "ZIP phải chứa ít nhất một tệp .md hoặc thư mục",
+    err_import_too_many_entries: "ZIP có {count} mục, vượt giới hạn {limit}",
+    err_import_too_large: "Nội dung ZIP vượt giới hạn {limit} byte",
+    err_import_entry_too_large: "Tệp {path} vượt giới hạn {limit} byte",
+    err_import_path_too_deep: "Đường dẫn lồng quá {limit} cấp: {path}",
+    err_import_compression_ratio: "Tệp nén bất thường (tỉ lệ {ratio}:1): {path}",
+    err_import_unsafe_path: "Đường dẫn không an toàn trong ZIP: {path}",
+    entity_workspace: "không gian làm việc",
+    entity_folder: "thư mục",
+    entity_note: "ghi chú",
+    entity_backup: "bản sao lưu",
+    recovery_title: "Không mở được dữ liệu",
+    recovery_busy: "Tệp dữ liệu đang bị một chương trình khác sử dụng. Hãy đóng chương trình đó rồi thử lại.",
+    recovery_corrupt: "Tệp dữ liệu bị hỏng. Hãy khôi phục từ bản sao lưu, mở ở chế độ chỉ đọc để lấy lại ghi chú, hoặc bắt đầu lại.",
+    recovery_incompatible: "Dữ liệu được tạo bởi phiên bản Lumenote mới hơn. Hãy cập nhật ứng dụng hoặc mở ở chế độ chỉ đọc.",
+    recovery_inaccessible: "Không thể đọc hoặc ghi thư mục dữ liệu. Hãy kiểm tra quyền truy cập rồi thử lại.",
+    recovery_other: "Đã xảy ra lỗi khi mở dữ liệu.",
+    recovery_retry: "Thử lại",
+    recovery_read_only: "Chỉ đọc",
+    recovery_start_fresh: "Bắt đầu lại",
+    recovery_restore_confirm: "Khôi phục bản sao lưu này? Tệp dữ liệu hỏng sẽ được giữ lại trong thư mục dữ liệu.",
+    recovery_start_fresh_confirm: "Tạo dữ liệu trống mới? Tệp dữ liệu hỏng sẽ được giữ lại trong thư mục dữ liệu.",
+    recovery_moved_aside: "Tệp dữ liệu cũ được giữ lại với tên {file}",
+    recovery_read_only_banner: "Dữ liệu đang mở ở chế độ chỉ đọc, mọi thay đổi sẽ không được lưu.",
+    recovery_resolve: "Khắc phục",
   },
   en: {
     settings: "Settings",
@@ -130,6 +222,7 @@ export const translations = {
     all_notes: "All Notes",
     trash: "Trash",
     rename: "Rename",
+    duplicate: "Duplicate",
     delete: "Delete",
     move: "Move",
     export: "Export",
@@ -178,6 +271,24 @@ export const translations = {
     editor_placeholder: "Start writing something awesome...",
     no_results: "No results found",
     export_markdown: "Export workspace (.zip)",
+    export_markdown_folder: "Export workspace to folder",
+    export_markdown_item: "Export as Markdown (.zip)",
+    export_site_zip: "Export website (.zip)",
+    export_site_folder: "Export website to folder",
+    export_site_attachments_confirm: "Copy local images and media into the website? You will pick the folder they are stored in.",
+    export_site_attachments_folder: "Folder with images to publish",
+    export_site_skipped: "{count} links to local files were left out of the website",
+    git_history: "Keep Git history...",
+    git_history_started: "Changes are now committed to Git after each sync",
+    git_history_stop_confirm: "Stop keeping Git history in {dir}? The repository is kept.",
+    git_history_stopped: "Stopped keeping Git history",
+    mirror_folder: "Mirror to folder...",
+    mirror_started: "The workspace is now mirrored to the folder",
+    mirror_stop_confirm: "Stop mirroring to {dir}? The files in the folder are kept.",
+    mirror_stopped: "Stopped mirroring",
+    export_pdf: "Export as PDF",
+    export_docx: "Export as Word (.docx)",
+    export_epub: "Export as EPUB book",
     export_success: "Export successful!",
     export_failed: "Export failed",
     import_workspace: "Import workspace (.zip)",
@@ -187,6 +298,10 @@ export const translations = {
     sync: "Sync",
     sync_desc: "Synchronization",
     server_url: "Server URL",
+    sync_backend_server: "Lumenote server",
+    sync_backend_webdav: "WebDAV",
+    webdav_username: "Username",
+    webdav_password: "Password",
     sync_key: "Sync Key (Token)",
     connect: "Connect",
     sync_now: "Sync Now",
@@ -204,6 +319,73 @@ export const translations = {
     update_available: "New version available!",
     up_to_date: "Up to date",
     update_error: "Update error",
+    db_encryption: "Database encryption",
+    db_locked: "Database is locked",
+    db_locked_desc: "Enter your passphrase to unlock your notes.",
+    passphrase: "Passphrase",
+    unlock: "Unlock",
+    lock_now: "Lock now",
+    encrypt_db: "Encrypt database",
+    decrypt_db: "Remove encryption",
+    auto_lock: "Auto-lock",
+    auto_lock_off: "Off",
+    backups: "Backups",
+    backup_now: "Back up now",
+    backup_frequency: "Frequency",
+    backup_daily: "Daily",
+    backup_weekly: "Weekly",
+    backup_failed: "The last automatic backup failed: {error}",
+    no_backups: "No backups yet",
+    restore: "Restore",
+    restore_confirm: "Restore this backup? Your current data will be kept as a separate backup.",
+    restore_success: "Backup restored",
+    export_full_backup: "Export full backup",
+    export_full_backup_unencrypted: "Full backups are not encrypted, even though the database is. Anyone with the file can read your notes and sync key. Export anyway?",
+    import_full_backup: "Restore from full backup",
+    import_full_backup_confirm: "Restore this backup? Where a note exists on both sides, the most recently edited version is kept.",
+    check_database: "Check database",
+    database_healthy: "No problems found",
+    repair_confirm: "Found {count} problem(s). Repair now? A backup is taken first.",
+    repair_success: "Database repaired",
+    repair_incomplete: "Some problems could not be repaired",
+    err_locked_database: "Database is locked",
+    err_locked_sync: "Encrypted sync is locked: enter your passphrase",
+    err_not_found: "The {entity} no longer exists",
+    err_conflict: "Not allowed: {message}",
+    err_validation: "Invalid {field}: {message}",
+    err_io: "File error: {message}",
+    err_database: "Database error: {message}",
+    err_cancelled: "Cancelled",
+    err_not_configured: "Sync is not configured",
+    err_offline: "Sync server unreachable",
+    err_remote: "Sync server error: {message}",
+    err_import_archive: "Could not read the ZIP file: {message}",
+    err_import_invalid_manifest: "The ZIP's manifest is invalid",
+    err_import_empty: "The ZIP must contain at least one .md file or folder",
+    err_import_too_many_entries: "The ZIP has {count} entries, more than the limit of {limit}",
+    err_import_too_large: "The ZIP's content exceeds the limit of {limit} bytes",
+    err_import_entry_too_large: "{path} is larger than the limit of {limit} bytes",
+    err_import_path_too_deep: "Path nested deeper than {limit} levels: {path}",
+    err_import_compression_ratio: "Suspicious compression ratio ({ratio}:1): {path}",
+    err_import_unsafe_path: "Unsafe path in the ZIP: {path}",
+    entity_workspace: "workspace",
+    entity_folder: "folder",
+    entity_note: "note",
+    entity_backup: "backup",
+    recovery_title: "Your notes could not be opened",
+    recovery_busy: "The database is in use by another program. Close it and try again.",
+    recovery_corrupt: "The database file is damaged. Restore a backup, open it read-only to rescue your notes, or start fresh.",
+    recovery_incompatible: "The database was created by a newer version of Lumenote. Update the app or open it read-only.",
+    recovery_inaccessible: "The data folder cannot be read or written. Check its permissions and try again.",
+    recovery_other: "Something went wrong while opening the database.",
+    recovery_retry: "Try again",
+    recovery_read_only: "Read-only",
+    recovery_start_fresh: "Start fresh",
+    recovery_restore_confirm: "Restore this backup? The broken database is kept in the data folder.",
+    recovery_start_fresh_confirm: "Start with an empty database? The broken database is kept in the data folder.",
+    recovery_moved_aside: "The old database was kept as {file}",
+    recovery_read_only_banner: "The database is open read-only; changes will not be saved.",
+    recovery_resolve: "Resolve",
   }
 };
 
@@ -218,3 +400,40 @@ export const t = (key: I18nKey, lang: Language, params?: Record<string, any>) =>
   }
   return text;
 };
+
+/** Localized text for an error thrown by a backend command. */
+export const errorMessage = (error: unknown, lang: Language): string => {
+  if (typeof error === 'string') return error;
+  if (error instanceof Error) return error.message;
+  const e = error as AppError | null;
+  switch (e?.kind) {
+    case 'locked':
+      return t(e.target === 'sync' ? 'err_locked_sync' : 'err_locked_database', lang);
+    case 'not_found':
+      return t('err_not_found', lang, { entity: t(`entity_${e.entity}`, lang) });
+    case 'conflict':
+      return t('err_conflict', lang, { message: e.message });
+    case 'validation':
+      return t('err_validation', lang, { field: e.field, message: e.message });
+    case 'io':
+      return t('err_io', lang, { message: e.path ? `${e.path}: ${e.message}` : e.message });
+    case 'database':
+      return t('err_database', lang, { message: e.message });
+    case 'import_invalid': {
+      const { reason, ...params } = e;
+      return t(`err_import_${reason}`, lang, params);
+    }
+    case 'cancelled':
+      return t('err_cancelled', lang);
+    case 'not_configured':
+      return t('err_not_configured', lang);
+    case 'offline':
+      return t('err_offline', lang);
+    case 'remote':
+      return t('err_remote', lang, { message: e.message });
+    case 'internal':
+      return e.message;
+    default:
+      return String(error);
+  }
+};